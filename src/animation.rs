@@ -0,0 +1,229 @@
+use crate::error::Error;
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, RgbaImage};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError};
+use std::time::{Duration, Instant};
+
+const MIN_DELAY: Duration = Duration::from_millis(10);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Memory {
+    Auto,
+    PreDecode,
+    Stream,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Info {
+    pub width: u32,
+    pub height: u32,
+    pub frames: usize,
+}
+
+impl Info {
+    pub fn decoded_size(&self) -> usize {
+        self.width as usize * self.height as usize * 4 * self.frames
+    }
+}
+
+fn skip_sub_blocks(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *data.get(pos)? as usize;
+        pos += 1 + len;
+        if len == 0 {
+            return Some(pos);
+        }
+    }
+}
+
+fn scan_gif(data: &[u8]) -> Option<Info> {
+    if !data.starts_with(b"GIF87a") && !data.starts_with(b"GIF89a") {
+        return None;
+    }
+    let width = u16::from_le_bytes([*data.get(6)?, *data.get(7)?]) as u32;
+    let height = u16::from_le_bytes([*data.get(8)?, *data.get(9)?]) as u32;
+    let flags = *data.get(10)?;
+    let mut pos = 13;
+    if flags & 0x80 != 0 {
+        pos += 3 << ((flags & 0x07) + 1);
+    }
+    let mut frames = 0;
+    loop {
+        match *data.get(pos)? {
+            0x21 => pos = skip_sub_blocks(data, pos + 2)?,
+            0x2c => {
+                let flags = *data.get(pos + 9)?;
+                pos += 10;
+                if flags & 0x80 != 0 {
+                    pos += 3 << ((flags & 0x07) + 1);
+                }
+                pos = skip_sub_blocks(data, pos + 1)?;
+                frames += 1;
+            }
+            _ => break,
+        }
+    }
+    Some(Info {
+        width,
+        height,
+        frames,
+    })
+}
+
+pub fn probe(path: impl AsRef<Path>) -> Option<Info> {
+    let path = path.as_ref();
+    let is_gif = match path.extension() {
+        Some(ext) => ext.to_string_lossy().eq_ignore_ascii_case("gif"),
+        None => false,
+    };
+    if !is_gif {
+        return None;
+    }
+    let mut data = vec![];
+    File::open(path).ok()?.read_to_end(&mut data).ok()?;
+    scan_gif(&data).filter(|info| info.frames > 1)
+}
+
+enum Message {
+    Frame(RgbaImage, Duration),
+    End,
+}
+
+fn decode(path: &Path, tx: &SyncSender<Message>, repeat: bool) -> Result<(), Error> {
+    loop {
+        let decoder = GifDecoder::new(BufReader::new(File::open(path)?))?;
+        for frame in decoder.into_frames() {
+            let frame = frame?;
+            let delay = match Duration::from(frame.delay()) {
+                d if d < MIN_DELAY => DEFAULT_DELAY,
+                d => d,
+            };
+            if tx.send(Message::Frame(frame.into_buffer(), delay)).is_err() {
+                return Ok(());
+            }
+        }
+        if tx.send(Message::End).is_err() || !repeat {
+            return Ok(());
+        }
+    }
+}
+
+pub struct Animation {
+    path: PathBuf,
+    rx: Receiver<Message>,
+    pre_decode: bool,
+    frames: Vec<(RgbaImage, Duration)>,
+    complete: bool,
+    index: usize,
+    current: Option<(RgbaImage, Duration)>,
+    next_at: Instant,
+}
+
+impl Animation {
+    pub fn new(
+        path: impl AsRef<Path>,
+        info: &Info,
+        memory: Memory,
+        ring_frames: usize,
+        headroom: usize,
+    ) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let pre_decode = match memory {
+            Memory::Auto => info.decoded_size() <= headroom,
+            Memory::PreDecode => true,
+            Memory::Stream => false,
+        };
+        debug!(
+            "animation: {}: {} frames, {}",
+            path.to_string_lossy(),
+            info.frames,
+            if pre_decode { "pre-decode" } else { "stream" }
+        );
+        let (tx, rx) = if pre_decode {
+            sync_channel(info.frames + 1)
+        } else {
+            sync_channel(ring_frames.max(1))
+        };
+        let src = path.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = decode(&src, &tx, !pre_decode) {
+                error!("animation: {}: {}", src.to_string_lossy(), e);
+            }
+        });
+        Self {
+            path,
+            rx,
+            pre_decode,
+            frames: vec![],
+            complete: false,
+            index: 0,
+            current: None,
+            next_at: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn frame(&self) -> Option<&RgbaImage> {
+        if self.pre_decode {
+            self.frames.get(self.index).map(|(img, _)| img)
+        } else {
+            self.current.as_ref().map(|(img, _)| img)
+        }
+    }
+
+    pub fn next_frame_in(&self) -> Duration {
+        self.next_at.saturating_duration_since(Instant::now())
+    }
+
+    pub fn advance(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_at {
+            return false;
+        }
+        if self.complete {
+            if self.frames.is_empty() {
+                return false;
+            }
+            self.index = (self.index + 1) % self.frames.len();
+            self.next_at = now + self.frames[self.index].1;
+            return true;
+        }
+        loop {
+            match self.rx.try_recv() {
+                Ok(Message::Frame(img, delay)) => {
+                    self.next_at = now + delay;
+                    if self.pre_decode {
+                        self.frames.push((img, delay));
+                        self.index = self.frames.len() - 1;
+                    } else {
+                        self.current = Some((img, delay));
+                    }
+                    return true;
+                }
+                Ok(Message::End) if self.pre_decode => {
+                    self.complete = true;
+                    return self.advance();
+                }
+                Ok(Message::End) => continue,
+                Err(TryRecvError::Empty) => {
+                    self.next_at = now + MIN_DELAY;
+                    return false;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    self.complete = true;
+                    self.next_at = now + DEFAULT_DELAY;
+                    return false;
+                }
+            }
+        }
+    }
+}
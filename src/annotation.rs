@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tool {
+    Pen,
+    Rectangle,
+    Arrow,
+    Text,
+}
+
+#[derive(Clone, Debug)]
+pub enum Shape {
+    Pen(Vec<(f32, f32)>),
+    Rectangle((f32, f32), (f32, f32)),
+    Arrow((f32, f32), (f32, f32)),
+    Text((f32, f32), String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Annotation {
+    path: PathBuf,
+    width: f32,
+    height: f32,
+    tool: Tool,
+    shapes: Vec<Shape>,
+    drawing: Option<Shape>,
+}
+
+impl Annotation {
+    pub fn new(path: impl AsRef<Path>, width: u32, height: u32) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            width: width as f32,
+            height: height as f32,
+            tool: Tool::Pen,
+            shapes: vec![],
+            drawing: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn image_size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    pub fn tool(&self) -> Tool {
+        self.tool
+    }
+
+    pub fn set_tool(&mut self, tool: Tool) {
+        self.end();
+        self.tool = tool;
+    }
+
+    pub fn stroke_width(&self) -> f32 {
+        (self.width.max(self.height) / 400.0).max(2.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty() && self.drawing.is_none()
+    }
+
+    pub fn is_typing(&self) -> bool {
+        matches!(self.drawing, Some(Shape::Text(..)))
+    }
+
+    pub fn shapes(&self) -> Vec<Shape> {
+        self.shapes
+            .iter()
+            .chain(self.drawing.iter())
+            .cloned()
+            .collect()
+    }
+
+    fn clamp(&self, x: f32, y: f32) -> (f32, f32) {
+        (x.max(0.0).min(self.width), y.max(0.0).min(self.height))
+    }
+
+    pub fn begin(&mut self, x: f32, y: f32) {
+        self.end();
+        let p = self.clamp(x, y);
+        self.drawing = Some(match self.tool {
+            Tool::Pen => Shape::Pen(vec![p]),
+            Tool::Rectangle => Shape::Rectangle(p, p),
+            Tool::Arrow => Shape::Arrow(p, p),
+            Tool::Text => Shape::Text(p, String::new()),
+        });
+    }
+
+    pub fn update(&mut self, x: f32, y: f32) {
+        let p = self.clamp(x, y);
+        match self.drawing.as_mut() {
+            Some(Shape::Pen(points)) => points.push(p),
+            Some(Shape::Rectangle(_, end)) | Some(Shape::Arrow(_, end)) => *end = p,
+            _ => {}
+        }
+    }
+
+    pub fn is_drawing(&self) -> bool {
+        matches!(
+            self.drawing,
+            Some(Shape::Pen(_)) | Some(Shape::Rectangle(..)) | Some(Shape::Arrow(..))
+        )
+    }
+
+    pub fn input(&mut self, c: char) {
+        if let Some(Shape::Text(_, text)) = self.drawing.as_mut() {
+            text.push(c);
+        }
+    }
+
+    pub fn back(&mut self) {
+        if let Some(Shape::Text(_, text)) = self.drawing.as_mut() {
+            text.pop();
+        }
+    }
+
+    pub fn cancel(&mut self) {
+        self.drawing = None;
+    }
+
+    pub fn end(&mut self) {
+        let shape = match self.drawing.take() {
+            Some(shape) => shape,
+            None => return,
+        };
+        let keep = match &shape {
+            Shape::Pen(points) => points.len() > 1,
+            Shape::Rectangle(a, b) | Shape::Arrow(a, b) => a != b,
+            Shape::Text(_, text) => !text.is_empty(),
+        };
+        if keep {
+            self.shapes.push(shape);
+        }
+    }
+
+    pub fn undo(&mut self) {
+        if self.drawing.take().is_none() {
+            self.shapes.pop();
+        }
+    }
+}
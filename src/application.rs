@@ -1,96 +1,634 @@
+use crate::animation::{self, Animation};
+use crate::annotation::{Annotation, Shape, Tool};
+use crate::archive::{self, ArchiveCache};
+use crate::args::Args;
+use crate::clipboard;
 use crate::config::*;
-use crate::dialog::file_open_dialog;
-use crate::directory::Directory;
-use crate::images::ImageManager;
+use crate::control::{
+    open_control_pipe, watch_config, Command, CommandQueue, CommandQueues, ControlClient,
+};
+use crate::convert;
+use crate::crop::Crop;
+use crate::cursor::{client_position, IdleCursor};
+use crate::device;
+use crate::dialog::{
+    confirm_dialog, file_open_dialog, file_save_dialog, folder_dialog, message_dialog,
+};
+use crate::directory::{self, Comparison, DirEntryInfo, Directory, Order, Siblings};
+use crate::duplicates;
+use crate::formats;
+use crate::gamepad::{self, GamepadEvent};
+use crate::gesture::Recognizer;
+use crate::images::{self, ImageManager};
+use crate::memory;
+use crate::metadata;
+use crate::notes;
+use crate::panorama::{self, Panorama};
+use crate::properties::Properties;
+use crate::ratings::{Flag, Rating, Ratings};
 use crate::renderer::*;
-use log::{debug, error};
+use crate::replay::{self, InputEvent, Recorder};
+use crate::sequence::{self, Sequence};
+use crate::shell;
+use crate::stats::{self, FolderStats};
+use crate::stereo::{self, StereoMode};
+use crate::touch::{self, TouchEvent};
+use crate::wndproc;
+use com_ptr::ComPtr;
+use log::{debug, error, warn};
+use std::collections::HashMap;
 use std::fs::File;
 use std::path::Path;
+use std::sync::Arc;
+use winapi::shared::minwindef::{DWORD, FALSE, LRESULT};
+use winapi::shared::windef::*;
 use winapi::um::combaseapi::CoInitializeEx;
+use winapi::um::d2d1_1::ID2D1Bitmap1;
+use winapi::um::dwmapi::DwmExtendFrameIntoClientArea;
 use winapi::um::objbase::{COINIT_APARTMENTTHREADED, COINIT_DISABLE_OLE1DDE};
+use winapi::um::uxtheme::MARGINS;
+use winapi::um::winbase::SetThreadExecutionState;
+use winapi::um::winnt::{ES_CONTINUOUS, ES_DISPLAY_REQUIRED, ES_SYSTEM_REQUIRED};
 use winapi::um::winuser::*;
 
-fn get_keyboard_delay() -> std::time::Duration {
+const PALETTE_ITEMS: usize = 10;
+const INFO_LINES: usize = 12;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Setting {
+    Background,
+    Interpolation,
+    Order,
+    Comparison,
+    FitMode,
+    SlideshowInterval,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Adjustment {
+    Brightness,
+    Contrast,
+    Gamma,
+    Exposure,
+}
+
+#[derive(PartialEq, Debug)]
+struct TitleKey {
+    path: Option<std::path::PathBuf>,
+    index: usize,
+    len: usize,
+    total: usize,
+    size: Option<(u32, u32)>,
+    scale: f32,
+    light_table: bool,
+    min_rating: u8,
+    hide_rejected: bool,
+}
+
+const ADJUSTMENTS: [Adjustment; 4] = [
+    Adjustment::Brightness,
+    Adjustment::Contrast,
+    Adjustment::Gamma,
+    Adjustment::Exposure,
+];
+
+const SETTINGS: [Setting; 6] = [
+    Setting::Background,
+    Setting::Interpolation,
+    Setting::Order,
+    Setting::Comparison,
+    Setting::FitMode,
+    Setting::SlideshowInterval,
+];
+
+fn cycle<T: Copy + PartialEq>(values: &[T], current: T, step: i32) -> T {
+    let index = values.iter().position(|v| *v == current).unwrap_or(0) as i32;
+    let len = values.len() as i32;
+    values[(index + step).rem_euclid(len) as usize]
+}
+
+fn text_info(font: &Font) -> TextInfo {
+    let mut face_names = vec![font.family.clone()];
+    face_names.extend(font.fallbacks.iter().cloned());
+    TextInfo {
+        face_names,
+        color: RgbaColor(1.0, 1.0, 1.0, 1.0),
+        size: font.size,
+    }
+}
+
+fn font_warning(font: &Font, renderer: &Renderer) -> Option<String> {
+    if renderer.face_name() == font.family {
+        return None;
+    }
+    Some(format!(
+        "font \"{}\" is not available: using \"{}\"",
+        font.family,
+        renderer.face_name()
+    ))
+}
+
+fn embed_window(wnd: &wita::Window, parent: isize) {
     unsafe {
-        let mut value = 0;
-        SystemParametersInfoW(SPI_GETKEYBOARDDELAY, 0, &mut value as *mut _ as _, 0);
-        std::time::Duration::from_millis((value + 1) * 250)
+        let hwnd = wnd.raw_handle() as HWND;
+        let parent = parent as HWND;
+        SetWindowLongPtrW(
+            hwnd,
+            GWL_STYLE,
+            (WS_CHILD | WS_VISIBLE | WS_CLIPSIBLINGS) as _,
+        );
+        SetParent(hwnd, parent);
+        let mut rc = RECT::default();
+        GetClientRect(parent, &mut rc);
+        SetWindowPos(
+            hwnd,
+            std::ptr::null_mut(),
+            0,
+            0,
+            rc.right - rc.left,
+            rc.bottom - rc.top,
+            SWP_NOZORDER | SWP_FRAMECHANGED | SWP_SHOWWINDOW,
+        );
     }
 }
 
-pub struct Application {
+fn set_borderless(wnd: &wita::Window, borderless: bool) {
+    unsafe {
+        let hwnd = wnd.raw_handle() as HWND;
+        let mut rc = RECT::default();
+        GetClientRect(hwnd, &mut rc);
+        let mut origin = POINT { x: 0, y: 0 };
+        ClientToScreen(hwnd, &mut origin);
+        let style = GetWindowLongPtrW(hwnd, GWL_STYLE) as DWORD;
+        let style = if borderless {
+            (style & !WS_OVERLAPPEDWINDOW) | WS_POPUP
+        } else {
+            (style & !WS_POPUP) | WS_OVERLAPPEDWINDOW
+        };
+        SetWindowLongPtrW(hwnd, GWL_STYLE, style as _);
+        let mut rc = RECT {
+            left: origin.x,
+            top: origin.y,
+            right: origin.x + rc.right,
+            bottom: origin.y + rc.bottom,
+        };
+        AdjustWindowRect(&mut rc, style, FALSE);
+        SetWindowPos(
+            hwnd,
+            std::ptr::null_mut(),
+            rc.left,
+            rc.top,
+            rc.right - rc.left,
+            rc.bottom - rc.top,
+            SWP_NOZORDER | SWP_FRAMECHANGED | SWP_SHOWWINDOW,
+        );
+    }
+}
+
+fn fit_window(wnd: &wita::Window, width: u32, height: u32, fit: &FitWindow) -> Option<(u32, u32)> {
+    unsafe {
+        let hwnd = wnd.raw_handle() as HWND;
+        if IsZoomed(hwnd) != 0 || IsIconic(hwnd) != 0 {
+            return None;
+        }
+        let monitor = MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST);
+        let mut info = MONITORINFO {
+            cbSize: std::mem::size_of::<MONITORINFO>() as _,
+            ..Default::default()
+        };
+        GetMonitorInfoW(monitor, &mut info);
+        let work = info.rcWork;
+        let style = GetWindowLongPtrW(hwnd, GWL_STYLE) as DWORD;
+        let mut frame = RECT::default();
+        AdjustWindowRect(&mut frame, style, FALSE);
+        let frame_width = frame.right - frame.left;
+        let frame_height = frame.bottom - frame.top;
+        let max_width = ((work.right - work.left) as f32 * fit.max_ratio) as i32 - frame_width;
+        let max_height = ((work.bottom - work.top) as f32 * fit.max_ratio) as i32 - frame_height;
+        let (w, h) = (width as f32, height as f32);
+        let scale = (max_width as f32 / w).min(max_height as f32 / h);
+        let scale = scale
+            .max(fit.min_width as f32 / w)
+            .max(fit.min_height as f32 / h);
+        let client_width = (w * scale).round().max(1.0) as i32;
+        let client_height = (h * scale).round().max(1.0) as i32;
+        let mut rc = RECT::default();
+        GetWindowRect(hwnd, &mut rc);
+        let center_x = (rc.left + rc.right) / 2;
+        let center_y = (rc.top + rc.bottom) / 2;
+        let window_width = client_width + frame_width;
+        let window_height = client_height + frame_height;
+        let left = (center_x - window_width / 2)
+            .min(work.right - window_width)
+            .max(work.left);
+        let top = (center_y - window_height / 2)
+            .min(work.bottom - window_height)
+            .max(work.top);
+        SetWindowPos(
+            hwnd,
+            std::ptr::null_mut(),
+            left,
+            top,
+            window_width,
+            window_height,
+            SWP_NOZORDER | SWP_NOACTIVATE,
+        );
+        Some((client_width as u32, client_height as u32))
+    }
+}
+
+fn set_fullscreen(wnd: &wita::Window, restore: Option<(DWORD, RECT)>) -> Option<(DWORD, RECT)> {
+    unsafe {
+        let hwnd = wnd.raw_handle() as HWND;
+        if let Some((style, rc)) = restore {
+            SetWindowLongPtrW(hwnd, GWL_STYLE, style as _);
+            SetWindowPos(
+                hwnd,
+                std::ptr::null_mut(),
+                rc.left,
+                rc.top,
+                rc.right - rc.left,
+                rc.bottom - rc.top,
+                SWP_NOZORDER | SWP_FRAMECHANGED | SWP_SHOWWINDOW,
+            );
+            return None;
+        }
+        let style = GetWindowLongPtrW(hwnd, GWL_STYLE) as DWORD;
+        let mut rc = RECT::default();
+        GetWindowRect(hwnd, &mut rc);
+        let monitor = MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST);
+        let mut info = MONITORINFO {
+            cbSize: std::mem::size_of::<MONITORINFO>() as _,
+            ..Default::default()
+        };
+        GetMonitorInfoW(monitor, &mut info);
+        let area = info.rcMonitor;
+        SetWindowLongPtrW(
+            hwnd,
+            GWL_STYLE,
+            ((style & !WS_OVERLAPPEDWINDOW) | WS_POPUP) as _,
+        );
+        SetWindowPos(
+            hwnd,
+            std::ptr::null_mut(),
+            area.left,
+            area.top,
+            area.right - area.left,
+            area.bottom - area.top,
+            SWP_NOZORDER | SWP_FRAMECHANGED | SWP_SHOWWINDOW,
+        );
+        Some((style, rc))
+    }
+}
+
+fn center_on_cursor_monitor(wnd: &wita::Window) {
+    unsafe {
+        let hwnd = wnd.raw_handle() as HWND;
+        let mut cursor = POINT::default();
+        GetCursorPos(&mut cursor);
+        let monitor = MonitorFromPoint(cursor, MONITOR_DEFAULTTONEAREST);
+        let mut info = MONITORINFO {
+            cbSize: std::mem::size_of::<MONITORINFO>() as _,
+            ..Default::default()
+        };
+        GetMonitorInfoW(monitor, &mut info);
+        let work = info.rcWork;
+        let mut rc = RECT::default();
+        GetWindowRect(hwnd, &mut rc);
+        let width = rc.right - rc.left;
+        let height = rc.bottom - rc.top;
+        SetWindowPos(
+            hwnd,
+            std::ptr::null_mut(),
+            work.left + (work.right - work.left - width) / 2,
+            work.top + (work.bottom - work.top - height) / 2,
+            0,
+            0,
+            SWP_NOZORDER | SWP_NOSIZE,
+        );
+    }
+}
+
+fn set_transparent(wnd: &wita::Window, renderer: &mut Renderer, transparent: bool) {
+    let inset = if transparent { -1 } else { 0 };
+    unsafe {
+        DwmExtendFrameIntoClientArea(
+            wnd.raw_handle() as HWND,
+            &MARGINS {
+                cxLeftWidth: inset,
+                cxRightWidth: inset,
+                cyTopHeight: inset,
+                cyBottomHeight: inset,
+            },
+        );
+    }
+    if let Err(e) = renderer.set_transparent(transparent) {
+        error!("set_transparent: {}", e);
+    }
+}
+
+fn crop_image(img: image::RgbaImage, rect: Option<(u32, u32, u32, u32)>) -> image::RgbaImage {
+    match rect {
+        Some((x, y, w, h)) => image::imageops::crop_imm(&img, x, y, w, h).to_image(),
+        None => img,
+    }
+}
+
+fn hit_test(wnd: &wita::Window, position: wita::PhysicalPosition<i32>) -> LRESULT {
+    const EDGE: i32 = 8;
+    let size = wnd.inner_size();
+    let left = position.x < EDGE;
+    let right = position.x >= size.width as i32 - EDGE;
+    let top = position.y < EDGE;
+    let bottom = position.y >= size.height as i32 - EDGE;
+    match (left, right, top, bottom) {
+        (true, _, true, _) => HTTOPLEFT,
+        (_, true, true, _) => HTTOPRIGHT,
+        (true, _, _, true) => HTBOTTOMLEFT,
+        (_, true, _, true) => HTBOTTOMRIGHT,
+        (true, _, _, _) => HTLEFT,
+        (_, true, _, _) => HTRIGHT,
+        (_, _, true, _) => HTTOP,
+        (_, _, _, true) => HTBOTTOM,
+        _ => HTCAPTION,
+    }
+}
+
+struct Viewer {
     wnd: wita::Window,
     config: Config,
+    base_config: Config,
+    profile: Option<String>,
     images: ImageManager,
     renderer: Renderer,
     dir: Option<Directory>,
+    runtime: Arc<tokio::runtime::Runtime>,
+    scan_id: u64,
+    scanning: Option<(std::path::PathBuf, usize)>,
     pressed_keys: Vec<wita::VirtualKey>,
-    keyboard_delay: std::time::Duration,
-    pressed_time: std::time::Instant,
+    forward: bool,
+    key_repeat: Option<(Method, Vec<wita::VirtualKey>, std::time::Instant)>,
     print_memory: bool,
+    low_memory: bool,
+    cache_share: f64,
+    primary: bool,
+    folder_stats: Option<FolderStats>,
+    config_warnings: Vec<String>,
+    presets: HashMap<std::path::PathBuf, RenderPreset>,
+    embedded: bool,
+    commands: CommandQueue,
+    recorder: Option<Recorder>,
+    crop: Option<Crop>,
+    panorama: Option<Panorama>,
+    panorama_checked: Option<std::path::PathBuf>,
+    redraw_at: std::cell::Cell<Option<std::time::Instant>>,
+    animation: Option<(Animation, Option<ComPtr<ID2D1Bitmap1>>)>,
+    animation_probe: Option<std::path::PathBuf>,
+    file_size: Option<(std::path::PathBuf, Option<u64>)>,
+    edge_colors: HashMap<std::path::PathBuf, (f32, f32, f32)>,
+    title: String,
+    title_key: Option<TitleKey>,
+    controller: Option<ControlClient>,
+    synced: Option<std::path::PathBuf>,
+    grid: bool,
+    grid_page: Option<std::path::PathBuf>,
+    palette: Option<String>,
+    settings: Option<usize>,
+    adjustments: Adjustments,
+    adjusting: Option<usize>,
+    channel: Channel,
+    inspector: bool,
+    inspected: Option<(u32, u32, Option<[u8; 4]>)>,
+    loupe: Option<(f32, f32)>,
+    wheel_delta: i32,
+    gesture: Option<Recognizer>,
+    sequence: Sequence,
+    touch: touch::Recognizer,
+    zoomed: Option<std::path::PathBuf>,
+    slideshow: Option<std::time::Instant>,
+    screensaver: bool,
+    presentation: bool,
+    cursor: IdleCursor,
+    screensaver_cursor: Option<wita::PhysicalPosition<i32>>,
+    fitted: Option<std::path::PathBuf>,
+    show_formats: bool,
+    new_windows: Vec<Option<std::path::PathBuf>>,
+    fullscreen: Option<(DWORD, RECT)>,
+    light_table: Vec<std::path::PathBuf>,
+    light_table_return: Option<Directory>,
+    stereo_mode: StereoMode,
+    stereo: Option<(std::path::PathBuf, StereoMode, Option<ComPtr<ID2D1Bitmap1>>)>,
+    compare_mark: Option<std::path::PathBuf>,
+    compare: Option<(std::path::PathBuf, Option<ComPtr<ID2D1Bitmap1>>)>,
+    comparing: bool,
+    diffing: bool,
+    duplicates: Vec<Vec<std::path::PathBuf>>,
+    duplicate_group: usize,
+    duplicates_return: Option<Directory>,
+    ratings: Ratings,
+    min_rating: u8,
+    hide_rejected: bool,
+    show_note: bool,
+    show_info: bool,
+    info_scroll: usize,
+    show_properties: bool,
+    properties: Option<(std::path::PathBuf, Option<Properties>)>,
+    metadata: Option<(std::path::PathBuf, metadata::Metadata)>,
+    note: Option<(std::path::PathBuf, String)>,
+    note_editing: Option<String>,
+    annotation: Option<Annotation>,
 }
 
-impl Application {
-    pub fn new() -> anyhow::Result<Self> {
-        simplelog::CombinedLogger::init(vec![
-            simplelog::TermLogger::new(
-                simplelog::LevelFilter::Debug,
-                simplelog::Config::default(),
-                simplelog::TerminalMode::Mixed,
-            ),
-            simplelog::WriteLogger::new(
-                simplelog::LevelFilter::Info,
-                simplelog::Config::default(),
-                File::create("niv.log")?,
-            ),
-        ])
-        .unwrap();
-        unsafe {
-            CoInitializeEx(
-                std::ptr::null_mut(),
-                COINIT_APARTMENTTHREADED | COINIT_DISABLE_OLE1DDE,
-            );
-        }
-        let config = read_config("./config.json").unwrap_or_default();
+impl Viewer {
+    fn new(
+        args: Args,
+        config: Config,
+        mut config_warnings: Vec<String>,
+        runtime: Arc<tokio::runtime::Runtime>,
+        archive_cache: Arc<ArchiveCache>,
+        primary: bool,
+    ) -> anyhow::Result<Self> {
+        let profile = args.profile.clone().or_else(|| config.profile.clone());
+        let base_config = config;
+        let config = base_config.layered(profile.as_deref());
+        let window = config.window.on_screen();
         let wnd = wita::WindowBuilder::new()
             .title("niv")
-            .position(wita::ScreenPosition::new(config.window.x, config.window.y))
-            .inner_size(wita::PhysicalSize::new(
-                config.window.width,
-                config.window.height,
-            ))
+            .position(wita::ScreenPosition::new(window.x, window.y))
+            .inner_size(wita::PhysicalSize::new(window.width, window.height))
             .accept_drag_files(true)
             .build();
         wnd.disable_ime();
+        if let Some(parent) = args.parent_hwnd {
+            embed_window(&wnd, parent);
+        } else {
+            if config.borderless {
+                set_borderless(&wnd, true);
+            }
+            if window.maximized {
+                unsafe {
+                    ShowWindow(wnd.raw_handle() as HWND, SW_MAXIMIZE);
+                }
+            }
+        }
+        let commands = CommandQueue::new(wnd.clone());
+        if let Some(name) = args.pipe.as_ref() {
+            open_control_pipe(name, commands.clone());
+        }
+        let recorder = args.record_input.as_ref().and_then(|path| {
+            Recorder::new(path)
+                .map_err(|e| error!("record input: {}", e))
+                .ok()
+        });
+        if let Some(path) = args.replay_input.as_ref() {
+            if let Err(e) = replay::replay(path, args.path.is_some(), commands.clone()) {
+                error!("replay input: {}", e);
+            }
+        }
         let images = ImageManager::new(
-            config.worker_threads,
+            runtime.clone(),
             config.bmp_cache_size,
             config.image_cache_size,
+            config.cache_policy,
+            archive_cache,
+            std::time::Duration::from_secs(config.error_ttl),
+            std::time::Duration::from_millis(config.error_probe_interval),
         )?;
-        let text_info = TextInfo {
-            face_name: "Yu Gothic".into(),
-            color: RgbaColor(1.0, 1.0, 1.0, 1.0),
-            size: 14.0,
-        };
-        let renderer = Renderer::new(&wnd, text_info)?;
+        images.set_max_decode_size(config.max_decode_size);
+        images.set_preview(config.progressive_preview);
+        images.set_decoders(&config.decoders);
+        images.set_scaled_jpeg(config.scaled_jpeg);
+        images.set_mipmaps(config.mipmaps);
+        images.set_worker_threads(config.worker_threads);
+        images.set_disk_cache_size(config.disk_cache_size);
+        let transparent = config.transparent_background && args.parent_hwnd.is_none();
+        let mut renderer = Renderer::new(&wnd, text_info(&config.font), &config.osd, transparent)?;
+        renderer.set_fit_mode(config.fit_mode);
+        renderer.set_vsync(config.vsync);
+        if transparent {
+            set_transparent(&wnd, &mut renderer, true);
+        }
+        if let Some(w) = font_warning(&config.font, &renderer) {
+            config_warnings.push(w);
+        }
+        wndproc::hook_window(&wnd, commands.clone());
         let dir = None;
-        Ok(Application {
+        let mut viewer = Viewer {
             wnd,
             config,
+            base_config,
+            profile,
             images,
             renderer,
             dir,
+            runtime,
+            scan_id: 0,
+            scanning: None,
             pressed_keys: vec![],
-            keyboard_delay: get_keyboard_delay(),
-            pressed_time: std::time::Instant::now(),
+            forward: true,
+            key_repeat: None,
             print_memory: false,
-        })
+            low_memory: false,
+            cache_share: 1.0,
+            primary,
+            folder_stats: None,
+            config_warnings,
+            presets: HashMap::new(),
+            embedded: args.parent_hwnd.is_some(),
+            commands,
+            recorder,
+            crop: None,
+            panorama: None,
+            panorama_checked: None,
+            redraw_at: std::cell::Cell::new(None),
+            animation: None,
+            animation_probe: None,
+            file_size: None,
+            edge_colors: HashMap::new(),
+            title: String::new(),
+            title_key: None,
+            light_table: vec![],
+            light_table_return: None,
+            stereo_mode: StereoMode::Off,
+            stereo: None,
+            compare_mark: None,
+            compare: None,
+            comparing: false,
+            diffing: false,
+            duplicates: vec![],
+            duplicate_group: 0,
+            duplicates_return: None,
+            ratings: Ratings::default(),
+            min_rating: 0,
+            hide_rejected: false,
+            show_note: false,
+            show_info: false,
+            info_scroll: 0,
+            show_properties: false,
+            properties: None,
+            metadata: None,
+            note: None,
+            note_editing: None,
+            annotation: None,
+            controller: args.controller.as_deref().map(ControlClient::new),
+            synced: None,
+            grid: args.controller.is_some(),
+            grid_page: None,
+            palette: None,
+            settings: None,
+            adjustments: Adjustments::default(),
+            adjusting: None,
+            channel: Channel::All,
+            inspector: false,
+            inspected: None,
+            loupe: None,
+            wheel_delta: 0,
+            gesture: None,
+            sequence: Sequence::new(),
+            touch: touch::Recognizer::new(),
+            zoomed: None,
+            slideshow: None,
+            screensaver: false,
+            presentation: false,
+            cursor: IdleCursor::new(),
+            screensaver_cursor: None,
+            fitted: None,
+            show_formats: false,
+            new_windows: vec![],
+            fullscreen: None,
+        };
+        if args.screensaver {
+            viewer.start_screensaver();
+        } else if let Some(path) = args.path {
+            viewer.open_entity(path);
+            viewer.set_title();
+        }
+        Ok(viewer)
     }
 }
 
-impl Application {
+impl Viewer {
     fn open_entity(&mut self, path: impl AsRef<Path>) {
         let path = path.as_ref();
+        let archive = if archive::is_archive(path) && path.is_file() {
+            Some((path.to_path_buf(), None))
+        } else {
+            archive::split(path).map(|(archive, _)| (archive, Some(path)))
+        };
+        if let Some((archive, init)) = archive {
+            let paths = match archive::entries(&archive, &self.config.extensions) {
+                Ok(entries) => entries.into_iter().map(|(path, _)| path).collect(),
+                Err(e) => {
+                    error!("archive: {}: {}", archive.to_string_lossy(), e);
+                    return;
+                }
+            };
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.record(InputEvent::Open(path.to_path_buf()));
+            }
+            self.open_directory(Directory::from_paths(&archive, paths, &self.config, init));
+            return;
+        }
         let (dir_path, file) = if path.is_file() {
             (path.parent().unwrap(), Some(path))
         } else if path.is_dir() {
@@ -98,136 +636,3215 @@ impl Application {
         } else {
             return;
         };
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(InputEvent::Open(path.to_path_buf()));
+        }
+        let paths = file.iter().map(|path| path.to_path_buf()).collect();
+        self.open_directory(Directory::from_paths(dir_path, paths, &self.config, file));
+        self.scan_directory(dir_path);
+    }
+
+    fn open_directory(&mut self, dir: Directory) {
         self.images.clear();
-        self.dir = Some(Directory::new(
-            dir_path,
-            &self.config.extensions,
-            self.config.order,
-            self.config.comp,
-            self.config.lookahead as isize,
-            file,
+        self.folder_stats = None;
+        self.light_table_return = None;
+        self.duplicates_return = None;
+        self.scan_id += 1;
+        self.scanning = None;
+        self.dir = Some(dir);
+        self.apply_rating_filter();
+        self.measure_dimensions();
+        self.load_current();
+    }
+
+    fn measure_dimensions(&mut self) {
+        let dir = match self.dir.as_ref() {
+            Some(dir) if dir.sorted_by_resolution() => dir,
+            _ => return,
+        };
+        let paths = dir.unmeasured();
+        if paths.is_empty() {
+            return;
+        }
+        let dir = dir.path().to_path_buf();
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.runtime.spawn_blocking(move || {
+            let dimensions = paths
+                .into_iter()
+                .map(|path| {
+                    let size = image::image_dimensions(&path).ok();
+                    (path, size)
+                })
+                .collect();
+            commands.push(Command::Dimensions(dir, dimensions));
+            wnd.redraw();
+        });
+    }
+
+    fn open_playlist(&mut self, paths: Vec<std::path::PathBuf>) {
+        self.open_directory(Directory::playlist("playlist", paths, &self.config));
+    }
+
+    fn open_dropped(&mut self, paths: &[&Path]) {
+        let name = Path::new("dropped files");
+        let roots = paths.iter().map(|path| path.to_path_buf()).collect();
+        debug!("drop_files: {} paths", paths.len());
+        self.open_directory(Directory::from_paths(
+            name,
+            vec![],
+            &self.config,
+            None::<&Path>,
         ));
-        if let Some(current) = self.dir.as_ref().unwrap().current() {
-            let wnd = self.wnd.clone();
-            let dc = self.renderer.device_context();
-            self.images.load(dc, current, move |_| wnd.redraw());
+        self.scan_roots(name, roots);
+    }
+
+    fn scan_directory(&mut self, dir: &Path) {
+        self.scan_roots(dir, vec![dir.to_path_buf()]);
+    }
+
+    fn scan_roots(&mut self, name: &Path, roots: Vec<std::path::PathBuf>) {
+        self.scan_id += 1;
+        self.scanning = Some((name.to_path_buf(), 0));
+        let id = self.scan_id;
+        let config = self.config.clone();
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.runtime.spawn_blocking(move || {
+            directory::scan_roots(&roots, &config, |entries| {
+                commands.push(Command::Scanned(id, entries, false));
+                wnd.redraw();
+            });
+            commands.push(Command::Scanned(id, vec![], true));
+            wnd.redraw();
+        });
+    }
+
+    fn scanned(&mut self, id: u64, entries: Vec<(std::path::PathBuf, DirEntryInfo)>, done: bool) {
+        let path = match self.scanning.as_ref() {
+            Some((path, _)) if id == self.scan_id => path.clone(),
+            _ => return,
+        };
+        let dir = match self.dir.as_mut() {
+            Some(dir) if dir.path() == path => dir,
+            _ => {
+                if done {
+                    self.scanning = None;
+                }
+                return;
+            }
+        };
+        let empty = dir.current().is_none();
+        dir.extend(entries);
+        let total = dir.total();
+        if done {
+            debug!("scan: {}: {} images", path.to_string_lossy(), total);
+            self.scanning = None;
+            let dir = self.dir.take().unwrap();
+            self.dir = Some(dir.regroup(&self.config));
+            self.apply_rating_filter();
+            self.measure_dimensions();
+            self.load_current();
+        } else {
+            self.scanning = Some((path, total));
+            if empty {
+                self.load_current();
+            }
         }
     }
 
-    fn set_title(&self) {
-        let (num, path) = if let Some(dir) = self.dir.as_ref() {
-            let num = format!("{}/{}", dir.index() + 1, dir.len());
-            let path = dir
-                .current()
-                .map_or(String::new(), |path| path.to_string_lossy().to_string());
-            (num, path)
+    fn set_title(&mut self) {
+        let path = self.dir.as_ref().and_then(|dir| dir.current());
+        let size = match path.map(|path| self.images.get(path)) {
+            Some(Ok(Some(bmp))) => Some(images::original_size(&bmp)),
+            _ => None,
+        };
+        let scale = size.map_or(0.0, |(width, height)| {
+            self.renderer.layout(width as f32, height as f32).scale
+        });
+        let key = TitleKey {
+            path: path.map(|path| path.to_path_buf()),
+            index: self.dir.as_ref().map_or(0, |dir| dir.index()),
+            len: self.dir.as_ref().map_or(0, |dir| dir.len()),
+            total: self.dir.as_ref().map_or(0, |dir| dir.total()),
+            size,
+            scale,
+            light_table: self.light_table_return.is_some(),
+            min_rating: self.min_rating,
+            hide_rejected: self.hide_rejected,
+        };
+        if self.title_key.as_ref() == Some(&key) {
+            return;
+        }
+        let (index, total, path) = match self.dir.as_ref() {
+            Some(_) => (
+                (key.index + 1).to_string(),
+                key.len.to_string(),
+                key.path.clone(),
+            ),
+            None => (String::new(), String::new(), None),
+        };
+        let text = |s: Option<&std::ffi::OsStr>| {
+            s.map_or(String::new(), |s| s.to_string_lossy().to_string())
+        };
+        let name = text(path.as_deref().and_then(|path| path.file_name()));
+        let dir = text(
+            path.as_deref()
+                .and_then(|path| path.parent())
+                .map(|dir| dir.as_os_str()),
+        );
+        let full_path = text(path.as_deref().map(|path| path.as_os_str()));
+        let (zoom, resolution) = match key.size {
+            Some((width, height)) => (
+                format!("{:.0}%", key.scale * 100.0),
+                format!("{}x{}", width, height),
+            ),
+            None => (String::new(), String::new()),
+        };
+        let light_table = if key.light_table {
+            " [light table]"
+        } else {
+            ""
+        };
+        let filter = match self.dir.as_ref() {
+            Some(_) if key.min_rating > 0 || key.hide_rejected => {
+                let mut conditions = vec![];
+                if key.min_rating > 0 {
+                    conditions.push(format!("★{}+", key.min_rating));
+                }
+                if key.hide_rejected {
+                    conditions.push("no rejects".to_string());
+                }
+                format!(" [{} {}/{}]", conditions.join(", "), key.len, key.total)
+            }
+            _ => String::new(),
+        };
+        let title = if self.dir.is_none() {
+            "niv".to_string()
         } else {
-            (String::new(), String::new())
+            self.config
+                .title
+                .replace("{light_table}", light_table)
+                .replace("{filter}", &filter)
+                .replace("{index}", &index)
+                .replace("{total}", &total)
+                .replace("{name}", &name)
+                .replace("{dir}", &dir)
+                .replace("{path}", &full_path)
+                .replace("{zoom}", &zoom)
+                .replace("{resolution}", &resolution)
         };
-        self.wnd.set_title(&format!("niv {} {}", num, path))
+        self.title_key = Some(key);
+        if title != self.title {
+            self.wnd.set_title(&title);
+            self.title = title;
+        }
     }
-}
 
-impl wita::EventHandler for Application {
-    fn key_input(
-        &mut self,
-        _: &wita::Window,
-        _: wita::KeyCode,
-        state: wita::KeyState,
-        prev_pressed: bool,
-    ) {
-        match state {
-            wita::KeyState::Pressed => {
-                if !prev_pressed {
-                    self.pressed_time = std::time::Instant::now();
+    fn find_method(&self) -> Option<Method> {
+        self.config
+            .key_bindings
+            .iter()
+            .filter_map(|kb| {
+                kb.keys
+                    .iter()
+                    .filter(|kk| kk.iter().all(|k| self.pressed_keys.contains(k)))
+                    .map(|kk| kk.len())
+                    .max()
+                    .map(|len| (kb.method, len))
+            })
+            .min_by_key(|(_, len)| std::cmp::Reverse(*len))
+            .map(
+                |(method, _)| match (self.config.reading_direction, method) {
+                    (ReadingDirection::RightToLeft, Method::Prev) => Method::Next,
+                    (ReadingDirection::RightToLeft, Method::Next) => Method::Prev,
+                    _ => method,
+                },
+            )
+    }
+
+    fn confirm(&self, action: ConfirmAction, text: &str) -> bool {
+        if !self.config.confirmation.required(action) {
+            return true;
+        }
+        if let Some(key) = self.config.confirmation.bypass_key.as_ref() {
+            if wita::keyboard_state().contains(key) {
+                return true;
+            }
+        }
+        confirm_dialog(&self.wnd, text)
+    }
+
+    fn osd(&mut self, text: impl Into<String>) {
+        if self.config.osd.enabled && !self.presentation {
+            self.renderer.show_osd(text);
+        }
+    }
+
+    fn copy_current_path(&mut self, file_name_only: bool) {
+        let path = self.dir.as_ref().and_then(|dir| dir.current());
+        let text = match path {
+            Some(path) if file_name_only => path.file_name().map(|name| name.to_string_lossy()),
+            Some(path) => Some(path.to_string_lossy()),
+            None => None,
+        };
+        if let Some(text) = text {
+            match clipboard::set_text(&self.wnd, &text) {
+                Ok(_) => self.osd(format!("Copied {}", text)),
+                Err(e) => error!("copy_current_path: {}", e),
+            }
+        }
+    }
+
+    fn execute(&mut self, method: Method) {
+        if let Some(controller) = self.controller.as_mut() {
+            if matches!(
+                method,
+                Method::CycleRenderPreset
+                    | Method::CycleStereoMode
+                    | Method::Panorama
+                    | Method::ToggleStatusBar
+            ) {
+                controller.send(&Command::Method(method));
+            }
+        }
+        match method {
+            Method::Open => {
+                let mut extensions = self.config.extensions.clone();
+                extensions.extend(archive::EXTENSIONS.iter().map(|ext| ext.to_string()));
+                let filters = formats::dialog_filters(&extensions);
+                let index = self
+                    .config
+                    .open_dialog_filter
+                    .as_ref()
+                    .and_then(|name| filters.iter().position(|(n, _)| n == name))
+                    .unwrap_or(0);
+                let (mut paths, index) = file_open_dialog(&self.wnd, &filters, index)
+                    .unwrap_or_else(|e| {
+                        error!("open_dialog: {}", e);
+                        (vec![], index)
+                    });
+                self.config.open_dialog_filter = filters.get(index).map(|(name, _)| name.clone());
+                if paths.len() > 1 {
+                    debug!("open_dialog: {} files", paths.len());
+                    self.open_playlist(paths);
+                } else if let Some(path) = paths.pop() {
+                    debug!("open_dialog: {}", path.to_string_lossy());
+                    self.open_entity(path);
                 }
-                self.pressed_keys = wita::keyboard_state();
-                self.pressed_keys.retain(|key| {
-                    if let wita::VirtualKey::Other(i) = key {
-                        *i < 233
+            }
+            Method::OpenFolder => {
+                let path = folder_dialog(&self.wnd).unwrap_or_else(|e| {
+                    error!("folder_dialog: {}", e);
+                    None
+                });
+                if let Some(path) = path {
+                    debug!("folder_dialog: {}", path.to_string_lossy());
+                    self.open_entity(path);
+                }
+            }
+            Method::Prev | Method::Next => {
+                self.step(method);
+                self.load_current();
+            }
+            Method::PrevDirectory | Method::NextDirectory => {
+                if self.move_to_sibling(method == Method::NextDirectory, false) {
+                    self.load_current();
+                } else {
+                    self.osd("No more folders");
+                }
+            }
+            Method::First => self.jump_to(0),
+            Method::Last => self.jump_to(usize::MAX),
+            Method::GoTo => self.osd("GoTo: type an index before the key"),
+            Method::ReloadImage => {
+                if let Some(path) = self.dir.as_ref().and_then(|dir| dir.current()) {
+                    debug!("reload: {}", path.to_string_lossy());
+                    self.images.invalidate(path);
+                    self.file_size = None;
+                    self.load_current();
+                    self.osd("Reload");
+                }
+            }
+            Method::PrintMemory => self.print_memory = !self.print_memory,
+            Method::FolderStats => {
+                if self.folder_stats.take().is_none() {
+                    self.collect_folder_stats(false);
+                }
+            }
+            Method::ExportFolderStats => self.collect_folder_stats(true),
+            Method::CopyPath => self.copy_current_path(false),
+            Method::CopyFileName => self.copy_current_path(true),
+            Method::TogglePair => {
+                if let Some(path) = self.dir.as_mut().and_then(|dir| dir.toggle_pair()) {
+                    self.load_current();
+                    if let Some(name) = path.file_name() {
+                        self.osd(name.to_string_lossy());
+                    }
+                }
+            }
+            Method::RevealInExplorer => {
+                if let Some(path) = self.dir.as_ref().and_then(|dir| dir.current()) {
+                    if let Err(e) = shell::reveal_in_explorer(path) {
+                        error!("reveal_in_explorer: {}", e);
+                    }
+                }
+            }
+            Method::ExternalTool(i) => {
+                let path = self.dir.as_ref().and_then(|dir| dir.current());
+                if let (Some(path), Some(tool)) = (path, self.config.external_tools.get(i)) {
+                    debug!("external tool: {}: {}", tool.name, path.to_string_lossy());
+                    if let Err(e) = shell::run_external_tool(&tool.command, path) {
+                        error!("external tool: {}: {}", tool.name, e);
+                    }
+                }
+            }
+            Method::CycleRenderPreset => {
+                if let Some(path) = self.dir.as_ref().and_then(|dir| dir.current()) {
+                    let preset = self
+                        .presets
+                        .get(path)
+                        .copied()
+                        .unwrap_or(RenderPreset::Photo)
+                        .next();
+                    debug!("render preset: {:?}", preset);
+                    let text = format!("Preset: {:?}", preset);
+                    if preset == RenderPreset::Photo {
+                        self.presets.remove(path);
                     } else {
-                        true
+                        self.presets.insert(path.to_path_buf(), preset);
+                    }
+                    self.osd(text);
+                }
+            }
+            Method::SetWallpaper => {
+                if let Some(path) = self.dir.as_ref().and_then(|dir| dir.current()) {
+                    match shell::set_wallpaper(path, self.config.wallpaper_position) {
+                        Ok(_) => self.osd("Set as wallpaper"),
+                        Err(e) => error!("set_wallpaper: {}", e),
                     }
+                }
+            }
+            Method::SaveAs => self.save_as(),
+            Method::CopyImage => self.copy_image(),
+            Method::AddToLightTable => self.add_to_light_table(),
+            Method::ToggleLightTable => self.toggle_light_table(),
+            Method::ExportLightTable => self.export_light_table(),
+            Method::ToggleBorderless => {
+                if !self.embedded {
+                    self.config.borderless = !self.config.borderless;
+                    set_borderless(&self.wnd, self.config.borderless);
+                }
+            }
+            Method::ShowFormats => self.show_formats = !self.show_formats,
+            Method::CycleProfile => self.cycle_profile(),
+            Method::Settings => self.settings = Some(0),
+            Method::Adjustments => self.adjusting = Some(0),
+            Method::PixelInspector => {
+                self.inspector = !self.inspector;
+                self.inspected = None;
+                self.osd(if self.inspector {
+                    "Pixel inspector on"
+                } else {
+                    "Pixel inspector off"
                 });
-                let method = self.config.key_bindings.iter().find_map(|kb| {
-                    kb.keys
-                        .iter()
-                        .find(|kk| kk.iter().all(|k| self.pressed_keys.contains(k)))
-                        .map(|_| kb.method)
+            }
+            Method::ToggleLoupe => {
+                self.loupe = match self.loupe {
+                    Some(_) => None,
+                    None => Some(self.renderer.to_dip(client_position(&self.wnd))),
+                };
+                self.osd(if self.loupe.is_some() {
+                    "Loupe on"
+                } else {
+                    "Loupe off"
                 });
-                if let Some(method) = method {
-                    if matches!(method, Method::PrintMemory) {
-                        self.print_memory = !self.print_memory;
+            }
+            Method::ToggleGuide(guide) => {
+                let enabled = self.config.guides.toggle(guide);
+                self.osd(format!(
+                    "{:?}: {}",
+                    guide,
+                    if enabled { "on" } else { "off" }
+                ));
+            }
+            Method::MarkCompare => {
+                if let Some(path) = self.dir.as_ref().and_then(|dir| dir.current()) {
+                    let name = path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    self.compare_mark = Some(path.to_path_buf());
+                    self.osd(format!("Marked for compare: {}", name));
+                }
+            }
+            Method::ToggleCompare => {
+                if self.compare_mark.is_none() {
+                    self.osd("No image marked for compare");
+                } else {
+                    self.comparing = !self.comparing;
+                    self.osd(if self.comparing {
+                        "Compare on"
                     } else {
-                        if let Some(dir) = self.dir.as_mut() {
-                            let path = match method {
-                                Method::Prev => dir.prev().first().cloned(),
-                                Method::Next => dir.next().first().cloned(),
-                                _ => None,
-                            };
-                            if let Some(path) = path {
-                                let t = std::time::Instant::now();
-                                if t - self.pressed_time <= self.keyboard_delay {
-                                    let wnd = self.wnd.clone();
-                                    self.images.load(
-                                        self.renderer.device_context(),
-                                        &path,
-                                        move |_| wnd.redraw(),
-                                    );
-                                    debug!("pressed key: load: {}", path.to_string_lossy());
+                        "Compare off"
+                    });
+                }
+            }
+            Method::Rate(stars) => self.rate(|rating| rating.stars = stars.min(5)),
+            Method::ToggleFlag(flag) => self.rate(|rating| {
+                rating.flag = if rating.flag == Some(flag) {
+                    None
+                } else {
+                    Some(flag)
+                };
+            }),
+            Method::CycleRatingFilter => {
+                self.min_rating = (self.min_rating + 1) % 6;
+                self.apply_rating_filter();
+                self.load_current();
+                self.osd(if self.min_rating == 0 {
+                    "Rating filter off".to_string()
+                } else {
+                    format!("Rating filter: {}+ stars", self.min_rating)
+                });
+            }
+            Method::ToggleHideRejected => {
+                self.hide_rejected = !self.hide_rejected;
+                self.apply_rating_filter();
+                self.load_current();
+                self.osd(if self.hide_rejected {
+                    "Hide rejected"
+                } else {
+                    "Show rejected"
+                });
+            }
+            Method::ToggleNote => {
+                self.show_note = !self.show_note;
+                self.osd(if self.show_note {
+                    "Note on"
+                } else {
+                    "Note off"
+                });
+            }
+            Method::ToggleInfo => {
+                self.show_info = !self.show_info;
+                self.osd(if self.show_info {
+                    "Info on"
+                } else {
+                    "Info off"
+                });
+            }
+            Method::CycleOrder | Method::ToggleSortDirection => {
+                let setting = if method == Method::CycleOrder {
+                    Setting::Order
+                } else {
+                    Setting::Comparison
+                };
+                self.change_setting(setting, 1);
+                self.load_current();
+                self.osd(format!(
+                    "Order: {:?} ({:?})",
+                    self.config.order, self.config.comp
+                ));
+            }
+            Method::ToggleProperties => {
+                self.show_properties = !self.show_properties;
+                self.osd(if self.show_properties {
+                    "Properties on"
+                } else {
+                    "Properties off"
+                });
+            }
+            Method::CopyLocation => match self.current_metadata().and_then(|m| m.gps) {
+                Some(gps) => match clipboard::set_text(&self.wnd, &gps.to_string()) {
+                    Ok(_) => self.osd(format!("Copied {}", gps)),
+                    Err(e) => error!("copy_location: {}", e),
+                },
+                None => self.osd("No location"),
+            },
+            Method::OpenLocation => match self.current_metadata().and_then(|m| m.gps) {
+                Some(gps) => {
+                    let url = self
+                        .config
+                        .map_url
+                        .replace("{lat}", &format!("{:.6}", gps.latitude))
+                        .replace("{lon}", &format!("{:.6}", gps.longitude));
+                    if let Err(e) = shell::open_url(&url) {
+                        error!("open_location: {}", e);
+                    }
+                }
+                None => self.osd("No location"),
+            },
+            Method::EditNote => {
+                if let Some(path) = self.dir.as_ref().and_then(|dir| dir.current()) {
+                    if archive::split(path).is_none() {
+                        self.note_editing = Some(self.current_note().unwrap_or_default());
+                    }
+                }
+            }
+            Method::Annotate => {
+                if self.current_annotation().is_some() {
+                    self.annotation = None;
+                    self.osd("Annotation off");
+                } else {
+                    self.annotation =
+                        self.dir
+                            .as_ref()
+                            .and_then(|dir| dir.current())
+                            .and_then(|path| match self.images.get(path) {
+                                Ok(Some(bmp)) => {
+                                    let (width, height) = images::original_size(&bmp);
+                                    Some(Annotation::new(path, width, height))
                                 }
-                            }
-                        }
+                                _ => None,
+                            });
+                    if self.annotation.is_some() {
+                        self.osd("Annotation on");
                     }
                 }
-                self.set_title();
-                self.wnd.redraw();
             }
-            wita::KeyState::Released => {
-                let method = self.config.key_bindings.iter().find_map(|kb| {
-                    kb.keys
-                        .iter()
-                        .find(|kk| kk.iter().all(|k| self.pressed_keys.contains(k)))
-                        .map(|_| kb.method)
+            Method::FindDuplicates => self.find_duplicates(),
+            Method::Delete => self.delete_current(),
+            Method::ToggleDiff => {
+                if self.compare_mark.is_none() {
+                    self.osd("No image marked for compare");
+                } else {
+                    self.diffing = !self.diffing;
+                    self.osd(if self.diffing { "Diff on" } else { "Diff off" });
+                }
+            }
+            Method::CycleChannel => {
+                self.channel = self.channel.next();
+                self.osd(format!("Channel: {:?}", self.channel));
+            }
+            Method::ResetAdjustments => {
+                self.adjustments = Adjustments::default();
+                self.osd("Reset adjustments");
+            }
+            Method::ToggleSlideshow => {
+                self.slideshow = match self.slideshow {
+                    Some(_) => None,
+                    None => Some(std::time::Instant::now() + self.slideshow_interval()),
+                };
+                if self.slideshow.is_none() && self.zoomed.is_none() {
+                    self.renderer.reset_zoom();
+                }
+                self.osd(if self.slideshow.is_some() {
+                    "Slideshow on"
+                } else {
+                    "Slideshow off"
                 });
-                if let Some(method) = method {
-                    match method {
-                        Method::Open => {
-                            let path = file_open_dialog(&self.wnd, &self.config.extensions)
-                                .unwrap_or_else(|e| {
-                                    error!("open_dialog: {}", e);
-                                    None
-                                });
-                            if let Some(path) = path {
-                                debug!("open_dialog: {}", path.to_string_lossy());
-                                self.open_entity(path);
-                            }
-                        }
-                        Method::Prev | Method::Next => {
-                            if let Some(dir) = self.dir.as_mut() {
-                                let dc = self.renderer.device_context();
-                                let path = dir.current().unwrap();
-                                let wnd = self.wnd.clone();
-                                self.images.load(dc, &path, move |_| wnd.redraw());
-                                debug!("released key: load: {}", path.to_string_lossy());
-                            }
-                        }
-                        _ => (),
-                    }
+            }
+            Method::ToggleFullscreen => {
+                if !self.embedded {
+                    self.fullscreen = set_fullscreen(&self.wnd, self.fullscreen.take());
                 }
-                self.set_title();
-                self.wnd.redraw();
             }
-        }
+            Method::TogglePresentation => self.set_presentation(!self.presentation),
+            Method::NewWindow => {
+                let path = self
+                    .dir
+                    .as_ref()
+                    .and_then(|dir| dir.current())
+                    .map(|path| path.to_path_buf());
+                self.new_windows.push(path);
+            }
+            Method::ToggleTransparency => {
+                if !self.embedded {
+                    self.config.transparent_background = !self.config.transparent_background;
+                    set_transparent(
+                        &self.wnd,
+                        &mut self.renderer,
+                        self.config.transparent_background,
+                    );
+                }
+            }
+            Method::ToggleFitWindow => {
+                self.config.fit_window.enabled = !self.config.fit_window.enabled;
+                self.fitted = None;
+                self.osd(if self.config.fit_window.enabled {
+                    "Fit window on"
+                } else {
+                    "Fit window off"
+                });
+            }
+            Method::ToggleGrid => {
+                self.grid = !self.grid;
+                self.grid_page = None;
+            }
+            Method::CommandPalette => self.palette = Some(String::new()),
+            Method::ToggleStatusBar => self.config.status_bar = !self.config.status_bar,
+            Method::ImportFromDevice => {
+                let staging = std::env::temp_dir().join("niv-import");
+                let extensions = self.config.extensions.clone();
+                let import = self.config.import.clone();
+                let commands = self.commands.clone();
+                let wnd = self.wnd.clone();
+                self.osd("Importing from devices");
+                std::thread::spawn(move || {
+                    let results = match device::browse_devices(&staging, &extensions, import.verify)
+                    {
+                        Ok(results) => results,
+                        Err(e) => {
+                            error!("import: {}", e);
+                            return;
+                        }
+                    };
+                    let mut paths = vec![];
+                    for result in results {
+                        commands.push(Command::Notify(format!(
+                            "Found {} photos on {}",
+                            result.paths.len(),
+                            result.name
+                        )));
+                        paths.extend(result.paths);
+                    }
+                    if paths.is_empty() {
+                        commands.push(Command::Notify("No photos found on devices".into()));
+                    } else {
+                        commands.push(Command::Imported(staging, paths));
+                    }
+                    wnd.redraw();
+                });
+            }
+            Method::CycleStereoMode => {
+                self.stereo_mode = self.stereo_mode.next();
+                debug!("stereo mode: {:?}", self.stereo_mode);
+                self.osd(format!("Stereo: {:?}", self.stereo_mode));
+            }
+            Method::Panorama => {
+                if self.current_panorama().is_some() {
+                    self.panorama = None;
+                    self.osd("Panorama off");
+                } else if let Some(path) = self
+                    .dir
+                    .as_ref()
+                    .and_then(|dir| dir.current())
+                    .map(|path| path.to_path_buf())
+                {
+                    self.enter_panorama(&path);
+                }
+            }
+            Method::Crop => {
+                if self.current_crop().is_some() {
+                    self.crop = None;
+                    self.osd("Crop off");
+                } else {
+                    self.panorama = None;
+                    self.crop = self
+                        .dir
+                        .as_ref()
+                        .and_then(|dir| dir.current())
+                        .and_then(|path| match self.images.get(path) {
+                            Ok(Some(bmp)) => {
+                                let (width, height) = images::original_size(&bmp);
+                                Some(Crop::new(path, width, height))
+                            }
+                            _ => None,
+                        });
+                    if self.crop.is_some() {
+                        self.osd("Crop: drag to select");
+                    }
+                }
+            }
+        }
+    }
+
+    fn key_pressed(&mut self, method: Option<Method>, prev_pressed: bool) {
+        if let Some(method) = method {
+            if prev_pressed && matches!(method, Method::Prev | Method::Next) {
+                return;
+            }
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.record(InputEvent::Pressed {
+                    method,
+                    repeat: prev_pressed,
+                });
+            }
+            if matches!(method, Method::PrintMemory) {
+                self.print_memory = !self.print_memory;
+            } else if matches!(method, Method::Prev | Method::Next) && self.step(method) {
+                self.load_current();
+                let delay = std::time::Duration::from_millis(self.config.key_repeat.delay);
+                self.key_repeat = Some((
+                    method,
+                    self.pressed_keys.clone(),
+                    std::time::Instant::now() + delay,
+                ));
+            }
+        }
+        self.set_title();
+        self.wnd.redraw();
+    }
+
+    fn key_released(&mut self, method: Option<Method>) {
+        if let Some(method) = method {
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.record(InputEvent::Released(method));
+            }
+        }
+        self.key_repeat = None;
+        match method {
+            Some(Method::Prev) | Some(Method::Next) => self.load_current(),
+            Some(Method::PrintMemory) | None => (),
+            Some(method) => self.execute(method),
+        }
+        self.set_title();
+        self.wnd.redraw();
+    }
+
+    fn process_commands(&mut self) {
+        let commands = self.commands.take();
+        if commands.is_empty() {
+            return;
+        }
+        for command in commands {
+            match command {
+                Command::Open(path) => self.open_entity(path),
+                Command::Method(method) => self.execute(method),
+                Command::Resize(width, height) => {
+                    unsafe {
+                        SetWindowPos(
+                            self.wnd.raw_handle() as _,
+                            std::ptr::null_mut(),
+                            0,
+                            0,
+                            width as _,
+                            height as _,
+                            SWP_NOZORDER | SWP_NOMOVE,
+                        );
+                    }
+                    self.renderer.resize(wita::PhysicalSize::new(width, height));
+                }
+                Command::Close => self.wnd.close(),
+                Command::Input(InputEvent::Open(path)) => self.open_entity(path),
+                Command::Input(InputEvent::Pressed { method, repeat }) => {
+                    self.pressed_keys.clear();
+                    self.key_pressed(Some(method), repeat)
+                }
+                Command::Input(InputEvent::Released(method)) => self.key_released(Some(method)),
+                Command::Notify(text) => self.osd(text),
+                Command::ReloadConfig => self.reload_config(),
+                Command::Touch(event) => self.touch_input(event),
+                Command::Gamepad(event) => self.gamepad_input(event),
+                Command::MemoryPressure(low) => {
+                    if low {
+                        warn!("low memory: trimming caches");
+                    } else {
+                        debug!("memory recovered: restoring caches");
+                    }
+                    self.low_memory = low;
+                    self.apply_cache_sizes();
+                }
+                Command::DisplayChanged => self.renderer.display_changed(),
+                Command::Duplicates(groups) => self.review_duplicates(groups),
+                Command::Scanned(id, entries, done) => self.scanned(id, entries, done),
+                Command::PanoramaDetected(path) => {
+                    if self.dir.as_ref().and_then(|dir| dir.current()) == Some(path.as_path()) {
+                        debug!("panorama: {}", path.to_string_lossy());
+                        self.enter_panorama(&path);
+                    }
+                }
+                Command::Panorama(path, img) => self.panorama_decoded(path, img),
+                Command::Dimensions(path, dimensions) => {
+                    if let Some(dir) = self.dir.as_mut().filter(|dir| dir.path() == path) {
+                        dir.set_dimensions(dimensions);
+                    }
+                }
+                Command::AnimationProbed(path, info) => self.animation_probed(path, info),
+                Command::FolderStats(stats, export) => self.folder_stats_collected(stats, export),
+                Command::Stereo(path, mode, img) => {
+                    if matches!(&self.stereo, Some((p, m, None)) if *p == path && *m == mode) {
+                        let bmp = self
+                            .renderer
+                            .create_bitmap(img.width(), img.height(), img.as_raw())
+                            .map_err(|e| error!("stereo: {}", e))
+                            .ok();
+                        self.stereo = Some((path, mode, bmp));
+                    }
+                }
+                Command::CopyImage(img, selection) => self.image_copied(img, selection),
+                Command::Annotated(path, format, img, shapes, stroke) => {
+                    self.annotated(path, format, img, shapes, stroke)
+                }
+                Command::CompareImage(path, img) => {
+                    if self.compare_mark.as_ref() == Some(&path) {
+                        let bmp = self
+                            .renderer
+                            .create_bitmap(img.width(), img.height(), img.as_raw())
+                            .map_err(|e| error!("compare: {}", e))
+                            .ok();
+                        self.compare = Some((path, bmp));
+                    }
+                }
+                Command::Imported(staging, paths) => {
+                    let dir = Directory::from_paths(staging, paths, &self.config, None::<&Path>);
+                    self.open_directory(dir);
+                }
+                Command::MediaKey(key) => {
+                    let pressed_keys = std::mem::replace(&mut self.pressed_keys, vec![key]);
+                    let method = self.find_method();
+                    self.pressed_keys = pressed_keys;
+                    if let Some(method) = method {
+                        debug!("media key: {:?}: {:?}", key, method);
+                        self.execute(method);
+                    }
+                }
+            }
+        }
+    }
+
+    fn reload_config(&mut self) {
+        let base_config = match load_config() {
+            Some(config) => config,
+            None => {
+                warn!(
+                    "config: {} could not be read",
+                    config_path().to_string_lossy()
+                );
+                self.config_warnings = vec!["config could not be read".into()];
+                return;
+            }
+        };
+        debug!("config: reload");
+        let config = base_config.layered(self.profile.as_deref());
+        let prev = std::mem::replace(&mut self.config, config);
+        self.base_config = base_config;
+        self.title_key = None;
+        if !self.embedded {
+            self.config.window = prev.window.clone();
+        }
+        self.config.last_path = prev.last_path.clone();
+        self.config_warnings = self.config.validate();
+        for w in &self.config_warnings {
+            warn!("config: {}", w);
+        }
+        self.apply_config(&prev);
+        self.osd("Config reloaded");
+    }
+
+    fn unlayered_config(&self) -> Config {
+        let mut config = self
+            .config
+            .unlayered(&self.base_config, self.profile.as_deref());
+        config.profile = self.base_config.profile.clone();
+        config
+    }
+
+    fn cycle_profile(&mut self) {
+        let names = self.base_config.profile_names();
+        let next = match self.profile.as_ref() {
+            Some(current) => names.iter().skip_while(|name| *name != current).nth(1),
+            None => names.first(),
+        }
+        .cloned();
+        let mut base_config = self.unlayered_config();
+        base_config.profile = next.clone();
+        let config = base_config.layered(next.as_deref());
+        let prev = std::mem::replace(&mut self.config, config);
+        self.base_config = base_config;
+        self.title_key = None;
+        self.profile = next;
+        self.apply_config(&prev);
+        let text = format!(
+            "Profile: {}",
+            self.profile.as_deref().unwrap_or("(default)")
+        );
+        self.osd(text);
+    }
+
+    fn cache_budget(&self) -> (usize, usize) {
+        let ratio = if self.low_memory {
+            self.config.low_memory_cache_ratio.clamp(0.0, 1.0) as f64
+        } else {
+            1.0
+        } * self.cache_share;
+        (
+            (self.config.bmp_cache_size as f64 * ratio) as usize,
+            (self.config.image_cache_size as f64 * ratio) as usize,
+        )
+    }
+
+    fn apply_cache_sizes(&self) {
+        let (bmp_cache_size, image_cache_size) = self.cache_budget();
+        self.images
+            .set_cache_sizes(bmp_cache_size, image_cache_size);
+        self.images
+            .set_disk_cache_size((self.config.disk_cache_size as f64 * self.cache_share) as usize);
+    }
+
+    fn set_cache_share(&mut self, share: f64) {
+        self.cache_share = share;
+        self.apply_cache_sizes();
+    }
+
+    fn apply_config(&mut self, prev: &Config) {
+        self.apply_cache_sizes();
+        self.images.set_cache_policy(self.config.cache_policy);
+        if self.config.max_decode_size != prev.max_decode_size {
+            self.images.set_max_decode_size(self.config.max_decode_size);
+            self.load_current();
+        }
+        self.images.set_preview(self.config.progressive_preview);
+        self.images.set_decoders(&self.config.decoders);
+        self.images.set_scaled_jpeg(self.config.scaled_jpeg);
+        self.images.set_mipmaps(self.config.mipmaps);
+        self.images.set_worker_threads(self.config.worker_threads);
+        self.renderer.set_osd(&self.config.osd);
+        self.renderer.set_fit_mode(self.config.fit_mode);
+        self.renderer.set_vsync(self.config.vsync);
+        if self.config.fit_mode != prev.fit_mode {
+            self.renderer.reset_zoom();
+            self.zoomed = None;
+        }
+        if self.config.font.family != prev.font.family
+            || self.config.font.fallbacks != prev.font.fallbacks
+            || self.config.font.size != prev.font.size
+        {
+            if let Err(e) = self.renderer.set_text_info(text_info(&self.config.font)) {
+                error!("font: {}", e);
+            }
+            if let Some(w) = font_warning(&self.config.font, &self.renderer) {
+                self.config_warnings.push(w);
+            }
+        }
+        if self.config.order != prev.order
+            || self.config.secondary_order != prev.secondary_order
+            || self.config.comp != prev.comp
+        {
+            if let Some(dir) = self.dir.as_mut() {
+                dir.change_order(
+                    self.config.order,
+                    self.config.secondary_order,
+                    self.config.comp,
+                );
+            }
+            self.measure_dimensions();
+        }
+        if !self.embedded {
+            if self.config.borderless != prev.borderless && self.fullscreen.is_none() {
+                set_borderless(&self.wnd, self.config.borderless);
+            }
+            if self.config.transparent_background != prev.transparent_background {
+                set_transparent(
+                    &self.wnd,
+                    &mut self.renderer,
+                    self.config.transparent_background,
+                );
+            }
+        }
+        self.edge_colors.clear();
+        self.fitted = None;
+    }
+
+    fn overlay_text(&self) -> Option<String> {
+        let mut texts = vec![];
+        if !self.config_warnings.is_empty() {
+            texts.push(format!(
+                "config warning:\n{}",
+                self.config_warnings.join("\n")
+            ));
+        }
+        if self.print_memory {
+            let stats = self.images.stats();
+            let (bmp_cache_size, image_cache_size) = self.cache_budget();
+            let ms = |d: Option<std::time::Duration>| {
+                d.map_or("-".to_string(), |d| {
+                    format!("{:.1}ms", d.as_secs_f32() * 1000.0)
+                })
+            };
+            texts.push(format!(
+                "bmp: {}/{}(MB) hit {:.0}% ({}/{})\nimage: {}/{}(MB) hit {:.0}% ({}/{})\n\
+                 decode: {}\nupload: {}\nframe: {}\nprefetch queue: {}",
+                self.images.bmp_cache_size() as f32 / 1024.0 / 1024.0,
+                bmp_cache_size as f32 / 1024.0 / 1024.0,
+                stats.bmp.hit_rate() * 100.0,
+                stats.bmp.hits,
+                stats.bmp.hits + stats.bmp.misses,
+                self.images.image_cache_size() as f32 / 1024.0 / 1024.0,
+                image_cache_size as f32 / 1024.0 / 1024.0,
+                stats.image.hit_rate() * 100.0,
+                stats.image.hits,
+                stats.image.hits + stats.image.misses,
+                ms(stats.image.last_load),
+                ms(stats.bmp.last_load),
+                ms(Some(self.renderer.frame_time())),
+                stats.queue_depth
+            ));
+        }
+        if let Some(stats) = self.folder_stats.as_ref() {
+            texts.push(stats.to_string());
+        }
+        if self.duplicates_return.is_some() {
+            texts.push(format!(
+                "duplicates: group {}/{} ({} images)",
+                self.duplicate_group + 1,
+                self.duplicates.len(),
+                self.duplicates
+                    .get(self.duplicate_group)
+                    .map_or(0, |group| group.len())
+            ));
+        }
+        if self.show_formats {
+            texts.push(formats::report(&self.config.extensions));
+        }
+        if let Some(query) = self.palette.as_ref() {
+            texts.push(self.palette_text(query));
+        }
+        if let Some(selected) = self.settings {
+            texts.push(self.settings_text(selected));
+        }
+        if let Some(selected) = self.adjusting {
+            texts.push(self.adjustments_text(selected));
+        }
+        if let Some(annotation) = self.current_annotation() {
+            texts.push(if annotation.is_typing() {
+                "annotate: Text  Enter: done  Esc: cancel".to_string()
+            } else {
+                format!(
+                    "annotate: {:?}  P: pen  R: rectangle  A: arrow  T: text  \
+                     BackSpace: undo  S: export  Esc: exit",
+                    annotation.tool()
+                )
+            });
+        }
+        if let Some(text) = self.note_editing.as_ref() {
+            texts.push(format!(
+                "note (Enter: save, Shift+Enter: new line, Esc: cancel):\n{}_",
+                text
+            ));
+        } else if self.show_note {
+            if let Some(note) = self.note.as_ref().map(|(_, note)| note) {
+                if !note.is_empty() {
+                    texts.push(format!("note:\n{}", note));
+                }
+            }
+        }
+        if self.show_info {
+            let lines = self.info_lines();
+            if lines.len() > INFO_LINES {
+                let start = self.info_scroll.min(lines.len() - INFO_LINES);
+                texts.push(format!(
+                    "info ({}-{}/{}  Up/Down: scroll):\n{}",
+                    start + 1,
+                    start + INFO_LINES,
+                    lines.len(),
+                    lines[start..start + INFO_LINES].join("\n")
+                ));
+            } else if !lines.is_empty() {
+                texts.push(format!("info:\n{}", lines.join("\n")));
+            }
+        }
+        if let Some((_, count)) = self.scanning.as_ref() {
+            texts.push(format!("scanning: {} images found", count));
+        }
+        if self.show_properties {
+            if let Some((_, properties)) = self.properties.as_ref() {
+                let format = self.metadata.as_ref().and_then(|(_, m)| m.format);
+                texts.push(match properties {
+                    Some(properties) => {
+                        format!("properties:\n{}", properties.lines(format).join("\n"))
+                    }
+                    None => "properties: -".to_string(),
+                });
+            }
+        }
+        if self.inspector {
+            texts.push(match self.inspected {
+                Some((x, y, Some([r, g, b, a]))) => format!(
+                    "pixel: {}, {}  RGBA({}, {}, {}, {})  #{:02X}{:02X}{:02X}{:02X}  (click: copy)",
+                    x, y, r, g, b, a, r, g, b, a
+                ),
+                Some((x, y, None)) => format!("pixel: {}, {}  (not decoded)", x, y),
+                None => "pixel: -".to_string(),
+            });
+        }
+        if let Some(gesture) = self.gesture.as_ref().filter(|g| !g.is_empty()) {
+            texts.push(format!("gesture: {}", gesture.strokes()));
+        }
+        if let Some(keys) = self.sequence.text() {
+            texts.push(format!("keys: {}", keys));
+        }
+        if texts.is_empty() {
+            None
+        } else {
+            Some(texts.join("\n\n"))
+        }
+    }
+
+    fn schedule_redraw(&self) {
+        let osd_delay = if self.renderer.osd_visible() {
+            Some(std::time::Duration::from_millis(33))
+        } else {
+            None
+        };
+        let animation_delay = self
+            .animation
+            .as_ref()
+            .map(|(animation, _)| animation.next_frame_in());
+        let slideshow_delay = self
+            .slideshow
+            .map(|next| next.saturating_duration_since(std::time::Instant::now()));
+        let sequence_delay = self
+            .sequence
+            .deadline()
+            .map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()));
+        let key_repeat_delay = self
+            .key_repeat
+            .as_ref()
+            .map(|(_, _, next)| next.saturating_duration_since(std::time::Instant::now()));
+        let ken_burns_delay = if self.slideshow.is_some() && self.config.ken_burns > 0.0 {
+            Some(std::time::Duration::from_millis(16))
+        } else {
+            None
+        };
+        let cursor_delay = self.cursor.delay(self.cursor_timeout());
+        let recover_delay = if self.renderer.device_lost() {
+            Some(std::time::Duration::from_millis(100))
+        } else {
+            None
+        };
+        let delay = [
+            osd_delay,
+            animation_delay,
+            slideshow_delay,
+            sequence_delay,
+            key_repeat_delay,
+            ken_burns_delay,
+            cursor_delay,
+            recover_delay,
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied();
+        if let Some(delay) = delay {
+            let now = std::time::Instant::now();
+            let at = now + delay;
+            if self
+                .redraw_at
+                .get()
+                .is_none_or(|pending| at < pending || pending <= now)
+            {
+                self.redraw_at.set(Some(at));
+                wndproc::set_redraw_timer(&self.wnd, delay);
+            }
+        }
+    }
+
+    fn sync_viewer(&mut self) {
+        let controller = match self.controller.as_mut() {
+            Some(controller) => controller,
+            None => return,
+        };
+        let current = self.dir.as_ref().and_then(|dir| dir.current());
+        if let Some(current) = current {
+            if self.synced.as_deref() != Some(current) {
+                controller.send(&Command::Open(current.to_path_buf()));
+                self.synced = Some(current.to_path_buf());
+            }
+        }
+    }
+
+    fn draw_grid(&mut self) {
+        let columns = self.config.grid_columns.max(1);
+        let page_size = columns * self.renderer.grid_rows(columns);
+        let (paths, index) = match self.dir.as_ref() {
+            Some(dir) => (dir.paths().to_vec(), dir.index()),
+            None => (vec![], 0),
+        };
+        let start = index / page_size * page_size;
+        let paths = &paths[start..(start + page_size).min(paths.len())];
+        let page = paths.first().map(|path| path.to_path_buf());
+        if self.grid_page != page {
+            let wnd = self.wnd.clone();
+            self.images
+                .load_all(self.renderer.device_context(), paths, move |_| wnd.redraw());
+            self.grid_page = page;
+        }
+        let cells = paths
+            .iter()
+            .map(|path| self.images.get(path).unwrap_or_default())
+            .collect::<Vec<_>>();
+        self.set_title();
+        let background = self.background();
+        self.renderer.render_grid(
+            &background,
+            &cells,
+            index - start,
+            columns,
+            self.config.interpolation,
+            self.overlay_text(),
+        );
+    }
+
+    fn palette_methods(&self, query: &str) -> Vec<Method> {
+        let query = query.to_lowercase();
+        let mut methods = self
+            .config
+            .key_bindings
+            .iter()
+            .map(|kb| kb.method)
+            .filter(|method| format!("{:?}", method).to_lowercase().contains(&query))
+            .collect::<Vec<_>>();
+        methods.dedup();
+        methods
+    }
+
+    fn palette_text(&self, query: &str) -> String {
+        let mut lines = vec![format!("> {}", query)];
+        lines.extend(
+            self.palette_methods(query)
+                .iter()
+                .take(PALETTE_ITEMS)
+                .map(|method| format!("  {:?}", method)),
+        );
+        lines.join("\n")
+    }
+
+    fn current_annotation(&self) -> Option<&Annotation> {
+        let current = self.dir.as_ref().and_then(|dir| dir.current());
+        self.annotation
+            .as_ref()
+            .filter(|annotation| Some(annotation.path()) == current)
+    }
+
+    fn annotation_position(
+        &self,
+        annotation: &Annotation,
+        position: wita::PhysicalPosition<i32>,
+    ) -> (f32, f32) {
+        let (width, height) = annotation.image_size();
+        let layout = self.renderer.layout(width as f32, height as f32);
+        let (x, y) = self.renderer.to_dip(position);
+        layout.to_image(x, y)
+    }
+
+    fn annotation_input(&mut self, key: wita::VirtualKey) {
+        let annotation = match self.annotation.as_mut() {
+            Some(annotation) => annotation,
+            None => return,
+        };
+        if annotation.is_typing() {
+            match key {
+                wita::VirtualKey::Esc => annotation.cancel(),
+                wita::VirtualKey::Enter => annotation.end(),
+                wita::VirtualKey::BackSpace => annotation.back(),
+                _ => {}
+            }
+            self.wnd.redraw();
+            return;
+        }
+        match key {
+            wita::VirtualKey::Esc => {
+                self.annotation = None;
+                self.osd("Annotation off");
+            }
+            wita::VirtualKey::Char('P') => annotation.set_tool(Tool::Pen),
+            wita::VirtualKey::Char('R') => annotation.set_tool(Tool::Rectangle),
+            wita::VirtualKey::Char('A') => annotation.set_tool(Tool::Arrow),
+            wita::VirtualKey::Char('T') => annotation.set_tool(Tool::Text),
+            wita::VirtualKey::BackSpace => annotation.undo(),
+            wita::VirtualKey::Char('S') => self.export_annotation(),
+            _ => {}
+        }
+        self.wnd.redraw();
+    }
+
+    fn export_annotation(&mut self) {
+        let (src, shapes, stroke) = match self.current_annotation() {
+            Some(annotation) if !annotation.is_empty() => (
+                annotation.path().to_path_buf(),
+                annotation.shapes(),
+                annotation.stroke_width(),
+            ),
+            _ => return,
+        };
+        let file_name = src.file_stem().map_or(String::new(), |name| {
+            format!("{}_annotated", name.to_string_lossy())
+        });
+        let (path, format) = match file_save_dialog(&self.wnd, &file_name) {
+            Ok(Some(ret)) => ret,
+            Ok(None) => return,
+            Err(e) => {
+                error!("save_dialog: {}", e);
+                return;
+            }
+        };
+        if path.exists()
+            && !self.confirm(
+                ConfirmAction::Overwrite,
+                &format!("{}を上書きしますか？", path.to_string_lossy()),
+            )
+        {
+            return;
+        }
+        debug!(
+            "export_annotation: {} -> {}",
+            src.to_string_lossy(),
+            path.to_string_lossy()
+        );
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.images.open(&src, move |_, img| match img {
+            Ok(img) => {
+                commands.push(Command::Annotated(path, format, img, shapes, stroke));
+                wnd.redraw();
+            }
+            Err(e) => error!("export_annotation: {}", e),
+        });
+    }
+
+    fn annotated(
+        &mut self,
+        path: std::path::PathBuf,
+        format: convert::OutputFormat,
+        img: image::RgbaImage,
+        shapes: Vec<Shape>,
+        stroke: f32,
+    ) {
+        let img = match self.renderer.flatten_annotations(&img, &shapes, stroke) {
+            Ok(img) => img,
+            Err(e) => {
+                error!("export_annotation: {}", e);
+                return;
+            }
+        };
+        let encode = self.config.encode.clone();
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.runtime.spawn_blocking(move || {
+            match convert::save_image(&img, &path, format, &encode) {
+                Ok(_) => commands.push(Command::Notify(format!(
+                    "Exported {}",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                ))),
+                Err(e) => error!("export_annotation: {}", e),
+            }
+            wnd.redraw();
+        });
+    }
+
+    fn current_metadata(&mut self) -> Option<metadata::Metadata> {
+        let path = self.dir.as_ref()?.current()?;
+        if !matches!(&self.metadata, Some((p, _)) if p == path) {
+            self.metadata = Some((path.to_path_buf(), metadata::Metadata::read(path)));
+            self.info_scroll = 0;
+        }
+        self.metadata.as_ref().map(|(_, metadata)| metadata.clone())
+    }
+
+    fn current_properties(&mut self) {
+        let path = match self.dir.as_ref().and_then(|dir| dir.current()) {
+            Some(path) => path,
+            None => return,
+        };
+        if !matches!(&self.properties, Some((p, _)) if p == path) {
+            let properties = Properties::read(path)
+                .map_err(|e| debug!("properties: {}: {}", path.to_string_lossy(), e))
+                .ok();
+            self.properties = Some((path.to_path_buf(), properties));
+        }
+    }
+
+    fn info_lines(&self) -> Vec<String> {
+        let metadata = match self.metadata.as_ref() {
+            Some((_, metadata)) => metadata,
+            None => return vec![],
+        };
+        let mut lines = vec![];
+        if let Some(title) = metadata.title.as_ref() {
+            lines.push(format!("Title: {}", title));
+        }
+        if let Some(caption) = metadata.caption.as_ref() {
+            let mut caption = caption.lines();
+            lines.push(format!("Caption: {}", caption.next().unwrap_or_default()));
+            lines.extend(caption.map(|line| format!("  {}", line)));
+        }
+        if !metadata.keywords.is_empty() {
+            lines.push(format!("Keywords: {}", metadata.keywords.join(", ")));
+        }
+        if let Some(copyright) = metadata.copyright.as_ref() {
+            lines.push(format!("Copyright: {}", copyright));
+        }
+        lines.push(match metadata.gps {
+            Some(gps) => match gps.altitude {
+                Some(altitude) => format!("GPS: {} ({:.1} m)", gps, altitude),
+                None => format!("GPS: {}", gps),
+            },
+            None => "GPS: -".to_string(),
+        });
+        lines
+    }
+
+    fn info_input(&mut self, key: wita::VirtualKey) {
+        let max = self.info_lines().len().saturating_sub(INFO_LINES);
+        self.info_scroll = match key {
+            wita::VirtualKey::Up => self.info_scroll.saturating_sub(1),
+            wita::VirtualKey::Down => self.info_scroll + 1,
+            wita::VirtualKey::PageUp => self.info_scroll.saturating_sub(INFO_LINES),
+            wita::VirtualKey::PageDown => self.info_scroll + INFO_LINES,
+            _ => return,
+        }
+        .min(max);
+        self.wnd.redraw();
+    }
+
+    fn current_note(&mut self) -> Option<String> {
+        let path = self.dir.as_ref()?.current()?;
+        if !matches!(&self.note, Some((p, _)) if p == path) {
+            self.note = Some((path.to_path_buf(), notes::load(path)));
+        }
+        self.note.as_ref().map(|(_, note)| note.clone())
+    }
+
+    fn note_input(&mut self, key: wita::VirtualKey) {
+        let text = match self.note_editing.as_mut() {
+            Some(text) => text,
+            None => return,
+        };
+        match key {
+            wita::VirtualKey::Esc => self.note_editing = None,
+            wita::VirtualKey::BackSpace => {
+                text.pop();
+            }
+            wita::VirtualKey::Enter
+                if wita::keyboard_state().contains(&wita::VirtualKey::Shift) =>
+            {
+                text.push('\n');
+            }
+            wita::VirtualKey::Enter => {
+                let text = self.note_editing.take().unwrap();
+                let path = match self.dir.as_ref().and_then(|dir| dir.current()) {
+                    Some(path) => path.to_path_buf(),
+                    None => return,
+                };
+                match notes::save(&path, &text) {
+                    Ok(_) => {
+                        self.note = Some((path, text));
+                        self.show_note = true;
+                        self.osd("Saved note");
+                    }
+                    Err(e) => error!("note: {}: {}", path.to_string_lossy(), e),
+                }
+            }
+            _ => {}
+        }
+        self.wnd.redraw();
+    }
+
+    fn palette_input(&mut self, key: wita::VirtualKey) {
+        let query = match self.palette.as_mut() {
+            Some(query) => query,
+            None => return,
+        };
+        match key {
+            wita::VirtualKey::Esc => self.palette = None,
+            wita::VirtualKey::BackSpace => {
+                query.pop();
+            }
+            wita::VirtualKey::Enter => {
+                let query = self.palette.take().unwrap();
+                if let Some(method) = self.palette_methods(&query).first() {
+                    self.execute(*method);
+                }
+            }
+            _ => {}
+        }
+        self.wnd.redraw();
+    }
+
+    fn setting_value(&self, setting: Setting) -> String {
+        match setting {
+            Setting::Background => {
+                let ClearColor(r, g, b) = self.config.background;
+                format!("{:.2}, {:.2}, {:.2}", r, g, b)
+            }
+            Setting::Interpolation => format!("{:?}", self.config.interpolation),
+            Setting::Order => format!("{:?}", self.config.order),
+            Setting::Comparison => format!("{:?}", self.config.comp),
+            Setting::FitMode => format!("{:?}", self.config.fit_mode),
+            Setting::SlideshowInterval => format!("{} ms", self.config.slideshow_interval),
+        }
+    }
+
+    fn settings_text(&self, selected: usize) -> String {
+        let mut lines =
+            vec!["settings (Up/Down: select, Left/Right: change, Esc: close)".to_string()];
+        lines.extend(SETTINGS.iter().enumerate().map(|(i, setting)| {
+            format!(
+                "{} {:?}: {}",
+                if i == selected { ">" } else { " " },
+                setting,
+                self.setting_value(*setting)
+            )
+        }));
+        lines.join("\n")
+    }
+
+    fn change_setting(&mut self, setting: Setting, step: i32) {
+        let prev = self.config.clone();
+        match setting {
+            Setting::Background => {
+                let ClearColor(r, g, b) = self.config.background;
+                let d = step as f32 * 0.05;
+                self.config.background = ClearColor(
+                    (r + d).clamp(0.0, 1.0),
+                    (g + d).clamp(0.0, 1.0),
+                    (b + d).clamp(0.0, 1.0),
+                );
+            }
+            Setting::Interpolation => {
+                self.config.interpolation = cycle(
+                    &[
+                        Interpolation::Nearest,
+                        Interpolation::Linear,
+                        Interpolation::Cubic,
+                        Interpolation::HighQualityCubic,
+                        Interpolation::Lanczos,
+                    ],
+                    self.config.interpolation,
+                    step,
+                );
+            }
+            Setting::Order => {
+                self.config.order = cycle(
+                    &[
+                        Order::Name,
+                        Order::UpdatedDate,
+                        Order::FileSize,
+                        Order::Resolution,
+                        Order::Extension,
+                    ],
+                    self.config.order,
+                    step,
+                );
+            }
+            Setting::Comparison => {
+                self.config.comp = cycle(
+                    &[Comparison::Ascending, Comparison::Descending],
+                    self.config.comp,
+                    step,
+                );
+            }
+            Setting::FitMode => {
+                self.config.fit_mode = cycle(
+                    &[
+                        FitMode::Shrink,
+                        FitMode::Fit,
+                        FitMode::Width,
+                        FitMode::Height,
+                        FitMode::Original,
+                    ],
+                    self.config.fit_mode,
+                    step,
+                );
+            }
+            Setting::SlideshowInterval => {
+                let interval = self.config.slideshow_interval as i64 + step as i64 * 500;
+                self.config.slideshow_interval = interval.max(500) as u64;
+            }
+        }
+        self.apply_config(&prev);
+    }
+
+    fn settings_input(&mut self, key: wita::VirtualKey) {
+        let selected = match self.settings {
+            Some(selected) => selected,
+            None => return,
+        };
+        match key {
+            wita::VirtualKey::Esc | wita::VirtualKey::Enter | wita::VirtualKey::F(4) => {
+                self.settings = None;
+                if let Err(e) = write_state(&self.unlayered_config()) {
+                    error!("write_state error: {}", e);
+                }
+            }
+            wita::VirtualKey::Up => {
+                self.settings = Some((selected + SETTINGS.len() - 1) % SETTINGS.len());
+            }
+            wita::VirtualKey::Down => self.settings = Some((selected + 1) % SETTINGS.len()),
+            wita::VirtualKey::Left => self.change_setting(SETTINGS[selected], -1),
+            wita::VirtualKey::Right => self.change_setting(SETTINGS[selected], 1),
+            _ => {}
+        }
+        self.set_title();
+        self.wnd.redraw();
+    }
+
+    fn inspect(&mut self, position: wita::PhysicalPosition<i32>) {
+        let (width, height) = match self.current_image_size() {
+            Some(size) => size,
+            None => {
+                self.inspected = None;
+                return;
+            }
+        };
+        let (x, y) = self.renderer.to_dip(position);
+        let (x, y) = self.renderer.layout(width, height).to_image(x, y);
+        let path = self.dir.as_ref().and_then(|dir| dir.current());
+        self.inspected = match path {
+            Some(path) if x >= 0.0 && y >= 0.0 && x < width && y < height => {
+                let (x, y) = (x as u32, y as u32);
+                Some((x, y, self.images.pixel(path, x, y)))
+            }
+            _ => None,
+        };
+    }
+
+    fn copy_inspected_pixel(&mut self) {
+        let [r, g, b, a] = match self.inspected {
+            Some((_, _, Some(rgba))) => rgba,
+            _ => return,
+        };
+        let text = format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a);
+        match clipboard::set_text(&self.wnd, &text) {
+            Ok(_) => self.osd(format!("Copied {}", text)),
+            Err(e) => error!("copy_inspected_pixel: {}", e),
+        }
+    }
+
+    fn adjustment_value(&self, adjustment: Adjustment) -> f32 {
+        match adjustment {
+            Adjustment::Brightness => self.adjustments.brightness,
+            Adjustment::Contrast => self.adjustments.contrast,
+            Adjustment::Gamma => self.adjustments.gamma,
+            Adjustment::Exposure => self.adjustments.exposure,
+        }
+    }
+
+    fn adjustments_text(&self, selected: usize) -> String {
+        let mut lines = vec![
+            "adjustments (Up/Down: select, Left/Right: change, BackSpace: reset, Esc: close)"
+                .to_string(),
+        ];
+        lines.extend(ADJUSTMENTS.iter().enumerate().map(|(i, adjustment)| {
+            format!(
+                "{} {:?}: {:+.2}",
+                if i == selected { ">" } else { " " },
+                adjustment,
+                self.adjustment_value(*adjustment)
+            )
+        }));
+        lines.join("\n")
+    }
+
+    fn change_adjustment(&mut self, adjustment: Adjustment, step: i32) {
+        let step = step as f32;
+        let adj = &mut self.adjustments;
+        match adjustment {
+            Adjustment::Brightness => {
+                adj.brightness = (adj.brightness + step * 0.05).clamp(-1.0, 1.0);
+            }
+            Adjustment::Contrast => adj.contrast = (adj.contrast + step * 0.05).clamp(-1.0, 1.0),
+            Adjustment::Gamma => adj.gamma = (adj.gamma + step * 0.1).clamp(0.1, 5.0),
+            Adjustment::Exposure => adj.exposure = (adj.exposure + step * 0.1).clamp(-2.0, 2.0),
+        }
+    }
+
+    fn adjustments_input(&mut self, key: wita::VirtualKey) {
+        let selected = match self.adjusting {
+            Some(selected) => selected,
+            None => return,
+        };
+        match key {
+            wita::VirtualKey::Esc | wita::VirtualKey::Enter | wita::VirtualKey::Char('V') => {
+                self.adjusting = None;
+            }
+            wita::VirtualKey::Up => {
+                self.adjusting = Some((selected + ADJUSTMENTS.len() - 1) % ADJUSTMENTS.len());
+            }
+            wita::VirtualKey::Down => self.adjusting = Some((selected + 1) % ADJUSTMENTS.len()),
+            wita::VirtualKey::Left => self.change_adjustment(ADJUSTMENTS[selected], -1),
+            wita::VirtualKey::Right => self.change_adjustment(ADJUSTMENTS[selected], 1),
+            wita::VirtualKey::BackSpace => {
+                let default = Adjustments::default();
+                match ADJUSTMENTS[selected] {
+                    Adjustment::Brightness => self.adjustments.brightness = default.brightness,
+                    Adjustment::Contrast => self.adjustments.contrast = default.contrast,
+                    Adjustment::Gamma => self.adjustments.gamma = default.gamma,
+                    Adjustment::Exposure => self.adjustments.exposure = default.exposure,
+                }
+            }
+            _ => {}
+        }
+        self.wnd.redraw();
+    }
+
+    fn gesture_input(&mut self, state: wita::KeyState, mouse_state: wita::MouseState) {
+        match state {
+            wita::KeyState::Pressed => {
+                let (x, y) = self.renderer.to_dip(mouse_state.position);
+                self.gesture = Some(Recognizer::new(x, y, self.config.gesture_threshold));
+            }
+            wita::KeyState::Released => {
+                let gesture = match self.gesture.take() {
+                    Some(gesture) if !gesture.is_empty() => gesture.strokes(),
+                    _ => return,
+                };
+                let method = self
+                    .config
+                    .gestures
+                    .iter()
+                    .find(|g| g.strokes.eq_ignore_ascii_case(&gesture))
+                    .map(|g| g.method);
+                match method {
+                    Some(method) => {
+                        debug!("gesture: {}: {:?}", gesture, method);
+                        self.execute(method);
+                    }
+                    None => self.osd(format!("Unknown gesture: {}", gesture)),
+                }
+                self.set_title();
+                self.wnd.redraw();
+            }
+        }
+    }
+
+    fn touch_input(&mut self, event: TouchEvent) {
+        let to_dip = |x: i32, y: i32| self.renderer.to_dip(wita::PhysicalPosition::new(x, y));
+        match event {
+            TouchEvent::Down(id, x, y) => {
+                let (x, y) = to_dip(x, y);
+                self.touch.down(id, x, y);
+            }
+            TouchEvent::Move(id, x, y) => {
+                let (x, y) = to_dip(x, y);
+                for action in self.touch.moved(id, x, y) {
+                    self.touch_action(action);
+                }
+            }
+            TouchEvent::Up(id, x, y) => {
+                let (x, y) = to_dip(x, y);
+                if let Some(action) = self.touch.up(id, x, y, self.config.swipe_threshold) {
+                    self.touch_action(action);
+                }
+            }
+        }
+    }
+
+    fn touch_action(&mut self, action: touch::Action) {
+        if self.grid || self.current_panorama().is_some() {
+            return;
+        }
+        let current = self
+            .dir
+            .as_ref()
+            .and_then(|dir| dir.current())
+            .map(|path| path.to_path_buf());
+        match action {
+            touch::Action::Zoom { x, y, factor } => {
+                if let Some((width, height)) = self.current_image_size() {
+                    self.renderer.zoom_at(width, height, x, y, factor);
+                    self.zoomed = current;
+                    self.load_full_resolution();
+                }
+            }
+            touch::Action::Pan { dx, dy } => {
+                if self.zoomed.is_none() {
+                    return;
+                }
+                if let Some((width, height)) = self.current_image_size() {
+                    self.renderer.pan(width, height, dx, dy);
+                }
+            }
+            touch::Action::Swipe(dx) => {
+                if self.zoomed.is_some() {
+                    return;
+                }
+                let forward = match self.config.reading_direction {
+                    ReadingDirection::LeftToRight => dx < 0.0,
+                    ReadingDirection::RightToLeft => dx > 0.0,
+                };
+                self.execute(if forward { Method::Next } else { Method::Prev });
+            }
+        }
+    }
+
+    fn gamepad_input(&mut self, event: GamepadEvent) {
+        if !self.config.gamepad.enabled {
+            return;
+        }
+        match event {
+            GamepadEvent::Pressed(button) => {
+                let method = self
+                    .config
+                    .gamepad
+                    .bindings
+                    .iter()
+                    .find(|b| b.button == button)
+                    .map(|b| b.method);
+                if let Some(method) = method {
+                    debug!("gamepad: {:?}: {:?}", button, method);
+                    self.execute(method);
+                }
+            }
+            GamepadEvent::Zoom(value) => {
+                if self.grid || self.current_panorama().is_some() {
+                    return;
+                }
+                let (width, height) = match self.current_image_size() {
+                    Some(size) => size,
+                    None => return,
+                };
+                let size = self.wnd.inner_size();
+                let (x, y) = self.renderer.to_dip(wita::PhysicalPosition::new(
+                    size.width as i32 / 2,
+                    size.height as i32 / 2,
+                ));
+                let factor = self.config.gamepad.zoom_speed.max(1.01).powf(value / 30.0);
+                let scale = self.renderer.zoom_at(width, height, x, y, factor);
+                self.zoomed = self
+                    .dir
+                    .as_ref()
+                    .and_then(|dir| dir.current())
+                    .map(|path| path.to_path_buf());
+                self.load_full_resolution();
+                self.osd(format!("Zoom {:.0}%", scale * 100.0));
+            }
+        }
+    }
+
+    fn sequence_match(&mut self, m: sequence::Match) {
+        match m {
+            sequence::Match::Found(method, count) => {
+                debug!("sequence: {:?} {:?}", count, method);
+                self.execute_count(method, count);
+            }
+            sequence::Match::Fallback(keys, count) => {
+                let pressed_keys = std::mem::replace(&mut self.pressed_keys, keys);
+                let method = self.find_method();
+                self.pressed_keys = pressed_keys;
+                if let Some(method) = method {
+                    self.execute_count(method, count);
+                }
+            }
+            sequence::Match::Pending | sequence::Match::Discarded => (),
+        }
+        self.set_title();
+        self.wnd.redraw();
+    }
+
+    fn execute_count(&mut self, method: Method, count: Option<usize>) {
+        let (n, index) = match (count, self.dir.as_ref()) {
+            (Some(n), Some(dir)) => (n, dir.index()),
+            _ => return self.execute(method),
+        };
+        match method {
+            Method::First | Method::Last | Method::GoTo => self.jump_to(n.saturating_sub(1)),
+            Method::Prev => self.jump_to(index.saturating_sub(n)),
+            Method::Next => self.jump_to(index.saturating_add(n)),
+            _ => self.execute(method),
+        }
+    }
+
+    fn jump_to(&mut self, index: usize) {
+        if let Some(dir) = self.dir.as_mut() {
+            if dir.len() == 0 {
+                return;
+            }
+            dir.set_index(index.min(dir.len() - 1));
+        }
+        self.load_current();
+    }
+
+    fn current_image_size(&self) -> Option<(f32, f32)> {
+        let path = self.dir.as_ref()?.current()?;
+        let bmp = self.images.get(path).ok()??;
+        let (width, height) = images::original_size(&bmp);
+        Some((width as f32, height as f32))
+    }
+
+    fn slideshow_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.config.slideshow_interval.max(500))
+    }
+
+    fn advance_slideshow(&mut self) {
+        let next = match self.slideshow {
+            Some(next) => next,
+            None => return,
+        };
+        let now = std::time::Instant::now();
+        if now < next {
+            return;
+        }
+        self.execute(Method::Next);
+        self.slideshow = Some(now + self.slideshow_interval());
+    }
+
+    fn set_presentation(&mut self, enabled: bool) {
+        if self.embedded || self.presentation == enabled {
+            return;
+        }
+        self.presentation = enabled;
+        if enabled != self.fullscreen.is_some() {
+            self.fullscreen = set_fullscreen(&self.wnd, self.fullscreen.take());
+        }
+        unsafe {
+            SetThreadExecutionState(if enabled {
+                ES_CONTINUOUS | ES_DISPLAY_REQUIRED | ES_SYSTEM_REQUIRED
+            } else {
+                ES_CONTINUOUS
+            });
+        }
+        self.cursor.update(&self.wnd, self.cursor_timeout());
+        if !enabled {
+            self.osd("Presentation off");
+        }
+    }
+
+    fn cursor_timeout(&self) -> Option<std::time::Duration> {
+        let timeout = self.config.cursor_hide_timeout;
+        let configured = (timeout > 0).then(|| std::time::Duration::from_millis(timeout));
+        let presentation = self.presentation.then(|| std::time::Duration::from_secs(2));
+        configured.into_iter().chain(presentation).min()
+    }
+
+    fn start_screensaver(&mut self) {
+        self.screensaver = true;
+        let folder = self.config.screensaver_folder.clone().or_else(|| {
+            std::env::var_os("USERPROFILE")
+                .map(|home| std::path::PathBuf::from(home).join("Pictures"))
+        });
+        if let Some(folder) = folder {
+            self.open_entity(folder);
+        }
+        self.slideshow = Some(std::time::Instant::now() + self.slideshow_interval());
+        if self.embedded {
+            return;
+        }
+        self.fullscreen = set_fullscreen(&self.wnd, None);
+        unsafe {
+            SetWindowPos(
+                self.wnd.raw_handle() as HWND,
+                HWND_TOPMOST,
+                0,
+                0,
+                0,
+                0,
+                SWP_NOMOVE | SWP_NOSIZE,
+            );
+            ShowCursor(FALSE);
+        }
+    }
+
+    fn exit_screensaver(&self) -> bool {
+        if !self.screensaver || self.embedded {
+            return false;
+        }
+        self.wnd.close();
+        true
+    }
+
+    fn ken_burns(&mut self) {
+        let amplitude = self.config.ken_burns;
+        let next = match self.slideshow {
+            Some(next) if amplitude > 0.0 && self.zoomed.is_none() => next,
+            _ => return,
+        };
+        let (width, height) = match self.current_image_size() {
+            Some(size) => size,
+            None => return,
+        };
+        let interval = self.slideshow_interval().as_secs_f32();
+        let remaining = next
+            .saturating_duration_since(std::time::Instant::now())
+            .as_secs_f32();
+        let progress = (1.0 - remaining / interval).clamp(0.0, 1.0);
+        let seed = self.dir.as_ref().map_or(0, |dir| dir.index());
+        self.renderer
+            .ken_burns(width, height, progress, amplitude, seed);
+    }
+
+    fn move_to_sibling(&mut self, forward: bool, last: bool) -> bool {
+        let dir = match self.dir.as_ref() {
+            Some(dir) if dir.path().is_dir() => dir,
+            _ => return false,
+        };
+        let mut sibling = match Siblings::new(dir.path(), forward, &self.config).next() {
+            Some(sibling) => sibling,
+            None => return false,
+        };
+        debug!("sibling: {}", sibling.path().to_string_lossy());
+        if last {
+            sibling.set_index(sibling.len() - 1);
+        }
+        if let Some(name) = sibling.path().file_name() {
+            self.osd(name.to_string_lossy());
+        }
+        self.dir = Some(sibling);
+        self.apply_rating_filter();
+        true
+    }
+
+    fn step(&mut self, method: Method) -> bool {
+        let forward = method != Method::Prev;
+        let at_end = match self.dir.as_ref() {
+            Some(dir) if forward => dir.index() + 1 >= dir.len(),
+            Some(dir) => dir.index() == 0,
+            None => return false,
+        };
+        self.forward = forward;
+        if at_end && self.config.continue_siblings && self.move_to_sibling(forward, !forward) {
+            return true;
+        }
+        let dir = self.dir.as_mut().unwrap();
+        if forward {
+            dir.next();
+        } else {
+            dir.prev();
+        }
+        dir.len() > 0
+    }
+
+    fn advance_key_repeat(&mut self) {
+        let (method, keys, next) = match self.key_repeat.take() {
+            Some(repeat) => repeat,
+            None => return,
+        };
+        let now = std::time::Instant::now();
+        if now < next {
+            self.key_repeat = Some((method, keys, next));
+            return;
+        }
+        let state = wita::keyboard_state();
+        if !keys.iter().all(|key| state.contains(key)) {
+            self.load_current();
+            return;
+        }
+        let interval = std::time::Duration::from_millis(self.config.key_repeat.interval.max(1));
+        let steps = ((now - next).as_nanos() / interval.as_nanos()) as u32 + 1;
+        for _ in 0..steps {
+            self.step(method);
+        }
+        self.key_repeat = Some((method, keys, next + interval * steps));
+        self.set_title();
+    }
+
+    fn light_table_dir(&self, init: Option<&Path>) -> Directory {
+        Directory::from_paths("light table", self.light_table.clone(), &self.config, init)
+    }
+
+    fn load_current(&self) {
+        let dir = match self.dir.as_ref() {
+            Some(dir) => dir,
+            None => return,
+        };
+        if let Some(path) = dir.current() {
+            let wnd = self.wnd.clone();
+            let dc = self.renderer.device_context();
+            self.images.pin(&dir.neighbors());
+            let preview = wnd.clone();
+            self.images.load(
+                dc.clone(),
+                path,
+                move |_| preview.redraw(),
+                move |_| wnd.redraw(),
+            );
+            self.images
+                .prefetch(dc, dir.lookahead(self.forward).into_paths());
+        }
+    }
+
+    fn recover_device(&mut self) {
+        warn!("device lost: recreating the render target");
+        if let Err(e) = self.renderer.recover() {
+            error!("recover device: {}", e);
+            return;
+        }
+        self.images.clear_bitmaps();
+        self.animation = None;
+        self.animation_probe = None;
+        self.stereo = None;
+        self.compare = None;
+        self.load_current();
+    }
+
+    fn load_full_resolution(&self) {
+        let path = match self.dir.as_ref().and_then(|dir| dir.current()) {
+            Some(path) => path,
+            None => return,
+        };
+        let bmp = match self.images.get(path) {
+            Ok(Some(bmp)) => bmp,
+            _ => return,
+        };
+        let (width, height) = images::original_size(&bmp);
+        let pixel = unsafe { bmp.GetPixelSize() };
+        let layout = self.renderer.layout(width as f32, height as f32);
+        if layout.scale <= pixel.width as f32 / width.max(1) as f32 {
+            return;
+        }
+        let wnd = self.wnd.clone();
+        self.images
+            .load_full(self.renderer.device_context(), path, move |_| wnd.redraw());
+    }
+
+    fn add_to_light_table(&mut self) {
+        let path = match self.dir.as_ref().and_then(|dir| dir.current()) {
+            Some(path) => path.to_path_buf(),
+            None => return,
+        };
+        if let Some(i) = self.light_table.iter().position(|p| *p == path) {
+            self.light_table.remove(i);
+            debug!("light table: remove: {}", path.to_string_lossy());
+            self.osd(format!(
+                "Removed from light table ({})",
+                self.light_table.len()
+            ));
+        } else {
+            debug!("light table: add: {}", path.to_string_lossy());
+            self.light_table.push(path.clone());
+            self.osd(format!("Added to light table ({})", self.light_table.len()));
+        }
+        if self.light_table_return.is_some() {
+            if self.light_table.is_empty() {
+                self.dir = self.light_table_return.take();
+            } else {
+                let init = self.dir.as_ref().and_then(|dir| {
+                    let paths = dir
+                        .paths()
+                        .iter()
+                        .filter(|p| self.light_table.contains(p))
+                        .collect::<Vec<_>>();
+                    paths
+                        .get(dir.index().min(paths.len().saturating_sub(1)))
+                        .map(|p| p.to_path_buf())
+                });
+                self.dir = Some(self.light_table_dir(init.as_deref()));
+            }
+            self.load_current();
+        }
+    }
+
+    fn rate(&mut self, f: impl FnOnce(&mut Rating)) {
+        let path = match self.dir.as_ref().and_then(|dir| dir.current()) {
+            Some(path) => path.to_path_buf(),
+            None => return,
+        };
+        let mut rating = self.ratings.get(&path);
+        f(&mut rating);
+        if let Err(e) = self.ratings.set(&path, rating) {
+            error!("rating: {}: {}", path.to_string_lossy(), e);
+            return;
+        }
+        debug!("rating: {}: {:?}", path.to_string_lossy(), rating);
+        self.osd(rating.to_string());
+        if self.light_table_return.is_none() && self.duplicates_return.is_none() {
+            self.apply_rating_filter();
+            self.load_current();
+        }
+    }
+
+    fn apply_rating_filter(&mut self) {
+        let dir = match self.dir.as_mut() {
+            Some(dir) => dir,
+            None => return,
+        };
+        let (min_rating, hide_rejected) = (self.min_rating, self.hide_rejected);
+        let ratings = &mut self.ratings;
+        dir.filter(|path| {
+            if min_rating == 0 && !hide_rejected {
+                return true;
+            }
+            let rating = ratings.get(path);
+            rating.stars >= min_rating && !(hide_rejected && rating.flag == Some(Flag::Reject))
+        });
+    }
+
+    fn find_duplicates(&mut self) {
+        if self.duplicates_return.is_some() {
+            self.show_duplicate_group(self.duplicate_group + 1);
+            return;
+        }
+        let paths = match self.dir.as_ref() {
+            Some(dir) => dir.paths().to_vec(),
+            None => return,
+        };
+        let threshold = self.config.duplicate_threshold;
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        debug!("duplicates: {} images", paths.len());
+        self.osd(format!("Searching duplicates in {} images", paths.len()));
+        self.images.perceptual_hashes(paths, move |hashes| {
+            commands.push(Command::Duplicates(duplicates::group(hashes, threshold)));
+            wnd.redraw();
+        });
+    }
+
+    fn review_duplicates(&mut self, groups: Vec<Vec<std::path::PathBuf>>) {
+        if groups.is_empty() {
+            self.osd("No duplicates found");
+            return;
+        }
+        debug!("duplicates: {} groups", groups.len());
+        self.duplicates = groups;
+        self.duplicates_return = self.dir.take();
+        self.show_duplicate_group(0);
+    }
+
+    fn show_duplicate_group(&mut self, index: usize) {
+        if index >= self.duplicates.len() {
+            self.dir = self.duplicates_return.take();
+            self.duplicates.clear();
+            self.osd("Duplicates reviewed");
+        } else {
+            self.duplicate_group = index;
+            self.dir = Some(Directory::from_paths(
+                "duplicates",
+                self.duplicates[index].clone(),
+                &self.config,
+                None::<&Path>,
+            ));
+            self.osd(format!(
+                "Duplicates {}/{}",
+                index + 1,
+                self.duplicates.len()
+            ));
+        }
+        self.load_current();
+    }
+
+    fn delete_current(&mut self) {
+        let path = match self.dir.as_ref().and_then(|dir| dir.current()) {
+            Some(path) => path.to_path_buf(),
+            None => return,
+        };
+        if archive::split(&path).is_some() {
+            return;
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !self.confirm(
+            ConfirmAction::Delete,
+            &format!("{}をごみ箱に移動しますか？", name),
+        ) {
+            return;
+        }
+        if let Err(e) = shell::move_to_recycle_bin(&path) {
+            error!("delete: {}: {}", path.to_string_lossy(), e);
+            return;
+        }
+        debug!("delete: {}", path.to_string_lossy());
+        self.images.invalidate(&path);
+        self.light_table.retain(|p| *p != path);
+        for dir in [
+            &mut self.duplicates_return,
+            &mut self.light_table_return,
+            &mut self.dir,
+        ] {
+            if let Some(dir) = dir.as_mut() {
+                dir.remove(&path);
+            }
+        }
+        self.osd(format!("Deleted {}", name));
+        if self.duplicates_return.is_some() {
+            let group = &mut self.duplicates[self.duplicate_group];
+            group.retain(|p| *p != path);
+            if group.len() < 2 {
+                self.duplicates.remove(self.duplicate_group);
+                self.show_duplicate_group(self.duplicate_group);
+                return;
+            }
+        }
+        self.load_current();
+    }
+
+    fn toggle_light_table(&mut self) {
+        if self.light_table_return.is_some() {
+            self.dir = self.light_table_return.take();
+            self.osd("Folder");
+        } else if !self.light_table.is_empty() {
+            let dir = self.light_table_dir(None::<&Path>);
+            self.light_table_return = self.dir.replace(dir);
+            self.osd(format!("Light table ({})", self.light_table.len()));
+        } else {
+            self.osd("Light table is empty");
+            return;
+        }
+        self.load_current();
+    }
+
+    fn export_light_table(&mut self) {
+        if self.light_table.is_empty() {
+            return;
+        }
+        let dest = match folder_dialog(&self.wnd) {
+            Ok(Some(dest)) => dest,
+            Ok(None) => return,
+            Err(e) => {
+                error!("export_light_table: {}", e);
+                return;
+            }
+        };
+        if !self.confirm(
+            ConfirmAction::Batch,
+            &format!(
+                "{}個のファイルを{}にコピーしますか？",
+                self.light_table.len(),
+                dest.to_string_lossy()
+            ),
+        ) {
+            return;
+        }
+        let targets = self
+            .light_table
+            .iter()
+            .filter_map(|src| Some((src, dest.join(src.file_name()?))))
+            .collect::<Vec<_>>();
+        let exists = targets.iter().filter(|(_, dest)| dest.exists()).count();
+        if exists > 0
+            && !self.confirm(
+                ConfirmAction::Overwrite,
+                &format!("{}個のファイルを上書きしますか？", exists),
+            )
+        {
+            return;
+        }
+        let targets = targets
+            .into_iter()
+            .map(|(src, dest)| (src.clone(), dest))
+            .collect::<Vec<_>>();
+        let offer_eject = self.config.import.offer_eject;
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.osd(format!("Copying {} files", targets.len()));
+        self.runtime.spawn_blocking(move || {
+            let mut count = 0;
+            let mut failed = vec![];
+            let mut devices = vec![];
+            for (src, dest) in targets {
+                let device = device::device_of(&src);
+                let ret = device::resolve(&src).and_then(|resolved| {
+                    std::fs::copy(resolved.as_deref().unwrap_or(&src), &dest)?;
+                    Ok(())
+                });
+                match ret {
+                    Ok(_) => {
+                        count += 1;
+                        if let Some(device) = device {
+                            if !devices.contains(&device) {
+                                devices.push(device);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("export_light_table: {}: {}", src.to_string_lossy(), e);
+                        failed.push(src);
+                    }
+                }
+            }
+            commands.push(Command::Notify(format!("Copied {} files", count)));
+            wnd.redraw();
+            if !failed.is_empty() {
+                let files = failed
+                    .iter()
+                    .filter_map(|path| path.file_name())
+                    .map(|name| name.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                message_dialog(
+                    &wnd,
+                    &format!(
+                        "以下の{}個のファイルをコピーできませんでした。\n{}",
+                        failed.len(),
+                        files
+                    ),
+                );
+            }
+            if !offer_eject {
+                return;
+            }
+            for (id, name) in devices {
+                if !confirm_dialog(&wnd, &format!("{}を取り外しますか?", name)) {
+                    continue;
+                }
+                match device::eject(&id) {
+                    Ok(_) => {
+                        commands.push(Command::Notify(format!("{} can be safely removed", name)))
+                    }
+                    Err(e) => error!("eject: {}: {}", name, e),
+                }
+            }
+            wnd.redraw();
+        });
+    }
+
+    fn stereo_image(&mut self) -> Option<ComPtr<ID2D1Bitmap1>> {
+        if self.stereo_mode == StereoMode::Off {
+            return None;
+        }
+        let path = self.dir.as_ref()?.current()?.to_path_buf();
+        let cached =
+            matches!(&self.stereo, Some((p, mode, _)) if *p == path && *mode == self.stereo_mode);
+        if !cached {
+            let mode = self.stereo_mode;
+            let commands = self.commands.clone();
+            let wnd = self.wnd.clone();
+            self.stereo = Some((path.clone(), mode, None));
+            self.runtime
+                .spawn_blocking(move || match stereo::load_pair(&path) {
+                    Ok(Some((left, right))) => {
+                        let img = stereo::compose(mode, &left, &right);
+                        commands.push(Command::Stereo(path, mode, img));
+                        wnd.redraw();
+                    }
+                    Ok(None) => {}
+                    Err(e) => error!("stereo: {}", e),
+                });
+        }
+        self.stereo.as_ref().and_then(|(_, _, bmp)| bmp.clone())
+    }
+
+    fn compare_image(&mut self) -> Option<ComPtr<ID2D1Bitmap1>> {
+        if !self.comparing {
+            return None;
+        }
+        let path = self.compare_mark.clone()?;
+        if !matches!(&self.compare, Some((p, _)) if *p == path) {
+            let bmp = match self.images.get(&path) {
+                Ok(Some(bmp)) => Some(bmp),
+                _ => {
+                    let commands = self.commands.clone();
+                    let wnd = self.wnd.clone();
+                    let limit = self.renderer.max_bitmap_size();
+                    self.images
+                        .decode(&path, limit, move |path, img| match img {
+                            Ok(img) => {
+                                commands.push(Command::CompareImage(path, img));
+                                wnd.redraw();
+                            }
+                            Err(e) => error!("compare: {}", e),
+                        });
+                    None
+                }
+            };
+            self.compare = Some((path, bmp));
+        }
+        self.compare.as_ref().and_then(|(_, bmp)| bmp.clone())
+    }
+
+    fn diff_image(&self) -> Option<ComPtr<ID2D1Bitmap1>> {
+        if !self.diffing {
+            return None;
+        }
+        let current = self.dir.as_ref()?.current()?;
+        let marked = self.compare_mark.as_ref()?;
+        let wnd = self.wnd.clone();
+        self.images.diff(
+            self.renderer.device_context(),
+            current,
+            marked,
+            self.config.diff_amplification,
+            move || wnd.redraw(),
+        )
+    }
+
+    fn background(&mut self) -> ClearColor {
+        if self.config.transparent_background && !self.embedded {
+            return ClearColor(0.0, 0.0, 0.0);
+        }
+        let mode = self.config.auto_background;
+        let path = self.dir.as_ref().and_then(|dir| dir.current());
+        let path = match path {
+            Some(path) if mode != AutoBackground::Off => path.to_path_buf(),
+            _ => return self.config.background.clone(),
+        };
+        if !self.edge_colors.contains_key(&path) {
+            if let Some(color) = self.images.edge_color(&path) {
+                self.edge_colors.insert(path.clone(), color);
+            }
+        }
+        let (r, g, b) = match self.edge_colors.get(&path) {
+            Some(color) => *color,
+            None => return self.config.background.clone(),
+        };
+        match mode {
+            AutoBackground::Average => ClearColor(r, g, b),
+            _ if 0.2126 * r + 0.7152 * g + 0.0722 * b >= 0.5 => {
+                self.config.light_background.clone()
+            }
+            _ => self.config.background.clone(),
+        }
+    }
+
+    fn status_text(&mut self, img: Option<&ComPtr<ID2D1Bitmap1>>) -> String {
+        let dir = match self.dir.as_ref() {
+            Some(dir) => dir,
+            None => return String::new(),
+        };
+        let mut items = vec![];
+        if let Some(bmp) = img {
+            let (width, height) = images::original_size(bmp);
+            let layout = self.renderer.layout(width as f32, height as f32);
+            items.push(format!("{} x {}", width, height));
+            items.push(format!("{:.0}%", layout.scale * 100.0));
+        }
+        if let Some(path) = dir.current() {
+            let len = match dir.info(path) {
+                Some(info) => Some(info.len),
+                None => match self.file_size.as_ref() {
+                    Some((p, len)) if p == path => *len,
+                    _ => {
+                        let len = path.metadata().ok().map(|meta| meta.len());
+                        self.file_size = Some((path.to_path_buf(), len));
+                        len
+                    }
+                },
+            };
+            if let Some(len) = len {
+                items.push(stats::format_size(len));
+            }
+            if let Some(ext) = path.extension() {
+                items.push(ext.to_string_lossy().to_uppercase());
+            }
+            let rating = self.ratings.get(path);
+            if !rating.is_empty() {
+                items.push(rating.to_string());
+            }
+        }
+        items.push(format!("{}/{}", dir.index() + 1, dir.len()));
+        items.join("    ")
+    }
+
+    fn animation_frame(&mut self) -> Option<ComPtr<ID2D1Bitmap1>> {
+        let path = self.dir.as_ref().and_then(|dir| dir.current());
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                self.animation = None;
+                return None;
+            }
+        };
+        let opened = matches!(&self.animation, Some((animation, _)) if animation.path() == path);
+        if !opened && self.animation_probe.as_ref() != Some(&path) {
+            self.animation = None;
+            self.animation_probe = Some(path.clone());
+            let commands = self.commands.clone();
+            let wnd = self.wnd.clone();
+            self.runtime.spawn_blocking(move || {
+                if let Some(info) = animation::probe(&path) {
+                    commands.push(Command::AnimationProbed(path, info));
+                    wnd.redraw();
+                }
+            });
+        }
+        let (animation, bmp) = self.animation.as_mut()?;
+        if animation.advance() {
+            if let Some(frame) = animation.frame() {
+                *bmp = self
+                    .renderer
+                    .create_bitmap(frame.width(), frame.height(), frame.as_raw())
+                    .map_err(|e| error!("animation: {}", e))
+                    .ok();
+            }
+        }
+        bmp.clone()
+    }
+
+    fn animation_probed(&mut self, path: std::path::PathBuf, info: animation::Info) {
+        if self.dir.as_ref().and_then(|dir| dir.current()) != Some(path.as_path())
+            || self.animation_probe.as_ref() != Some(&path)
+        {
+            return;
+        }
+        let headroom = self
+            .cache_budget()
+            .1
+            .saturating_sub(self.images.image_cache_size());
+        let animation = Animation::new(
+            &path,
+            &info,
+            self.config.animation_memory,
+            self.config.animation_ring_frames,
+            headroom,
+        );
+        self.animation = Some((animation, None));
+    }
+
+    fn enter_panorama(&mut self, path: &Path) {
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        let limit = self.renderer.max_bitmap_size();
+        self.images.decode(path, limit, move |path, img| match img {
+            Ok(img) => {
+                commands.push(Command::Panorama(path, img));
+                wnd.redraw();
+            }
+            Err(e) => error!("panorama: {}", e),
+        });
+    }
+
+    fn panorama_decoded(&mut self, path: std::path::PathBuf, img: image::RgbaImage) {
+        if self.dir.as_ref().and_then(|dir| dir.current()) != Some(path.as_path()) {
+            return;
+        }
+        self.crop = None;
+        self.panorama = Some(Panorama::new(&path, img, self.config.panorama_fov));
+        self.osd(format!("Panorama: FOV {}°", self.config.panorama_fov));
+    }
+
+    fn detect_panorama(&mut self, path: &Path) {
+        let path = path.to_path_buf();
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.runtime.spawn_blocking(move || {
+            if panorama::is_equirectangular(&path) {
+                commands.push(Command::PanoramaDetected(path));
+                wnd.redraw();
+            }
+        });
+    }
+
+    fn current_panorama(&self) -> Option<&Panorama> {
+        let current = self.dir.as_ref().and_then(|dir| dir.current());
+        self.panorama
+            .as_ref()
+            .filter(|panorama| Some(panorama.path()) == current)
+    }
+
+    fn current_crop(&self) -> Option<&Crop> {
+        let current = self.dir.as_ref().and_then(|dir| dir.current());
+        self.crop
+            .as_ref()
+            .filter(|crop| Some(crop.path()) == current)
+    }
+
+    fn crop_position(&self, crop: &Crop, position: wita::PhysicalPosition<i32>) -> (f32, f32, f32) {
+        const HANDLE_RADIUS: f32 = 6.0;
+        let (width, height) = crop.image_size();
+        let layout = self.renderer.layout(width as f32, height as f32);
+        let (x, y) = self.renderer.to_dip(position);
+        let (x, y) = layout.to_image(x, y);
+        (x, y, HANDLE_RADIUS / layout.scale)
+    }
+
+    fn crop_rect(&self) -> Option<(u32, u32, u32, u32)> {
+        self.current_crop().and_then(|crop| crop.pixel_rect())
+    }
+
+    fn copy_image(&mut self) {
+        let path = match self.dir.as_ref().and_then(|dir| dir.current()) {
+            Some(path) => path.to_path_buf(),
+            None => return,
+        };
+        let rect = self.crop_rect();
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.images.open(&path, move |_, img| match img {
+            Ok(img) => {
+                commands.push(Command::CopyImage(crop_image(img, rect), rect.is_some()));
+                wnd.redraw();
+            }
+            Err(e) => error!("copy_image: {}", e),
+        });
+    }
+
+    fn image_copied(&mut self, img: image::RgbaImage, selection: bool) {
+        match clipboard::set_image(&self.wnd, &img) {
+            Ok(_) if selection => self.osd("Copied selection"),
+            Ok(_) => self.osd("Copied image"),
+            Err(e) => error!("copy_image: {}", e),
+        }
+    }
+
+    fn save_as(&mut self) {
+        let src = match self.dir.as_ref().and_then(|dir| dir.current()) {
+            Some(path) => path.to_path_buf(),
+            None => return,
+        };
+        let file_name = src
+            .file_stem()
+            .map_or(String::new(), |name| name.to_string_lossy().into());
+        let (path, format) = match file_save_dialog(&self.wnd, &file_name) {
+            Ok(Some(ret)) => ret,
+            Ok(None) => return,
+            Err(e) => {
+                error!("save_dialog: {}", e);
+                return;
+            }
+        };
+        if path.exists()
+            && !self.confirm(
+                ConfirmAction::Overwrite,
+                &format!("{}を上書きしますか？", path.to_string_lossy()),
+            )
+        {
+            return;
+        }
+        debug!(
+            "save_as: {} -> {} ({:?})",
+            src.to_string_lossy(),
+            path.to_string_lossy(),
+            format
+        );
+        let rect = self.crop_rect();
+        let encode = self.config.encode.clone();
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.images.open(&src, move |_, img| {
+            let ret = img.and_then(|img| {
+                convert::save_image(&crop_image(img, rect), &path, format, &encode)
+            });
+            match ret {
+                Ok(_) => commands.push(Command::Notify(format!(
+                    "Saved {}",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                ))),
+                Err(e) => error!("save_as: {}", e),
+            }
+            wnd.redraw();
+        });
+    }
+
+    fn collect_folder_stats(&self, export: bool) {
+        let dir = match self.dir.as_ref() {
+            Some(dir) => dir,
+            None => return,
+        };
+        let stats = self.folder_stats.clone();
+        let dir_path = dir.path().to_path_buf();
+        let paths = dir.paths().to_vec();
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.runtime.spawn_blocking(move || {
+            let stats = stats.unwrap_or_else(|| FolderStats::new(dir_path, &paths));
+            let export = if export {
+                match stats.export_path() {
+                    Some(path) => {
+                        let exists = path.exists();
+                        Some((path, exists))
+                    }
+                    None => {
+                        error!(
+                            "export_folder_stats: no folder for {}",
+                            stats.dir.to_string_lossy()
+                        );
+                        return;
+                    }
+                }
+            } else {
+                None
+            };
+            commands.push(Command::FolderStats(stats, export));
+            wnd.redraw();
+        });
+    }
+
+    fn folder_stats_collected(
+        &mut self,
+        stats: FolderStats,
+        export: Option<(std::path::PathBuf, bool)>,
+    ) {
+        if self.dir.as_ref().map(|dir| dir.path()) != Some(stats.dir.as_path()) {
+            return;
+        }
+        let (path, exists) = match export {
+            Some(export) => export,
+            None => {
+                self.folder_stats = Some(stats);
+                return;
+            }
+        };
+        if exists
+            && !self.confirm(
+                ConfirmAction::Overwrite,
+                &format!("{}を上書きしますか？", path.to_string_lossy()),
+            )
+        {
+            return;
+        }
+        let text = stats.to_string();
+        let commands = self.commands.clone();
+        let wnd = self.wnd.clone();
+        self.runtime.spawn_blocking(move || {
+            match std::fs::write(&path, text) {
+                Ok(_) => commands.push(Command::Notify(format!(
+                    "Exported {}",
+                    path.to_string_lossy()
+                ))),
+                Err(e) => error!("export_folder_stats: {}", e),
+            }
+            wnd.redraw();
+        });
+    }
+}
+
+impl Viewer {
+    fn key_input(
+        &mut self,
+        _: &wita::Window,
+        key_code: wita::KeyCode,
+        state: wita::KeyState,
+        prev_pressed: bool,
+    ) {
+        if state == wita::KeyState::Pressed && self.exit_screensaver() {
+            return;
+        }
+        if self.palette.is_some() {
+            if state == wita::KeyState::Pressed {
+                self.palette_input(key_code.vkey);
+            }
+            self.pressed_keys.clear();
+            return;
+        }
+        if self.note_editing.is_some() {
+            if state == wita::KeyState::Pressed {
+                self.note_input(key_code.vkey);
+            }
+            self.pressed_keys.clear();
+            return;
+        }
+        if self.current_annotation().is_some() {
+            if state == wita::KeyState::Pressed {
+                self.annotation_input(key_code.vkey);
+            }
+            self.pressed_keys.clear();
+            return;
+        }
+        if self.settings.is_some() {
+            if state == wita::KeyState::Pressed {
+                self.settings_input(key_code.vkey);
+            }
+            self.pressed_keys.clear();
+            return;
+        }
+        if self.adjusting.is_some() {
+            if state == wita::KeyState::Pressed {
+                self.adjustments_input(key_code.vkey);
+            }
+            self.pressed_keys.clear();
+            return;
+        }
+        if self.show_info
+            && self.info_lines().len() > INFO_LINES
+            && matches!(
+                key_code.vkey,
+                wita::VirtualKey::Up
+                    | wita::VirtualKey::Down
+                    | wita::VirtualKey::PageUp
+                    | wita::VirtualKey::PageDown
+            )
+        {
+            if state == wita::KeyState::Pressed {
+                self.info_input(key_code.vkey);
+            }
+            return;
+        }
+        match state {
+            wita::KeyState::Pressed => {
+                self.config_warnings.clear();
+                if prev_pressed && self.pressed_keys.is_empty() {
+                    return;
+                }
+                self.pressed_keys = wita::keyboard_state();
+                self.pressed_keys.retain(|key| {
+                    if let wita::VirtualKey::Other(i) = key {
+                        *i < 233
+                    } else {
+                        true
+                    }
+                });
+                if !prev_pressed {
+                    let m = self.sequence.push(
+                        key_code.vkey,
+                        &self.pressed_keys,
+                        &self.config.key_bindings,
+                        std::time::Duration::from_millis(self.config.sequence_timeout),
+                    );
+                    if !matches!(m, sequence::Match::Fallback(_, None)) {
+                        self.pressed_keys.clear();
+                        self.sequence_match(m);
+                        return;
+                    }
+                }
+                let method = self.find_method();
+                self.key_pressed(method, prev_pressed);
+            }
+            wita::KeyState::Released => {
+                let method = self.find_method();
+                self.key_released(method);
+            }
+        }
+    }
+
+    fn char_input(&mut self, wnd: &wita::Window, c: char) {
+        if let Some(query) = self.palette.as_mut().or(self.note_editing.as_mut()) {
+            if !c.is_control() {
+                query.push(c);
+                wnd.redraw();
+            }
+            return;
+        }
+        if let Some(annotation) = self.annotation.as_mut().filter(|a| a.is_typing()) {
+            if !c.is_control() {
+                annotation.input(c);
+                wnd.redraw();
+            }
+        }
+    }
+
+    fn mouse_input(
+        &mut self,
+        wnd: &wita::Window,
+        button: wita::MouseButton,
+        state: wita::KeyState,
+        mouse_state: wita::MouseState,
+    ) {
+        if self.exit_screensaver() {
+            return;
+        }
+        if button == wita::MouseButton::Right {
+            self.gesture_input(state, mouse_state);
+            return;
+        }
+        if button != wita::MouseButton::Left {
+            return;
+        }
+        if self.grid {
+            if state == wita::KeyState::Pressed {
+                let (x, y) = self.renderer.to_dip(mouse_state.position);
+                let columns = self.config.grid_columns.max(1);
+                let page_size = columns * self.renderer.grid_rows(columns);
+                let cell = self.renderer.grid_cell(columns, x, y);
+                if let Some(dir) = self.dir.as_mut() {
+                    let index = dir.index() / page_size * page_size + cell;
+                    if cell < page_size && index < dir.len() {
+                        dir.set_index(index);
+                        self.set_title();
+                        wnd.redraw();
+                    }
+                }
+            }
+            return;
+        }
+        if self.current_panorama().is_some() {
+            let (x, y) = self.renderer.to_dip(mouse_state.position);
+            let panorama = self.panorama.as_mut().unwrap();
+            match state {
+                wita::KeyState::Pressed => panorama.begin_drag(x, y),
+                wita::KeyState::Released => panorama.end_drag(),
+            }
+            return;
+        }
+        if self.inspector {
+            if state == wita::KeyState::Pressed {
+                self.copy_inspected_pixel();
+                wnd.redraw();
+            }
+            return;
+        }
+        if let Some(annotation) = self.current_annotation() {
+            let (x, y) = self.annotation_position(annotation, mouse_state.position);
+            let annotation = self.annotation.as_mut().unwrap();
+            match state {
+                wita::KeyState::Pressed => annotation.begin(x, y),
+                wita::KeyState::Released if annotation.is_drawing() => annotation.end(),
+                _ => {}
+            }
+            wnd.redraw();
+            return;
+        }
+        let (x, y, radius) = match self.current_crop() {
+            Some(crop) => self.crop_position(crop, mouse_state.position),
+            None => {
+                if self.config.borderless && !self.embedded && state == wita::KeyState::Pressed {
+                    let ht = hit_test(wnd, mouse_state.position);
+                    unsafe {
+                        ReleaseCapture();
+                        SendMessageW(wnd.raw_handle() as _, WM_NCLBUTTONDOWN, ht as _, 0);
+                    }
+                }
+                return;
+            }
+        };
+        let crop = self.crop.as_mut().unwrap();
+        match state {
+            wita::KeyState::Pressed => crop.begin(x, y, radius),
+            wita::KeyState::Released => crop.end(),
+        }
+        wnd.redraw();
+    }
+
+    fn cursor_moved(&mut self, wnd: &wita::Window, mouse_state: wita::MouseState) {
+        if self.screensaver && !self.embedded {
+            let position = mouse_state.position;
+            let start = *self.screensaver_cursor.get_or_insert(position);
+            if (position.x - start.x).abs() + (position.y - start.y).abs() > 8 {
+                self.exit_screensaver();
+            }
+            return;
+        }
+        if self.cursor.moved(mouse_state.position) {
+            self.schedule_redraw();
+        }
+        if self.inspector {
+            self.inspect(mouse_state.position);
+            wnd.redraw();
+        }
+        if self.loupe.is_some() {
+            self.loupe = Some(self.renderer.to_dip(mouse_state.position));
+            wnd.redraw();
+        }
+        if let Some(gesture) = self.gesture.as_mut() {
+            let (x, y) = self.renderer.to_dip(mouse_state.position);
+            if gesture.moved(x, y) {
+                wnd.redraw();
+            }
+            return;
+        }
+        if let Some(true) = self.current_panorama().map(|p| p.is_dragging()) {
+            let (x, y) = self.renderer.to_dip(mouse_state.position);
+            let (width, _) = self.renderer.viewport_size();
+            self.panorama.as_mut().unwrap().drag(x, y, width);
+            wnd.redraw();
+            return;
+        }
+        if let Some(annotation) = self.current_annotation().filter(|a| a.is_drawing()) {
+            let (x, y) = self.annotation_position(annotation, mouse_state.position);
+            self.annotation.as_mut().unwrap().update(x, y);
+            wnd.redraw();
+            return;
+        }
+        let (x, y, _) = match self.current_crop() {
+            Some(crop) if crop.is_dragging() => self.crop_position(crop, mouse_state.position),
+            Some(_) => return,
+            None => {
+                if self.config.borderless && !self.embedded {
+                    let cursor = match hit_test(wnd, mouse_state.position) {
+                        HTLEFT | HTRIGHT => IDC_SIZEWE,
+                        HTTOP | HTBOTTOM => IDC_SIZENS,
+                        HTTOPLEFT | HTBOTTOMRIGHT => IDC_SIZENWSE,
+                        HTTOPRIGHT | HTBOTTOMLEFT => IDC_SIZENESW,
+                        _ => IDC_ARROW,
+                    };
+                    unsafe {
+                        SetCursor(LoadCursorW(std::ptr::null_mut(), cursor));
+                    }
+                }
+                return;
+            }
+        };
+        self.crop.as_mut().unwrap().update(x, y);
+        wnd.redraw();
+    }
+
+    fn mouse_wheel(
+        &mut self,
+        wnd: &wita::Window,
+        axis: wita::MouseWheelAxis,
+        distance: i32,
+        mouse_state: wita::MouseState,
+    ) {
+        if self.exit_screensaver() {
+            return;
+        }
+        if self.current_panorama().is_some() {
+            if axis != wita::MouseWheelAxis::Vertical {
+                return;
+            }
+            let panorama = self.panorama.as_mut().unwrap();
+            panorama.zoom(distance as f32 / WHEEL_DELTA as f32 * 5.0);
+            let text = format!("FOV {}°", panorama.fov());
+            self.osd(text);
+            wnd.redraw();
+            return;
+        }
+        if self.grid
+            || self.palette.is_some()
+            || self.note_editing.is_some()
+            || self.settings.is_some()
+            || self.adjusting.is_some()
+        {
+            return;
+        }
+        let keys = wita::keyboard_state();
+        let action = if keys.contains(&wita::VirtualKey::Ctrl) {
+            self.config.wheel.ctrl
+        } else if keys.contains(&wita::VirtualKey::Shift) {
+            self.config.wheel.shift
+        } else {
+            self.config.wheel.action
+        };
+        match action {
+            WheelAction::Navigate => {
+                if axis != wita::MouseWheelAxis::Vertical {
+                    return;
+                }
+                self.wheel_delta += distance;
+                while self.wheel_delta.abs() >= WHEEL_DELTA as i32 {
+                    let method = if self.wheel_delta > 0 {
+                        self.wheel_delta -= WHEEL_DELTA as i32;
+                        Method::Prev
+                    } else {
+                        self.wheel_delta += WHEEL_DELTA as i32;
+                        Method::Next
+                    };
+                    self.execute(method);
+                }
+                self.set_title();
+            }
+            WheelAction::Zoom => {
+                let (width, height) = match self.current_image_size() {
+                    Some(size) => size,
+                    None => return,
+                };
+                let (x, y) = self.renderer.to_dip(mouse_state.position);
+                let factor = self
+                    .config
+                    .wheel
+                    .zoom_step
+                    .max(1.01)
+                    .powf(distance as f32 / WHEEL_DELTA as f32);
+                let scale = self.renderer.zoom_at(width, height, x, y, factor);
+                self.zoomed = self
+                    .dir
+                    .as_ref()
+                    .and_then(|dir| dir.current())
+                    .map(|path| path.to_path_buf());
+                self.load_full_resolution();
+                self.osd(format!("Zoom {:.0}%", scale * 100.0));
+                self.set_title();
+            }
+            WheelAction::Pan => {
+                let (width, height) = match self.current_image_size() {
+                    Some(size) => size,
+                    None => return,
+                };
+                let d = distance as f32 / WHEEL_DELTA as f32 * 48.0;
+                let (dx, dy) = match axis {
+                    wita::MouseWheelAxis::Vertical => (0.0, d),
+                    wita::MouseWheelAxis::Horizontal => (-d, 0.0),
+                };
+                self.renderer.pan(width, height, dx, dy);
+                self.zoomed = self
+                    .dir
+                    .as_ref()
+                    .and_then(|dir| dir.current())
+                    .map(|path| path.to_path_buf());
+            }
+            WheelAction::None => return,
+        }
+        wnd.redraw();
     }
 
     fn drop_files(&mut self, wnd: &wita::Window, paths: &[&Path], _: wita::PhysicalPosition<f32>) {
-        self.open_entity(paths[0]);
-        self.set_title();
+        if paths.len() > 1 {
+            self.open_dropped(paths);
+        } else {
+            self.open_entity(paths[0]);
+        }
         wnd.redraw();
     }
 
-    fn resizing(&mut self, _: &wita::Window, size: wita::PhysicalSize<u32>) {
+    fn resizing(&mut self, wnd: &wita::Window, size: wita::PhysicalSize<u32>) {
+        self.renderer.resize(size);
+        self.draw(wnd);
+    }
+
+    fn resized(&mut self, _: &wita::Window, size: wita::PhysicalSize<u32>) {
         self.renderer.resize(size);
     }
 
@@ -236,6 +3853,29 @@ impl wita::EventHandler for Application {
     }
 
     fn draw(&mut self, _: &wita::Window) {
+        self.process_commands();
+        if self.renderer.device_lost() {
+            self.recover_device();
+        }
+        self.cursor.update(&self.wnd, self.cursor_timeout());
+        self.advance_slideshow();
+        self.advance_key_repeat();
+        if let Some(m) = self.sequence.expire() {
+            self.sequence_match(m);
+        }
+        self.sync_viewer();
+        if self.zoomed.is_some()
+            && self.zoomed.as_deref() != self.dir.as_ref().and_then(|dir| dir.current())
+        {
+            self.zoomed = None;
+            self.renderer.reset_zoom();
+        }
+        self.ken_burns();
+        if self.grid {
+            self.draw_grid();
+            self.schedule_redraw();
+            return;
+        }
         let img = self
             .dir
             .as_ref()
@@ -248,29 +3888,376 @@ impl wita::EventHandler for Application {
                 }
                 img.unwrap()
             });
-        let text = if self.print_memory {
-            Some(format!(
-                "bmp: {}/{}(MB)\nimage: {}/{}(MB)",
-                self.images.bmp_cache_size() as f32 / 1024.0 / 1024.0,
-                self.config.bmp_cache_size as f32 / 1024.0 / 1024.0,
-                self.images.image_cache_size() as f32 / 1024.0 / 1024.0,
-                self.config.image_cache_size as f32 / 1024.0 / 1024.0
-            ))
+        if let (Some(path), Some(bmp)) = (self.dir.as_ref().and_then(|d| d.current()), img.as_ref())
+        {
+            if self.config.fit_window.enabled
+                && !self.embedded
+                && self.fitted.as_deref() != Some(path)
+            {
+                let (width, height) = images::original_size(bmp);
+                if let Some((width, height)) =
+                    fit_window(&self.wnd, width, height, &self.config.fit_window)
+                {
+                    self.renderer.resize(wita::PhysicalSize::new(width, height));
+                }
+                self.fitted = Some(path.to_path_buf());
+            }
+            if self.config.auto_panorama && self.panorama_checked.as_deref() != Some(path) {
+                let path = path.to_path_buf();
+                self.detect_panorama(&path);
+                self.panorama_checked = Some(path);
+            }
+        }
+        let status = if self.config.status_bar {
+            Some(self.status_text(img.as_ref()))
         } else {
             None
         };
-        self.renderer.render(
-            &self.config.background,
-            img,
-            self.config.interpolation,
-            text,
-        );
+        self.renderer.set_status_bar(status);
+        let mipmaps = self
+            .dir
+            .as_ref()
+            .and_then(|d| d.current())
+            .map(|path| self.images.mipmaps(path))
+            .unwrap_or_default();
+        self.renderer.set_mipmaps(img.clone(), mipmaps);
+        let compare = self.compare_image();
+        self.renderer.set_compare(compare);
+        let loupe = self.loupe.map(|(x, y)| (x, y, self.config.loupe.clone()));
+        self.renderer.set_loupe(loupe);
+        self.renderer.set_guides(self.config.guides.clone());
+        let annotations = self
+            .current_annotation()
+            .map(|annotation| (annotation.shapes(), annotation.stroke_width()));
+        self.renderer.set_annotations(annotations);
+        let img = match self.animation_frame() {
+            Some(bmp) => Some(bmp),
+            None => img,
+        };
+        let img = match self.stereo_image() {
+            Some(bmp) => Some(bmp),
+            None => img,
+        };
+        let img = match self.diff_image() {
+            Some(bmp) => Some(bmp),
+            None => img,
+        };
+        let current = self.dir.as_ref().and_then(|d| d.current());
+        let panorama = self
+            .panorama
+            .as_ref()
+            .filter(|panorama| Some(panorama.path()) == current);
+        let img = match panorama {
+            Some(panorama) => self
+                .renderer
+                .project_panorama(panorama)
+                .map_err(|e| error!("panorama: {}", e))
+                .ok(),
+            None => img,
+        };
+        if self.show_note {
+            self.current_note();
+        }
+        if self.show_info || self.show_properties {
+            self.current_metadata();
+        }
+        if self.show_properties {
+            self.current_properties();
+        }
+        let text = self.overlay_text();
+        let preset = self
+            .dir
+            .as_ref()
+            .and_then(|d| d.current())
+            .and_then(|path| self.presets.get(path))
+            .copied()
+            .unwrap_or(RenderPreset::Photo);
+        let doc = &self.config.document;
+        let (interpolation, preset_effects) = match preset {
+            RenderPreset::Photo => (self.config.interpolation, vec![]),
+            RenderPreset::Document => (doc.interpolation, vec![Effect::Contrast(doc.contrast)]),
+            RenderPreset::Binarized => (
+                doc.interpolation,
+                vec![
+                    Effect::Contrast(doc.contrast),
+                    Effect::Binarize(doc.threshold),
+                ],
+            ),
+        };
+        let mut effects = self.adjustments.effects();
+        effects.extend(preset_effects);
+        if self.channel != Channel::All {
+            effects.push(Effect::Channel(self.channel));
+        }
+        let crop = self.current_crop().and_then(|crop| crop.region());
+        self.set_title();
+        let background = self.background();
+        self.renderer
+            .render(&background, img, interpolation, &effects, crop, text);
+        self.schedule_redraw();
+    }
+
+    fn apply_startup(&mut self, open_last: bool) {
+        let startup = self.config.startup.clone();
+        if open_last && startup.last_session {
+            if let Some(path) = self.config.last_path.clone() {
+                if path.exists() || archive::split(&path).is_some() {
+                    debug!("startup: last session: {}", path.to_string_lossy());
+                    self.open_entity(path);
+                    self.set_title();
+                }
+            }
+        }
+        if self.embedded || self.screensaver {
+            return;
+        }
+        if startup.center_on_cursor {
+            center_on_cursor_monitor(&self.wnd);
+        }
+        if startup.maximized {
+            unsafe {
+                ShowWindow(self.wnd.raw_handle() as HWND, SW_MAXIMIZE);
+            }
+        }
+        if startup.fullscreen {
+            self.fullscreen = set_fullscreen(&self.wnd, None);
+        }
+    }
+
+    fn closed(&mut self, wnd: &wita::Window) {
+        if self.screensaver || !self.primary {
+            return;
+        }
+        if !self.embedded {
+            if let Some(restore) = self.fullscreen.take() {
+                set_fullscreen(wnd, Some(restore));
+            }
+            self.config.window = Rect::from_window(wnd);
+        }
+        if let Some(path) = self.dir.as_ref().and_then(|dir| dir.current()) {
+            self.config.last_path = Some(path.to_path_buf());
+        }
+        if let Err(e) = write_state(&self.unlayered_config()) {
+            error!("write_state error: {}", e);
+        }
+    }
+}
+
+pub struct Application {
+    runtime: Arc<tokio::runtime::Runtime>,
+    archive_cache: Arc<ArchiveCache>,
+    queues: CommandQueues,
+    viewers: Vec<Viewer>,
+}
+
+impl Application {
+    pub fn new() -> anyhow::Result<Self> {
+        let dir = config_dir();
+        std::fs::create_dir_all(&dir)?;
+        simplelog::CombinedLogger::init(vec![
+            simplelog::TermLogger::new(
+                simplelog::LevelFilter::Debug,
+                simplelog::Config::default(),
+                simplelog::TerminalMode::Mixed,
+            ),
+            simplelog::WriteLogger::new(
+                simplelog::LevelFilter::Info,
+                simplelog::Config::default(),
+                File::create(dir.join("niv.log"))?,
+            ),
+        ])
+        .unwrap();
+        unsafe {
+            CoInitializeEx(
+                std::ptr::null_mut(),
+                COINIT_APARTMENTTHREADED | COINIT_DISABLE_OLE1DDE,
+            );
+        }
+        let args = Args::parse();
+        migrate_config();
+        let config = load_config().unwrap_or_default();
+        let config_warnings = config.validate();
+        for w in &config_warnings {
+            warn!("config: {}", w);
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(config.worker_threads.max(1))
+            .enable_time()
+            .build()?;
+        let runtime = Arc::new(runtime);
+        let archive_cache = Arc::new(ArchiveCache::new(
+            std::env::temp_dir().join("niv-archive-cache"),
+            config.archive_cache_size as u64,
+        ));
+        let open_last = args.path.is_none() && args.replay_input.is_none() && !args.screensaver;
+        let mut viewer = Viewer::new(
+            args,
+            config,
+            config_warnings,
+            runtime.clone(),
+            archive_cache.clone(),
+            true,
+        )?;
+        viewer.apply_startup(open_last);
+        let queues = CommandQueues::default();
+        queues.insert(viewer.commands.clone());
+        watch_config(config_path(), queues.clone());
+        gamepad::poll_gamepads(queues.clone());
+        memory::watch_memory_pressure(queues.clone());
+        Ok(Self {
+            runtime,
+            archive_cache,
+            queues,
+            viewers: vec![viewer],
+        })
+    }
+
+    fn viewer(&mut self, wnd: &wita::Window) -> Option<&mut Viewer> {
+        self.viewers
+            .iter_mut()
+            .find(|viewer| viewer.wnd.raw_handle() == wnd.raw_handle())
+    }
+
+    fn open_new_windows(&mut self) {
+        let mut requests = vec![];
+        for viewer in self.viewers.iter_mut() {
+            let new_windows = viewer.new_windows.drain(..).collect::<Vec<_>>();
+            for path in new_windows {
+                let mut config = viewer.unlayered_config();
+                if !viewer.embedded {
+                    config.window = Rect::from_window(&viewer.wnd);
+                    config.window.x += 32;
+                    config.window.y += 32;
+                    config.window.maximized = false;
+                }
+                requests.push((path, config, viewer.profile.clone()));
+            }
+        }
+        for (path, config, profile) in requests {
+            let args = Args {
+                path,
+                profile,
+                ..Default::default()
+            };
+            match Viewer::new(
+                args,
+                config,
+                vec![],
+                self.runtime.clone(),
+                self.archive_cache.clone(),
+                false,
+            ) {
+                Ok(viewer) => {
+                    self.queues.insert(viewer.commands.clone());
+                    self.viewers.push(viewer);
+                    self.share_caches();
+                }
+                Err(e) => error!("new window: {}", e),
+            }
+        }
+    }
+
+    fn share_caches(&mut self) {
+        let share = 1.0 / self.viewers.len().max(1) as f64;
+        for viewer in self.viewers.iter_mut() {
+            viewer.set_cache_share(share);
+        }
+    }
+}
+
+impl wita::EventHandler for Application {
+    fn key_input(
+        &mut self,
+        wnd: &wita::Window,
+        key_code: wita::KeyCode,
+        state: wita::KeyState,
+        prev_pressed: bool,
+    ) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.key_input(wnd, key_code, state, prev_pressed);
+        }
+        self.open_new_windows();
+    }
+
+    fn char_input(&mut self, wnd: &wita::Window, c: char) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.char_input(wnd, c);
+        }
+    }
+
+    fn mouse_input(
+        &mut self,
+        wnd: &wita::Window,
+        button: wita::MouseButton,
+        state: wita::KeyState,
+        mouse_state: wita::MouseState,
+    ) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.mouse_input(wnd, button, state, mouse_state);
+        }
+    }
+
+    fn cursor_moved(&mut self, wnd: &wita::Window, mouse_state: wita::MouseState) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.cursor_moved(wnd, mouse_state);
+        }
+    }
+
+    fn mouse_wheel(
+        &mut self,
+        wnd: &wita::Window,
+        axis: wita::MouseWheelAxis,
+        distance: i32,
+        mouse_state: wita::MouseState,
+    ) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.mouse_wheel(wnd, axis, distance, mouse_state);
+        }
+    }
+
+    fn drop_files(
+        &mut self,
+        wnd: &wita::Window,
+        paths: &[&Path],
+        position: wita::PhysicalPosition<f32>,
+    ) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.drop_files(wnd, paths, position);
+        }
+    }
+
+    fn resizing(&mut self, wnd: &wita::Window, size: wita::PhysicalSize<u32>) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.resizing(wnd, size);
+        }
+    }
+
+    fn resized(&mut self, wnd: &wita::Window, size: wita::PhysicalSize<u32>) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.resized(wnd, size);
+        }
+    }
+
+    fn dpi_changed(&mut self, wnd: &wita::Window) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.dpi_changed(wnd);
+        }
+    }
+
+    fn draw(&mut self, wnd: &wita::Window) {
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.draw(wnd);
+        }
+        self.open_new_windows();
     }
 
     fn closed(&mut self, wnd: &wita::Window) {
-        self.config.window = Rect::from_window(wnd);
-        if let Err(e) = write_config("./config.json", &self.config) {
-            error!("write_config error: {}", e);
+        if let Some(viewer) = self.viewer(wnd) {
+            viewer.closed(wnd);
         }
+        self.queues.remove(wnd);
+        self.viewers
+            .retain(|viewer| viewer.wnd.raw_handle() != wnd.raw_handle());
+        self.share_caches();
     }
 }
@@ -0,0 +1,266 @@
+use crate::device;
+use crate::error::Error;
+use log::debug;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use winapi::um::fileapi::{CreateFileW, CREATE_ALWAYS};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::winbase::FILE_FLAG_DELETE_ON_CLOSE;
+use winapi::um::winnt::{FILE_ATTRIBUTE_NORMAL, GENERIC_WRITE, HANDLE};
+
+pub const EXTENSIONS: [&str; 2] = ["zip", "cbz"];
+
+pub fn is_archive(path: impl AsRef<Path>) -> bool {
+    match path.as_ref().extension() {
+        Some(ext) => EXTENSIONS
+            .iter()
+            .any(|e| ext.to_string_lossy().eq_ignore_ascii_case(e)),
+        None => false,
+    }
+}
+
+pub fn split(path: impl AsRef<Path>) -> Option<(PathBuf, String)> {
+    let path = path.as_ref();
+    let archive = path
+        .ancestors()
+        .skip(1)
+        .find(|p| is_archive(p) && p.is_file())?;
+    let entry = path
+        .strip_prefix(archive)
+        .ok()?
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    Some((archive.to_path_buf(), entry))
+}
+
+pub fn entries(
+    archive: impl AsRef<Path>,
+    extensions: &[String],
+) -> Result<Vec<(PathBuf, u64)>, Error> {
+    let archive = archive.as_ref();
+    let mut zip = zip::ZipArchive::new(File::open(archive)?).map_err(|e| Error::Other(e.into()))?;
+    let mut paths = vec![];
+    for i in 0..zip.len() {
+        let file = zip.by_index(i).map_err(|e| Error::Other(e.into()))?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name();
+        let is_image = match Path::new(name).extension() {
+            Some(ext) => extensions
+                .iter()
+                .any(|e| ext.to_string_lossy().eq_ignore_ascii_case(e)),
+            None => false,
+        };
+        if is_image {
+            paths.push((archive.join(name), file.size()));
+        }
+    }
+    Ok(paths)
+}
+
+static EXTRACTS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+struct Lock(HANDLE);
+
+unsafe impl Send for Lock {}
+unsafe impl Sync for Lock {}
+
+impl Lock {
+    fn acquire(dir: &Path) -> Option<Self> {
+        std::fs::create_dir_all(dir).ok()?;
+        let path = dir
+            .join("lock")
+            .to_string_lossy()
+            .encode_utf16()
+            .chain(Some(0))
+            .collect::<Vec<_>>();
+        unsafe {
+            let handle = CreateFileW(
+                path.as_ptr(),
+                GENERIC_WRITE,
+                0,
+                std::ptr::null_mut(),
+                CREATE_ALWAYS,
+                FILE_ATTRIBUTE_NORMAL | FILE_FLAG_DELETE_ON_CLOSE,
+                std::ptr::null_mut(),
+            );
+            if handle == INVALID_HANDLE_VALUE {
+                return None;
+            }
+            Some(Self(handle))
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ArchiveCache {
+    dir: PathBuf,
+    target_size: u64,
+    files: Mutex<(VecDeque<(PathBuf, u64)>, u64)>,
+    lock: Option<Lock>,
+}
+
+impl ArchiveCache {
+    pub fn new(dir: impl AsRef<Path>, target_size: u64) -> Self {
+        let lock = Lock::acquire(dir.as_ref());
+        let dir = match lock {
+            Some(_) => dir.as_ref().to_path_buf(),
+            None => {
+                let private = dir.as_ref().join(std::process::id().to_string());
+                debug!(
+                    "archive cache: {} is in use: using {}",
+                    dir.as_ref().to_string_lossy(),
+                    private.to_string_lossy()
+                );
+                private
+            }
+        };
+        let mut files = dir
+            .read_dir()
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| {
+                        let entry = entry.ok()?;
+                        let meta = entry.metadata().ok()?;
+                        if !meta.is_file() || entry.file_name() == "lock" {
+                            return None;
+                        }
+                        if entry.path().extension().is_some_and(|ext| ext == "part") {
+                            std::fs::remove_file(entry.path()).ok();
+                            return None;
+                        }
+                        Some((entry.path(), meta.len(), meta.modified().ok()?))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        files.sort_by_key(|(_, _, modified)| *modified);
+        let size = files.iter().map(|(_, len, _)| len).sum();
+        let files = files
+            .into_iter()
+            .map(|(path, len, _)| (path, len))
+            .collect::<VecDeque<_>>();
+        let cache = Self {
+            dir,
+            target_size,
+            files: Mutex::new((files, size)),
+            lock,
+        };
+        cache.prune();
+        cache
+    }
+
+    fn cache_path(&self, archive: &Path, entry: &str) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        archive.hash(&mut hasher);
+        entry.hash(&mut hasher);
+        if let Ok(meta) = archive.metadata() {
+            meta.len().hash(&mut hasher);
+            meta.modified().ok().hash(&mut hasher);
+        }
+        let name = format!("{:016x}", hasher.finish());
+        match Path::new(entry).extension() {
+            Some(ext) => self.dir.join(name).with_extension(ext),
+            None => self.dir.join(name),
+        }
+    }
+
+    fn touch(&self, path: &Path) -> bool {
+        let mut files = self.files.lock().unwrap();
+        let (files, _) = &mut *files;
+        match files.iter().position(|(p, _)| p == path) {
+            Some(i) => {
+                let item = files.remove(i).unwrap();
+                files.push_back(item);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn prune(&self) {
+        let mut files = self.files.lock().unwrap();
+        let (files, size) = &mut *files;
+        while *size > self.target_size && files.len() > 1 {
+            let (path, len) = files.pop_front().unwrap();
+            debug!("archive cache: prune: {}", path.to_string_lossy());
+            std::fs::remove_file(&path).ok();
+            *size -= len;
+        }
+    }
+
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf, Error> {
+        if let Some(path) = device::resolve(path)? {
+            return Ok(path);
+        }
+        let (archive, entry) = match split(path) {
+            Some(ret) => ret,
+            None => return Ok(path.to_path_buf()),
+        };
+        let cached = self.cache_path(&archive, &entry);
+        if self.touch(&cached) && cached.is_file() {
+            return Ok(cached);
+        }
+        debug!(
+            "archive cache: extract: {}: {}",
+            archive.to_string_lossy(),
+            entry
+        );
+        std::fs::create_dir_all(&self.dir)?;
+        let mut zip =
+            zip::ZipArchive::new(File::open(&archive)?).map_err(|e| Error::Other(e.into()))?;
+        let mut file = zip.by_name(&entry).map_err(|e| Error::Other(e.into()))?;
+        let tmp = cached.with_extension(format!(
+            "{}-{}.part",
+            std::process::id(),
+            EXTRACTS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let len = match std::io::copy(&mut file, &mut File::create(&tmp)?) {
+            Ok(len) => len,
+            Err(e) => {
+                std::fs::remove_file(&tmp).ok();
+                return Err(e.into());
+            }
+        };
+        if let Err(e) = std::fs::rename(&tmp, &cached) {
+            std::fs::remove_file(&tmp).ok();
+            if !cached.is_file() {
+                return Err(e.into());
+            }
+        }
+        {
+            let mut files = self.files.lock().unwrap();
+            let (files, size) = &mut *files;
+            files.retain(|(p, _)| *p != cached);
+            files.push_back((cached.clone(), len));
+            *size = files.iter().map(|(_, len)| len).sum();
+        }
+        self.prune();
+        Ok(cached)
+    }
+}
+
+impl Drop for ArchiveCache {
+    fn drop(&mut self) {
+        if self.lock.is_none() {
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::convert::OutputFormat;
+use log::error;
+use std::path::PathBuf;
+
+fn parse_hwnd(s: &str) -> Option<isize> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        isize::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    pub path: Option<PathBuf>,
+    pub parent_hwnd: Option<isize>,
+    pub pipe: Option<String>,
+    pub controller: Option<String>,
+    pub profile: Option<String>,
+    pub record_input: Option<PathBuf>,
+    pub replay_input: Option<PathBuf>,
+    pub screensaver: bool,
+}
+
+pub fn is_screensaver_config() -> bool {
+    std::env::args()
+        .nth(1)
+        .is_some_and(|arg| arg.to_ascii_lowercase().starts_with("/c"))
+}
+
+impl Args {
+    pub fn parse() -> Self {
+        let mut args = Self::default();
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            let lower = arg.to_ascii_lowercase();
+            match arg.as_str() {
+                _ if lower == "/s" => args.screensaver = true,
+                _ if lower == "/p" => {
+                    args.screensaver = true;
+                    args.parent_hwnd = iter.next().as_deref().and_then(parse_hwnd);
+                }
+                _ if lower.starts_with("/p:") => {
+                    args.screensaver = true;
+                    args.parent_hwnd = parse_hwnd(&arg[3..]);
+                }
+                "--parent-hwnd" => {
+                    args.parent_hwnd = iter.next().as_deref().and_then(parse_hwnd);
+                    if args.parent_hwnd.is_none() {
+                        error!("--parent-hwnd: invalid window handle");
+                    }
+                }
+                "--pipe" => args.pipe = iter.next(),
+                "--controller" => args.controller = iter.next(),
+                "--profile" => args.profile = iter.next(),
+                "--record-input" => args.record_input = iter.next().map(|p| p.into()),
+                "--replay-input" => args.replay_input = iter.next().map(|p| p.into()),
+                _ if arg.starts_with("--") => error!("unknown option: {}", arg),
+                _ => args.path = Some(arg.into()),
+            }
+        }
+        if args.pipe.is_none() {
+            args.pipe = args.parent_hwnd.map(|hwnd| format!("niv-{}", hwnd));
+        }
+        args
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConvertArgs {
+    pub to: OutputFormat,
+    pub quality: Option<f32>,
+    pub out: Option<PathBuf>,
+    pub overwrite: bool,
+    pub dir: PathBuf,
+}
+
+impl ConvertArgs {
+    pub fn parse() -> Option<anyhow::Result<Self>> {
+        let mut iter = std::env::args().skip(1);
+        if iter.next().as_deref() != Some("convert") {
+            return None;
+        }
+        let mut to = None;
+        let mut quality = None;
+        let mut out = None;
+        let mut overwrite = false;
+        let mut dir = None;
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--to" => {
+                    let ext = iter.next().unwrap_or_default();
+                    to = OutputFormat::from_extension(&ext);
+                    if to.is_none() {
+                        return Some(Err(anyhow::anyhow!("--to: unknown format: {}", ext)));
+                    }
+                }
+                "--quality" => {
+                    quality = iter.next().and_then(|q| q.parse().ok());
+                    if quality.is_none() {
+                        return Some(Err(anyhow::anyhow!("--quality: invalid value")));
+                    }
+                }
+                "--out" => out = iter.next().map(|p| p.into()),
+                "--overwrite" => overwrite = true,
+                _ if arg.starts_with("--") => {
+                    return Some(Err(anyhow::anyhow!("unknown option: {}", arg)))
+                }
+                _ => dir = Some(arg.into()),
+            }
+        }
+        let to = match to {
+            Some(to) => to,
+            None => return Some(Err(anyhow::anyhow!("--to is required"))),
+        };
+        let dir = match dir {
+            Some(dir) => dir,
+            None => return Some(Err(anyhow::anyhow!("no input directory"))),
+        };
+        Some(Ok(Self {
+            to,
+            quality,
+            out,
+            overwrite,
+            dir,
+        }))
+    }
+}
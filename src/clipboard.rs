@@ -0,0 +1,57 @@
+use crate::error::Error;
+use image::RgbaImage;
+use winapi::um::winbase::*;
+use winapi::um::winuser::*;
+
+unsafe fn set_data_impl(wnd: &wita::Window, format: u32, data: &[u8]) -> Result<(), Error> {
+    if OpenClipboard(wnd.raw_handle() as _) == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    EmptyClipboard();
+    let mem = GlobalAlloc(GMEM_MOVEABLE, data.len());
+    if mem.is_null() {
+        let e = std::io::Error::last_os_error();
+        CloseClipboard();
+        return Err(e.into());
+    }
+    let p = GlobalLock(mem) as *mut u8;
+    std::ptr::copy_nonoverlapping(data.as_ptr(), p, data.len());
+    GlobalUnlock(mem);
+    if SetClipboardData(format, mem as _).is_null() {
+        let e = std::io::Error::last_os_error();
+        GlobalFree(mem);
+        CloseClipboard();
+        return Err(e.into());
+    }
+    CloseClipboard();
+    Ok(())
+}
+
+pub fn set_text(wnd: &wita::Window, text: &str) -> Result<(), Error> {
+    let data = text
+        .encode_utf16()
+        .chain(Some(0))
+        .flat_map(|c| c.to_le_bytes().to_vec())
+        .collect::<Vec<_>>();
+    unsafe { set_data_impl(wnd, CF_UNICODETEXT, &data) }
+}
+
+pub fn set_image(wnd: &wita::Window, img: &RgbaImage) -> Result<(), Error> {
+    const BITMAPINFOHEADER_SIZE: u32 = 40;
+    let (width, height) = img.dimensions();
+    let mut data = Vec::with_capacity((BITMAPINFOHEADER_SIZE + width * height * 4) as usize);
+    data.extend_from_slice(&BITMAPINFOHEADER_SIZE.to_le_bytes());
+    data.extend_from_slice(&(width as i32).to_le_bytes());
+    data.extend_from_slice(&(height as i32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&32u16.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&(width * height * 4).to_le_bytes());
+    data.extend_from_slice(&[0; 16]);
+    for row in img.as_raw().chunks_exact((width * 4) as usize).rev() {
+        for px in row.chunks_exact(4) {
+            data.extend_from_slice(&[px[2], px[1], px[0], px[3]]);
+        }
+    }
+    unsafe { set_data_impl(wnd, CF_DIB, &data) }
+}
@@ -1,8 +1,20 @@
+use crate::animation;
+use crate::convert::EncodeOptions;
 use crate::directory;
-use crate::renderer::Interpolation;
+use crate::gamepad::Button;
+use crate::images::{CachePolicyKind, DecodeBackend};
+use crate::ratings::Flag;
+use crate::renderer::{FitMode, Interpolation};
+use crate::shell::WallpaperPosition;
+use log::{debug, info, warn};
 use serde::*;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPARAM, TRUE};
+use winapi::shared::windef::*;
+use winapi::um::winuser::*;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Rect {
@@ -10,17 +22,140 @@ pub struct Rect {
     pub y: i32,
     pub width: u32,
     pub height: u32,
+    #[serde(default)]
+    pub maximized: bool,
+    #[serde(default)]
+    pub monitor: Option<String>,
+}
+
+fn monitor_info(monitor: HMONITOR) -> Option<MONITORINFOEXW> {
+    unsafe {
+        let mut info = MONITORINFOEXW {
+            cbSize: std::mem::size_of::<MONITORINFOEXW>() as _,
+            ..std::mem::zeroed()
+        };
+        if GetMonitorInfoW(monitor, &mut info as *mut _ as *mut MONITORINFO) == FALSE {
+            return None;
+        }
+        Some(info)
+    }
+}
+
+fn monitor_name(info: &MONITORINFOEXW) -> String {
+    let len = info
+        .szDevice
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(info.szDevice.len());
+    String::from_utf16_lossy(&info.szDevice[..len])
+}
+
+fn find_monitor(name: &str) -> Option<MONITORINFOEXW> {
+    unsafe extern "system" fn callback(
+        monitor: HMONITOR,
+        _: HDC,
+        _: LPRECT,
+        param: LPARAM,
+    ) -> BOOL {
+        (*(param as *mut Vec<HMONITOR>)).push(monitor);
+        TRUE
+    }
+    let mut monitors: Vec<HMONITOR> = vec![];
+    unsafe {
+        EnumDisplayMonitors(
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            Some(callback),
+            &mut monitors as *mut _ as LPARAM,
+        );
+    }
+    monitors
+        .into_iter()
+        .filter_map(monitor_info)
+        .find(|info| monitor_name(info) == name)
 }
 
 impl Rect {
     pub fn from_window(wnd: &wita::Window) -> Self {
         let pos = wnd.position();
         let size = wnd.inner_size();
-        Self {
+        let mut rect = Self {
             x: pos.x,
             y: pos.y,
             width: size.width as _,
             height: size.height as _,
+            maximized: false,
+            monitor: None,
+        };
+        unsafe {
+            let hwnd = wnd.raw_handle() as HWND;
+            let info = monitor_info(MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST));
+            rect.monitor = info.as_ref().map(monitor_name);
+            if IsZoomed(hwnd) != 0 {
+                let mut placement = WINDOWPLACEMENT {
+                    length: std::mem::size_of::<WINDOWPLACEMENT>() as _,
+                    ..Default::default()
+                };
+                GetWindowPlacement(hwnd, &mut placement);
+                let normal = placement.rcNormalPosition;
+                let (dx, dy) = match info.as_ref() {
+                    Some(info) => (
+                        info.rcWork.left - info.rcMonitor.left,
+                        info.rcWork.top - info.rcMonitor.top,
+                    ),
+                    None => (0, 0),
+                };
+                let style = GetWindowLongPtrW(hwnd, GWL_STYLE) as DWORD;
+                let mut frame = RECT::default();
+                AdjustWindowRect(&mut frame, style, FALSE);
+                rect.x = normal.left + dx;
+                rect.y = normal.top + dy;
+                rect.width = (normal.right - normal.left - (frame.right - frame.left)).max(1) as _;
+                rect.height = (normal.bottom - normal.top - (frame.bottom - frame.top)).max(1) as _;
+                rect.maximized = true;
+            }
+        }
+        rect
+    }
+
+    pub fn on_screen(&self) -> Self {
+        let rc = RECT {
+            left: self.x,
+            top: self.y,
+            right: self.x + self.width as i32,
+            bottom: self.y + self.height as i32,
+        };
+        let visible = unsafe { !MonitorFromRect(&rc, MONITOR_DEFAULTTONULL).is_null() };
+        if visible {
+            return self.clone();
+        }
+        warn!(
+            "window: saved position ({}, {}) is not on a connected monitor",
+            self.x, self.y
+        );
+        let info = self
+            .monitor
+            .as_deref()
+            .and_then(find_monitor)
+            .or_else(|| unsafe {
+                monitor_info(MonitorFromPoint(
+                    POINT { x: 0, y: 0 },
+                    MONITOR_DEFAULTTOPRIMARY,
+                ))
+            });
+        let work = match info {
+            Some(info) => info.rcWork,
+            None => return self.clone(),
+        };
+        let width = self.width.min((work.right - work.left).max(1) as u32);
+        let height = self.height.min((work.bottom - work.top).max(1) as u32);
+        Self {
+            x: work.left + (work.right - work.left - width as i32) / 2,
+            y: work.top + (work.bottom - work.top - height as i32) / 2,
+            width,
+            height,
+            maximized: self.maximized,
+            monitor: self.monitor.clone(),
         }
     }
 }
@@ -28,6 +163,13 @@ impl Rect {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ClearColor(pub f32, pub f32, pub f32);
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum AutoBackground {
+    Off,
+    Contrast,
+    Average,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RgbaColor(pub f32, pub f32, pub f32, pub f32);
 
@@ -37,46 +179,402 @@ pub enum Method {
     Prev,
     Next,
     PrintMemory,
+    FolderStats,
+    ExportFolderStats,
+    CopyPath,
+    CopyFileName,
+    TogglePair,
+    RevealInExplorer,
+    ExternalTool(usize),
+    SetWallpaper,
+    CycleRenderPreset,
+    SaveAs,
+    Crop,
+    Panorama,
+    CopyImage,
+    CycleStereoMode,
+    AddToLightTable,
+    ToggleLightTable,
+    ExportLightTable,
+    ImportFromDevice,
+    ToggleStatusBar,
+    ToggleBorderless,
+    ToggleGrid,
+    CommandPalette,
+    ToggleFitWindow,
+    ToggleTransparency,
+    ShowFormats,
+    NewWindow,
+    ToggleFullscreen,
+    CycleProfile,
+    Settings,
+    ToggleSlideshow,
+    First,
+    Last,
+    GoTo,
+    PrevDirectory,
+    NextDirectory,
+    ReloadImage,
+    TogglePresentation,
+    Adjustments,
+    ResetAdjustments,
+    CycleChannel,
+    PixelInspector,
+    ToggleLoupe,
+    ToggleGuide(Guide),
+    MarkCompare,
+    ToggleCompare,
+    ToggleDiff,
+    FindDuplicates,
+    Delete,
+    Rate(u8),
+    ToggleFlag(Flag),
+    CycleRatingFilter,
+    ToggleHideRejected,
+    ToggleNote,
+    EditNote,
+    Annotate,
+    ToggleInfo,
+    ToggleProperties,
+    CopyLocation,
+    OpenLocation,
+    CycleOrder,
+    ToggleSortDirection,
+    OpenFolder,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RenderPreset {
+    Photo,
+    Document,
+    Binarized,
+}
+
+impl RenderPreset {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Photo => Self::Document,
+            Self::Document => Self::Binarized,
+            Self::Binarized => Self::Photo,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DocumentPreset {
+    pub interpolation: Interpolation,
+    pub contrast: f32,
+    pub threshold: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Osd {
+    pub enabled: bool,
+    pub duration: u64,
+    pub fade: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Guide {
+    PixelGrid,
+    Thirds,
+    Crosshair,
+    AspectRatio,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Guides {
+    pub pixel_grid: bool,
+    pub thirds: bool,
+    pub crosshair: bool,
+    pub aspect_ratio: bool,
+    pub pixel_grid_scale: f32,
+    pub aspect_ratios: Vec<(f32, f32)>,
+    pub color: RgbaColor,
+}
+
+impl Guides {
+    pub fn toggle(&mut self, guide: Guide) -> bool {
+        let flag = match guide {
+            Guide::PixelGrid => &mut self.pixel_grid,
+            Guide::Thirds => &mut self.thirds,
+            Guide::Crosshair => &mut self.crosshair,
+            Guide::AspectRatio => &mut self.aspect_ratio,
+        };
+        *flag = !*flag;
+        *flag
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum LoupeShape {
+    Circle,
+    Rectangle,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Loupe {
+    pub factor: f32,
+    pub radius: f32,
+    pub shape: LoupeShape,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Font {
+    pub family: String,
+    pub fallbacks: Vec<String>,
+    pub size: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ReadingDirection {
+    LeftToRight,
+    RightToLeft,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Startup {
+    pub fullscreen: bool,
+    pub maximized: bool,
+    pub center_on_cursor: bool,
+    pub last_session: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum WheelAction {
+    Navigate,
+    Zoom,
+    Pan,
+    None,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Wheel {
+    pub action: WheelAction,
+    pub ctrl: WheelAction,
+    pub shift: WheelAction,
+    pub zoom_step: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MouseGesture {
+    pub strokes: String,
+    pub method: Method,
+}
+
+impl MouseGesture {
+    fn new(strokes: &str, method: Method) -> Self {
+        Self {
+            strokes: strokes.into(),
+            method,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GamepadBinding {
+    pub button: Button,
+    pub method: Method,
+}
+
+impl GamepadBinding {
+    fn new(button: Button, method: Method) -> Self {
+        Self { button, method }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Gamepad {
+    pub enabled: bool,
+    pub bindings: Vec<GamepadBinding>,
+    pub zoom_speed: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Import {
+    pub verify: bool,
+    pub offer_eject: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FitWindow {
+    pub enabled: bool,
+    pub min_width: u32,
+    pub min_height: u32,
+    pub max_ratio: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct KeyBinding {
     pub method: Method,
+    #[serde(default)]
     pub keys: Vec<Vec<wita::VirtualKey>>,
+    #[serde(default)]
+    pub sequences: Vec<Vec<Vec<wita::VirtualKey>>>,
 }
 
 impl KeyBinding {
     fn new(method: Method, keys: Vec<Vec<wita::VirtualKey>>) -> Self {
-        Self { method, keys }
+        Self {
+            method,
+            keys,
+            sequences: vec![],
+        }
+    }
+
+    fn sequence(method: Method, sequences: Vec<Vec<Vec<wita::VirtualKey>>>) -> Self {
+        Self {
+            method,
+            keys: vec![],
+            sequences,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyRepeat {
+    pub delay: u64,
+    pub interval: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExternalTool {
+    pub name: String,
+    pub command: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConfirmAction {
+    Delete,
+    Overwrite,
+    Batch,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Confirmation {
+    pub delete: bool,
+    pub overwrite: bool,
+    pub batch: bool,
+    pub bypass_key: Option<wita::VirtualKey>,
+}
+
+impl Confirmation {
+    pub fn required(&self, action: ConfirmAction) -> bool {
+        match action {
+            ConfirmAction::Delete => self.delete,
+            ConfirmAction::Overwrite => self.overwrite,
+            ConfirmAction::Batch => self.batch,
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Default for Confirmation {
+    fn default() -> Self {
+        Self {
+            delete: true,
+            overwrite: true,
+            batch: true,
+            bypass_key: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub window: Rect,
     pub background: ClearColor,
+    pub auto_background: AutoBackground,
+    pub light_background: ClearColor,
     pub extensions: Vec<String>,
-    pub lookahead: usize,
+    pub ignore: Vec<String>,
+    pub group_pairs: bool,
+    pub pair_suffixes: Vec<String>,
+    #[serde(alias = "lookahead")]
+    pub lookahead_forward: usize,
+    pub lookahead_backward: usize,
     pub order: directory::Order,
+    pub secondary_order: directory::Order,
     pub comp: directory::Comparison,
+    pub fit_mode: FitMode,
+    pub reading_direction: ReadingDirection,
+    pub continue_siblings: bool,
     pub interpolation: Interpolation,
+    pub document: DocumentPreset,
+    pub auto_panorama: bool,
+    pub panorama_fov: f32,
+    pub animation_memory: animation::Memory,
+    pub animation_ring_frames: usize,
+    pub status_bar: bool,
+    pub title: String,
+    pub borderless: bool,
+    pub grid_columns: usize,
+    pub slideshow_interval: u64,
+    pub ken_burns: f32,
+    pub screensaver_folder: Option<std::path::PathBuf>,
+    pub cursor_hide_timeout: u64,
+    pub loupe: Loupe,
+    pub guides: Guides,
+    pub diff_amplification: f32,
+    pub duplicate_threshold: u32,
+    pub map_url: String,
+    pub open_dialog_filter: Option<String>,
+    pub wheel: Wheel,
+    pub gestures: Vec<MouseGesture>,
+    pub gesture_threshold: f32,
+    pub swipe_threshold: f32,
+    pub sequence_timeout: u64,
+    pub gamepad: Gamepad,
+    pub fit_window: FitWindow,
+    pub transparent_background: bool,
     pub worker_threads: usize,
     pub bmp_cache_size: usize,
     pub image_cache_size: usize,
+    pub disk_cache_size: usize,
+    pub low_memory_cache_ratio: f32,
+    pub cache_policy: CachePolicyKind,
+    pub max_decode_size: u32,
+    pub decoders: BTreeMap<String, DecodeBackend>,
+    #[serde(alias = "hardware_jpeg")]
+    pub scaled_jpeg: bool,
+    pub mipmaps: bool,
+    pub vsync: bool,
+    pub progressive_preview: bool,
+    pub archive_cache_size: usize,
+    pub error_ttl: u64,
+    pub error_probe_interval: u64,
     pub key_bindings: Vec<KeyBinding>,
+    pub key_repeat: KeyRepeat,
+    pub external_tools: Vec<ExternalTool>,
+    pub wallpaper_position: WallpaperPosition,
+    pub encode: EncodeOptions,
+    pub confirmation: Confirmation,
+    pub osd: Osd,
+    pub font: Font,
+    pub import: Import,
+    pub startup: Startup,
+    pub last_path: Option<std::path::PathBuf>,
+    pub profile: Option<String>,
+    pub profiles: BTreeMap<String, toml::Value>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         const LOOKAHEAD: usize = 5;
+        const LOOKBEHIND: usize = 3;
         Self {
             window: Rect {
                 x: 0,
                 y: 0,
                 width: 640,
                 height: 480,
+                maximized: false,
+                monitor: None,
             },
             background: ClearColor(0.15, 0.15, 0.15),
+            auto_background: AutoBackground::Off,
+            light_background: ClearColor(0.85, 0.85, 0.85),
             extensions: vec![
                 "png".into(),
                 "jpg".into(),
@@ -90,11 +588,92 @@ impl Default for Config {
                 "pgm".into(),
                 "ppm".into(),
                 "tga".into(),
+                "gif".into(),
+                "mpo".into(),
+                "jps".into(),
             ],
-            lookahead: LOOKAHEAD,
+            ignore: vec!["*_thumb.*".into(), ".*".into(), "__MACOSX/*".into()],
+            group_pairs: true,
+            pair_suffixes: vec!["_edited".into(), "-edited".into(), "_edit".into()],
+            lookahead_forward: LOOKAHEAD,
+            lookahead_backward: LOOKBEHIND,
             order: directory::Order::Name,
+            secondary_order: directory::Order::Name,
             comp: directory::Comparison::Ascending,
+            fit_mode: FitMode::Shrink,
+            reading_direction: ReadingDirection::LeftToRight,
+            continue_siblings: false,
             interpolation: Interpolation::HighQualityCubic,
+            document: DocumentPreset {
+                interpolation: Interpolation::Linear,
+                contrast: 0.5,
+                threshold: 0.6,
+            },
+            auto_panorama: true,
+            panorama_fov: 90.0,
+            animation_memory: animation::Memory::Auto,
+            animation_ring_frames: 8,
+            status_bar: false,
+            title: "niv{light_table}{filter} {index}/{total} {path}".into(),
+            borderless: false,
+            grid_columns: 4,
+            slideshow_interval: 3000,
+            ken_burns: 0.0,
+            screensaver_folder: None,
+            cursor_hide_timeout: 0,
+            loupe: Loupe {
+                factor: 3.0,
+                radius: 120.0,
+                shape: LoupeShape::Circle,
+            },
+            diff_amplification: 1.0,
+            duplicate_threshold: 5,
+            map_url: "bingmaps:?cp={lat}~{lon}&lvl=16&collection=point.{lat}_{lon}".into(),
+            open_dialog_filter: None,
+            guides: Guides {
+                pixel_grid: false,
+                thirds: false,
+                crosshair: false,
+                aspect_ratio: false,
+                pixel_grid_scale: 8.0,
+                aspect_ratios: vec![(16.0, 9.0), (4.0, 3.0)],
+                color: RgbaColor(1.0, 1.0, 1.0, 0.5),
+            },
+            wheel: Wheel {
+                action: WheelAction::Navigate,
+                ctrl: WheelAction::Zoom,
+                shift: WheelAction::Pan,
+                zoom_step: 1.25,
+            },
+            gestures: vec![
+                MouseGesture::new("L", Method::Prev),
+                MouseGesture::new("R", Method::Next),
+                MouseGesture::new("U", Method::ToggleFullscreen),
+                MouseGesture::new("D", Method::ToggleGrid),
+                MouseGesture::new("DR", Method::CopyPath),
+            ],
+            gesture_threshold: 24.0,
+            swipe_threshold: 80.0,
+            sequence_timeout: 800,
+            gamepad: Gamepad {
+                enabled: true,
+                bindings: vec![
+                    GamepadBinding::new(Button::DPadLeft, Method::Prev),
+                    GamepadBinding::new(Button::DPadRight, Method::Next),
+                    GamepadBinding::new(Button::LeftShoulder, Method::Prev),
+                    GamepadBinding::new(Button::RightShoulder, Method::Next),
+                    GamepadBinding::new(Button::Start, Method::ToggleSlideshow),
+                    GamepadBinding::new(Button::Back, Method::ToggleFullscreen),
+                ],
+                zoom_speed: 2.0,
+            },
+            fit_window: FitWindow {
+                enabled: false,
+                min_width: 320,
+                min_height: 240,
+                max_ratio: 0.9,
+            },
+            transparent_background: false,
             worker_threads: {
                 let n = num_cpus::get() / 2;
                 match n {
@@ -105,13 +684,42 @@ impl Default for Config {
             },
             bmp_cache_size: 512 * 1024 * 1024,
             image_cache_size: 1024 * 1024 * 1024,
+            disk_cache_size: 0,
+            low_memory_cache_ratio: 0.25,
+            cache_policy: CachePolicyKind::Lru,
+            max_decode_size: 8192,
+            decoders: BTreeMap::new(),
+            scaled_jpeg: true,
+            mipmaps: true,
+            vsync: true,
+            progressive_preview: true,
+            archive_cache_size: 512 * 1024 * 1024,
+            error_ttl: 30,
+            error_probe_interval: 1000,
             key_bindings: vec![
                 KeyBinding::new(Method::Open, vec![vec![wita::VirtualKey::Char('O')]]),
+                KeyBinding::new(
+                    Method::OpenFolder,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('O')]],
+                ),
+                KeyBinding::new(
+                    Method::CycleOrder,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('O')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleSortDirection,
+                    vec![vec![
+                        wita::VirtualKey::Ctrl,
+                        wita::VirtualKey::Shift,
+                        wita::VirtualKey::Char('O'),
+                    ]],
+                ),
                 KeyBinding::new(
                     Method::Prev,
                     vec![
                         vec![wita::VirtualKey::Char('A')],
                         vec![wita::VirtualKey::Left],
+                        vec![wita::VirtualKey::Other(VK_MEDIA_PREV_TRACK as u32)],
                     ],
                 ),
                 KeyBinding::new(
@@ -119,22 +727,795 @@ impl Default for Config {
                     vec![
                         vec![wita::VirtualKey::Char('D')],
                         vec![wita::VirtualKey::Right],
+                        vec![wita::VirtualKey::Other(VK_MEDIA_NEXT_TRACK as u32)],
                     ],
                 ),
+                KeyBinding::new(
+                    Method::PrevDirectory,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Left]],
+                ),
+                KeyBinding::new(
+                    Method::NextDirectory,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Right]],
+                ),
                 KeyBinding::new(Method::PrintMemory, vec![vec![wita::VirtualKey::F(1)]]),
+                KeyBinding::new(Method::FolderStats, vec![vec![wita::VirtualKey::F(2)]]),
+                KeyBinding::new(
+                    Method::ExportFolderStats,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::F(2)]],
+                ),
+                KeyBinding::new(
+                    Method::CopyPath,
+                    vec![vec![
+                        wita::VirtualKey::Ctrl,
+                        wita::VirtualKey::Shift,
+                        wita::VirtualKey::Char('C'),
+                    ]],
+                ),
+                KeyBinding::new(
+                    Method::CopyFileName,
+                    vec![vec![
+                        wita::VirtualKey::Ctrl,
+                        wita::VirtualKey::Alt,
+                        wita::VirtualKey::Char('C'),
+                    ]],
+                ),
+                KeyBinding::new(Method::TogglePair, vec![vec![wita::VirtualKey::Tab]]),
+                KeyBinding::new(
+                    Method::RevealInExplorer,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('E')]],
+                ),
+                KeyBinding::new(
+                    Method::ExternalTool(0),
+                    vec![vec![
+                        wita::VirtualKey::Ctrl,
+                        wita::VirtualKey::Shift,
+                        wita::VirtualKey::Char('E'),
+                    ]],
+                ),
+                KeyBinding::new(
+                    Method::SetWallpaper,
+                    vec![vec![
+                        wita::VirtualKey::Ctrl,
+                        wita::VirtualKey::Shift,
+                        wita::VirtualKey::Char('W'),
+                    ]],
+                ),
+                KeyBinding::new(
+                    Method::CycleRenderPreset,
+                    vec![vec![wita::VirtualKey::Char('T')]],
+                ),
+                KeyBinding::new(
+                    Method::SaveAs,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('S')]],
+                ),
+                KeyBinding::new(Method::Crop, vec![vec![wita::VirtualKey::Char('C')]]),
+                KeyBinding::new(
+                    Method::Panorama,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('P')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleStatusBar,
+                    vec![vec![wita::VirtualKey::Char('B')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleFitWindow,
+                    vec![vec![wita::VirtualKey::Char('F')]],
+                ),
+                KeyBinding::new(Method::ShowFormats, vec![vec![wita::VirtualKey::F(3)]]),
+                KeyBinding::new(Method::Settings, vec![vec![wita::VirtualKey::F(4)]]),
+                KeyBinding::new(Method::ReloadImage, vec![vec![wita::VirtualKey::F(5)]]),
+                KeyBinding::new(
+                    Method::ToggleSlideshow,
+                    vec![
+                        vec![wita::VirtualKey::Char('S')],
+                        vec![wita::VirtualKey::Other(VK_MEDIA_PLAY_PAUSE as u32)],
+                    ],
+                ),
+                KeyBinding::sequence(
+                    Method::First,
+                    vec![vec![
+                        vec![wita::VirtualKey::Char('G')],
+                        vec![wita::VirtualKey::Char('G')],
+                    ]],
+                ),
+                KeyBinding::sequence(
+                    Method::Last,
+                    vec![vec![vec![
+                        wita::VirtualKey::Shift,
+                        wita::VirtualKey::Char('G'),
+                    ]]],
+                ),
+                KeyBinding::sequence(Method::GoTo, vec![vec![vec![wita::VirtualKey::Char('G')]]]),
+                KeyBinding::new(
+                    Method::CycleProfile,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('P')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleFullscreen,
+                    vec![vec![wita::VirtualKey::F(11)]],
+                ),
+                KeyBinding::new(Method::Adjustments, vec![vec![wita::VirtualKey::Char('V')]]),
+                KeyBinding::new(
+                    Method::CycleChannel,
+                    vec![vec![wita::VirtualKey::Char('H')]],
+                ),
+                KeyBinding::new(
+                    Method::PixelInspector,
+                    vec![vec![wita::VirtualKey::Char('I')]],
+                ),
+                KeyBinding::new(Method::ToggleLoupe, vec![vec![wita::VirtualKey::Char('Z')]]),
+                KeyBinding::new(Method::MarkCompare, vec![vec![wita::VirtualKey::Char('K')]]),
+                KeyBinding::new(Method::ToggleDiff, vec![vec![wita::VirtualKey::Char('J')]]),
+                KeyBinding::new(
+                    Method::FindDuplicates,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('D')]],
+                ),
+                KeyBinding::new(Method::Delete, vec![vec![wita::VirtualKey::Delete]]),
+                KeyBinding::new(Method::Rate(0), vec![vec![wita::VirtualKey::Char('0')]]),
+                KeyBinding::new(Method::Rate(1), vec![vec![wita::VirtualKey::Char('1')]]),
+                KeyBinding::new(Method::Rate(2), vec![vec![wita::VirtualKey::Char('2')]]),
+                KeyBinding::new(Method::Rate(3), vec![vec![wita::VirtualKey::Char('3')]]),
+                KeyBinding::new(Method::Rate(4), vec![vec![wita::VirtualKey::Char('4')]]),
+                KeyBinding::new(Method::Rate(5), vec![vec![wita::VirtualKey::Char('5')]]),
+                KeyBinding::new(Method::ToggleNote, vec![vec![wita::VirtualKey::Char('N')]]),
+                KeyBinding::new(Method::Annotate, vec![vec![wita::VirtualKey::Char('U')]]),
+                KeyBinding::new(Method::ToggleInfo, vec![vec![wita::VirtualKey::Char('E')]]),
+                KeyBinding::new(
+                    Method::ToggleProperties,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('E')]],
+                ),
+                KeyBinding::new(
+                    Method::CopyLocation,
+                    vec![vec![
+                        wita::VirtualKey::Ctrl,
+                        wita::VirtualKey::Shift,
+                        wita::VirtualKey::Char('G'),
+                    ]],
+                ),
+                KeyBinding::new(
+                    Method::OpenLocation,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('M')]],
+                ),
+                KeyBinding::new(
+                    Method::EditNote,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('N')]],
+                ),
+                KeyBinding::new(
+                    Method::CycleRatingFilter,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('R')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleHideRejected,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('X')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleFlag(Flag::Pick),
+                    vec![vec![wita::VirtualKey::Char('P')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleFlag(Flag::Reject),
+                    vec![vec![wita::VirtualKey::Char('X')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleCompare,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('K')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleGuide(Guide::PixelGrid),
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('1')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleGuide(Guide::Thirds),
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('2')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleGuide(Guide::Crosshair),
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('3')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleGuide(Guide::AspectRatio),
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('4')]],
+                ),
+                KeyBinding::new(
+                    Method::ResetAdjustments,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('V')]],
+                ),
+                KeyBinding::new(
+                    Method::TogglePresentation,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::F(11)]],
+                ),
+                KeyBinding::new(
+                    Method::NewWindow,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('N')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleTransparency,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('T')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleGrid,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('G')]],
+                ),
+                KeyBinding::new(
+                    Method::CommandPalette,
+                    vec![vec![
+                        wita::VirtualKey::Ctrl,
+                        wita::VirtualKey::Shift,
+                        wita::VirtualKey::Char('P'),
+                    ]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleBorderless,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('B')]],
+                ),
+                KeyBinding::new(
+                    Method::AddToLightTable,
+                    vec![vec![wita::VirtualKey::Char('L')]],
+                ),
+                KeyBinding::new(
+                    Method::ToggleLightTable,
+                    vec![vec![wita::VirtualKey::Shift, wita::VirtualKey::Char('L')]],
+                ),
+                KeyBinding::new(
+                    Method::ExportLightTable,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('L')]],
+                ),
+                KeyBinding::new(
+                    Method::ImportFromDevice,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('I')]],
+                ),
+                KeyBinding::new(
+                    Method::CycleStereoMode,
+                    vec![vec![wita::VirtualKey::Char('M')]],
+                ),
+                KeyBinding::new(
+                    Method::CopyImage,
+                    vec![vec![wita::VirtualKey::Ctrl, wita::VirtualKey::Char('C')]],
+                ),
             ],
+            key_repeat: KeyRepeat {
+                delay: 400,
+                interval: 50,
+            },
+            external_tools: vec![ExternalTool {
+                name: "Paint".into(),
+                command: "mspaint.exe \"{path}\"".into(),
+            }],
+            wallpaper_position: WallpaperPosition::Fill,
+            encode: EncodeOptions::default(),
+            confirmation: Confirmation::default(),
+            osd: Osd {
+                enabled: true,
+                duration: 1000,
+                fade: 500,
+            },
+            font: Font {
+                family: "Yu Gothic".into(),
+                fallbacks: vec!["Meiryo".into(), "Segoe UI".into()],
+                size: 14.0,
+            },
+            import: Import {
+                verify: true,
+                offer_eject: true,
+            },
+            startup: Startup {
+                fullscreen: false,
+                maximized: false,
+                center_on_cursor: false,
+                last_session: false,
+            },
+            last_path: None,
+            profile: None,
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Vec<String> {
+        const MB: usize = 1024 * 1024;
+        const TYPICAL_IMAGE_SIZE: usize = 24 * MB;
+        let default = Config::default();
+        let cpus = num_cpus::get();
+        let lookahead = self.lookahead_forward + self.lookahead_backward;
+        let mut warnings = vec![];
+        if self.worker_threads == 0 {
+            warnings.push("worker_threads is 0: suggested value is 1 or more".to_string());
+        } else if self.worker_threads > cpus {
+            warnings.push(format!(
+                "worker_threads ({}) exceeds the number of CPUs: suggested value is {}",
+                self.worker_threads, default.worker_threads
+            ));
+        }
+        for (name, size, default_size) in [
+            (
+                "bmp_cache_size",
+                self.bmp_cache_size,
+                default.bmp_cache_size,
+            ),
+            (
+                "image_cache_size",
+                self.image_cache_size,
+                default.image_cache_size,
+            ),
+        ]
+        .iter()
+        {
+            if *size == 0 {
+                warnings.push(format!(
+                    "{} is 0: no image can be cached, suggested value is {}",
+                    name, default_size
+                ));
+            } else if *size < (lookahead + 1) * TYPICAL_IMAGE_SIZE {
+                warnings.push(format!(
+                    "{} ({}MB) cannot hold lookahead ({}) images: suggested value is {} or more",
+                    name,
+                    size / MB,
+                    lookahead,
+                    (lookahead + 1) * TYPICAL_IMAGE_SIZE
+                ));
+            }
+        }
+        if self.extensions.is_empty() {
+            warnings.push("extensions is empty: no file can be opened".to_string());
+        }
+        warnings
+    }
+}
+
+const PROFILE_KEYS: [&str; 2] = ["profile", "profiles"];
+
+fn merge_value(base: &mut toml::Value, overlay: &toml::Value) {
+    match (base.as_table_mut(), overlay.as_table()) {
+        (Some(base), Some(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(key) {
+                    Some(b) => merge_value(b, value),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        _ => *base = overlay.clone(),
+    }
+}
+
+impl Config {
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    pub fn layered(&self, profile: Option<&str>) -> Config {
+        let overlay = match profile {
+            Some(name) => match self.profiles.get(name) {
+                Some(overlay) => overlay,
+                None => {
+                    warn!("config: unknown profile: {}", name);
+                    return self.clone();
+                }
+            },
+            None => return self.clone(),
+        };
+        let mut value = match toml::Value::try_from(self) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("config: profile {}: {}", profile.unwrap(), e);
+                return self.clone();
+            }
+        };
+        if let (Some(table), Some(overlay)) = (value.as_table_mut(), overlay.as_table()) {
+            for (key, v) in overlay {
+                if PROFILE_KEYS.contains(&key.as_str()) {
+                    continue;
+                }
+                match table.get_mut(key) {
+                    Some(b) => merge_value(b, v),
+                    None => {
+                        table.insert(key.clone(), v.clone());
+                    }
+                }
+            }
+        }
+        match value.try_into::<Config>() {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("config: profile {}: {}", profile.unwrap(), e);
+                self.clone()
+            }
+        }
+    }
+
+    pub fn unlayered(&self, base: &Config, profile: Option<&str>) -> Config {
+        let overlay = match profile.and_then(|name| base.profiles.get(name)) {
+            Some(overlay) => overlay,
+            None => return self.clone(),
+        };
+        let ret = toml::Value::try_from(self).and_then(|mut value| {
+            let base = toml::Value::try_from(base)?;
+            if let (Some(table), Some(base), Some(overlay)) =
+                (value.as_table_mut(), base.as_table(), overlay.as_table())
+            {
+                for key in overlay.keys() {
+                    match base.get(key) {
+                        Some(v) => {
+                            table.insert(key.clone(), v.clone());
+                        }
+                        None => {
+                            table.remove(key);
+                        }
+                    }
+                }
+            }
+            Ok(value)
+        });
+        match ret.ok().and_then(|value| value.try_into::<Config>().ok()) {
+            Some(config) => config,
+            None => self.clone(),
+        }
+    }
+}
+
+const LEGACY_CONFIG_PATH: &str = "./config.json";
+const STATE_KEYS: [&str; 2] = ["window", "last_path"];
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+struct Override {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base: Option<toml::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<toml::Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct State {
+    window: Rect,
+    #[serde(default)]
+    last_path: Option<PathBuf>,
+    #[serde(default)]
+    overrides: BTreeMap<String, Override>,
+}
+
+fn settings_table(config: &Config) -> Option<toml::value::Table> {
+    let mut table = match toml::Value::try_from(config).ok()? {
+        toml::Value::Table(table) => table,
+        _ => return None,
+    };
+    for key in STATE_KEYS.iter() {
+        table.remove(*key);
+    }
+    Some(table)
+}
+
+fn overrides(file: &Config, config: &Config) -> BTreeMap<String, Override> {
+    let (file, config) = match (settings_table(file), settings_table(config)) {
+        (Some(file), Some(config)) => (file, config),
+        _ => return BTreeMap::new(),
+    };
+    file.keys()
+        .chain(config.keys())
+        .filter(|key| file.get(*key) != config.get(*key))
+        .map(|key| {
+            let item = Override {
+                base: file.get(key).cloned(),
+                value: config.get(key).cloned(),
+            };
+            (key.clone(), item)
+        })
+        .collect()
+}
+
+fn apply_overrides(file: Config, overrides: &BTreeMap<String, Override>) -> Config {
+    let mut table = match settings_table(&file) {
+        Some(table) => table,
+        None => return file,
+    };
+    let mut applied = false;
+    for (key, item) in overrides {
+        if table.get(key) != item.base.as_ref() {
+            debug!("state: {} was changed in the config file", key);
+            continue;
+        }
+        match item.value.as_ref() {
+            Some(value) => table.insert(key.clone(), value.clone()),
+            None => table.remove(key),
+        };
+        applied = true;
+    }
+    if !applied {
+        return file;
+    }
+    match toml::Value::Table(table).try_into::<Config>() {
+        Ok(mut config) => {
+            config.window = file.window;
+            config.last_path = file.last_path;
+            config
+        }
+        Err(e) => {
+            warn!("state: overrides: {}", e);
+            file
+        }
+    }
+}
+
+fn portable_dir() -> Option<PathBuf> {
+    let dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+    if dir.join("config.toml").is_file() || dir.join("config.json").is_file() {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = portable_dir() {
+        return dir;
+    }
+    match std::env::var_os("APPDATA") {
+        Some(appdata) => Path::new(&appdata).join("niv"),
+        None => PathBuf::from("."),
+    }
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+fn state_path() -> PathBuf {
+    config_dir().join("state.toml")
+}
+
+fn json_config_path() -> Option<PathBuf> {
+    [
+        config_dir().join("config.json"),
+        PathBuf::from(LEGACY_CONFIG_PATH),
+    ]
+    .iter()
+    .find(|path| path.is_file())
+    .cloned()
+}
+
+pub fn migrate_config() {
+    let path = config_path();
+    if path.is_file() {
+        return;
+    }
+    let json = match json_config_path() {
+        Some(json) => json,
+        None => {
+            if let Err(e) = write_config(&path, &Config::default()) {
+                warn!("config: {}: {}", path.to_string_lossy(), e);
+            }
+            return;
+        }
+    };
+    let config = match read_config(&json) {
+        Some(config) => config,
+        None => {
+            warn!(
+                "config: migration: {} could not be read",
+                json.to_string_lossy()
+            );
+            return;
+        }
+    };
+    match write_config(&path, &config) {
+        Ok(_) => info!(
+            "config: migrated {} to {}",
+            json.to_string_lossy(),
+            path.to_string_lossy()
+        ),
+        Err(e) => {
+            warn!("config: migration to {}: {}", path.to_string_lossy(), e);
+            return;
+        }
+    }
+    if !state_path().is_file() {
+        if let Err(e) = write_state(&config) {
+            warn!("config: migration: state: {}", e);
+        }
+    }
+}
+
+pub fn load_config() -> Option<Config> {
+    let path = config_path();
+    let mut config = if path.is_file() {
+        read_config(path)
+    } else {
+        read_config(json_config_path()?)
+    }?;
+    if let Some(state) = read_state() {
+        config = apply_overrides(config, &state.overrides);
+        config.window = state.window;
+        config.last_path = state.last_path;
+    }
+    Some(config)
+}
+
+fn read_state() -> Option<State> {
+    let path = state_path();
+    let text = std::fs::read_to_string(&path).ok()?;
+    match toml::from_str(&text) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            warn!("state: {}: {}", path.to_string_lossy(), e);
+            None
+        }
+    }
+}
+
+fn file_config() -> Config {
+    let path = config_path();
+    if path.is_file() {
+        return read_config(path).unwrap_or_default();
+    }
+    json_config_path().and_then(read_config).unwrap_or_default()
+}
+
+pub fn write_state(config: &Config) -> anyhow::Result<()> {
+    let state = State {
+        window: config.window.clone(),
+        last_path: config.last_path.clone(),
+        overrides: overrides(&file_config(), config),
+    };
+    std::fs::create_dir_all(config_dir())?;
+    std::fs::write(state_path(), toml::to_string_pretty(&state)?)?;
+    Ok(())
+}
+
+fn is_json(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => ext.to_string_lossy().eq_ignore_ascii_case("json"),
+        None => false,
+    }
+}
+
+pub fn read_config(path: impl AsRef<Path>) -> Option<Config> {
+    let path = path.as_ref();
+    if is_json(path) {
+        let file = File::open(path).ok()?;
+        let reader = BufReader::new(file);
+        return serde_json::from_reader(reader).ok()?;
+    }
+    let text = std::fs::read_to_string(path).ok()?;
+    match toml::from_str(&text) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            warn!("config: {}: {}", path.to_string_lossy(), e);
+            None
         }
     }
 }
 
-pub fn read_config(path: impl AsRef<str>) -> Option<Config> {
-    let file = File::open(path.as_ref()).ok()?;
-    let reader = BufReader::new(file);
-    serde_json::from_reader(reader).ok()?
+fn write_config(path: impl AsRef<Path>, config: &Config) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if is_json(path) {
+        let file = File::create(path)?;
+        let writer = BufWriter::new(file);
+        return Ok(serde_json::to_writer_pretty(writer, config)?);
+    }
+    let without_state = |mut value: toml::Value| {
+        if let Some(table) = value.as_table_mut() {
+            for key in STATE_KEYS.iter() {
+                table.remove(*key);
+            }
+        }
+        value
+    };
+    let value = without_state(toml::Value::try_from(config)?);
+    let prev = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| toml::from_str::<toml::Value>(&text).ok())
+        .and_then(|prev| prev.try_into::<Config>().ok())
+        .and_then(|prev| toml::Value::try_from(prev).ok())
+        .map(without_state);
+    if prev.as_ref() == Some(&value) {
+        return Ok(());
+    }
+    std::fs::write(path, toml::to_string_pretty(&value)?)?;
+    Ok(())
 }
 
-pub fn write_config(path: impl AsRef<str>, config: &Config) -> anyhow::Result<()> {
-    let file = File::create(path.as_ref())?;
-    let writer = BufWriter::new(file);
-    Ok(serde_json::to_writer_pretty(writer, config)?)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_apply_to_unchanged_file() {
+        let file = Config::default();
+        let mut runtime = file.clone();
+        runtime.slideshow_interval = file.slideshow_interval + 1000;
+        let overrides = overrides(&file, &runtime);
+        assert_eq!(overrides.len(), 1);
+        assert!(overrides.contains_key("slideshow_interval"));
+        let config = apply_overrides(file.clone(), &overrides);
+        assert_eq!(config.slideshow_interval, runtime.slideshow_interval);
+    }
+
+    #[test]
+    fn file_edits_win_over_overrides() {
+        let file = Config::default();
+        let mut runtime = file.clone();
+        runtime.slideshow_interval = file.slideshow_interval + 1000;
+        let overrides = overrides(&file, &runtime);
+        let mut edited = file.clone();
+        edited.slideshow_interval = file.slideshow_interval + 5000;
+        let config = apply_overrides(edited.clone(), &overrides);
+        assert_eq!(config.slideshow_interval, edited.slideshow_interval);
+    }
+
+    #[test]
+    fn overrides_keep_window_state_out() {
+        let file = Config::default();
+        let mut runtime = file.clone();
+        runtime.window.x += 100;
+        runtime.last_path = Some(PathBuf::from("a.png"));
+        assert!(overrides(&file, &runtime).is_empty());
+    }
+
+    fn with_profile(name: &str, overlay: &str) -> Config {
+        let mut config = Config::default();
+        config
+            .profiles
+            .insert(name.into(), toml::from_str(overlay).unwrap());
+        config
+    }
+
+    #[test]
+    fn profile_overlays_base() {
+        let base = with_profile("fast", "slideshow_interval = 500");
+        let config = base.layered(Some("fast"));
+        assert_eq!(config.slideshow_interval, 500);
+        assert_eq!(config.lookahead_forward, base.lookahead_forward);
+        assert_eq!(config.profiles.len(), 1);
+    }
+
+    #[test]
+    fn unknown_profile_keeps_base() {
+        let base = with_profile("fast", "slideshow_interval = 500");
+        assert_eq!(
+            base.layered(Some("slow")).slideshow_interval,
+            base.slideshow_interval
+        );
+        assert_eq!(
+            base.layered(None).slideshow_interval,
+            base.slideshow_interval
+        );
+    }
+
+    #[test]
+    fn unlayered_restores_profile_keys_only() {
+        let base = with_profile("fast", "slideshow_interval = 500");
+        let mut runtime = base.layered(Some("fast"));
+        runtime.lookahead_forward = base.lookahead_forward + 3;
+        let config = runtime.unlayered(&base, Some("fast"));
+        assert_eq!(config.slideshow_interval, base.slideshow_interval);
+        assert_eq!(config.lookahead_forward, base.lookahead_forward + 3);
+    }
+
+    #[test]
+    fn json_config_migrates_to_toml() {
+        let dir = std::env::temp_dir().join(format!("niv-config-test-{}", std::process::id()));
+        let json = dir.join("config.json");
+        let toml_path = dir.join("config.toml");
+        let config = Config {
+            slideshow_interval: 1234,
+            last_path: Some(PathBuf::from("a.png")),
+            ..Config::default()
+        };
+        write_config(&json, &config).unwrap();
+        let migrated = read_config(&json).unwrap();
+        assert_eq!(migrated.slideshow_interval, 1234);
+        write_config(&toml_path, &migrated).unwrap();
+        let text = std::fs::read_to_string(&toml_path).unwrap();
+        let read = read_config(&toml_path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(read.slideshow_interval, 1234);
+        assert!(read.last_path.is_none());
+        assert!(!text.contains("last_path"));
+    }
 }
@@ -0,0 +1,357 @@
+use crate::animation;
+use crate::annotation::Shape;
+use crate::config::Method;
+use crate::convert::OutputFormat;
+use crate::directory::DirEntryInfo;
+use crate::gamepad::GamepadEvent;
+use crate::replay::InputEvent;
+use crate::stats::FolderStats;
+use crate::stereo::StereoMode;
+use crate::touch::TouchEvent;
+use image::RgbaImage;
+use log::{debug, error};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::winerror::*;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::fileapi::ReadFile;
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::minwinbase::SECURITY_ATTRIBUTES;
+use winapi::um::namedpipeapi::*;
+use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+use winapi::um::securitybaseapi::*;
+use winapi::um::winbase::*;
+use winapi::um::winnt::*;
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    Open(PathBuf),
+    Method(Method),
+    Resize(u32, u32),
+    Close,
+    Input(InputEvent),
+    Notify(String),
+    ReloadConfig,
+    Touch(TouchEvent),
+    Gamepad(GamepadEvent),
+    MediaKey(wita::VirtualKey),
+    MemoryPressure(bool),
+    DisplayChanged,
+    Duplicates(Vec<Vec<PathBuf>>),
+    Scanned(u64, Vec<(PathBuf, DirEntryInfo)>, bool),
+    PanoramaDetected(PathBuf),
+    Panorama(PathBuf, RgbaImage),
+    Imported(PathBuf, Vec<PathBuf>),
+    Dimensions(PathBuf, Vec<(PathBuf, Option<(u32, u32)>)>),
+    CopyImage(RgbaImage, bool),
+    Annotated(PathBuf, OutputFormat, RgbaImage, Vec<Shape>, f32),
+    CompareImage(PathBuf, RgbaImage),
+    Stereo(PathBuf, StereoMode, RgbaImage),
+    AnimationProbed(PathBuf, animation::Info),
+    FolderStats(FolderStats, Option<(PathBuf, bool)>),
+}
+
+impl Command {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        let (name, arg) = match line.find(' ') {
+            Some(i) => (&line[..i], line[i + 1..].trim()),
+            None => (line, ""),
+        };
+        match name {
+            "open" if !arg.is_empty() => Some(Command::Open(arg.into())),
+            "method" => serde_json::from_value(serde_json::Value::String(arg.into()))
+                .ok()
+                .map(Command::Method),
+            "resize" => {
+                let mut size = arg.split_whitespace().map(|v| v.parse::<u32>());
+                match (size.next(), size.next()) {
+                    (Some(Ok(width)), Some(Ok(height))) => Some(Command::Resize(width, height)),
+                    _ => None,
+                }
+            }
+            "close" => Some(Command::Close),
+            _ => None,
+        }
+    }
+
+    fn to_line(&self) -> Option<String> {
+        match self {
+            Command::Open(path) => Some(format!("open {}", path.to_string_lossy())),
+            Command::Method(method) => match serde_json::to_value(method) {
+                Ok(serde_json::Value::String(name)) => Some(format!("method {}", name)),
+                _ => None,
+            },
+            Command::Resize(width, height) => Some(format!("resize {} {}", width, height)),
+            Command::Close => Some("close".into()),
+            _ => None,
+        }
+    }
+}
+
+pub struct ControlClient {
+    name: String,
+    pipe: Option<File>,
+}
+
+impl ControlClient {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: format!(r"\\.\pipe\{}", name),
+            pipe: None,
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.pipe.is_none() {
+            self.pipe = Some(std::fs::OpenOptions::new().write(true).open(&self.name)?);
+        }
+        let ret = writeln!(self.pipe.as_mut().unwrap(), "{}", line);
+        if ret.is_err() {
+            self.pipe = None;
+        }
+        ret
+    }
+
+    pub fn send(&mut self, command: &Command) {
+        let line = match command.to_line() {
+            Some(line) => line,
+            None => return,
+        };
+        debug!("control client: {}", line);
+        let ret = self.write_line(&line).or_else(|_| self.write_line(&line));
+        if let Err(e) = ret {
+            error!("control client: {}: {}", self.name, e);
+        }
+    }
+}
+
+struct Security {
+    _user: Vec<u64>,
+    _acl: Vec<u64>,
+    descriptor: Box<SECURITY_DESCRIPTOR>,
+}
+
+impl Security {
+    fn current_user() -> Option<Self> {
+        unsafe {
+            let mut token = std::ptr::null_mut();
+            if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+                return None;
+            }
+            let mut len = 0;
+            GetTokenInformation(token, TokenUser, std::ptr::null_mut(), 0, &mut len);
+            let mut user = vec![0u64; (len as usize).div_ceil(8)];
+            let ret = GetTokenInformation(token, TokenUser, user.as_mut_ptr() as _, len, &mut len);
+            CloseHandle(token);
+            if ret == 0 {
+                return None;
+            }
+            let sid = (*(user.as_ptr() as *const TOKEN_USER)).User.Sid;
+            let acl_len = std::mem::size_of::<ACL>()
+                + std::mem::size_of::<ACCESS_ALLOWED_ACE>()
+                + GetLengthSid(sid) as usize;
+            let mut acl = vec![0u64; acl_len.div_ceil(8)];
+            let acl_ptr = acl.as_mut_ptr() as *mut ACL;
+            if InitializeAcl(acl_ptr, (acl.len() * 8) as u32, ACL_REVISION as u32) == 0
+                || AddAccessAllowedAce(acl_ptr, ACL_REVISION as u32, GENERIC_ALL, sid) == 0
+            {
+                return None;
+            }
+            let mut descriptor = Box::new(std::mem::zeroed::<SECURITY_DESCRIPTOR>());
+            if InitializeSecurityDescriptor(
+                descriptor.as_mut() as *mut _ as _,
+                SECURITY_DESCRIPTOR_REVISION,
+            ) == 0
+                || SetSecurityDescriptorDacl(descriptor.as_mut() as *mut _ as _, 1, acl_ptr, FALSE)
+                    == 0
+            {
+                return None;
+            }
+            Some(Self {
+                _user: user,
+                _acl: acl,
+                descriptor,
+            })
+        }
+    }
+}
+
+struct Pipe(HANDLE);
+
+impl Pipe {
+    fn accept(name: &[u16], security: &mut Security) -> Option<Self> {
+        unsafe {
+            let mut attributes = SECURITY_ATTRIBUTES {
+                nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+                lpSecurityDescriptor: security.descriptor.as_mut() as *mut _ as _,
+                bInheritHandle: FALSE,
+            };
+            let handle = CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_INBOUND | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                0,
+                4096,
+                0,
+                &mut attributes,
+            );
+            if handle == INVALID_HANDLE_VALUE {
+                return None;
+            }
+            let pipe = Pipe(handle);
+            if ConnectNamedPipe(handle, std::ptr::null_mut()) == 0
+                && GetLastError() != ERROR_PIPE_CONNECTED
+            {
+                return None;
+            }
+            Some(pipe)
+        }
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        unsafe {
+            let mut len = 0;
+            let ret = ReadFile(
+                self.0,
+                buf.as_mut_ptr() as _,
+                buf.len() as u32,
+                &mut len,
+                std::ptr::null_mut(),
+            );
+            if ret == 0 {
+                if GetLastError() == ERROR_BROKEN_PIPE {
+                    return Ok(0);
+                }
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(len as usize)
+        }
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            DisconnectNamedPipe(self.0);
+            CloseHandle(self.0);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CommandQueue {
+    commands: Arc<Mutex<VecDeque<Command>>>,
+    wnd: wita::Window,
+}
+
+impl CommandQueue {
+    pub fn new(wnd: wita::Window) -> Self {
+        Self {
+            commands: Arc::new(Mutex::new(VecDeque::new())),
+            wnd,
+        }
+    }
+
+    pub fn push(&self, command: Command) {
+        self.commands.lock().unwrap().push_back(command);
+        self.wnd.redraw();
+    }
+
+    pub fn take(&self) -> Vec<Command> {
+        self.commands.lock().unwrap().drain(..).collect()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CommandQueues(Arc<Mutex<Vec<CommandQueue>>>);
+
+impl CommandQueues {
+    pub fn insert(&self, queue: CommandQueue) {
+        self.0.lock().unwrap().push(queue);
+    }
+
+    pub fn remove(&self, wnd: &wita::Window) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|queue| queue.wnd.raw_handle() != wnd.raw_handle());
+    }
+
+    pub fn find(&self, hwnd: usize) -> Option<CommandQueue> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|queue| queue.wnd.raw_handle() as usize == hwnd)
+            .cloned()
+    }
+
+    pub fn push(&self, command: Command) {
+        for queue in self.0.lock().unwrap().iter() {
+            queue.push(command.clone());
+        }
+    }
+}
+
+pub fn open_control_pipe(name: &str, queue: CommandQueue) {
+    let pipe_name = format!(r"\\.\pipe\{}", name)
+        .encode_utf16()
+        .chain(Some(0))
+        .collect::<Vec<_>>();
+    std::thread::spawn(move || {
+        let mut security = match Security::current_user() {
+            Some(security) => security,
+            None => {
+                error!("control pipe: {}", std::io::Error::last_os_error());
+                return;
+            }
+        };
+        loop {
+            let pipe = match Pipe::accept(&pipe_name, &mut security) {
+                Some(pipe) => pipe,
+                None => {
+                    error!("control pipe: {}", std::io::Error::last_os_error());
+                    return;
+                }
+            };
+            for line in BufReader::new(pipe).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                match Command::parse(&line) {
+                    Some(command) => {
+                        debug!("control pipe: {:?}", command);
+                        queue.push(command);
+                    }
+                    None => error!("control pipe: unknown command: {}", line),
+                }
+            }
+        }
+    });
+}
+
+pub fn watch_config(path: impl Into<PathBuf>, queues: CommandQueues) {
+    let path = path.into();
+    let modified = |path: &PathBuf| path.metadata().and_then(|meta| meta.modified()).ok();
+    std::thread::spawn(move || {
+        let mut prev = modified(&path);
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            let current = modified(&path);
+            if current.is_some() && current != prev {
+                debug!("config changed: {}", path.to_string_lossy());
+                queues.push(Command::ReloadConfig);
+            }
+            prev = current;
+        }
+    });
+}
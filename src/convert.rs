@@ -0,0 +1,164 @@
+use crate::args::ConvertArgs;
+use crate::config::load_config;
+use crate::directory::Directory;
+use crate::error::Error;
+use crate::images::open_image;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, DynamicImage, RgbaImage};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 3] = [OutputFormat::Png, OutputFormat::Jpeg, OutputFormat::WebP];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Png => "PNG",
+            Self::Jpeg => "JPEG",
+            Self::WebP => "WebP",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::WebP => "webp",
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "webp" => Some(Self::WebP),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncodeOptions {
+    pub jpeg_quality: u8,
+    pub webp_quality: f32,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            jpeg_quality: 90,
+            webp_quality: 85.0,
+        }
+    }
+}
+
+pub fn save_image(
+    img: &RgbaImage,
+    path: impl AsRef<Path>,
+    format: OutputFormat,
+    options: &EncodeOptions,
+) -> Result<(), Error> {
+    let (width, height) = img.dimensions();
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        OutputFormat::Png => {
+            PngEncoder::new(&mut writer).encode(img.as_raw(), width, height, ColorType::Rgba8)?;
+        }
+        OutputFormat::Jpeg => {
+            let rgb = DynamicImage::ImageRgba8(img.clone()).to_rgb8();
+            JpegEncoder::new_with_quality(&mut writer, options.jpeg_quality).encode(
+                rgb.as_raw(),
+                width,
+                height,
+                ColorType::Rgb8,
+            )?;
+        }
+        OutputFormat::WebP => {
+            let data =
+                webp::Encoder::from_rgba(img.as_raw(), width, height).encode(options.webp_quality);
+            writer.write_all(&data)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn run_batch(args: ConvertArgs) -> anyhow::Result<()> {
+    if !args.dir.is_dir() {
+        anyhow::bail!("not a directory: {}", args.dir.to_string_lossy());
+    }
+    let mut config = load_config().unwrap_or_default();
+    config.group_pairs = false;
+    let mut options = config.encode.clone();
+    if let Some(quality) = args.quality {
+        options.jpeg_quality = quality.clamp(0.0, 100.0) as u8;
+        options.webp_quality = quality.clamp(0.0, 100.0);
+    }
+    let out_dir = args
+        .out
+        .clone()
+        .unwrap_or_else(|| args.dir.join("converted"));
+    std::fs::create_dir_all(&out_dir)?;
+    let dir = Directory::new(&args.dir, &config, None::<&Path>);
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.worker_threads.max(1))
+        .build()?;
+    let options = Arc::new(options);
+    let failed = Arc::new(AtomicUsize::new(0));
+    let total = dir.paths().len();
+    runtime.block_on(async {
+        let tasks = dir
+            .paths()
+            .iter()
+            .cloned()
+            .map(|src| {
+                let mut dest = out_dir.join(src.file_stem().unwrap_or_default());
+                dest.set_extension(args.to.extension());
+                let options = options.clone();
+                let failed = failed.clone();
+                let format = args.to;
+                let overwrite = args.overwrite;
+                tokio::spawn(async move {
+                    if dest.exists() && !overwrite {
+                        info!("skip: {}", dest.to_string_lossy());
+                        return;
+                    }
+                    let ret =
+                        open_image(&src).and_then(|img| save_image(&img, &dest, format, &options));
+                    match ret {
+                        Ok(_) => info!("{} -> {}", src.to_string_lossy(), dest.to_string_lossy()),
+                        Err(e) => {
+                            error!("{}: {}", src.to_string_lossy(), e);
+                            failed.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            if let Err(e) = task.await {
+                error!("{}", e);
+                failed.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    });
+    let failed = failed.load(Ordering::SeqCst);
+    info!("converted {}/{} files", total - failed, total);
+    if failed > 0 {
+        anyhow::bail!("{} files failed to convert", failed);
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Handle {
+    TopLeft,
+    Top,
+    TopRight,
+    Right,
+    BottomRight,
+    Bottom,
+    BottomLeft,
+    Left,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Region {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Region {
+    fn normalized(&self) -> Self {
+        Self {
+            left: self.left.min(self.right),
+            top: self.top.min(self.bottom),
+            right: self.left.max(self.right),
+            bottom: self.top.max(self.bottom),
+        }
+    }
+
+    fn contains(&self, x: f32, y: f32) -> bool {
+        self.left <= x && x <= self.right && self.top <= y && y <= self.bottom
+    }
+
+    pub fn handles(&self) -> [(Handle, f32, f32); 8] {
+        let cx = (self.left + self.right) / 2.0;
+        let cy = (self.top + self.bottom) / 2.0;
+        [
+            (Handle::TopLeft, self.left, self.top),
+            (Handle::Top, cx, self.top),
+            (Handle::TopRight, self.right, self.top),
+            (Handle::Right, self.right, cy),
+            (Handle::BottomRight, self.right, self.bottom),
+            (Handle::Bottom, cx, self.bottom),
+            (Handle::BottomLeft, self.left, self.bottom),
+            (Handle::Left, self.left, cy),
+        ]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Drag {
+    New,
+    Move(f32, f32),
+    Resize(Handle),
+}
+
+#[derive(Clone, Debug)]
+pub struct Crop {
+    path: PathBuf,
+    width: f32,
+    height: f32,
+    region: Option<Region>,
+    drag: Option<Drag>,
+}
+
+impl Crop {
+    pub fn new(path: impl AsRef<Path>, width: u32, height: u32) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            width: width as f32,
+            height: height as f32,
+            region: None,
+            drag: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn image_size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    pub fn region(&self) -> Option<Region> {
+        self.region.map(|r| r.normalized())
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    fn clamp(&self, x: f32, y: f32) -> (f32, f32) {
+        (x.max(0.0).min(self.width), y.max(0.0).min(self.height))
+    }
+
+    pub fn begin(&mut self, x: f32, y: f32, handle_radius: f32) {
+        let (x, y) = self.clamp(x, y);
+        if let Some(region) = self.region() {
+            let handle = region
+                .handles()
+                .iter()
+                .find(|(_, hx, hy)| {
+                    (x - hx).abs() <= handle_radius && (y - hy).abs() <= handle_radius
+                })
+                .map(|(handle, _, _)| *handle);
+            if let Some(handle) = handle {
+                self.region = Some(region);
+                self.drag = Some(Drag::Resize(handle));
+                return;
+            }
+            if region.contains(x, y) {
+                self.region = Some(region);
+                self.drag = Some(Drag::Move(x - region.left, y - region.top));
+                return;
+            }
+        }
+        self.region = Some(Region {
+            left: x,
+            top: y,
+            right: x,
+            bottom: y,
+        });
+        self.drag = Some(Drag::New);
+    }
+
+    pub fn update(&mut self, x: f32, y: f32) {
+        let (x, y) = self.clamp(x, y);
+        let (region, drag) = match (self.region.as_mut(), self.drag) {
+            (Some(region), Some(drag)) => (region, drag),
+            _ => return,
+        };
+        match drag {
+            Drag::New => {
+                region.right = x;
+                region.bottom = y;
+            }
+            Drag::Move(dx, dy) => {
+                let w = region.right - region.left;
+                let h = region.bottom - region.top;
+                let left = (x - dx).max(0.0).min(self.width - w);
+                let top = (y - dy).max(0.0).min(self.height - h);
+                *region = Region {
+                    left,
+                    top,
+                    right: left + w,
+                    bottom: top + h,
+                };
+            }
+            Drag::Resize(handle) => {
+                match handle {
+                    Handle::TopLeft | Handle::Left | Handle::BottomLeft => region.left = x,
+                    Handle::TopRight | Handle::Right | Handle::BottomRight => region.right = x,
+                    _ => {}
+                }
+                match handle {
+                    Handle::TopLeft | Handle::Top | Handle::TopRight => region.top = y,
+                    Handle::BottomLeft | Handle::Bottom | Handle::BottomRight => region.bottom = y,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub fn end(&mut self) {
+        self.drag = None;
+        self.region = self
+            .region()
+            .filter(|r| r.right - r.left >= 1.0 && r.bottom - r.top >= 1.0);
+    }
+
+    pub fn pixel_rect(&self) -> Option<(u32, u32, u32, u32)> {
+        self.region().and_then(|r| {
+            let left = r.left.round() as u32;
+            let top = r.top.round() as u32;
+            let width = r.right.round() as u32 - left;
+            let height = r.bottom.round() as u32 - top;
+            if width == 0 || height == 0 {
+                None
+            } else {
+                Some((left, top, width, height))
+            }
+        })
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+use winapi::shared::minwindef::{FALSE, TRUE};
+use winapi::shared::windef::{HWND, POINT, RECT};
+use winapi::um::winuser::{GetClientRect, GetCursorPos, ScreenToClient, ShowCursor};
+
+fn in_client_area(wnd: &wita::Window) -> bool {
+    unsafe {
+        let hwnd = wnd.raw_handle() as HWND;
+        let mut pt = POINT::default();
+        let mut rc = RECT::default();
+        if GetCursorPos(&mut pt) == 0 || GetClientRect(hwnd, &mut rc) == 0 {
+            return false;
+        }
+        ScreenToClient(hwnd, &mut pt);
+        pt.x >= rc.left && pt.x < rc.right && pt.y >= rc.top && pt.y < rc.bottom
+    }
+}
+
+pub fn client_position(wnd: &wita::Window) -> wita::PhysicalPosition<i32> {
+    unsafe {
+        let mut pt = POINT::default();
+        GetCursorPos(&mut pt);
+        ScreenToClient(wnd.raw_handle() as HWND, &mut pt);
+        wita::PhysicalPosition::new(pt.x, pt.y)
+    }
+}
+
+pub struct IdleCursor {
+    hidden: bool,
+    moved_at: Instant,
+    position: Option<(i32, i32)>,
+}
+
+impl IdleCursor {
+    pub fn new() -> Self {
+        Self {
+            hidden: false,
+            moved_at: Instant::now(),
+            position: None,
+        }
+    }
+
+    pub fn moved(&mut self, position: wita::PhysicalPosition<i32>) -> bool {
+        let position = (position.x, position.y);
+        if self.position == Some(position) {
+            return false;
+        }
+        self.position = Some(position);
+        self.moved_at = Instant::now();
+        self.show(true);
+        true
+    }
+
+    pub fn show(&mut self, show: bool) {
+        if self.hidden != show {
+            return;
+        }
+        self.hidden = !show;
+        unsafe {
+            ShowCursor(if show { TRUE } else { FALSE });
+        }
+    }
+
+    pub fn update(&mut self, wnd: &wita::Window, timeout: Option<Duration>) {
+        match timeout {
+            Some(timeout) if self.moved_at.elapsed() >= timeout => {
+                if in_client_area(wnd) {
+                    self.show(false);
+                }
+            }
+            Some(_) => {}
+            None => self.show(true),
+        }
+    }
+
+    pub fn delay(&self, timeout: Option<Duration>) -> Option<Duration> {
+        if self.hidden {
+            return None;
+        }
+        timeout.map(|timeout| timeout.saturating_sub(self.moved_at.elapsed()))
+    }
+}
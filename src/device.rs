@@ -0,0 +1,555 @@
+use crate::error::Error;
+use com_ptr::*;
+use log::{debug, error, info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use winapi::shared::cfg::PNP_VETO_TYPE;
+use winapi::shared::guiddef::{IsEqualGUID, GUID};
+use winapi::shared::winerror::*;
+use winapi::shared::wtypes::PROPERTYKEY;
+use winapi::shared::wtypesbase::CLSCTX_INPROC_SERVER;
+use winapi::um::cfgmgr32::*;
+use winapi::um::combaseapi::*;
+use winapi::um::coml2api::STGM_READ;
+use winapi::um::objbase::COINIT_MULTITHREADED;
+use winapi::um::portabledevice::*;
+use winapi::um::portabledeviceapi::*;
+use winapi::um::portabledevicetypes::*;
+use winapi::um::winnt::LPWSTR;
+
+const WPD_RESOURCE_DEFAULT: PROPERTYKEY = PROPERTYKEY {
+    fmtid: GUID {
+        Data1: 0xe81e79be,
+        Data2: 0x34f0,
+        Data3: 0x41bf,
+        Data4: [0xb5, 0x3f, 0xf1, 0xa0, 0x6a, 0xe8, 0x78, 0x42],
+    },
+    pid: 0,
+};
+
+const WPD_OBJECT_CONTENT_TYPE: PROPERTYKEY = PROPERTYKEY {
+    fmtid: GUID {
+        Data1: 0xef6b490d,
+        Data2: 0x5cd8,
+        Data3: 0x437a,
+        Data4: [0xaf, 0xfc, 0xda, 0x8b, 0x60, 0xee, 0x4a, 0x3c],
+    },
+    pid: 7,
+};
+
+const WPD_CONTENT_TYPE_FOLDER: GUID = GUID {
+    Data1: 0x27e2e392,
+    Data2: 0xa111,
+    Data3: 0x48e0,
+    Data4: [0xab, 0x0c, 0xe1, 0x77, 0x05, 0xa0, 0x5f, 0x85],
+};
+
+const WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT: GUID = GUID {
+    Data1: 0x99ed0160,
+    Data2: 0x17ff,
+    Data3: 0x4c44,
+    Data4: [0x9d, 0x98, 0x1d, 0x7a, 0x6f, 0x94, 0x19, 0x21],
+};
+
+const MAX_DEPTH: usize = 4;
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect::<Vec<_>>()
+}
+
+unsafe fn take_string(p: LPWSTR) -> String {
+    if p.is_null() {
+        return String::new();
+    }
+    let len = (0..isize::MAX).position(|i| *p.offset(i) == 0).unwrap();
+    let s = String::from_utf16_lossy(std::slice::from_raw_parts(p, len));
+    CoTaskMemFree(p as _);
+    s
+}
+
+fn check(ret: HRESULT) -> Result<(), Error> {
+    if ret == S_OK {
+        Ok(())
+    } else {
+        Err(HResult(ret).into())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct DeviceFile {
+    device_id: String,
+    device_name: String,
+    object_id: String,
+    size: Option<u64>,
+    verify: bool,
+}
+
+static FILES: Mutex<Option<HashMap<PathBuf, DeviceFile>>> = Mutex::new(None);
+static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub struct Browsed {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+}
+
+struct Object {
+    name: String,
+    file_name: String,
+    size: Option<u64>,
+    folder: bool,
+}
+
+fn file_hash(path: &Path) -> Result<u64, Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        hasher.write(&buffer[..len]);
+    }
+    Ok(hasher.finish())
+}
+
+fn is_image(file_name: &str, extensions: &[String]) -> bool {
+    match Path::new(file_name).extension() {
+        Some(ext) => extensions
+            .iter()
+            .any(|e| ext.to_string_lossy().eq_ignore_ascii_case(e)),
+        None => false,
+    }
+}
+
+fn sanitize(name: &str) -> String {
+    name.replace(|c: char| "\\/:*?\"<>|".contains(c), "_")
+}
+
+struct Device {
+    name: String,
+    content: ComPtr<IPortableDeviceContent>,
+    properties: ComPtr<IPortableDeviceProperties>,
+    resources: ComPtr<IPortableDeviceResources>,
+    keys: ComPtr<IPortableDeviceKeyCollection>,
+}
+
+impl Device {
+    unsafe fn open(manager: &ComPtr<IPortableDeviceManager>, id: &[u16]) -> Result<Self, Error> {
+        let name = {
+            let mut len = 0;
+            manager.GetDeviceFriendlyName(id.as_ptr(), std::ptr::null_mut(), &mut len);
+            let mut buffer = vec![0u16; len as usize];
+            manager.GetDeviceFriendlyName(id.as_ptr(), buffer.as_mut_ptr(), &mut len);
+            String::from_utf16_lossy(&buffer)
+                .trim_end_matches('\0')
+                .to_string()
+        };
+        let device = co_create_instance::<IPortableDevice>(
+            &CLSID_PortableDeviceFTM,
+            None,
+            CLSCTX_INPROC_SERVER,
+        )?;
+        let client_info = co_create_instance::<IPortableDeviceValues>(
+            &CLSID_PortableDeviceValues,
+            None,
+            CLSCTX_INPROC_SERVER,
+        )?;
+        check(device.Open(id.as_ptr(), client_info.as_ptr()))?;
+        let content = ComPtr::new(|| {
+            let mut obj = std::ptr::null_mut();
+            let ret = device.Content(&mut obj);
+            hresult(obj, ret)
+        })?;
+        let properties = ComPtr::new(|| {
+            let mut obj = std::ptr::null_mut();
+            let ret = content.Properties(&mut obj);
+            hresult(obj, ret)
+        })?;
+        let resources = ComPtr::new(|| {
+            let mut obj = std::ptr::null_mut();
+            let ret = content.Transfer(&mut obj);
+            hresult(obj, ret)
+        })?;
+        let keys = co_create_instance::<IPortableDeviceKeyCollection>(
+            &CLSID_PortableDeviceKeyCollection,
+            None,
+            CLSCTX_INPROC_SERVER,
+        )?;
+        keys.Add(&WPD_OBJECT_NAME);
+        keys.Add(&WPD_OBJECT_ORIGINAL_FILE_NAME);
+        keys.Add(&WPD_OBJECT_SIZE);
+        keys.Add(&WPD_OBJECT_CONTENT_TYPE);
+        Ok(Self {
+            name,
+            content,
+            properties,
+            resources,
+            keys,
+        })
+    }
+
+    unsafe fn children(&self, parent: &[u16]) -> Result<Vec<Vec<u16>>, Error> {
+        let objects = ComPtr::new(|| {
+            let mut obj = std::ptr::null_mut();
+            let ret = self
+                .content
+                .EnumObjects(0, parent.as_ptr(), std::ptr::null_mut(), &mut obj);
+            hresult(obj, ret)
+        })?;
+        let mut children = vec![];
+        loop {
+            let mut ids = [std::ptr::null_mut(); 16];
+            let mut fetched = 0;
+            let ret = objects.Next(ids.len() as _, ids.as_mut_ptr(), &mut fetched);
+            if ret != S_OK && ret != S_FALSE {
+                return Err(HResult(ret).into());
+            }
+            if fetched == 0 {
+                break;
+            }
+            children.extend(
+                ids[..fetched as usize]
+                    .iter()
+                    .map(|id| to_wide(&take_string(*id))),
+            );
+        }
+        Ok(children)
+    }
+
+    unsafe fn object(&self, id: &[u16]) -> Result<Object, Error> {
+        let values = ComPtr::new(|| {
+            let mut obj = std::ptr::null_mut();
+            let ret = self
+                .properties
+                .GetValues(id.as_ptr(), self.keys.as_ptr(), &mut obj);
+            hresult(obj, ret)
+        })?;
+        let mut name = std::ptr::null_mut();
+        values.GetStringValue(&WPD_OBJECT_NAME, &mut name);
+        let mut file_name = std::ptr::null_mut();
+        values.GetStringValue(&WPD_OBJECT_ORIGINAL_FILE_NAME, &mut file_name);
+        let mut size = 0;
+        let size = match values.GetUnsignedLargeIntegerValue(&WPD_OBJECT_SIZE, &mut size) {
+            S_OK => Some(size),
+            _ => None,
+        };
+        let mut content_type = GUID::default();
+        let folder = values.GetGuidValue(&WPD_OBJECT_CONTENT_TYPE, &mut content_type) == S_OK
+            && (IsEqualGUID(&content_type, &WPD_CONTENT_TYPE_FOLDER)
+                || IsEqualGUID(&content_type, &WPD_CONTENT_TYPE_FUNCTIONAL_OBJECT));
+        Ok(Object {
+            name: take_string(name),
+            file_name: take_string(file_name),
+            size,
+            folder,
+        })
+    }
+
+    unsafe fn objects(&self, parent: &[u16]) -> Vec<(Vec<u16>, Object)> {
+        let children = match self.children(parent) {
+            Ok(children) => children,
+            Err(e) => {
+                error!("import: {}: {}", self.name, e);
+                return vec![];
+            }
+        };
+        children
+            .into_iter()
+            .filter_map(|child| match self.object(&child) {
+                Ok(object) => Some((child, object)),
+                Err(e) => {
+                    warn!(
+                        "import: {}: {}: {}",
+                        self.name,
+                        String::from_utf16_lossy(&child[..child.len() - 1]),
+                        e
+                    );
+                    None
+                }
+            })
+            .collect()
+    }
+
+    unsafe fn download(&self, id: &[u16], dest: &Path) -> Result<(u64, u64), Error> {
+        let mut buffer_size = 0;
+        let stream = ComPtr::new(|| {
+            let mut obj = std::ptr::null_mut();
+            let ret = self.resources.GetStream(
+                id.as_ptr(),
+                &WPD_RESOURCE_DEFAULT,
+                STGM_READ,
+                &mut buffer_size,
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })?;
+        let mut buffer = vec![0u8; buffer_size.max(64 * 1024) as usize];
+        let mut file = File::create(dest)?;
+        let mut hasher = DefaultHasher::new();
+        let mut total = 0;
+        loop {
+            let mut read = 0;
+            let ret = stream.Read(buffer.as_mut_ptr() as _, buffer.len() as _, &mut read);
+            if ret != S_OK && ret != S_FALSE {
+                return Err(HResult(ret).into());
+            }
+            if read == 0 {
+                break;
+            }
+            hasher.write(&buffer[..read as usize]);
+            file.write_all(&buffer[..read as usize])?;
+            total += read as u64;
+        }
+        Ok((total, hasher.finish()))
+    }
+
+    unsafe fn find_dcim(&self, parent: &[u16], depth: usize) -> Vec<Vec<u16>> {
+        if depth > MAX_DEPTH {
+            return vec![];
+        }
+        let mut found = vec![];
+        for (child, object) in self.objects(parent) {
+            if !object.folder {
+                continue;
+            }
+            if object.name.eq_ignore_ascii_case("DCIM") {
+                found.push(child);
+            } else {
+                found.extend(self.find_dcim(&child, depth + 1));
+            }
+        }
+        found
+    }
+
+    unsafe fn browse(
+        &self,
+        parent: &[u16],
+        dir: &Path,
+        extensions: &[String],
+        depth: usize,
+        files: &mut Vec<(PathBuf, Vec<u16>, Option<u64>)>,
+    ) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        for (child, object) in self.objects(parent) {
+            if object.folder {
+                self.browse(
+                    &child,
+                    &dir.join(sanitize(&object.name)),
+                    extensions,
+                    depth + 1,
+                    files,
+                );
+                continue;
+            }
+            let file_name = if object.file_name.is_empty() {
+                object.name
+            } else {
+                object.file_name
+            };
+            if is_image(&file_name, extensions) {
+                files.push((dir.join(sanitize(&file_name)), child, object.size));
+            }
+        }
+    }
+}
+
+unsafe fn open_manager() -> Result<ComPtr<IPortableDeviceManager>, Error> {
+    Ok(co_create_instance::<IPortableDeviceManager>(
+        &CLSID_PortableDeviceManager,
+        None,
+        CLSCTX_INPROC_SERVER,
+    )?)
+}
+
+unsafe fn browse_impl(
+    staging: &Path,
+    extensions: &[String],
+    verify: bool,
+) -> Result<Vec<Browsed>, Error> {
+    let manager = open_manager()?;
+    let mut len = 0;
+    check(manager.GetDevices(std::ptr::null_mut(), &mut len))?;
+    let mut ids = vec![std::ptr::null_mut(); len as usize];
+    if len > 0 {
+        check(manager.GetDevices(ids.as_mut_ptr(), &mut len))?;
+    }
+    let ids = ids
+        .into_iter()
+        .map(|id| to_wide(&take_string(id)))
+        .collect::<Vec<_>>();
+    let mut results = vec![];
+    for id in ids {
+        let device = match Device::open(&manager, &id) {
+            Ok(device) => device,
+            Err(e) => {
+                error!("import: open device: {}", e);
+                continue;
+            }
+        };
+        let device_id = String::from_utf16_lossy(&id[..id.len() - 1]);
+        let dest = staging.join(sanitize(&device.name));
+        std::fs::remove_dir_all(&dest).ok();
+        let mut files = vec![];
+        for dcim in device.find_dcim(&to_wide(WPD_DEVICE_OBJECT_ID), 0) {
+            device.browse(&dcim, &dest, extensions, 0, &mut files);
+        }
+        info!("import: {}: {} files", device.name, files.len());
+        let mut registry = FILES.lock().unwrap();
+        let registry = registry.get_or_insert_with(HashMap::new);
+        registry.retain(|_, file| file.device_id != device_id);
+        let mut paths = vec![];
+        for (path, object_id, size) in files {
+            registry.insert(
+                path.clone(),
+                DeviceFile {
+                    device_id: device_id.clone(),
+                    device_name: device.name.clone(),
+                    object_id: String::from_utf16_lossy(&object_id[..object_id.len() - 1]),
+                    size,
+                    verify,
+                },
+            );
+            paths.push(path);
+        }
+        results.push(Browsed {
+            name: device.name.clone(),
+            paths,
+        });
+    }
+    Ok(results)
+}
+
+pub fn browse_devices(
+    staging: &Path,
+    extensions: &[String],
+    verify: bool,
+) -> Result<Vec<Browsed>, Error> {
+    unsafe {
+        let init = CoInitializeEx(std::ptr::null_mut(), COINIT_MULTITHREADED);
+        let ret = browse_impl(staging, extensions, verify);
+        if init >= 0 {
+            CoUninitialize();
+        }
+        ret
+    }
+}
+
+unsafe fn download_impl(path: &Path, file: &DeviceFile) -> Result<(), Error> {
+    let manager = open_manager()?;
+    let device = Device::open(&manager, &to_wide(&file.device_id))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension(format!(
+        "{}-{}.part",
+        std::process::id(),
+        DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+    debug!("import: {}: {}", file.device_name, path.to_string_lossy());
+    let (len, hash) = match device.download(&to_wide(&file.object_id), &tmp) {
+        Ok(ret) => ret,
+        Err(e) => {
+            std::fs::remove_file(&tmp).ok();
+            return Err(e);
+        }
+    };
+    if file.verify {
+        let size_matched = file.size.is_none_or(|size| size == len);
+        let hash_matched = file_hash(&tmp)? == hash;
+        if !size_matched || !hash_matched {
+            std::fs::remove_file(&tmp).ok();
+            return Err(Error::Other(anyhow::anyhow!(
+                "verification failed (size: {:?}, copied: {})",
+                file.size,
+                len
+            )));
+        }
+    }
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+pub fn resolve(path: &Path) -> Result<Option<PathBuf>, Error> {
+    let file = FILES
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|files| files.get(path).cloned());
+    let file = match file {
+        Some(file) => file,
+        None => return Ok(None),
+    };
+    if !path.is_file() {
+        unsafe {
+            let init = CoInitializeEx(std::ptr::null_mut(), COINIT_MULTITHREADED);
+            let ret = download_impl(path, &file);
+            if init >= 0 {
+                CoUninitialize();
+            }
+            ret?;
+        }
+    }
+    Ok(Some(path.to_path_buf()))
+}
+
+pub fn device_of(path: &Path) -> Option<(String, String)> {
+    FILES
+        .lock()
+        .unwrap()
+        .as_ref()?
+        .get(path)
+        .map(|file| (file.device_id.clone(), file.device_name.clone()))
+}
+
+fn instance_id(interface_path: &str) -> String {
+    let s = interface_path.trim_start_matches(r"\\?\");
+    let s = match s.rfind("#{") {
+        Some(i) => &s[..i],
+        None => s,
+    };
+    s.replace('#', "\\")
+}
+
+pub fn eject(id: &str) -> Result<(), Error> {
+    let instance_id = to_wide(&instance_id(id));
+    unsafe {
+        let mut dev_inst = 0;
+        let ret = CM_Locate_DevNodeW(
+            &mut dev_inst,
+            instance_id.as_ptr() as _,
+            CM_LOCATE_DEVNODE_NORMAL,
+        );
+        if ret != CR_SUCCESS {
+            return Err(Error::Other(anyhow::anyhow!("CM_Locate_DevNodeW: {}", ret)));
+        }
+        let mut veto_type: PNP_VETO_TYPE = 0;
+        let mut veto_name = vec![0u16; 260];
+        let ret = CM_Request_Device_EjectW(
+            dev_inst,
+            &mut veto_type,
+            veto_name.as_mut_ptr(),
+            veto_name.len() as _,
+            0,
+        );
+        if ret != CR_SUCCESS || veto_type != 0 {
+            let veto_name = String::from_utf16_lossy(&veto_name);
+            return Err(Error::Other(anyhow::anyhow!(
+                "CM_Request_Device_EjectW: {} (veto: {} {})",
+                ret,
+                veto_type,
+                veto_name.trim_end_matches('\0')
+            )));
+        }
+    }
+    Ok(())
+}
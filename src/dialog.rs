@@ -1,3 +1,4 @@
+use crate::convert::OutputFormat;
 use crate::error::Error;
 use com_ptr::*;
 use std::path::PathBuf;
@@ -7,29 +8,97 @@ use winapi::um::combaseapi::*;
 use winapi::um::shobjidl::*;
 use winapi::um::shobjidl_core::*;
 use winapi::um::shtypes::*;
+use winapi::um::winuser::*;
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect::<Vec<_>>()
+}
 
 unsafe fn file_open_dialog_impl(
     wnd: &wita::Window,
-    extensions: &Vec<String>,
-) -> Result<Option<PathBuf>, Error> {
+    filters: &[(String, String)],
+    index: usize,
+) -> Result<(Vec<PathBuf>, usize), Error> {
     let dialog =
         co_create_instance::<IFileOpenDialog>(&CLSID_FileOpenDialog, None, CLSCTX_INPROC_SERVER)?;
-    let ext_name = "画像ファイル"
-        .encode_utf16()
-        .chain(Some(0))
+    let names = filters
+        .iter()
+        .map(|(name, _)| to_wide(name))
+        .collect::<Vec<_>>();
+    let specs = filters
+        .iter()
+        .map(|(_, spec)| to_wide(spec))
         .collect::<Vec<_>>();
-    let ext_spec = extensions
+    let filters = names
         .iter()
-        .map(|ext| format!("*.{};", ext))
-        .collect::<String>()
-        .encode_utf16()
-        .chain(Some(0))
+        .zip(specs.iter())
+        .map(|(name, spec)| COMDLG_FILTERSPEC {
+            pszName: name.as_ptr(),
+            pszSpec: spec.as_ptr(),
+        })
         .collect::<Vec<_>>();
-    let dlg = COMDLG_FILTERSPEC {
-        pszName: ext_name.as_ptr(),
-        pszSpec: ext_spec.as_ptr(),
-    };
-    dialog.SetFileTypes(1, &dlg);
+    dialog.SetFileTypes(filters.len() as _, filters.as_ptr());
+    dialog.SetFileTypeIndex(index as u32 + 1);
+    let mut options = 0;
+    dialog.GetOptions(&mut options);
+    dialog.SetOptions(options | FOS_ALLOWMULTISELECT);
+    let ret = dialog.Show(wnd.raw_handle() as _);
+    if ret != S_OK {
+        if ret == HRESULT_FROM_WIN32(ERROR_CANCELLED) {
+            return Ok((vec![], index));
+        } else {
+            return Err(HResult(ret).into());
+        }
+    }
+    let items = ComPtr::new(|| {
+        let mut obj = std::ptr::null_mut();
+        let ret = dialog.GetResults(&mut obj);
+        hresult(obj, ret)
+    })?;
+    let mut count = 0;
+    let ret = items.GetCount(&mut count);
+    if ret != S_OK {
+        return Err(HResult(ret).into());
+    }
+    let mut paths = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let item = ComPtr::new(|| {
+            let mut obj = std::ptr::null_mut();
+            let ret = items.GetItemAt(i, &mut obj);
+            hresult(obj, ret)
+        })?;
+        paths.push(item_path(&item));
+    }
+    let mut selected = index as u32 + 1;
+    dialog.GetFileTypeIndex(&mut selected);
+    Ok((paths, selected.saturating_sub(1) as usize))
+}
+
+unsafe fn item_path(item: &ComPtr<IShellItem>) -> PathBuf {
+    let mut p = std::ptr::null_mut();
+    item.GetDisplayName(SIGDN_FILESYSPATH, &mut p);
+    let len = (0..std::isize::MAX)
+        .position(|i| *p.offset(i) == 0)
+        .unwrap();
+    let path = String::from_utf16_lossy(std::slice::from_raw_parts(p, len));
+    CoTaskMemFree(p as *mut _);
+    path.into()
+}
+
+pub fn file_open_dialog(
+    wnd: &wita::Window,
+    filters: &[(String, String)],
+    index: usize,
+) -> Result<(Vec<PathBuf>, usize), Error> {
+    unsafe { file_open_dialog_impl(wnd, filters, index) }
+}
+
+unsafe fn folder_dialog_impl(wnd: &wita::Window) -> Result<Option<PathBuf>, Error> {
+    let dialog =
+        co_create_instance::<IFileOpenDialog>(&CLSID_FileOpenDialog, None, CLSCTX_INPROC_SERVER)?;
+    let mut options = 0;
+    dialog.GetOptions(&mut options);
+    dialog.SetOptions(options | FOS_PICKFOLDERS);
     let ret = dialog.Show(wnd.raw_handle() as _);
     if ret != S_OK {
         if ret == HRESULT_FROM_WIN32(ERROR_CANCELLED) {
@@ -43,23 +112,115 @@ unsafe fn file_open_dialog_impl(
         let ret = dialog.GetResult(&mut obj);
         hresult(obj, ret)
     })?;
-    let path = {
-        let mut p = std::ptr::null_mut();
-        item.GetDisplayName(SIGDN_FILESYSPATH, &mut p);
-        let len = (0..std::isize::MAX)
-            .position(|i| *p.offset(i) == 0)
-            .unwrap();
-        let path = String::from_utf16_lossy(std::slice::from_raw_parts(p, len));
-        CoTaskMemFree(p as *mut _);
-        path
-    };
-    Ok(Some(path.into()))
+    Ok(Some(item_path(&item)))
 }
 
-pub fn file_open_dialog(
+pub fn folder_dialog(wnd: &wita::Window) -> Result<Option<PathBuf>, Error> {
+    unsafe { folder_dialog_impl(wnd) }
+}
+
+unsafe fn file_save_dialog_impl(
     wnd: &wita::Window,
-    extensions: &Vec<String>,
-) -> Result<Option<PathBuf>, Error> {
-    let exts = extensions.clone();
-    unsafe { file_open_dialog_impl(wnd, &exts) }
+    file_name: &str,
+) -> Result<Option<(PathBuf, OutputFormat)>, Error> {
+    let dialog =
+        co_create_instance::<IFileSaveDialog>(&CLSID_FileSaveDialog, None, CLSCTX_INPROC_SERVER)?;
+    let names = OutputFormat::ALL
+        .iter()
+        .map(|f| to_wide(f.name()))
+        .collect::<Vec<_>>();
+    let specs = OutputFormat::ALL
+        .iter()
+        .map(|f| match f {
+            OutputFormat::Jpeg => to_wide("*.jpg;*.jpeg"),
+            _ => to_wide(&format!("*.{}", f.extension())),
+        })
+        .collect::<Vec<_>>();
+    let filters = names
+        .iter()
+        .zip(specs.iter())
+        .map(|(name, spec)| COMDLG_FILTERSPEC {
+            pszName: name.as_ptr(),
+            pszSpec: spec.as_ptr(),
+        })
+        .collect::<Vec<_>>();
+    dialog.SetFileTypes(filters.len() as _, filters.as_ptr());
+    dialog.SetFileTypeIndex(1);
+    dialog.SetDefaultExtension(to_wide(OutputFormat::Png.extension()).as_ptr());
+    dialog.SetFileName(to_wide(file_name).as_ptr());
+    let mut options = 0;
+    dialog.GetOptions(&mut options);
+    dialog.SetOptions(options & !FOS_OVERWRITEPROMPT);
+    let ret = dialog.Show(wnd.raw_handle() as _);
+    if ret != S_OK {
+        if ret == HRESULT_FROM_WIN32(ERROR_CANCELLED) {
+            return Ok(None);
+        } else {
+            return Err(HResult(ret).into());
+        }
+    }
+    let item = ComPtr::new(|| {
+        let mut obj = std::ptr::null_mut();
+        let ret = dialog.GetResult(&mut obj);
+        hresult(obj, ret)
+    })?;
+    let path = item_path(&item);
+    let mut index = 1;
+    dialog.GetFileTypeIndex(&mut index);
+    let format = path
+        .extension()
+        .and_then(|ext| OutputFormat::from_extension(&ext.to_string_lossy()))
+        .unwrap_or_else(|| {
+            OutputFormat::ALL
+                .get(index.saturating_sub(1) as usize)
+                .copied()
+                .unwrap_or(OutputFormat::Png)
+        });
+    Ok(Some((path, format)))
+}
+
+pub fn file_save_dialog(
+    wnd: &wita::Window,
+    file_name: &str,
+) -> Result<Option<(PathBuf, OutputFormat)>, Error> {
+    unsafe { file_save_dialog_impl(wnd, file_name) }
+}
+
+pub fn confirm_dialog(wnd: &wita::Window, text: &str) -> bool {
+    let text = text.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    let caption = "niv".encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    unsafe {
+        MessageBoxW(
+            wnd.raw_handle() as _,
+            text.as_ptr(),
+            caption.as_ptr(),
+            MB_YESNO | MB_ICONWARNING | MB_DEFBUTTON2,
+        ) == IDYES
+    }
+}
+
+pub fn information_dialog(text: &str) {
+    let text = text.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    let caption = "niv".encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    unsafe {
+        MessageBoxW(
+            std::ptr::null_mut(),
+            text.as_ptr(),
+            caption.as_ptr(),
+            MB_OK | MB_ICONINFORMATION,
+        );
+    }
+}
+
+pub fn message_dialog(wnd: &wita::Window, text: &str) {
+    let text = text.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    let caption = "niv".encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    unsafe {
+        MessageBoxW(
+            wnd.raw_handle() as _,
+            text.as_ptr(),
+            caption.as_ptr(),
+            MB_OK | MB_ICONWARNING,
+        );
+    }
 }
@@ -1,12 +1,22 @@
+use crate::archive;
+use crate::config::Config;
+use crate::ignore::IgnoreRules;
+use log::error;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
+
+const SCAN_BATCH: usize = 1000;
+const SCAN_INTERVAL: Duration = Duration::from_millis(200);
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Order {
     Name,
     UpdatedDate,
     FileSize,
+    Resolution,
+    Extension,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -15,58 +25,305 @@ pub enum Comparison {
     Descending,
 }
 
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum SortKey {
+    Name(PathBuf),
+    Number(u64),
+    Dimensions(u64, u32, u32),
+    Extension(usize, String),
+}
+
+fn pair_key(path: &Path, suffixes: &[String]) -> Option<(String, bool)> {
+    let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+    let suffix = suffixes
+        .iter()
+        .map(|suffix| suffix.to_lowercase())
+        .find(|suffix| stem.len() > suffix.len() && stem.ends_with(suffix.as_str()));
+    match suffix {
+        Some(suffix) => Some((stem[..stem.len() - suffix.len()].to_string(), true)),
+        None => Some((stem, false)),
+    }
+}
+
+fn group_pairs(
+    paths: Vec<PathBuf>,
+    exts: &[String],
+    suffixes: &[String],
+    init: Option<&Path>,
+) -> (Vec<PathBuf>, HashMap<PathBuf, PathBuf>) {
+    let mut keys = HashMap::new();
+    let mut groups: Vec<Vec<(bool, PathBuf)>> = vec![];
+    for path in paths {
+        match pair_key(&path, suffixes) {
+            Some((key, suffixed)) => {
+                let i = *keys.entry(key).or_insert_with(|| {
+                    groups.push(vec![]);
+                    groups.len() - 1
+                });
+                groups[i].push((suffixed, path));
+            }
+            None => groups.push(vec![(false, path)]),
+        }
+    }
+    let ext_index = |path: &Path| {
+        path.extension()
+            .and_then(|ext| exts.iter().position(|e| ext == e.as_str()))
+            .unwrap_or(exts.len())
+    };
+    let mut alternates = HashMap::new();
+    let paths = groups
+        .into_iter()
+        .map(|mut group| {
+            group.sort_by_key(|(suffixed, path)| (*suffixed, ext_index(path)));
+            for i in 0..group.len() {
+                if group.len() > 1 {
+                    let next = group[(i + 1) % group.len()].1.clone();
+                    alternates.insert(group[i].1.clone(), next);
+                }
+            }
+            let displayed = group
+                .iter()
+                .find(|(_, path)| Some(path.as_path()) == init)
+                .unwrap_or(&group[0]);
+            displayed.1.clone()
+        })
+        .collect();
+    (paths, alternates)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DirEntryInfo {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl DirEntryInfo {
+    fn from_metadata(meta: &std::fs::Metadata) -> Self {
+        Self {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        }
+    }
+
+    fn read(path: &Path) -> Option<Self> {
+        path.metadata()
+            .ok()
+            .filter(|meta| meta.is_file())
+            .map(|meta| Self::from_metadata(&meta))
+    }
+}
+
+fn is_image(path: &Path, exts: &[String], ignore: &IgnoreRules) -> bool {
+    if ignore.is_ignored(path) {
+        return false;
+    }
+    match path.extension() {
+        Some(path_ext) => exts.iter().any(|ext| path_ext == ext.as_str()),
+        None => false,
+    }
+}
+
+pub fn scan(
+    dir: impl AsRef<Path>,
+    config: &Config,
+    mut found: impl FnMut(Vec<(PathBuf, DirEntryInfo)>),
+) {
+    let ignore = IgnoreRules::new(&config.ignore);
+    let entries = match dir.as_ref().read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut batch = vec![];
+    let mut first = true;
+    let mut last = Instant::now();
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let path = entry.path();
+        if !is_image(&path, &config.extensions, &ignore) {
+            continue;
+        }
+        let info = match entry.metadata() {
+            Ok(meta) if meta.file_type().is_symlink() => DirEntryInfo::read(&path),
+            Ok(meta) if meta.is_file() => Some(DirEntryInfo::from_metadata(&meta)),
+            _ => None,
+        };
+        let info = match info {
+            Some(info) => info,
+            None => continue,
+        };
+        batch.push((path, info));
+        if first || batch.len() >= SCAN_BATCH || last.elapsed() >= SCAN_INTERVAL {
+            found(std::mem::take(&mut batch));
+            first = false;
+            last = Instant::now();
+        }
+    }
+    if !batch.is_empty() {
+        found(batch);
+    }
+}
+
+pub fn scan_roots(
+    roots: &[PathBuf],
+    config: &Config,
+    mut found: impl FnMut(Vec<(PathBuf, DirEntryInfo)>),
+) {
+    let mut files = vec![];
+    for root in roots {
+        if root.is_dir() {
+            scan(root, config, &mut found);
+        } else if archive::is_archive(root) && root.is_file() {
+            let modified = DirEntryInfo::read(root).and_then(|info| info.modified);
+            match archive::entries(root, &config.extensions) {
+                Ok(entries) => found(
+                    entries
+                        .into_iter()
+                        .map(|(path, len)| (path, DirEntryInfo { len, modified }))
+                        .collect(),
+                ),
+                Err(e) => error!("archive: {}: {}", root.to_string_lossy(), e),
+            }
+        } else if let Some(info) = DirEntryInfo::read(root) {
+            files.push((root.clone(), info));
+        }
+    }
+    if !files.is_empty() {
+        found(files);
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Lookahead {
+    pub ahead: Vec<PathBuf>,
+    pub behind: Vec<PathBuf>,
+}
+
+impl Lookahead {
+    pub fn into_paths(self) -> Vec<PathBuf> {
+        self.ahead.into_iter().chain(self.behind).collect()
+    }
+}
+
 #[derive(Debug)]
 pub struct Directory {
+    path: PathBuf,
     paths: Vec<PathBuf>,
+    hidden: Vec<PathBuf>,
+    alternates: HashMap<PathBuf, PathBuf>,
+    infos: HashMap<PathBuf, Option<DirEntryInfo>>,
+    dimensions: HashMap<PathBuf, Option<(u32, u32)>>,
+    exts: Vec<String>,
     index: isize,
     order: Order,
+    secondary: Order,
     comp: Comparison,
-    lookahead: isize,
+    lookahead: usize,
+    lookbehind: usize,
+    keep_order: bool,
 }
 
 impl Directory {
-    pub fn new<T, U>(
-        dir: T,
-        exts: &Vec<String>,
-        order: Order,
-        comp: Comparison,
-        lookahead: isize,
-        init: Option<U>,
-    ) -> Self
+    pub fn new<T, U>(dir: T, config: &Config, init: Option<U>) -> Self
     where
         T: AsRef<Path>,
         U: AsRef<Path>,
     {
         assert!(dir.as_ref().is_dir());
-        let paths = dir
-            .as_ref()
-            .read_dir()
-            .unwrap()
-            .filter_map(|entry| {
-                let path = entry.ok()?.path();
-                if !path.is_file() {
-                    return None;
-                }
-                let path_ext = path.extension()?;
-                exts.iter()
-                    .find(|ext| path_ext == ext.as_str())
-                    .map(|_| path)
-            })
-            .collect::<Vec<_>>();
-        let index = init.map_or(0, |i| {
-            paths.iter().position(|p| p == i.as_ref()).unwrap_or(0)
-        }) as isize;
+        let mut entries = vec![];
+        scan(&dir, config, |batch| entries.extend(batch));
+        let infos = entries
+            .iter()
+            .map(|(path, info)| (path.clone(), Some(*info)))
+            .collect();
+        let paths = entries.into_iter().map(|(path, _)| path).collect();
+        Self::build(
+            dir.as_ref(),
+            paths,
+            infos,
+            config,
+            init.as_ref().map(|i| i.as_ref()),
+        )
+    }
+
+    pub fn from_paths<T, U>(path: T, paths: Vec<PathBuf>, config: &Config, init: Option<U>) -> Self
+    where
+        T: AsRef<Path>,
+        U: AsRef<Path>,
+    {
+        Self::build(
+            path.as_ref(),
+            paths,
+            HashMap::new(),
+            config,
+            init.as_ref().map(|i| i.as_ref()),
+        )
+    }
+
+    pub fn playlist(name: impl AsRef<Path>, paths: Vec<PathBuf>, config: &Config) -> Self {
+        let mut dir = Self::build(name.as_ref(), vec![], HashMap::new(), config, None);
+        let mut known = std::collections::HashSet::new();
+        dir.paths = paths
+            .into_iter()
+            .filter(|path| known.insert(path.clone()))
+            .collect();
+        dir.keep_order = true;
+        dir
+    }
+
+    pub fn regroup(self, config: &Config) -> Self {
+        let current = self.current().map(|path| path.to_path_buf());
+        let paths = self.paths.into_iter().chain(self.hidden).collect();
+        let mut dir = Self::build(&self.path, paths, self.infos, config, current.as_deref());
+        dir.dimensions = self.dimensions;
+        dir
+    }
+
+    fn build(
+        path: &Path,
+        paths: Vec<PathBuf>,
+        infos: HashMap<PathBuf, Option<DirEntryInfo>>,
+        config: &Config,
+        init: Option<&Path>,
+    ) -> Self {
+        let exts = &config.extensions;
+        let init = init.map(|i| i.to_path_buf());
+        let (paths, alternates) = if config.group_pairs {
+            group_pairs(paths, exts, &config.pair_suffixes, init.as_deref())
+        } else {
+            (paths, HashMap::new())
+        };
+        let index = init.map_or(0, |i| paths.iter().position(|p| *p == i).unwrap_or(0)) as isize;
         let mut obj = Self {
+            path: path.to_path_buf(),
             paths,
+            hidden: vec![],
+            alternates,
+            infos,
+            dimensions: HashMap::new(),
+            exts: exts.clone(),
             index,
-            order,
-            lookahead,
-            comp,
+            order: config.order,
+            secondary: config.secondary_order,
+            lookahead: config.lookahead_forward,
+            lookbehind: config.lookahead_backward,
+            comp: config.comp,
+            keep_order: false,
         };
-        obj.change_order(order, comp);
+        obj.change_order(config.order, config.secondary_order, config.comp);
         obj
     }
 
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
     pub fn index(&self) -> usize {
         self.index as usize
     }
@@ -75,6 +332,105 @@ impl Directory {
         self.paths.len()
     }
 
+    pub fn total(&self) -> usize {
+        self.paths.len() + self.hidden.len()
+    }
+
+    pub fn info(&self, path: &Path) -> Option<DirEntryInfo> {
+        self.infos.get(path).copied().flatten()
+    }
+
+    fn entry_info(&mut self, path: &Path) -> Option<DirEntryInfo> {
+        *self
+            .infos
+            .entry(path.to_path_buf())
+            .or_insert_with(|| DirEntryInfo::read(path))
+    }
+
+    pub fn sorted_by_resolution(&self) -> bool {
+        self.order == Order::Resolution || self.secondary == Order::Resolution
+    }
+
+    pub fn unmeasured(&self) -> Vec<PathBuf> {
+        self.paths
+            .iter()
+            .chain(self.hidden.iter())
+            .filter(|path| !self.dimensions.contains_key(*path))
+            .cloned()
+            .collect()
+    }
+
+    pub fn set_dimensions(&mut self, dimensions: Vec<(PathBuf, Option<(u32, u32)>)>) {
+        self.dimensions.extend(dimensions);
+        let current = self.current().map(|path| path.to_path_buf());
+        self.sort();
+        self.index = current
+            .and_then(|current| self.paths.iter().position(|p| *p == current))
+            .unwrap_or(0) as isize;
+    }
+
+    pub fn extend(&mut self, entries: Vec<(PathBuf, DirEntryInfo)>) {
+        let mut paths = Vec::with_capacity(entries.len());
+        for (path, info) in entries {
+            self.infos.insert(path.clone(), Some(info));
+            paths.push(path);
+        }
+        let current = self.current().map(|path| path.to_path_buf());
+        let known = self
+            .paths
+            .iter()
+            .chain(self.hidden.iter())
+            .cloned()
+            .collect::<std::collections::HashSet<_>>();
+        self.paths
+            .extend(paths.into_iter().filter(|path| !known.contains(path)));
+        self.sort();
+        self.index = current
+            .and_then(|current| self.paths.iter().position(|p| *p == current))
+            .unwrap_or(0) as isize;
+    }
+
+    pub fn filter(&mut self, mut keep: impl FnMut(&Path) -> bool) {
+        let current = self.current().map(|path| path.to_path_buf());
+        self.paths.append(&mut self.hidden);
+        self.change_order(self.order, self.secondary, self.comp);
+        let start = current
+            .and_then(|current| self.paths.iter().position(|p| *p == current))
+            .unwrap_or(0);
+        let mut index = None;
+        let (paths, hidden): (Vec<_>, Vec<_>) =
+            self.paths.drain(..).enumerate().partition(|(i, path)| {
+                let kept = keep(path);
+                if kept && index.is_none() && *i >= start {
+                    index = Some(*i);
+                }
+                kept
+            });
+        let index = index.map_or(paths.len().saturating_sub(1), |index| {
+            paths.iter().position(|(i, _)| *i == index).unwrap_or(0)
+        });
+        self.paths = paths.into_iter().map(|(_, path)| path).collect();
+        self.hidden = hidden.into_iter().map(|(_, path)| path).collect();
+        self.index = index as isize;
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.infos.remove(path);
+        self.hidden.retain(|p| p != path);
+        if let Some(i) = self.paths.iter().position(|p| p == path) {
+            self.paths.remove(i);
+            if self.index as usize >= self.paths.len() && self.index > 0 {
+                self.index -= 1;
+            }
+        }
+    }
+
+    pub fn set_index(&mut self, index: usize) {
+        if index < self.paths.len() {
+            self.index = index as isize;
+        }
+    }
+
     pub fn current(&self) -> Option<&Path> {
         if self.paths.is_empty() {
             None
@@ -83,97 +439,181 @@ impl Directory {
         }
     }
 
-    pub fn next(&mut self) -> Vec<PathBuf> {
-        if self.paths.is_empty() {
-            return vec![];
-        }
+    pub fn toggle_pair(&mut self) -> Option<PathBuf> {
+        let current = self.current()?;
+        let next = self.alternates.get(current)?.clone();
+        self.paths[self.index as usize] = next.clone();
+        Some(next)
+    }
+
+    pub fn next(&mut self) -> Lookahead {
         if self.index < self.paths.len() as isize - 1 {
             self.index += 1;
-            let n = self.index + self.lookahead;
-            let n = if n >= self.paths.len() as isize {
-                self.paths.len()
-            } else {
-                n as usize
-            };
-            self.paths[self.index as usize..n]
-                .iter()
-                .cloned()
-                .collect::<Vec<_>>()
-        } else {
-            vec![self.paths[self.paths.len() - 1].clone()]
         }
+        self.lookahead(true)
     }
 
-    pub fn prev(&mut self) -> Vec<PathBuf> {
-        if self.paths.is_empty() {
-            return vec![];
-        }
+    pub fn prev(&mut self) -> Lookahead {
         if self.index > 0 {
             self.index -= 1;
-            let n = self.index - self.lookahead;
-            let n = if n < 0 { 0 } else { n as usize };
-            self.paths[n..self.index as usize + 1]
-                .iter()
-                .cloned()
-                .collect::<Vec<_>>()
+        }
+        self.lookahead(false)
+    }
+
+    pub fn neighbors(&self) -> Vec<PathBuf> {
+        let index = self.index as usize;
+        let start = index.saturating_sub(1);
+        let end = (index + 2).min(self.paths.len());
+        self.paths.get(start..end).unwrap_or_default().to_vec()
+    }
+
+    pub fn lookahead(&self, forward: bool) -> Lookahead {
+        let index = self.index as usize;
+        let after = self.paths.iter().skip(index + 1);
+        let before = self.paths[..index.min(self.paths.len())].iter().rev();
+        let (ahead, behind) = if forward {
+            (
+                after.take(self.lookahead).cloned().collect(),
+                before.take(self.lookbehind).cloned().collect(),
+            )
         } else {
-            vec![self.paths[0].clone()]
+            (
+                before.take(self.lookahead).cloned().collect(),
+                after.take(self.lookbehind).cloned().collect(),
+            )
+        };
+        Lookahead { ahead, behind }
+    }
+
+    fn sort_key(&mut self, order: Order, path: &Path) -> SortKey {
+        match order {
+            Order::Name => SortKey::Name(path.to_path_buf()),
+            Order::UpdatedDate => SortKey::Number(
+                self.entry_info(path)
+                    .and_then(|info| info.modified)
+                    .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs())
+                    .unwrap_or(std::u64::MAX),
+            ),
+            Order::FileSize => SortKey::Number(
+                self.entry_info(path)
+                    .map(|info| info.len)
+                    .unwrap_or(std::u64::MAX),
+            ),
+            Order::Resolution => match self.dimensions.get(path).copied().flatten() {
+                Some((width, height)) => {
+                    SortKey::Dimensions(width as u64 * height as u64, width, height)
+                }
+                None => SortKey::Dimensions(std::u64::MAX, std::u32::MAX, std::u32::MAX),
+            },
+            Order::Extension => {
+                let ext = path
+                    .extension()
+                    .map_or(String::new(), |ext| ext.to_string_lossy().to_lowercase());
+                let index = self
+                    .exts
+                    .iter()
+                    .position(|e| e.to_lowercase() == ext)
+                    .unwrap_or(self.exts.len());
+                SortKey::Extension(index, ext)
+            }
         }
     }
 
-    pub fn change_order(&mut self, order: Order, comp: Comparison) {
+    pub fn change_order(&mut self, order: Order, secondary: Order, comp: Comparison) {
         self.order = order;
+        self.secondary = secondary;
         self.comp = comp;
         if self.paths.is_empty() {
             return;
         }
         let current = self.paths[self.index as usize].clone();
-        match self.order {
-            Order::Name => match self.comp {
-                Comparison::Ascending => self.paths.sort_by(|a, b| a.cmp(b)),
-                Comparison::Descending => self.paths.sort_by(|a, b| b.cmp(a)),
-            },
-            Order::UpdatedDate => {
-                let f = |a: &Path, b: &Path| -> std::cmp::Ordering {
-                    let a = a
-                        .metadata()
-                        .ok()
-                        .and_then(|meta| meta.modified().ok())
-                        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
-                        .map(|d| d.as_secs())
-                        .unwrap_or(std::u64::MAX);
-                    let b = b
-                        .metadata()
-                        .ok()
-                        .and_then(|meta| meta.modified().ok())
-                        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
-                        .map(|d| d.as_secs())
-                        .unwrap_or(std::u64::MAX);
-                    a.cmp(&b)
-                };
-                match self.comp {
-                    Comparison::Ascending => self.paths.sort_by(|a, b| f(a, b)),
-                    Comparison::Descending => self.paths.sort_by(|a, b| f(b, a)),
-                }
+        let infos = &self.infos;
+        self.paths.retain(|path| {
+            matches!(infos.get(path), Some(Some(_)))
+                || path.is_file()
+                || archive::split(path).is_some()
+        });
+        self.sort();
+        self.index = self.paths.iter().position(|p| *p == current).unwrap_or(0) as isize;
+    }
+
+    fn sort(&mut self) {
+        if self.keep_order {
+            return;
+        }
+        let (order, secondary, comp) = (self.order, self.secondary, self.comp);
+        let paths = std::mem::take(&mut self.paths);
+        let mut keyed = paths
+            .into_iter()
+            .map(|path| {
+                let primary = self.sort_key(order, &path);
+                let secondary = self.sort_key(secondary, &path);
+                (primary, secondary, path)
+            })
+            .collect::<Vec<_>>();
+        keyed.sort_by(|a, b| {
+            let primary = match comp {
+                Comparison::Ascending => a.0.cmp(&b.0),
+                Comparison::Descending => b.0.cmp(&a.0),
+            };
+            primary
+                .then_with(|| a.1.cmp(&b.1))
+                .then_with(|| a.2.cmp(&b.2))
+        });
+        self.paths = keyed.into_iter().map(|(_, _, path)| path).collect();
+    }
+}
+
+#[derive(Debug)]
+pub struct Siblings<'a> {
+    dirs: std::vec::IntoIter<PathBuf>,
+    config: &'a Config,
+}
+
+impl<'a> Siblings<'a> {
+    pub fn new(dir: impl AsRef<Path>, forward: bool, config: &'a Config) -> Self {
+        let dir = dir.as_ref();
+        let ignore = IgnoreRules::new(&config.ignore);
+        let mut dirs = dir
+            .parent()
+            .and_then(|parent| parent.read_dir().ok())
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| {
+                        Some(entry.ok()?.path())
+                            .filter(|path| path.is_dir() && !ignore.is_ignored(path))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        dirs.sort();
+        let dirs = match dirs.iter().position(|d| d == dir) {
+            Some(i) if forward => dirs.split_off(i + 1),
+            Some(i) => {
+                dirs.truncate(i);
+                dirs.reverse();
+                dirs
             }
-            Order::FileSize => {
-                let f = |a: &Path, b: &Path| -> std::cmp::Ordering {
-                    let a = a.metadata().map(|meta| meta.len()).unwrap_or(std::u64::MAX);
-                    let b = b.metadata().map(|meta| meta.len()).unwrap_or(std::u64::MAX);
-                    a.cmp(&b)
-                };
-                match self.comp {
-                    Comparison::Ascending => self.paths.sort_by(|a, b| f(a, b)),
-                    Comparison::Descending => self.paths.sort_by(|a, b| f(b, a)),
-                }
+            None => vec![],
+        };
+        Self {
+            dirs: dirs.into_iter(),
+            config,
+        }
+    }
+}
+
+impl<'a> Iterator for Siblings<'a> {
+    type Item = Directory;
+
+    fn next(&mut self) -> Option<Directory> {
+        for path in &mut self.dirs {
+            let dir = Directory::new::<_, &Path>(&path, self.config, None);
+            if dir.len() > 0 {
+                return Some(dir);
             }
         }
-        self.paths = self
-            .paths
-            .iter()
-            .filter(|path| path.is_file())
-            .cloned()
-            .collect::<Vec<_>>();
-        self.index = self.paths.iter().position(|p| *p == current).unwrap_or(0) as isize;
+        None
     }
 }
@@ -0,0 +1,62 @@
+use image::imageops::FilterType;
+use image::RgbaImage;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub fn dhash(img: &RgbaImage) -> u64 {
+    let small = image::imageops::resize(img, 9, 8, FilterType::Triangle);
+    let luma = |x: u32, y: u32| {
+        let p = small.get_pixel(x, y);
+        p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114
+    };
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if luma(x, y) < luma(x + 1, y) {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    let mut i = i;
+    while parents[i] != root {
+        let next = parents[i];
+        parents[i] = root;
+        i = next;
+    }
+    root
+}
+
+pub fn group(hashes: Vec<(PathBuf, u64)>, threshold: u32) -> Vec<Vec<PathBuf>> {
+    let mut parents = (0..hashes.len()).collect::<Vec<_>>();
+    for i in 0..hashes.len() {
+        for j in i + 1..hashes.len() {
+            if (hashes[i].1 ^ hashes[j].1).count_ones() <= threshold {
+                let a = find(&mut parents, i);
+                let b = find(&mut parents, j);
+                parents[b] = a;
+            }
+        }
+    }
+    let mut groups = BTreeMap::<usize, Vec<PathBuf>>::new();
+    for (i, (path, _)) in hashes.into_iter().enumerate() {
+        let root = find(&mut parents, i);
+        groups.entry(root).or_default().push(path);
+    }
+    groups
+        .into_values()
+        .map(|mut paths| {
+            paths.sort();
+            paths
+        })
+        .filter(|paths| paths.len() > 1)
+        .collect()
+}
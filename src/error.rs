@@ -8,6 +8,8 @@ pub enum Error {
     FileNotFound,
     #[error("読み込もうとしたファイルの形式をサポートしていません")]
     Unsupported,
+    #[error("読み込みを中止しました")]
+    Canceled,
     #[error("HRESULTエラー: (0x{:<08x}){0}", .0.code())]
     HResult(HResult),
     #[error("エラー: {0}")]
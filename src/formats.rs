@@ -0,0 +1,200 @@
+pub struct Format {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    pub backend: &'static str,
+    pub animation: &'static str,
+    pub hdr: &'static str,
+    pub metadata: &'static str,
+}
+
+pub const FORMATS: [Format; 15] = [
+    Format {
+        name: "PNG",
+        extensions: &["png"],
+        backend: "image crate",
+        animation: "no (first frame of APNG)",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "JPEG",
+        extensions: &["jpg", "jpeg"],
+        backend: "image crate",
+        animation: "no",
+        hdr: "no",
+        metadata: "XMP (GPano)",
+    },
+    Format {
+        name: "MPO",
+        extensions: &["mpo"],
+        backend: "image crate + stereo",
+        animation: "no",
+        hdr: "no",
+        metadata: "MPF",
+    },
+    Format {
+        name: "JPS",
+        extensions: &["jps"],
+        backend: "image crate + stereo",
+        animation: "no",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "GIF",
+        extensions: &["gif"],
+        backend: "image crate",
+        animation: "yes",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "BMP",
+        extensions: &["bmp"],
+        backend: "image crate",
+        animation: "no",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "ICO",
+        extensions: &["ico"],
+        backend: "image crate",
+        animation: "no",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "TIFF",
+        extensions: &["tif", "tiff"],
+        backend: "image crate",
+        animation: "no",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "PNM",
+        extensions: &["pnm", "pbm", "pgm", "ppm"],
+        backend: "image crate",
+        animation: "no",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "TGA",
+        extensions: &["tga"],
+        backend: "image crate",
+        animation: "no",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "WebP",
+        extensions: &["webp"],
+        backend: "image crate",
+        animation: "no (lossy still images only)",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "DDS",
+        extensions: &["dds"],
+        backend: "image crate",
+        animation: "no",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "Farbfeld",
+        extensions: &["ff"],
+        backend: "image crate",
+        animation: "no",
+        hdr: "no",
+        metadata: "no",
+    },
+    Format {
+        name: "Radiance HDR",
+        extensions: &["hdr"],
+        backend: "image crate",
+        animation: "no",
+        hdr: "clamped to 8-bit",
+        metadata: "no",
+    },
+    Format {
+        name: "ZIP archive",
+        extensions: &["zip", "cbz"],
+        backend: "zip crate (container)",
+        animation: "-",
+        hdr: "-",
+        metadata: "-",
+    },
+];
+
+pub fn dialog_filters(enabled: &[String]) -> Vec<(String, String)> {
+    let spec = |exts: &[&str]| {
+        exts.iter()
+            .map(|ext| format!("*.{}", ext))
+            .collect::<Vec<_>>()
+            .join(";")
+    };
+    let enabled = enabled.iter().map(|ext| ext.as_str()).collect::<Vec<_>>();
+    let mut filters = vec![("画像ファイル".to_string(), spec(&enabled))];
+    for format in FORMATS.iter() {
+        let exts = format
+            .extensions
+            .iter()
+            .copied()
+            .filter(|ext| enabled.contains(ext))
+            .collect::<Vec<_>>();
+        if !exts.is_empty() {
+            filters.push((format!("{} ({})", format.name, spec(&exts)), spec(&exts)));
+        }
+    }
+    for ext in enabled.iter() {
+        if !FORMATS.iter().any(|f| f.extensions.contains(ext)) {
+            filters.push((
+                format!("{} (*.{})", ext.to_uppercase(), ext),
+                format!("*.{}", ext),
+            ));
+        }
+    }
+    filters.push(("すべてのファイル (*.*)".to_string(), "*.*".to_string()));
+    filters
+}
+
+pub fn report(enabled: &[String]) -> String {
+    let mut lines = vec![];
+    for format in FORMATS.iter() {
+        let extensions = format
+            .extensions
+            .iter()
+            .map(|ext| {
+                let on = format.name == "ZIP archive"
+                    || enabled.iter().any(|e| e.eq_ignore_ascii_case(ext));
+                if on {
+                    ext.to_string()
+                } else {
+                    format!("{} (disabled)", ext)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        lines.push(format!(
+            "{}: {}\n  decoder: {}, animation: {}, HDR: {}, metadata: {}",
+            format.name, extensions, format.backend, format.animation, format.hdr, format.metadata
+        ));
+    }
+    let unknown = enabled
+        .iter()
+        .filter(|e| {
+            !FORMATS
+                .iter()
+                .any(|f| f.extensions.iter().any(|ext| ext.eq_ignore_ascii_case(e)))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        lines.push(format!("unsupported: {}", unknown.join(", ")));
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,114 @@
+use crate::control::{Command, CommandQueues};
+use serde::*;
+use std::time::{Duration, Instant};
+use winapi::shared::minwindef::WORD;
+use winapi::shared::winerror::ERROR_SUCCESS;
+use winapi::um::winuser::GetForegroundWindow;
+use winapi::um::xinput::*;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(33);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Button {
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Start,
+    Back,
+    LeftThumb,
+    RightThumb,
+    LeftShoulder,
+    RightShoulder,
+    A,
+    B,
+    X,
+    Y,
+}
+
+impl Button {
+    const ALL: [(Button, WORD); 14] = [
+        (Button::DPadUp, XINPUT_GAMEPAD_DPAD_UP),
+        (Button::DPadDown, XINPUT_GAMEPAD_DPAD_DOWN),
+        (Button::DPadLeft, XINPUT_GAMEPAD_DPAD_LEFT),
+        (Button::DPadRight, XINPUT_GAMEPAD_DPAD_RIGHT),
+        (Button::Start, XINPUT_GAMEPAD_START),
+        (Button::Back, XINPUT_GAMEPAD_BACK),
+        (Button::LeftThumb, XINPUT_GAMEPAD_LEFT_THUMB),
+        (Button::RightThumb, XINPUT_GAMEPAD_RIGHT_THUMB),
+        (Button::LeftShoulder, XINPUT_GAMEPAD_LEFT_SHOULDER),
+        (Button::RightShoulder, XINPUT_GAMEPAD_RIGHT_SHOULDER),
+        (Button::A, XINPUT_GAMEPAD_A),
+        (Button::B, XINPUT_GAMEPAD_B),
+        (Button::X, XINPUT_GAMEPAD_X),
+        (Button::Y, XINPUT_GAMEPAD_Y),
+    ];
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GamepadEvent {
+    Pressed(Button),
+    Zoom(f32),
+}
+
+fn trigger(value: u8) -> f32 {
+    let threshold = XINPUT_GAMEPAD_TRIGGER_THRESHOLD;
+    if value <= threshold {
+        return 0.0;
+    }
+    (value - threshold) as f32 / (255 - threshold) as f32
+}
+
+struct Pad {
+    connected: bool,
+    buttons: WORD,
+    retry: Instant,
+}
+
+pub fn poll_gamepads(queues: CommandQueues) {
+    std::thread::spawn(move || {
+        let mut pads = (0..XUSER_MAX_COUNT)
+            .map(|_| Pad {
+                connected: true,
+                buttons: 0,
+                retry: Instant::now(),
+            })
+            .collect::<Vec<_>>();
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let now = Instant::now();
+            let foreground = queues.find(unsafe { GetForegroundWindow() as usize });
+            for (index, pad) in pads.iter_mut().enumerate() {
+                if !pad.connected && now < pad.retry {
+                    continue;
+                }
+                let mut state: XINPUT_STATE = unsafe { std::mem::zeroed() };
+                if unsafe { XInputGetState(index as _, &mut state) } != ERROR_SUCCESS {
+                    pad.connected = false;
+                    pad.buttons = 0;
+                    pad.retry = now + RECONNECT_INTERVAL;
+                    continue;
+                }
+                pad.connected = true;
+                let buttons = state.Gamepad.wButtons;
+                let pressed = buttons & !pad.buttons;
+                pad.buttons = buttons;
+                let queue = match foreground.as_ref() {
+                    Some(queue) => queue,
+                    None => continue,
+                };
+                for (button, mask) in Button::ALL.iter() {
+                    if pressed & mask != 0 {
+                        queue.push(Command::Gamepad(GamepadEvent::Pressed(*button)));
+                    }
+                }
+                let zoom =
+                    trigger(state.Gamepad.bRightTrigger) - trigger(state.Gamepad.bLeftTrigger);
+                if zoom != 0.0 {
+                    queue.push(Command::Gamepad(GamepadEvent::Zoom(zoom)));
+                }
+            }
+        }
+    });
+}
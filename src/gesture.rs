@@ -0,0 +1,67 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stroke {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Stroke {
+    fn to_char(self) -> char {
+        match self {
+            Stroke::Left => 'L',
+            Stroke::Right => 'R',
+            Stroke::Up => 'U',
+            Stroke::Down => 'D',
+        }
+    }
+}
+
+pub struct Recognizer {
+    last: (f32, f32),
+    strokes: Vec<Stroke>,
+    threshold: f32,
+}
+
+impl Recognizer {
+    pub fn new(x: f32, y: f32, threshold: f32) -> Self {
+        Self {
+            last: (x, y),
+            strokes: vec![],
+            threshold: threshold.max(1.0),
+        }
+    }
+
+    pub fn moved(&mut self, x: f32, y: f32) -> bool {
+        let dx = x - self.last.0;
+        let dy = y - self.last.1;
+        if dx.abs().max(dy.abs()) < self.threshold {
+            return false;
+        }
+        self.last = (x, y);
+        let stroke = if dx.abs() >= dy.abs() {
+            if dx < 0.0 {
+                Stroke::Left
+            } else {
+                Stroke::Right
+            }
+        } else if dy < 0.0 {
+            Stroke::Up
+        } else {
+            Stroke::Down
+        };
+        if self.strokes.last() == Some(&stroke) {
+            return false;
+        }
+        self.strokes.push(stroke);
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strokes.is_empty()
+    }
+
+    pub fn strokes(&self) -> String {
+        self.strokes.iter().map(|s| s.to_char()).collect()
+    }
+}
@@ -0,0 +1,102 @@
+use std::path::Path;
+
+fn wildcard_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn to_chars(s: &str) -> Vec<char> {
+    s.to_lowercase().chars().collect()
+}
+
+#[derive(Debug)]
+pub struct IgnoreRules {
+    patterns: Vec<Vec<Vec<char>>>,
+}
+
+impl IgnoreRules {
+    pub fn new(patterns: &[String]) -> Self {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                pattern
+                    .split(|c| c == '/' || c == '\\')
+                    .filter(|component| !component.is_empty())
+                    .map(to_chars)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|components| !components.is_empty())
+            .collect();
+        Self { patterns }
+    }
+
+    pub fn is_ignored(&self, path: impl AsRef<Path>) -> bool {
+        let components = path
+            .as_ref()
+            .components()
+            .map(|c| to_chars(&c.as_os_str().to_string_lossy()))
+            .collect::<Vec<_>>();
+        self.patterns.iter().any(|pattern| {
+            pattern.len() <= components.len()
+                && pattern
+                    .iter()
+                    .zip(&components[components.len() - pattern.len()..])
+                    .all(|(p, c)| wildcard_match(p, c))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(patterns: &[&str]) -> IgnoreRules {
+        IgnoreRules::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn wildcards_match_file_names() {
+        let rules = rules(&["*.tmp", "thumb?.db"]);
+        assert!(rules.is_ignored("/images/a.tmp"));
+        assert!(rules.is_ignored("/images/thumb1.db"));
+        assert!(!rules.is_ignored("/images/thumbs10.db"));
+        assert!(!rules.is_ignored("/images/a.png"));
+    }
+
+    #[test]
+    fn matching_ignores_case() {
+        let rules = rules(&["*.TMP"]);
+        assert!(rules.is_ignored("/images/A.tmp"));
+    }
+
+    #[test]
+    fn patterns_with_separators_match_trailing_components() {
+        let rules = rules(&["cache/*", "\\backup\\*.png"]);
+        assert!(rules.is_ignored("/images/cache/a.png"));
+        assert!(rules.is_ignored("/images/backup/a.png"));
+        assert!(!rules.is_ignored("/images/cache"));
+        assert!(!rules.is_ignored("/images/backup/a.jpg"));
+    }
+
+    #[test]
+    fn empty_patterns_ignore_nothing() {
+        let rules = rules(&["", "/"]);
+        assert!(!rules.is_ignored("/images/a.png"));
+    }
+}
@@ -1,113 +1,501 @@
+use crate::archive::ArchiveCache;
+use crate::duplicates;
 use crate::error::Error;
+use crate::strip;
+use crate::wic;
 use com_ptr::*;
-use image::RgbaImage;
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ColorType, ImageDecoder, RgbaImage};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::hash_map::DefaultHasher;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Read};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, Notify};
 use winapi::shared::dxgiformat::*;
 use winapi::um::{d2d1_1::*, dcommon::*};
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 struct PathHash(u64);
 
+#[derive(Clone, Default, Debug)]
+struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn is_canceled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
 trait GetSize {
     fn get_size(&self) -> usize;
 }
 
-impl GetSize for ComPtr<ID2D1Bitmap1> {
+#[derive(Debug)]
+struct Diff {
+    key: (PathHash, PathHash, u32),
+    bmp: Option<ComPtr<ID2D1Bitmap1>>,
+}
+
+#[derive(Clone, Debug)]
+struct Bitmap {
+    bmp: ComPtr<ID2D1Bitmap1>,
+    mips: Vec<ComPtr<ID2D1Bitmap1>>,
+    preview: bool,
+}
+
+impl GetSize for Bitmap {
     fn get_size(&self) -> usize {
-        unsafe {
-            let size = self.GetPixelSize();
-            (size.width * size.height * 4) as usize
-        }
+        std::iter::once(&self.bmp)
+            .chain(self.mips.iter())
+            .map(|bmp| unsafe {
+                let size = bmp.GetPixelSize();
+                (size.width * size.height * 4) as usize
+            })
+            .sum()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Decoded {
+    image: Arc<RgbaImage>,
+    width: u32,
+    height: u32,
+}
+
+impl Decoded {
+    fn is_full(&self) -> bool {
+        self.image.dimensions() == (self.width, self.height)
     }
 }
 
-impl GetSize for RgbaImage {
+impl GetSize for Decoded {
     fn get_size(&self) -> usize {
-        self.as_raw().len()
+        self.image.as_raw().len()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub inserted: u64,
+    pub used: u64,
+    pub size: usize,
+    pub pinned: bool,
+}
+
+pub trait CachePolicy: Send + Sync + std::fmt::Debug {
+    fn priority(&self, entry: &Entry, tick: u64) -> Option<u64>;
+}
+
+#[derive(Debug)]
+struct Lru;
+
+impl CachePolicy for Lru {
+    fn priority(&self, entry: &Entry, _: u64) -> Option<u64> {
+        Some(entry.used)
     }
 }
 
+#[derive(Debug)]
+struct Fifo;
+
+impl CachePolicy for Fifo {
+    fn priority(&self, entry: &Entry, _: u64) -> Option<u64> {
+        Some(entry.inserted)
+    }
+}
+
+#[derive(Debug)]
+struct SizeWeighted;
+
+impl CachePolicy for SizeWeighted {
+    fn priority(&self, entry: &Entry, tick: u64) -> Option<u64> {
+        let age = tick.saturating_sub(entry.used) + 1;
+        Some(u64::MAX - age.saturating_mul(entry.size as u64))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum CachePolicyKind {
+    Lru,
+    Fifo,
+    SizeWeighted,
+}
+
+impl CachePolicyKind {
+    fn policy(self) -> Box<dyn CachePolicy> {
+        match self {
+            CachePolicyKind::Lru => Box::new(Lru),
+            CachePolicyKind::Fifo => Box::new(Fifo),
+            CachePolicyKind::SizeWeighted => Box::new(SizeWeighted),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub last_load: Option<Duration>,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f32 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f32 / total as f32
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stats {
+    pub bmp: CacheStats,
+    pub image: CacheStats,
+    pub queue_depth: usize,
+}
+
 #[derive(Debug)]
 struct Cache<T: GetSize> {
-    buffer: VecDeque<(PathHash, T)>,
+    items: HashMap<PathHash, (Entry, T)>,
+    tick: u64,
     size: usize,
     target_size: usize,
+    policy: Box<dyn CachePolicy>,
+    pinned: Vec<PathHash>,
+    stats: CacheStats,
+    evicted: Option<Vec<(PathHash, T)>>,
 }
 
 impl<T: GetSize> Cache<T> {
-    fn new(target_size: usize) -> Self {
+    fn new(target_size: usize, policy: CachePolicyKind) -> Self {
         Self {
-            buffer: VecDeque::new(),
+            items: HashMap::new(),
+            tick: 0,
             size: 0,
             target_size,
+            policy: policy.policy(),
+            pinned: vec![],
+            stats: CacheStats::default(),
+            evicted: None,
         }
     }
 
+    fn keep_evicted(mut self) -> Self {
+        self.evicted = Some(vec![]);
+        self
+    }
+
+    fn take_evicted(&mut self) -> Vec<(PathHash, T)> {
+        self.evicted
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
     fn size(&self) -> usize {
         self.size
     }
 
     fn clear(&mut self) {
-        self.buffer.clear();
+        self.items.clear();
+        self.size = 0;
     }
 
-    fn find(&self, path: PathHash) -> Option<&T> {
-        self.buffer
-            .iter()
-            .find(|(p, _)| *p == path)
-            .map(|(_, obj)| obj)
+    fn set_target_size(&mut self, target_size: usize) {
+        self.target_size = target_size;
+        self.evict(0);
+    }
+
+    fn set_policy(&mut self, policy: CachePolicyKind) {
+        self.policy = policy.policy();
+        self.evict(0);
+    }
+
+    fn set_pinned(&mut self, pinned: Vec<PathHash>) {
+        for (path, (entry, _)) in self.items.iter_mut() {
+            entry.pinned = pinned.contains(path);
+        }
+        self.pinned = pinned;
+    }
+
+    fn evict(&mut self, incoming: usize) {
+        while self.size + incoming > self.target_size {
+            let policy = &self.policy;
+            let tick = self.tick;
+            let victim = self
+                .items
+                .iter()
+                .filter(|(_, (entry, _))| !entry.pinned)
+                .filter_map(|(path, (entry, _))| Some((policy.priority(entry, tick)?, *path)))
+                .min()
+                .map(|(_, path)| path);
+            match victim.and_then(|path| self.items.remove(&path).map(|item| (path, item))) {
+                Some((path, (_, obj))) => {
+                    self.size -= obj.get_size();
+                    if let Some(evicted) = self.evicted.as_mut() {
+                        evicted.push((path, obj));
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn lookup(&mut self, path: PathHash) -> Option<&T> {
+        if self.items.contains_key(&path) {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        self.find(path)
+    }
+
+    fn push_timed(&mut self, path: PathHash, obj: T, elapsed: Duration) {
+        self.stats.last_load = Some(elapsed);
+        self.replace(path, obj);
+    }
+
+    fn find(&mut self, path: PathHash) -> Option<&T> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.items.get_mut(&path).map(|(entry, obj)| {
+            entry.used = tick;
+            &*obj
+        })
+    }
+
+    fn remove(&mut self, path: PathHash) {
+        if let Some((_, old)) = self.items.remove(&path) {
+            self.size -= old.get_size();
+        }
+    }
+
+    fn replace(&mut self, path: PathHash, obj: T) {
+        self.remove(path);
+        self.push(path, obj);
     }
 
     fn push(&mut self, path: PathHash, obj: T) {
-        if self.find(path).is_some() {
+        if self.items.contains_key(&path) {
             return;
         }
-        let push_size = obj.get_size();
-        while self.size + push_size > self.target_size {
-            let item = self.buffer.pop_front().unwrap();
-            self.size -= item.1.get_size();
+        let size = obj.get_size();
+        self.evict(size);
+        self.tick += 1;
+        let entry = Entry {
+            inserted: self.tick,
+            used: self.tick,
+            size,
+            pinned: self.pinned.contains(&path),
+        };
+        self.items.insert(path, (entry, obj));
+        self.size += size;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum DecodeBackend {
+    Image,
+    Wic,
+}
+
+pub fn open_image(path: &Path) -> Result<RgbaImage, Error> {
+    Ok(image::io::Reader::open(path)?
+        .with_guessed_format()?
+        .decode()?
+        .to_rgba8())
+}
+
+pub fn fit_size(width: u32, height: u32, max_size: u32) -> (u32, u32) {
+    if max_size == 0 || (width <= max_size && height <= max_size) {
+        return (width, height);
+    }
+    let scale = max_size as f64 / width.max(height) as f64;
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+pub fn original_size(bmp: &ComPtr<ID2D1Bitmap1>) -> (u32, u32) {
+    let size = unsafe { bmp.GetSize() };
+    (size.width.round() as u32, size.height.round() as u32)
+}
+
+fn is_jpeg(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| {
+            let ext = ext.to_string_lossy();
+            ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg")
+        })
+        .unwrap_or(false)
+}
+
+fn is_large_jpeg(path: &Path) -> bool {
+    const LARGE_JPEG_PIXELS: u64 = 12_000_000;
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    match JpegDecoder::new(std::io::BufReader::new(file)) {
+        Ok(decoder) => {
+            let (width, height) = decoder.dimensions();
+            width as u64 * height as u64 >= LARGE_JPEG_PIXELS
         }
-        self.buffer.push_back((path, obj));
-        self.size += push_size;
+        Err(_) => false,
     }
 }
 
-fn to_path_hash(path: impl AsRef<Path>) -> PathHash {
-    let mut hasher = DefaultHasher::new();
-    path.as_ref().hash(&mut hasher);
-    PathHash(hasher.finish())
+fn is_tiff(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| {
+            let ext = ext.to_string_lossy();
+            ext.eq_ignore_ascii_case("tif") || ext.eq_ignore_ascii_case("tiff")
+        })
+        .unwrap_or(false)
 }
 
-type BitmapCache = Arc<Mutex<Cache<ComPtr<ID2D1Bitmap1>>>>;
-type ImageCache = Arc<Mutex<Cache<RgbaImage>>>;
+fn decode_limit(dc: &ComPtr<ID2D1DeviceContext>, max_size: u32) -> u32 {
+    let device = unsafe { dc.GetMaximumBitmapSize() };
+    if max_size == 0 {
+        device
+    } else {
+        max_size.min(device)
+    }
+}
 
-async fn load_image(
-    dc: ComPtr<ID2D1DeviceContext>,
-    path: PathBuf,
-    path_hash: PathHash,
-    bmp_cache: BitmapCache,
-    image_cache: ImageCache,
-) -> Result<(), Error> {
-    let mut bmp_cache = bmp_cache.lock().await;
-    if bmp_cache.find(path_hash).is_some() {
-        return Ok(());
+fn average_rows(src: &RgbaImage, width: u32, height: u32, rows: std::ops::Range<u32>) -> Vec<u8> {
+    let (src_width, src_height) = src.dimensions();
+    let span = |i: u32, size: u32, src_size: u32| {
+        let start = (i as u64 * src_size as u64 / size as u64) as u32;
+        let end = ((i as u64 + 1) * src_size as u64 / size as u64) as u32;
+        start..end.max(start + 1).min(src_size)
+    };
+    let mut buffer = Vec::with_capacity(width as usize * rows.len() * 4);
+    for y in rows {
+        let sy = span(y, height, src_height);
+        for x in 0..width {
+            let sx = span(x, width, src_width);
+            let mut sum = [0u64; 4];
+            for py in sy.clone() {
+                for px in sx.clone() {
+                    let p = src.get_pixel(px, py);
+                    for c in 0..4 {
+                        sum[c] += p[c] as u64;
+                    }
+                }
+            }
+            let n = (sx.len() * sy.len()).max(1) as u64;
+            buffer.extend(sum.iter().map(|v| (v / n) as u8));
+        }
+    }
+    buffer
+}
+
+fn downscale(path: &Path, img: RgbaImage, limit: u32) -> Decoded {
+    let (width, height) = img.dimensions();
+    let (w, h) = fit_size(width, height, limit);
+    let image = if (w, h) == (width, height) {
+        img
+    } else {
+        debug!(
+            "downscale: {}: {}x{} -> {}x{}",
+            path.to_string_lossy(),
+            width,
+            height,
+            w,
+            h
+        );
+        image::imageops::resize(&img, w, h, image::imageops::FilterType::Triangle)
+    };
+    Decoded {
+        image: Arc::new(image),
+        width,
+        height,
+    }
+}
+
+fn exif_thumbnail(data: &[u8]) -> Option<&[u8]> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xff {
+        let marker = data[pos + 1];
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xe1 && segment.starts_with(b"Exif\0\0") {
+            let start = segment.windows(2).position(|w| w == [0xff, 0xd8])?;
+            let end = segment.windows(2).rposition(|w| w == [0xff, 0xd9])? + 2;
+            return segment.get(start..end);
+        }
+        if marker == 0xda {
+            break;
+        }
+        pos += 2 + len;
     }
-    let mut image_cache = image_cache.lock().await;
-    let img = match image_cache.find(path_hash) {
-        Some(img) => img,
+    None
+}
+
+fn decode_preview(path: &Path) -> Option<Decoded> {
+    const PREVIEW_SIZE: u16 = 512;
+    const EXIF_LIMIT: u64 = 128 * 1024;
+    let file = std::fs::File::open(path).ok()?;
+    let mut decoder = JpegDecoder::new(std::io::BufReader::new(file)).ok()?;
+    let (width, height) = decoder.dimensions();
+    if width.max(height) <= PREVIEW_SIZE as u32 * 2 {
+        return None;
+    }
+    let mut head = vec![];
+    std::fs::File::open(path)
+        .ok()?
+        .take(EXIF_LIMIT)
+        .read_to_end(&mut head)
+        .ok()?;
+    let aspect = width as f32 / height as f32;
+    let thumbnail = exif_thumbnail(&head)
+        .and_then(|data| image::load_from_memory(data).ok())
+        .map(|thumbnail| thumbnail.to_rgba8())
+        .filter(|thumbnail| {
+            let (w, h) = thumbnail.dimensions();
+            h > 0 && (w as f32 / h as f32 - aspect).abs() < aspect * 0.02
+        });
+    let image = match thumbnail {
+        Some(thumbnail) => thumbnail,
         None => {
-            image_cache.push(path_hash, image::open(path)?.to_rgba8());
-            image_cache.find(path_hash).unwrap()
+            decoder.scale(PREVIEW_SIZE, PREVIEW_SIZE).ok()?;
+            image::DynamicImage::from_decoder(decoder).ok()?.to_rgba8()
         }
     };
-    let bmp = ComPtr::new(|| unsafe {
+    Some(Decoded {
+        image: Arc::new(image),
+        width,
+        height,
+    })
+}
+
+fn create_bitmap(
+    dc: &ComPtr<ID2D1DeviceContext>,
+    decoded: &Decoded,
+) -> Result<ComPtr<ID2D1Bitmap1>, Error> {
+    let img = &decoded.image;
+    let size = img.dimensions();
+    let dpi_x = 96.0 * size.0 as f32 / decoded.width.max(1) as f32;
+    let dpi_y = 96.0 * size.1 as f32 / decoded.height.max(1) as f32;
+    Ok(ComPtr::new(|| unsafe {
         let mut obj = std::ptr::null_mut();
-        let size = img.dimensions();
         let ret = dc.CreateBitmap(
             winapi::um::d2d1::D2D1_SIZE_U {
                 width: size.0,
@@ -121,102 +509,1212 @@ async fn load_image(
                     format: DXGI_FORMAT_R8G8B8A8_UNORM,
                     alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
                 },
-                dpiX: 96.0,
-                dpiY: 96.0,
+                dpiX: dpi_x,
+                dpiY: dpi_y,
                 colorContext: std::ptr::null_mut(),
             },
             &mut obj,
         );
         hresult(obj, ret)
-    })?;
-    bmp_cache.push(path_hash, bmp);
-    Ok(())
+    })?)
+}
+
+fn create_mipmaps(dc: &ComPtr<ID2D1DeviceContext>, decoded: &Decoded) -> Vec<ComPtr<ID2D1Bitmap1>> {
+    const MIN_SIZE: u32 = 256;
+    let mut mips = vec![];
+    let mut level = decoded.image.clone();
+    loop {
+        let (width, height) = level.dimensions();
+        if width.max(height) <= MIN_SIZE || width < 2 || height < 2 {
+            break;
+        }
+        let (width, height) = (width / 2, height / 2);
+        let buffer = average_rows(&level, width, height, 0..height);
+        level = Arc::new(RgbaImage::from_raw(width, height, buffer).unwrap());
+        let bmp = create_bitmap(
+            dc,
+            &Decoded {
+                image: level.clone(),
+                width: decoded.width,
+                height: decoded.height,
+            },
+        );
+        match bmp {
+            Ok(bmp) => mips.push(bmp),
+            Err(e) => {
+                warn!("create_mipmaps: {}", e);
+                break;
+            }
+        }
+    }
+    mips
+}
+
+fn diff_image(a: &RgbaImage, b: &RgbaImage, amplification: f32) -> RgbaImage {
+    let (width, height) = a.dimensions();
+    let resized;
+    let b = if b.dimensions() == (width, height) {
+        b
+    } else {
+        resized = image::imageops::resize(b, width, height, image::imageops::FilterType::Triangle);
+        &resized
+    };
+    let mut diff = RgbaImage::new(width, height);
+    for ((d, a), b) in diff.pixels_mut().zip(a.pixels()).zip(b.pixels()) {
+        for i in 0..3 {
+            let v = (a[i] as f32 - b[i] as f32).abs() * amplification;
+            d[i] = v.min(255.0) as u8;
+        }
+        d[3] = 255;
+    }
+    diff
 }
 
+pub fn edge_color(img: &RgbaImage) -> (f32, f32, f32) {
+    const SAMPLES: u32 = 64;
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return (0.0, 0.0, 0.0);
+    }
+    let mut points = vec![];
+    for i in 0..SAMPLES {
+        let x = i * (width - 1) / (SAMPLES - 1);
+        let y = i * (height - 1) / (SAMPLES - 1);
+        points.extend_from_slice(&[(x, 0), (x, height - 1), (0, y), (width - 1, y)]);
+    }
+    let mut sum = [0.0f32; 3];
+    let mut weight = 0.0;
+    for (x, y) in points {
+        let px = img.get_pixel(x, y);
+        let a = px[3] as f32 / 255.0;
+        for c in 0..3 {
+            sum[c] += px[c] as f32 / 255.0 * a;
+        }
+        weight += a;
+    }
+    if weight == 0.0 {
+        return (0.0, 0.0, 0.0);
+    }
+    (sum[0] / weight, sum[1] / weight, sum[2] / weight)
+}
+
+fn to_path_hash(path: impl AsRef<Path>) -> PathHash {
+    let mut hasher = DefaultHasher::new();
+    path.as_ref().hash(&mut hasher);
+    PathHash(hasher.finish())
+}
+
+type BitmapCache = Arc<Mutex<Cache<Bitmap>>>;
+type ImageCache = Arc<Mutex<Cache<Decoded>>>;
 #[derive(Debug)]
-pub struct ImageManager {
-    runtime: tokio::runtime::Runtime,
+struct Failure {
+    error: Arc<Error>,
+    stamp: Option<(u64, SystemTime)>,
+    failed_at: Instant,
+}
+
+type Errors = Arc<Mutex<Vec<(PathHash, Failure)>>>;
+
+#[derive(Clone, Copy, Debug)]
+struct DiskEntry {
+    path: PathHash,
+    len: u64,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug)]
+struct DiskCache {
+    dir: PathBuf,
+    target_size: AtomicU64,
+    files: Mutex<(VecDeque<DiskEntry>, u64)>,
+    pending: Mutex<HashSet<PathHash>>,
+}
+
+impl DiskCache {
+    fn new(target_size: u64) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "niv-image-cache-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        Self {
+            dir,
+            target_size: AtomicU64::new(target_size),
+            files: Mutex::new((VecDeque::new(), 0)),
+            pending: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn file_path(&self, path: PathHash) -> PathBuf {
+        self.dir.join(format!("{:016x}.png", path.0))
+    }
+
+    fn set_target_size(&self, target_size: u64) {
+        self.target_size.store(target_size, Ordering::Relaxed);
+        self.prune();
+    }
+
+    fn prune(&self) {
+        let target_size = self.target_size.load(Ordering::Relaxed);
+        let mut files = self.files.lock().unwrap();
+        let (files, size) = &mut *files;
+        while *size > target_size {
+            let file = match files.pop_front() {
+                Some(file) => file,
+                None => break,
+            };
+            std::fs::remove_file(self.file_path(file.path)).ok();
+            *size -= file.len;
+        }
+    }
+
+    fn remove(&self, path: PathHash) {
+        self.pending.lock().unwrap().remove(&path);
+        let mut files = self.files.lock().unwrap();
+        let (files, size) = &mut *files;
+        if let Some(i) = files.iter().position(|file| file.path == path) {
+            let file = files.remove(i).unwrap();
+            std::fs::remove_file(self.file_path(path)).ok();
+            *size -= file.len;
+        }
+    }
+
+    fn clear(&self) {
+        self.pending.lock().unwrap().clear();
+        let mut files = self.files.lock().unwrap();
+        *files = (VecDeque::new(), 0);
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+
+    fn enqueue(&self, path: PathHash) -> bool {
+        self.target_size.load(Ordering::Relaxed) > 0 && self.pending.lock().unwrap().insert(path)
+    }
+
+    fn store(&self, path: PathHash, decoded: &Decoded) -> Result<(), Error> {
+        if !self.pending.lock().unwrap().contains(&path) {
+            return Ok(());
+        }
+        if self
+            .files
+            .lock()
+            .unwrap()
+            .0
+            .iter()
+            .any(|file| file.path == path)
+        {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let file_path = self.file_path(path);
+        let tmp = file_path.with_extension("part");
+        {
+            let mut writer = BufWriter::new(std::fs::File::create(&tmp)?);
+            let (width, height) = decoded.image.dimensions();
+            PngEncoder::new_with_quality(&mut writer, CompressionType::Fast, FilterType::Sub)
+                .encode(decoded.image.as_raw(), width, height, ColorType::Rgba8)?;
+        }
+        if !self.pending.lock().unwrap().remove(&path) {
+            std::fs::remove_file(&tmp).ok();
+            return Ok(());
+        }
+        std::fs::rename(&tmp, &file_path)?;
+        let len = file_path.metadata()?.len();
+        {
+            let mut files = self.files.lock().unwrap();
+            let (files, size) = &mut *files;
+            files.push_back(DiskEntry {
+                path,
+                len,
+                width: decoded.width,
+                height: decoded.height,
+            });
+            *size += len;
+        }
+        self.prune();
+        Ok(())
+    }
+
+    fn load(&self, path: PathHash) -> Option<Decoded> {
+        let file = {
+            let mut files = self.files.lock().unwrap();
+            let (files, _) = &mut *files;
+            let i = files.iter().position(|file| file.path == path)?;
+            let file = files.remove(i).unwrap();
+            files.push_back(file);
+            file
+        };
+        let image = image::open(self.file_path(path)).ok()?.to_rgba8();
+        Some(Decoded {
+            image: Arc::new(image),
+            width: file.width,
+            height: file.height,
+        })
+    }
+}
+
+impl Drop for DiskCache {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Loader {
     bmp_cache: BitmapCache,
     image_cache: ImageCache,
-    errors: Arc<Mutex<Vec<(PathHash, Arc<Error>)>>>,
+    disk_cache: Arc<DiskCache>,
+    spill: mpsc::UnboundedSender<(PathHash, Decoded)>,
+    archive_cache: Arc<ArchiveCache>,
+    max_size: Arc<AtomicU32>,
+    backends: Arc<Mutex<BTreeMap<String, DecodeBackend>>>,
+    scaled_jpeg: Arc<AtomicBool>,
+    threads: Arc<AtomicUsize>,
+    mipmaps: Arc<AtomicBool>,
+}
+
+impl Loader {
+    fn bitmap(&self, dc: &ComPtr<ID2D1DeviceContext>, decoded: &Decoded) -> Result<Bitmap, Error> {
+        let bmp = create_bitmap(dc, decoded)?;
+        let mips = if self.mipmaps.load(Ordering::Relaxed) {
+            create_mipmaps(dc, decoded)
+        } else {
+            vec![]
+        };
+        Ok(Bitmap {
+            bmp,
+            mips,
+            preview: false,
+        })
+    }
+
+    fn backend(&self, path: &Path) -> DecodeBackend {
+        let ext = match path.extension() {
+            Some(ext) => ext.to_string_lossy().to_ascii_lowercase(),
+            None => return DecodeBackend::Image,
+        };
+        self.backends
+            .lock()
+            .unwrap()
+            .get(&ext)
+            .copied()
+            .unwrap_or(DecodeBackend::Image)
+    }
+
+    fn decode(&self, path: &Path, limit: u32) -> Result<Decoded, Error> {
+        if self.scaled_jpeg.load(Ordering::Relaxed) && is_jpeg(path) {
+            let resolved = self.archive_cache.resolve(path)?;
+            if is_large_jpeg(&resolved) {
+                match wic::open_image_scaled(&resolved, limit) {
+                    Ok((image, width, height)) => {
+                        debug!("scaled jpeg: {}", path.to_string_lossy());
+                        return Ok(Decoded {
+                            image: Arc::new(image),
+                            width,
+                            height,
+                        });
+                    }
+                    Err(e) => debug!("scaled jpeg: {}: {}", path.to_string_lossy(), e),
+                }
+            }
+        }
+        Ok(downscale(path, self.open(path)?, limit))
+    }
+
+    fn open(&self, path: &Path) -> Result<RgbaImage, Error> {
+        let resolved = self.archive_cache.resolve(path)?;
+        if is_tiff(&resolved) {
+            match strip::open(&resolved, self.threads.load(Ordering::Relaxed)) {
+                Ok(Some(image)) => return Ok(image),
+                Ok(None) => {}
+                Err(e) => debug!("strip decode: {}: {}", path.to_string_lossy(), e),
+            }
+        }
+        match self.backend(path) {
+            DecodeBackend::Image => match open_image(&resolved) {
+                Err(Error::Unsupported) => {
+                    debug!("wic fallback: {}", path.to_string_lossy());
+                    wic::open_image(&resolved)
+                }
+                ret => ret,
+            },
+            DecodeBackend::Wic => wic::open_image(&resolved),
+        }
+    }
+
+    fn spill(&self) {
+        let evicted = self.image_cache.lock().unwrap().take_evicted();
+        for (path, decoded) in evicted {
+            if self.disk_cache.enqueue(path) {
+                self.spill.send((path, decoded)).ok();
+            }
+        }
+    }
+}
+
+async fn spill_writer(
+    disk_cache: Arc<DiskCache>,
+    mut rx: mpsc::UnboundedReceiver<(PathHash, Decoded)>,
+) {
+    while let Some((path, decoded)) = rx.recv().await {
+        let disk_cache = disk_cache.clone();
+        let ret = tokio::task::spawn_blocking(move || disk_cache.store(path, &decoded)).await;
+        if let Ok(Err(e)) = ret {
+            debug!("disk cache: {}", e);
+        }
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let meta = path.metadata().ok()?;
+    Some((meta.len(), meta.modified().ok()?))
+}
+
+fn set_error(errors: &Errors, path: &Path, e: Error) {
+    let path_hash = to_path_hash(path);
+    let failure = Failure {
+        error: Arc::new(e),
+        stamp: file_stamp(path),
+        failed_at: Instant::now(),
+    };
+    let mut errors = errors.lock().unwrap();
+    if let Some(elem) = errors.iter_mut().find(|(p, _)| *p == path_hash) {
+        elem.1 = failure;
+    } else {
+        errors.push((path_hash, failure));
+    }
+}
+
+fn quarantined(errors: &Errors, path: &Path, ttl: Duration) -> Option<Arc<Error>> {
+    let path_hash = to_path_hash(path);
+    let (error, stamp, failed_at) = {
+        let errors = errors.lock().unwrap();
+        let (_, failure) = errors.iter().find(|(p, _)| *p == path_hash)?;
+        (failure.error.clone(), failure.stamp, failure.failed_at)
+    };
+    if failed_at.elapsed() < ttl && file_stamp(path) == stamp {
+        return Some(error);
+    }
+    debug!("re-probe: {}", path.to_string_lossy());
+    errors.lock().unwrap().retain(|(p, _)| *p != path_hash);
+    None
+}
+
+fn load_image(
+    dc: ComPtr<ID2D1DeviceContext>,
+    path: PathBuf,
+    loader: &Loader,
+    token: &CancelToken,
+) -> Result<(), Error> {
+    let path_hash = to_path_hash(&path);
+    if matches!(loader.bmp_cache.lock().unwrap().lookup(path_hash), Some(b) if !b.preview) {
+        return Ok(());
+    }
+    if token.is_canceled() {
+        return Err(Error::Canceled);
+    }
+    let cached = loader
+        .image_cache
+        .lock()
+        .unwrap()
+        .lookup(path_hash)
+        .cloned();
+    let decoded = match cached {
+        Some(decoded) => decoded,
+        None => {
+            let start = Instant::now();
+            let decoded = match loader.disk_cache.load(path_hash) {
+                Some(decoded) => {
+                    debug!("disk cache: hit: {}", path.to_string_lossy());
+                    decoded
+                }
+                None => loader.decode(
+                    &path,
+                    decode_limit(&dc, loader.max_size.load(Ordering::Relaxed)),
+                )?,
+            };
+            loader.image_cache.lock().unwrap().push_timed(
+                path_hash,
+                decoded.clone(),
+                start.elapsed(),
+            );
+            loader.spill();
+            decoded
+        }
+    };
+    let start = Instant::now();
+    let bitmap = loader.bitmap(&dc, &decoded)?;
+    loader
+        .bmp_cache
+        .lock()
+        .unwrap()
+        .push_timed(path_hash, bitmap, start.elapsed());
+    Ok(())
+}
+
+fn load_preview(dc: &ComPtr<ID2D1DeviceContext>, path: &Path, loader: &Loader) -> bool {
+    let path_hash = to_path_hash(path);
+    if loader.bmp_cache.lock().unwrap().find(path_hash).is_some()
+        || loader.image_cache.lock().unwrap().find(path_hash).is_some()
+    {
+        return false;
+    }
+    let decoded = match loader
+        .archive_cache
+        .resolve(path)
+        .ok()
+        .and_then(|resolved| decode_preview(&resolved))
+    {
+        Some(decoded) => decoded,
+        None => return false,
+    };
+    let bmp = match create_bitmap(dc, &decoded) {
+        Ok(bmp) => bmp,
+        Err(_) => return false,
+    };
+    debug!("preview: {}", path.to_string_lossy());
+    loader.bmp_cache.lock().unwrap().push(
+        path_hash,
+        Bitmap {
+            bmp,
+            mips: vec![],
+            preview: true,
+        },
+    );
+    true
+}
+
+#[derive(Default)]
+struct PrefetchQueue {
+    paths: VecDeque<PathBuf>,
+    dc: Option<ComPtr<ID2D1DeviceContext>>,
+}
+
+impl std::fmt::Debug for PrefetchQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.paths.iter()).finish()
+    }
+}
+
+async fn prefetch(
+    queue: Arc<Mutex<PrefetchQueue>>,
+    notify: Arc<Notify>,
+    loader: Loader,
+    errors: Errors,
+    error_ttl: Duration,
+) {
+    loop {
+        let next = {
+            let mut queue = queue.lock().unwrap();
+            let dc = queue.dc.clone();
+            queue.paths.pop_front().zip(dc)
+        };
+        let (path, dc) = match next {
+            Some(next) => next,
+            None => {
+                notify.notified().await;
+                continue;
+            }
+        };
+        let loader = loader.clone();
+        let errors = errors.clone();
+        tokio::task::spawn_blocking(move || {
+            if quarantined(&errors, &path, error_ttl).is_some() {
+                return;
+            }
+            debug!("prefetch: {}", path.to_string_lossy());
+            if let Err(e) = load_image(dc, path.clone(), &loader, &CancelToken::default()) {
+                set_error(&errors, &path, e);
+            }
+        })
+        .await
+        .ok();
+    }
+}
+
+#[derive(Debug)]
+pub struct ImageManager {
+    runtime: Arc<tokio::runtime::Runtime>,
+    loader: Loader,
+    errors: Errors,
+    error_ttl: Duration,
+    probe_interval: Duration,
+    request: Mutex<CancelToken>,
+    prefetch_queue: Arc<Mutex<PrefetchQueue>>,
+    prefetch_notify: Arc<Notify>,
+    preview: Arc<AtomicBool>,
+    full_request: Arc<Mutex<Option<PathHash>>>,
+    diff: Arc<Mutex<Option<Diff>>>,
 }
 
 impl ImageManager {
     pub fn new(
-        worker_threads: usize,
+        runtime: Arc<tokio::runtime::Runtime>,
         bmp_target_size: usize,
         image_target_size: usize,
+        cache_policy: CachePolicyKind,
+        archive_cache: Arc<ArchiveCache>,
+        error_ttl: Duration,
+        probe_interval: Duration,
     ) -> anyhow::Result<Self> {
-        let runtime = tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(worker_threads)
-            .build()?;
+        let disk_cache = Arc::new(DiskCache::new(0));
+        let (spill, rx) = mpsc::unbounded_channel();
+        runtime.spawn(spill_writer(disk_cache.clone(), rx));
+        let loader = Loader {
+            bmp_cache: Arc::new(Mutex::new(Cache::new(bmp_target_size, cache_policy))),
+            image_cache: Arc::new(Mutex::new(
+                Cache::new(image_target_size, cache_policy).keep_evicted(),
+            )),
+            disk_cache,
+            spill,
+            archive_cache,
+            max_size: Arc::new(AtomicU32::new(0)),
+            backends: Arc::new(Mutex::new(BTreeMap::new())),
+            scaled_jpeg: Arc::new(AtomicBool::new(true)),
+            threads: Arc::new(AtomicUsize::new(1)),
+            mipmaps: Arc::new(AtomicBool::new(true)),
+        };
+        let errors = Arc::new(Mutex::new(vec![]));
+        let prefetch_queue = Arc::new(Mutex::new(PrefetchQueue::default()));
+        let prefetch_notify = Arc::new(Notify::new());
+        runtime.spawn(prefetch(
+            prefetch_queue.clone(),
+            prefetch_notify.clone(),
+            loader.clone(),
+            errors.clone(),
+            error_ttl,
+        ));
         Ok(Self {
             runtime,
-            bmp_cache: Arc::new(Mutex::new(Cache::new(bmp_target_size))),
-            image_cache: Arc::new(Mutex::new(Cache::new(image_target_size))),
-            errors: Arc::new(Mutex::new(vec![])),
+            loader,
+            errors,
+            error_ttl,
+            probe_interval,
+            request: Mutex::new(CancelToken::default()),
+            prefetch_queue,
+            prefetch_notify,
+            preview: Arc::new(AtomicBool::new(true)),
+            full_request: Arc::new(Mutex::new(None)),
+            diff: Arc::new(Mutex::new(None)),
         })
     }
 
     pub fn clear(&self) {
-        self.runtime.block_on(async {
-            self.bmp_cache.lock().await.clear();
-            self.image_cache.lock().await.clear();
-        });
+        self.loader.bmp_cache.lock().unwrap().clear();
+        self.loader.image_cache.lock().unwrap().clear();
+        self.loader.disk_cache.clear();
+        self.errors.lock().unwrap().clear();
+        self.prefetch_queue.lock().unwrap().paths.clear();
+    }
+
+    pub fn clear_bitmaps(&self) {
+        self.loader.bmp_cache.lock().unwrap().clear();
+        self.diff.lock().unwrap().take();
+        let mut queue = self.prefetch_queue.lock().unwrap();
+        queue.paths.clear();
+        queue.dc = None;
+    }
+
+    pub fn invalidate(&self, path: &Path) {
+        let path_hash = to_path_hash(path);
+        self.loader.bmp_cache.lock().unwrap().remove(path_hash);
+        self.loader.image_cache.lock().unwrap().remove(path_hash);
+        self.loader.disk_cache.remove(path_hash);
+        self.errors.lock().unwrap().retain(|(p, _)| *p != path_hash);
+    }
+
+    pub fn set_cache_sizes(&self, bmp_target_size: usize, image_target_size: usize) {
+        self.loader
+            .bmp_cache
+            .lock()
+            .unwrap()
+            .set_target_size(bmp_target_size);
+        self.loader
+            .image_cache
+            .lock()
+            .unwrap()
+            .set_target_size(image_target_size);
+        self.loader.spill();
+    }
+
+    pub fn set_disk_cache_size(&self, target_size: usize) {
+        self.loader.disk_cache.set_target_size(target_size as u64);
+    }
+
+    pub fn pin(&self, paths: &[PathBuf]) {
+        let pinned = paths.iter().map(to_path_hash).collect::<Vec<_>>();
+        self.loader
+            .bmp_cache
+            .lock()
+            .unwrap()
+            .set_pinned(pinned.clone());
+        self.loader.image_cache.lock().unwrap().set_pinned(pinned);
+    }
+
+    pub fn set_cache_policy(&self, policy: CachePolicyKind) {
+        self.loader.bmp_cache.lock().unwrap().set_policy(policy);
+        self.loader.image_cache.lock().unwrap().set_policy(policy);
+        self.loader.spill();
+    }
+
+    pub fn set_max_decode_size(&self, max_size: u32) {
+        if self.loader.max_size.swap(max_size, Ordering::Relaxed) != max_size {
+            self.clear();
+        }
+    }
+
+    pub fn set_decoders(&self, decoders: &BTreeMap<String, DecodeBackend>) {
+        let backends = decoders
+            .iter()
+            .map(|(ext, backend)| (ext.to_ascii_lowercase(), *backend))
+            .collect();
+        *self.loader.backends.lock().unwrap() = backends;
+    }
+
+    pub fn set_worker_threads(&self, threads: usize) {
+        self.loader.threads.store(threads.max(1), Ordering::Relaxed);
+    }
+
+    pub fn set_scaled_jpeg(&self, enabled: bool) {
+        self.loader.scaled_jpeg.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn set_mipmaps(&self, enabled: bool) {
+        self.loader.mipmaps.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn set_preview(&self, enabled: bool) {
+        self.preview.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            bmp: self.loader.bmp_cache.lock().unwrap().stats,
+            image: self.loader.image_cache.lock().unwrap().stats,
+            queue_depth: self.prefetch_queue.lock().unwrap().paths.len(),
+        }
     }
 
     pub fn bmp_cache_size(&self) -> usize {
-        self.runtime.block_on(async {
-            let cache = self.bmp_cache.lock().await;
-            cache.size()
-        })
+        self.loader.bmp_cache.lock().unwrap().size()
     }
 
     pub fn image_cache_size(&self) -> usize {
-        self.runtime.block_on(async {
-            let cache = self.image_cache.lock().await;
-            cache.size()
-        })
+        self.loader.image_cache.lock().unwrap().size()
+    }
+
+    fn supersede(&self) -> CancelToken {
+        let mut request = self.request.lock().unwrap();
+        request.cancel();
+        *request = CancelToken::default();
+        request.clone()
     }
 
     pub fn load(
         &self,
         dc: ComPtr<ID2D1DeviceContext>,
         path: &Path,
-        complete: impl FnOnce(PathBuf) + Send + 'static,
+        preview: impl Fn(PathBuf) + Send + 'static,
+        complete: impl Fn(PathBuf) + Send + 'static,
+    ) {
+        let token = self.supersede();
+        self.spawn_load(dc, path, token, preview, complete);
+    }
+
+    pub fn prefetch(&self, dc: ComPtr<ID2D1DeviceContext>, paths: Vec<PathBuf>) {
+        let mut queue = self.prefetch_queue.lock().unwrap();
+        queue.paths = paths.into();
+        queue.dc = Some(dc);
+        self.prefetch_notify.notify_one();
+    }
+
+    pub fn load_all(
+        &self,
+        dc: ComPtr<ID2D1DeviceContext>,
+        paths: &[PathBuf],
+        complete: impl Fn(PathBuf) + Send + Clone + 'static,
+    ) {
+        let token = self.supersede();
+        for path in paths {
+            self.spawn_load(
+                dc.clone(),
+                path,
+                token.clone(),
+                complete.clone(),
+                complete.clone(),
+            );
+        }
+    }
+
+    fn spawn_load(
+        &self,
+        dc: ComPtr<ID2D1DeviceContext>,
+        path: &Path,
+        token: CancelToken,
+        preview: impl Fn(PathBuf) + Send + 'static,
+        complete: impl Fn(PathBuf) + Send + 'static,
     ) {
-        self.runtime.block_on(async {
-            let path_hash = to_path_hash(path);
-            let path = path.to_path_buf();
-            let bmp_cache = self.bmp_cache.clone();
-            let image_cache = self.image_cache.clone();
-            let errors = self.errors.clone();
-            self.runtime.spawn(async move {
-                let img = load_image(dc, path.clone(), path_hash, bmp_cache, image_cache).await;
-                if let Err(e) = img {
-                    let mut errors = errors.lock().await;
-                    let e = Arc::new(e);
-                    if let Some(elem) = errors.iter_mut().find(|(p, _)| *p == path_hash) {
-                        elem.1 = e;
-                    } else {
-                        errors.push((path_hash, e));
+        let path = path.to_path_buf();
+        let loader = self.loader.clone();
+        let errors = self.errors.clone();
+        let error_ttl = self.error_ttl;
+        let probe_interval = self.probe_interval;
+        let preview_enabled = self.preview.clone();
+        self.runtime.spawn(async move {
+            let img = {
+                let dc = dc.clone();
+                let path = path.clone();
+                let loader = loader.clone();
+                let errors = errors.clone();
+                let token = token.clone();
+                tokio::task::spawn_blocking(move || {
+                    if quarantined(&errors, &path, error_ttl).is_some() {
+                        return None;
+                    }
+                    if preview_enabled.load(Ordering::Relaxed)
+                        && !token.is_canceled()
+                        && load_preview(&dc, &path, &loader)
+                    {
+                        preview(path.clone());
                     }
+                    let ret = load_image(dc, path.clone(), &loader, &token);
+                    if let Err(e) = ret {
+                        if matches!(e, Error::Canceled) {
+                            return Some(Err(e));
+                        }
+                        set_error(&errors, &path, e);
+                        return None;
+                    }
+                    Some(Ok(()))
+                })
+                .await
+                .unwrap_or(Some(Err(Error::Canceled)))
+            };
+            match img {
+                Some(Ok(_)) => {
+                    complete(path);
+                    return;
+                }
+                Some(Err(_)) => {
+                    debug!("canceled: {}", path.to_string_lossy());
+                    return;
+                }
+                None => complete(path.clone()),
+            }
+            loop {
+                tokio::time::sleep(probe_interval).await;
+                if token.is_canceled() {
+                    break;
+                }
+                let img = {
+                    let dc = dc.clone();
+                    let path = path.clone();
+                    let loader = loader.clone();
+                    let errors = errors.clone();
+                    tokio::task::spawn_blocking(move || {
+                        if quarantined(&errors, &path, error_ttl).is_some() {
+                            return None;
+                        }
+                        let ret = load_image(dc, path.clone(), &loader, &CancelToken::default());
+                        if let Err(e) = ret {
+                            set_error(&errors, &path, e);
+                            return None;
+                        }
+                        Some(())
+                    })
+                    .await
+                    .unwrap_or(None)
+                };
+                if img.is_some() {
+                    complete(path);
+                    break;
+                }
+            }
+        });
+    }
+
+    pub fn load_full(
+        &self,
+        dc: ComPtr<ID2D1DeviceContext>,
+        path: &Path,
+        complete: impl Fn(PathBuf) + Send + 'static,
+    ) {
+        let path_hash = to_path_hash(path);
+        let bmp = match self
+            .loader
+            .bmp_cache
+            .lock()
+            .unwrap()
+            .find(path_hash)
+            .cloned()
+        {
+            Some(bitmap) if !bitmap.preview => bitmap.bmp,
+            _ => return,
+        };
+        let (width, height) = original_size(&bmp);
+        let pixel = unsafe { bmp.GetPixelSize() };
+        let limit = decode_limit(&dc, 0);
+        if (pixel.width, pixel.height) >= fit_size(width, height, limit) {
+            return;
+        }
+        {
+            let mut full_request = self.full_request.lock().unwrap();
+            if *full_request == Some(path_hash) {
+                return;
+            }
+            *full_request = Some(path_hash);
+        }
+        debug!("load full resolution: {}", path.to_string_lossy());
+        let path = path.to_path_buf();
+        let loader = self.loader.clone();
+        let full_request = self.full_request.clone();
+        self.runtime.spawn_blocking(move || {
+            let bitmap = loader
+                .decode(&path, limit)
+                .and_then(|decoded| loader.bitmap(&dc, &decoded));
+            match bitmap {
+                Ok(bitmap) => {
+                    loader.bmp_cache.lock().unwrap().replace(path_hash, bitmap);
+                    complete(path);
                 }
-                complete(path);
+                Err(e) => debug!("load full resolution: {}: {}", path.to_string_lossy(), e),
+            }
+            full_request.lock().unwrap().take();
+        });
+    }
+
+    pub fn diff(
+        &self,
+        dc: ComPtr<ID2D1DeviceContext>,
+        a: &Path,
+        b: &Path,
+        amplification: f32,
+        complete: impl Fn() + Send + 'static,
+    ) -> Option<ComPtr<ID2D1Bitmap1>> {
+        let key = (to_path_hash(a), to_path_hash(b), amplification.to_bits());
+        {
+            let mut diff = self.diff.lock().unwrap();
+            if let Some(diff) = diff.as_ref().filter(|diff| diff.key == key) {
+                return diff.bmp.clone();
+            }
+            *diff = Some(Diff { key, bmp: None });
+        }
+        debug!("diff: {} {}", a.to_string_lossy(), b.to_string_lossy());
+        let (a, b) = (a.to_path_buf(), b.to_path_buf());
+        let loader = self.loader.clone();
+        let diff = self.diff.clone();
+        self.runtime.spawn_blocking(move || {
+            let limit = decode_limit(&dc, 0);
+            let decoded = loader.decode(&a, limit).and_then(|decoded_a| {
+                let decoded_b = loader.decode(&b, limit)?;
+                let image = diff_image(&decoded_a.image, &decoded_b.image, amplification);
+                let decoded = Decoded {
+                    image: Arc::new(image),
+                    width: decoded_a.width,
+                    height: decoded_a.height,
+                };
+                create_bitmap(&dc, &decoded)
             });
+            match decoded {
+                Ok(bmp) => {
+                    let mut diff = diff.lock().unwrap();
+                    if let Some(diff) = diff.as_mut().filter(|diff| diff.key == key) {
+                        diff.bmp = Some(bmp);
+                    }
+                    complete();
+                }
+                Err(e) => warn!("diff: {}", e),
+            }
         });
+        None
     }
 
-    pub fn get(&self, path: &Path) -> Result<Option<ComPtr<ID2D1Bitmap1>>, Arc<Error>> {
-        self.runtime.block_on(async {
-            let path_hash = to_path_hash(path);
-            let bmp_cache = self.bmp_cache.lock().await;
-            let errors = self.errors.lock().await;
-            if let Some(e) = errors.iter().find(|(p, _)| *p == path_hash).map(|(_, e)| e) {
-                Err(e.clone())
-            } else {
-                Ok(bmp_cache.find(path_hash).cloned())
+    pub fn perceptual_hashes(
+        &self,
+        paths: Vec<PathBuf>,
+        complete: impl FnOnce(Vec<(PathBuf, u64)>) + Send + 'static,
+    ) {
+        const HASH_SIZE: u32 = 256;
+        let threads = self.loader.threads.load(Ordering::Relaxed).max(1);
+        let chunk = paths.len().div_ceil(threads).max(1);
+        let results = Arc::new(Mutex::new(vec![]));
+        let remaining = Arc::new(AtomicUsize::new(paths.len().div_ceil(chunk)));
+        let complete = Arc::new(Mutex::new(Some(complete)));
+        if paths.is_empty() {
+            if let Some(complete) = complete.lock().unwrap().take() {
+                complete(vec![]);
             }
-        })
+            return;
+        }
+        for chunk in paths.chunks(chunk) {
+            let chunk = chunk.to_vec();
+            let loader = self.loader.clone();
+            let results = results.clone();
+            let remaining = remaining.clone();
+            let complete = complete.clone();
+            self.runtime.spawn_blocking(move || {
+                let hashes = chunk
+                    .into_iter()
+                    .filter_map(|path| match loader.decode(&path, HASH_SIZE) {
+                        Ok(decoded) => Some((path, duplicates::dhash(&decoded.image))),
+                        Err(e) => {
+                            debug!("perceptual hash: {}: {}", path.to_string_lossy(), e);
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                results.lock().unwrap().extend(hashes);
+                if remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    let results = std::mem::take(&mut *results.lock().unwrap());
+                    if let Some(complete) = complete.lock().unwrap().take() {
+                        complete(results);
+                    }
+                }
+            });
+        }
+    }
+
+    pub fn decode(
+        &self,
+        path: &Path,
+        limit: u32,
+        complete: impl FnOnce(PathBuf, Result<RgbaImage, Error>) + Send + 'static,
+    ) {
+        let path = path.to_path_buf();
+        let loader = self.loader.clone();
+        self.runtime.spawn_blocking(move || {
+            let cached = loader
+                .image_cache
+                .lock()
+                .unwrap()
+                .find(to_path_hash(&path))
+                .cloned()
+                .filter(|decoded| {
+                    decoded.image.dimensions() == fit_size(decoded.width, decoded.height, limit)
+                });
+            let ret = match cached {
+                Some(decoded) => Ok(RgbaImage::clone(&decoded.image)),
+                None => loader.decode(&path, limit).map(|decoded| {
+                    Arc::try_unwrap(decoded.image).unwrap_or_else(|image| RgbaImage::clone(&image))
+                }),
+            };
+            complete(path, ret);
+        });
+    }
+
+    pub fn open(
+        &self,
+        path: &Path,
+        complete: impl FnOnce(PathBuf, Result<RgbaImage, Error>) + Send + 'static,
+    ) {
+        let path = path.to_path_buf();
+        let loader = self.loader.clone();
+        let errors = self.errors.clone();
+        let error_ttl = self.error_ttl;
+        self.runtime.spawn_blocking(move || {
+            if let Some(e) = quarantined(&errors, &path, error_ttl) {
+                complete(path, Err(Error::Other(anyhow::anyhow!("{}", e))));
+                return;
+            }
+            let cached = loader
+                .image_cache
+                .lock()
+                .unwrap()
+                .find(to_path_hash(&path))
+                .cloned()
+                .filter(|decoded| decoded.is_full());
+            let ret = match cached {
+                Some(decoded) => Ok(RgbaImage::clone(&decoded.image)),
+                None => loader.open(&path),
+            };
+            complete(path, ret);
+        });
+    }
+
+    pub fn edge_color(&self, path: &Path) -> Option<(f32, f32, f32)> {
+        let mut image_cache = self.loader.image_cache.lock().unwrap();
+        image_cache
+            .find(to_path_hash(path))
+            .map(|decoded| edge_color(&decoded.image))
+    }
+
+    pub fn get(&self, path: &Path) -> Result<Option<ComPtr<ID2D1Bitmap1>>, Arc<Error>> {
+        let path_hash = to_path_hash(path);
+        let errors = self.errors.lock().unwrap();
+        if let Some((_, failure)) = errors.iter().find(|(p, _)| *p == path_hash) {
+            return Err(failure.error.clone());
+        }
+        Ok(self
+            .loader
+            .bmp_cache
+            .lock()
+            .unwrap()
+            .find(path_hash)
+            .map(|bitmap| bitmap.bmp.clone()))
+    }
+
+    pub fn pixel(&self, path: &Path, x: u32, y: u32) -> Option<[u8; 4]> {
+        let mut cache = self.loader.image_cache.lock().unwrap();
+        let decoded = cache.find(to_path_hash(path))?;
+        if x >= decoded.width || y >= decoded.height {
+            return None;
+        }
+        let (width, height) = decoded.image.dimensions();
+        let x = (x as u64 * width as u64 / decoded.width as u64) as u32;
+        let y = (y as u64 * height as u64 / decoded.height as u64) as u32;
+        Some(decoded.image.get_pixel(x, y).0)
+    }
+
+    pub fn mipmaps(&self, path: &Path) -> Vec<ComPtr<ID2D1Bitmap1>> {
+        self.loader
+            .bmp_cache
+            .lock()
+            .unwrap()
+            .find(to_path_hash(path))
+            .map(|bitmap| bitmap.mips.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Item(usize);
+
+    impl GetSize for Item {
+        fn get_size(&self) -> usize {
+            self.0
+        }
+    }
+
+    fn keys(cache: &Cache<Item>) -> Vec<u64> {
+        let mut keys = cache.items.keys().map(|path| path.0).collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn lru_find_refreshes_recency() {
+        let mut cache = Cache::new(20, CachePolicyKind::Lru);
+        cache.push(PathHash(1), Item(10));
+        cache.push(PathHash(2), Item(10));
+        assert!(cache.find(PathHash(1)).is_some());
+        cache.push(PathHash(3), Item(10));
+        assert_eq!(keys(&cache), [1, 3]);
+        assert_eq!(cache.size(), 20);
+    }
+
+    #[test]
+    fn fifo_ignores_recency() {
+        let mut cache = Cache::new(20, CachePolicyKind::Fifo);
+        cache.push(PathHash(1), Item(10));
+        cache.push(PathHash(2), Item(10));
+        assert!(cache.find(PathHash(1)).is_some());
+        cache.push(PathHash(3), Item(10));
+        assert_eq!(keys(&cache), [2, 3]);
+    }
+
+    #[test]
+    fn size_weighted_evicts_large_entries_first() {
+        let mut cache = Cache::new(30, CachePolicyKind::SizeWeighted);
+        cache.push(PathHash(1), Item(5));
+        cache.push(PathHash(2), Item(20));
+        cache.push(PathHash(3), Item(10));
+        assert_eq!(keys(&cache), [1, 3]);
+        assert_eq!(cache.size(), 15);
+    }
+
+    #[test]
+    fn size_weighted_evicts_old_small_entries() {
+        let mut cache = Cache::new(30, CachePolicyKind::SizeWeighted);
+        cache.push(PathHash(1), Item(5));
+        cache.push(PathHash(2), Item(20));
+        for _ in 0..10 {
+            assert!(cache.find(PathHash(2)).is_some());
+        }
+        cache.push(PathHash(3), Item(10));
+        assert_eq!(keys(&cache), [2, 3]);
+        assert_eq!(cache.size(), 30);
+    }
+
+    #[test]
+    fn size_accounting() {
+        let mut cache = Cache::new(100, CachePolicyKind::Lru);
+        cache.push(PathHash(1), Item(10));
+        cache.push(PathHash(2), Item(20));
+        assert_eq!(cache.size(), 30);
+        cache.push(PathHash(1), Item(50));
+        assert_eq!(cache.size(), 30);
+        cache.replace(PathHash(1), Item(40));
+        assert_eq!(cache.size(), 60);
+        cache.remove(PathHash(2));
+        assert_eq!(cache.size(), 40);
+        cache.remove(PathHash(2));
+        assert_eq!(cache.size(), 40);
+        cache.clear();
+        assert_eq!(cache.size(), 0);
+        assert!(keys(&cache).is_empty());
+    }
+
+    #[test]
+    fn pinned_entries_are_never_evicted() {
+        let mut cache = Cache::new(20, CachePolicyKind::Lru);
+        cache.set_pinned(vec![PathHash(1)]);
+        cache.push(PathHash(1), Item(10));
+        cache.push(PathHash(2), Item(10));
+        assert!(cache.find(PathHash(2)).is_some());
+        cache.push(PathHash(3), Item(10));
+        assert_eq!(keys(&cache), [1, 3]);
+        cache.set_pinned(vec![PathHash(1), PathHash(3)]);
+        cache.push(PathHash(4), Item(10));
+        assert_eq!(keys(&cache), [1, 3, 4]);
+        assert_eq!(cache.size(), 30);
+    }
+
+    #[test]
+    fn set_target_size_shrinks_cache() {
+        let mut cache = Cache::new(40, CachePolicyKind::Lru).keep_evicted();
+        for i in 1..=4 {
+            cache.push(PathHash(i), Item(10));
+        }
+        assert!(cache.find(PathHash(1)).is_some());
+        cache.set_target_size(20);
+        assert_eq!(keys(&cache), [1, 4]);
+        assert_eq!(cache.size(), 20);
+        let mut evicted = cache
+            .take_evicted()
+            .into_iter()
+            .map(|(path, _)| path.0)
+            .collect::<Vec<_>>();
+        evicted.sort_unstable();
+        assert_eq!(evicted, [2, 3]);
+    }
+
+    fn errored_file(name: &str) -> (PathBuf, Errors) {
+        let path = std::env::temp_dir().join(format!("niv-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, b"partial").unwrap();
+        let errors: Errors = Arc::new(Mutex::new(vec![]));
+        set_error(&errors, &path, Error::Unsupported);
+        (path, errors)
+    }
+
+    #[test]
+    fn quarantine_holds_until_ttl() {
+        let (path, errors) = errored_file("ttl");
+        assert!(quarantined(&errors, &path, Duration::from_secs(60)).is_some());
+        assert!(quarantined(&errors, &path, Duration::from_secs(0)).is_none());
+        assert!(errors.lock().unwrap().is_empty());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn quarantine_releases_changed_file() {
+        let (path, errors) = errored_file("stamp");
+        std::fs::write(&path, b"partial and now complete").unwrap();
+        assert!(quarantined(&errors, &path, Duration::from_secs(60)).is_none());
+        std::fs::remove_file(&path).ok();
     }
 }
@@ -1,14 +1,69 @@
+mod animation;
+mod annotation;
 mod application;
+mod archive;
+mod args;
+mod clipboard;
 mod config;
+mod control;
+mod convert;
+mod crop;
+mod cursor;
+mod device;
 mod dialog;
 mod directory;
+mod duplicates;
 mod error;
+mod formats;
+mod gamepad;
+mod gesture;
+mod ignore;
 mod images;
+mod memory;
+mod metadata;
+mod notes;
+mod panorama;
+mod properties;
+mod ratings;
 mod renderer;
+mod replay;
+mod sequence;
+mod shell;
+mod stats;
+mod stereo;
+mod strip;
+mod touch;
+mod wic;
+mod wndproc;
 
 use application::*;
 
 fn main() {
+    if std::env::args().skip(1).any(|arg| arg == "--formats") {
+        let config = config::load_config().unwrap_or_default();
+        println!("{}", formats::report(&config.extensions));
+        return;
+    }
+    if args::is_screensaver_config() {
+        dialog::information_dialog(&format!(
+            "スクリーンセーバーで表示するフォルダーは{}の`screensaver_folder`、表示する間隔は`slideshow_interval`で設定します。",
+            config::config_path().to_string_lossy()
+        ));
+        return;
+    }
+    if let Some(args) = args::ConvertArgs::parse() {
+        simplelog::TermLogger::init(
+            simplelog::LevelFilter::Info,
+            simplelog::Config::default(),
+            simplelog::TerminalMode::Mixed,
+        )
+        .unwrap();
+        if let Err(e) = args.and_then(convert::run_batch) {
+            log::error!("convert: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     wita::initialize::<Application>();
     wita::run(wita::RunType::Wait, Application::new().unwrap());
 }
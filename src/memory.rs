@@ -0,0 +1,31 @@
+use crate::control::{Command, CommandQueues};
+use log::error;
+use std::time::Duration;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::memoryapi::*;
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::{INFINITE, WAIT_OBJECT_0};
+
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn watch_memory_pressure(queues: CommandQueues) {
+    std::thread::spawn(move || unsafe {
+        let low = CreateMemoryResourceNotification(LowMemoryResourceNotification);
+        if low.is_null() {
+            error!("CreateMemoryResourceNotification failed");
+            return;
+        }
+        while WaitForSingleObject(low, INFINITE) == WAIT_OBJECT_0 {
+            queues.push(Command::MemoryPressure(true));
+            loop {
+                std::thread::sleep(RECOVERY_INTERVAL);
+                let mut state = 0;
+                if QueryMemoryResourceNotification(low, &mut state) == 0 || state == 0 {
+                    break;
+                }
+            }
+            queues.push(Command::MemoryPressure(false));
+        }
+        CloseHandle(low);
+    });
+}
@@ -0,0 +1,501 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const SCAN_SIZE: u64 = 256 * 1024;
+
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_GPS_IFD: u16 = 0x8825;
+const TAG_GPS_LATITUDE_REF: u16 = 1;
+const TAG_GPS_LATITUDE: u16 = 2;
+const TAG_GPS_LONGITUDE_REF: u16 = 3;
+const TAG_GPS_LONGITUDE: u16 = 4;
+const TAG_GPS_ALTITUDE_REF: u16 = 5;
+const TAG_GPS_ALTITUDE: u16 = 6;
+
+const IPTC_TITLE: u8 = 5;
+const IPTC_KEYWORDS: u8 = 25;
+const IPTC_COPYRIGHT: u8 = 116;
+const IPTC_CAPTION: u8 = 120;
+
+const TYPE_BYTE: u16 = 1;
+const TYPE_ASCII: u16 = 2;
+const TYPE_SHORT: u16 = 3;
+const TYPE_RATIONAL: u16 = 5;
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    pos: usize,
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    le: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let le = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self { data, le })
+    }
+
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let b = self.data.get(pos..pos + 2)?;
+        Some(if self.le {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let b = self.data.get(pos..pos + 4)?;
+        Some(if self.le {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        })
+    }
+
+    fn first_ifd(&self) -> Option<usize> {
+        self.u32(4).map(|offset| offset as usize)
+    }
+
+    fn entries(&self, ifd: usize) -> Vec<Entry> {
+        let count = self.u16(ifd).unwrap_or(0) as usize;
+        (0..count)
+            .filter_map(|i| {
+                let pos = ifd + 2 + i * 12;
+                Some(Entry {
+                    tag: self.u16(pos)?,
+                    kind: self.u16(pos + 2)?,
+                    count: self.u32(pos + 4)?,
+                    pos: pos + 8,
+                })
+            })
+            .collect()
+    }
+
+    fn value_pos(&self, entry: &Entry, size: usize) -> Option<usize> {
+        if size * entry.count as usize <= 4 {
+            Some(entry.pos)
+        } else {
+            self.u32(entry.pos).map(|offset| offset as usize)
+        }
+    }
+
+    fn byte(&self, entry: &Entry) -> Option<u8> {
+        if entry.kind != TYPE_BYTE {
+            return None;
+        }
+        self.data.get(entry.pos).copied()
+    }
+
+    fn ascii(&self, entry: &Entry) -> Option<String> {
+        if entry.kind != TYPE_ASCII {
+            return None;
+        }
+        let pos = self.value_pos(entry, 1)?;
+        let bytes = self.data.get(pos..pos + entry.count as usize)?;
+        let bytes = bytes.split(|b| *b == 0).next().unwrap_or_default();
+        Some(String::from_utf8_lossy(bytes).trim().to_string())
+    }
+
+    fn shorts(&self, entry: &Entry) -> Option<Vec<u16>> {
+        if entry.kind != TYPE_SHORT {
+            return None;
+        }
+        let pos = self.value_pos(entry, 2)?;
+        (0..entry.count as usize)
+            .map(|i| self.u16(pos + i * 2))
+            .collect()
+    }
+
+    fn rationals(&self, entry: &Entry) -> Option<Vec<f64>> {
+        if entry.kind != TYPE_RATIONAL {
+            return None;
+        }
+        let pos = self.value_pos(entry, 8)?;
+        (0..entry.count as usize)
+            .map(|i| {
+                let n = self.u32(pos + i * 8)?;
+                let d = self.u32(pos + i * 8 + 4)?;
+                if d == 0 {
+                    None
+                } else {
+                    Some(n as f64 / d as f64)
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Gps {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+impl std::fmt::Display for Gps {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:.6}, {:.6}", self.latitude, self.longitude)
+    }
+}
+
+fn parse_gps(tiff: &Tiff) -> Option<Gps> {
+    let ifd0 = tiff.entries(tiff.first_ifd()?);
+    let gps_ifd = ifd0.iter().find(|e| e.tag == TAG_GPS_IFD)?;
+    let entries = tiff.entries(tiff.u32(gps_ifd.pos)? as usize);
+    let find = |tag| entries.iter().find(|e| e.tag == tag);
+    let degrees = |tag| {
+        let v = tiff.rationals(find(tag)?)?;
+        Some(v.first()? + v.get(1).unwrap_or(&0.0) / 60.0 + v.get(2).unwrap_or(&0.0) / 3600.0)
+    };
+    let sign = |tag, negative| match find(tag).and_then(|e| tiff.ascii(e)) {
+        Some(r) if r == negative => -1.0,
+        _ => 1.0,
+    };
+    let latitude = degrees(TAG_GPS_LATITUDE)? * sign(TAG_GPS_LATITUDE_REF, "S");
+    let longitude = degrees(TAG_GPS_LONGITUDE)? * sign(TAG_GPS_LONGITUDE_REF, "W");
+    let altitude = find(TAG_GPS_ALTITUDE)
+        .and_then(|e| tiff.rationals(e))
+        .and_then(|v| v.first().copied())
+        .map(
+            |altitude| match find(TAG_GPS_ALTITUDE_REF).and_then(|e| tiff.byte(e)) {
+                Some(1) => -altitude,
+                _ => altitude,
+            },
+        );
+    Some(Gps {
+        latitude,
+        longitude,
+        altitude,
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Format {
+    pub bit_depth: u8,
+    pub channels: u8,
+    pub subsampling: Option<&'static str>,
+}
+
+fn jpeg_format(segments: &[(u8, &[u8])]) -> Option<Format> {
+    let sof = segments
+        .iter()
+        .find(|(marker, _)| (0xc0..=0xcf).contains(marker) && ![0xc4, 0xc8, 0xcc].contains(marker))
+        .map(|(_, segment)| *segment)?;
+    let channels = *sof.get(5)?;
+    let sampling = |i: usize| {
+        let factor = *sof.get(7 + i * 3)?;
+        Some((factor >> 4, factor & 0x0f))
+    };
+    let subsampling = if channels == 3 {
+        let (yh, yv) = sampling(0)?;
+        let (ch, cv) = sampling(1)?;
+        if ch == 0 || cv == 0 {
+            None
+        } else {
+            match (yh / ch, yv / cv) {
+                (1, 1) => Some("4:4:4"),
+                (2, 1) => Some("4:2:2"),
+                (2, 2) => Some("4:2:0"),
+                (4, 1) => Some("4:1:1"),
+                (1, 2) => Some("4:4:0"),
+                _ => None,
+            }
+        }
+    } else {
+        None
+    };
+    Some(Format {
+        bit_depth: *sof.first()?,
+        channels,
+        subsampling,
+    })
+}
+
+fn png_format(data: &[u8]) -> Option<Format> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") || data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let channels = match *data.get(25)? {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return None,
+    };
+    Some(Format {
+        bit_depth: *data.get(24)?,
+        channels,
+        subsampling: None,
+    })
+}
+
+fn tiff_format(tiff: &Tiff) -> Option<Format> {
+    let ifd0 = tiff.entries(tiff.first_ifd()?);
+    let find = |tag| {
+        ifd0.iter()
+            .find(|e| e.tag == tag)
+            .and_then(|e| tiff.shorts(e))
+    };
+    let bits = find(TAG_BITS_PER_SAMPLE).unwrap_or_else(|| vec![1]);
+    let channels = find(TAG_SAMPLES_PER_PIXEL)
+        .and_then(|v| v.first().copied())
+        .unwrap_or(1);
+    Some(Format {
+        bit_depth: *bits.first()? as u8,
+        channels: channels as u8,
+        subsampling: None,
+    })
+}
+
+fn bmp_format(data: &[u8]) -> Option<Format> {
+    if !data.starts_with(b"BM") {
+        return None;
+    }
+    let bits = u16::from_le_bytes([*data.get(28)?, *data.get(29)?]);
+    let (bit_depth, channels) = match bits {
+        24 => (8, 3),
+        32 => (8, 4),
+        _ => (bits as u8, 1),
+    };
+    Some(Format {
+        bit_depth,
+        channels,
+        subsampling: None,
+    })
+}
+
+fn jpeg_segments(data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = vec![];
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return segments;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xff {
+        let marker = data[pos + 1];
+        if marker == 0xda {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        match data.get(pos + 4..pos + 2 + len) {
+            Some(segment) => segments.push((marker, segment)),
+            None => break,
+        }
+        pos += 2 + len;
+    }
+    segments
+}
+
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len()).position(|w| w == pattern)
+}
+
+fn decode(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+fn photoshop_iptc(segment: &[u8]) -> Option<&[u8]> {
+    let data = segment.strip_prefix(b"Photoshop 3.0\0")?;
+    let mut pos = 0;
+    while data.get(pos..pos + 4)? == b"8BIM" {
+        let id = u16::from_be_bytes([*data.get(pos + 4)?, *data.get(pos + 5)?]);
+        let name_len = (*data.get(pos + 6)? as usize + 2) & !1;
+        let size_pos = pos + 6 + name_len;
+        let size = data.get(size_pos..size_pos + 4)?;
+        let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+        let start = size_pos + 4;
+        if id == 0x0404 {
+            return data.get(start..start + size);
+        }
+        pos = start + size + (size & 1);
+    }
+    None
+}
+
+fn parse_iptc(data: &[u8]) -> Vec<(u8, u8, String)> {
+    let mut records = vec![];
+    let mut pos = 0;
+    while pos + 5 <= data.len() && data[pos] == 0x1c {
+        let len = u16::from_be_bytes([data[pos + 3], data[pos + 4]]) as usize;
+        if len & 0x8000 != 0 {
+            break;
+        }
+        let value = match data.get(pos + 5..pos + 5 + len) {
+            Some(value) => value,
+            None => break,
+        };
+        records.push((
+            data[pos + 1],
+            data[pos + 2],
+            decode(value).trim().to_string(),
+        ));
+        pos += 5 + len;
+    }
+    records
+}
+
+fn unescape(s: &str) -> String {
+    let mut ret = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        ret.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let c = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            e if e.starts_with("#x") => u32::from_str_radix(&e[2..], 16)
+                .ok()
+                .and_then(std::char::from_u32),
+            e if e.starts_with('#') => e[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None,
+        };
+        match c {
+            Some(c) => {
+                ret.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                ret.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    ret.push_str(rest);
+    ret
+}
+
+fn xmp_values(xmp: &str, name: &str) -> Vec<String> {
+    let attribute = format!("{}=\"", name);
+    if let Some(i) = xmp.find(&attribute) {
+        let value = &xmp[i + attribute.len()..];
+        if let Some(end) = value.find('"') {
+            return vec![unescape(value[..end].trim())];
+        }
+    }
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let start = match xmp
+        .match_indices(&open)
+        .find(|(i, _)| xmp[i + open.len()..].starts_with(|c: char| c == '>' || c.is_whitespace()))
+    {
+        Some((i, _)) => i,
+        None => return vec![],
+    };
+    let inner = &xmp[start..];
+    let inner = match (inner.find('>'), inner.find(&close)) {
+        (Some(begin), Some(end)) if begin < end => &inner[begin + 1..end],
+        _ => return vec![],
+    };
+    let mut values = vec![];
+    let mut rest = inner;
+    while let Some(i) = rest.find("<rdf:li") {
+        rest = &rest[i..];
+        let (begin, end) = match (rest.find('>'), rest.find("</rdf:li>")) {
+            (Some(begin), Some(end)) if begin < end => (begin, end),
+            _ => break,
+        };
+        values.push(unescape(rest[begin + 1..end].trim()));
+        rest = &rest[end..];
+    }
+    if values.is_empty() && !inner.contains('<') {
+        values.push(unescape(inner.trim()));
+    }
+    values.retain(|v| !v.is_empty());
+    values
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Metadata {
+    pub gps: Option<Gps>,
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    pub keywords: Vec<String>,
+    pub copyright: Option<String>,
+    pub format: Option<Format>,
+}
+
+impl Metadata {
+    pub fn read(path: impl AsRef<Path>) -> Self {
+        let mut data = vec![];
+        let ret =
+            File::open(path.as_ref()).and_then(|file| file.take(SCAN_SIZE).read_to_end(&mut data));
+        if ret.is_err() {
+            return Self::default();
+        }
+        let mut metadata = Self::default();
+        let segments = jpeg_segments(&data);
+        let is_tiff = data.starts_with(b"II*\0") || data.starts_with(b"MM\0*");
+        let exif = if is_tiff {
+            Some(&data[..])
+        } else {
+            segments
+                .iter()
+                .find(|(marker, segment)| *marker == 0xe1 && segment.starts_with(b"Exif\0\0"))
+                .map(|(_, segment)| &segment[6..])
+        };
+        if let Some(tiff) = exif.and_then(Tiff::new) {
+            metadata.gps = parse_gps(&tiff);
+            if is_tiff {
+                metadata.format = tiff_format(&tiff);
+            }
+        }
+        if metadata.format.is_none() {
+            metadata.format = jpeg_format(&segments)
+                .or_else(|| png_format(&data))
+                .or_else(|| bmp_format(&data));
+        }
+        let xmp = find(&data, b"<x:xmpmeta").and_then(|begin| {
+            let end = find(&data[begin..], b"</x:xmpmeta>")?;
+            Some(decode(&data[begin..begin + end]))
+        });
+        if let Some(xmp) = xmp {
+            metadata.title = xmp_values(&xmp, "dc:title").into_iter().next();
+            metadata.caption = xmp_values(&xmp, "dc:description").into_iter().next();
+            metadata.keywords = xmp_values(&xmp, "dc:subject");
+            metadata.copyright = xmp_values(&xmp, "dc:rights").into_iter().next();
+        }
+        let iptc = segments
+            .into_iter()
+            .filter(|(marker, _)| *marker == 0xed)
+            .find_map(|(_, segment)| photoshop_iptc(segment))
+            .map(parse_iptc)
+            .unwrap_or_default();
+        for (_, dataset, value) in iptc.into_iter().filter(|(record, ..)| *record == 2) {
+            if value.is_empty() {
+                continue;
+            }
+            match dataset {
+                IPTC_TITLE if metadata.title.is_none() => metadata.title = Some(value),
+                IPTC_CAPTION if metadata.caption.is_none() => metadata.caption = Some(value),
+                IPTC_COPYRIGHT if metadata.copyright.is_none() => metadata.copyright = Some(value),
+                IPTC_KEYWORDS if !metadata.keywords.contains(&value) => {
+                    metadata.keywords.push(value)
+                }
+                _ => {}
+            }
+        }
+        metadata
+    }
+}
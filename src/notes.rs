@@ -0,0 +1,30 @@
+use crate::archive;
+use crate::error::Error;
+use std::path::{Path, PathBuf};
+
+fn note_path(path: &Path) -> Option<PathBuf> {
+    if archive::split(path).is_some() {
+        return None;
+    }
+    let mut name = path.file_name()?.to_os_string();
+    name.push(".txt");
+    Some(path.with_file_name(name))
+}
+
+pub fn load(path: &Path) -> String {
+    note_path(path)
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(path: &Path, text: &str) -> Result<(), Error> {
+    let path = note_path(path).ok_or(Error::Unsupported)?;
+    if text.trim().is_empty() {
+        if path.is_file() {
+            std::fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    std::fs::write(path, text)?;
+    Ok(())
+}
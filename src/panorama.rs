@@ -0,0 +1,427 @@
+use com_ptr::{hresult, ComPtr, HResult};
+use image::RgbaImage;
+use log::error;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use winapi::shared::dxgi::IDXGISurface;
+use winapi::shared::dxgiformat::*;
+use winapi::shared::dxgitype::DXGI_SAMPLE_DESC;
+use winapi::shared::winerror::E_FAIL;
+use winapi::um::d3d11::*;
+use winapi::um::d3dcommon::*;
+use winapi::um::d3dcompiler::D3DCompile;
+use winapi::um::{d2d1_1::*, dcommon::*};
+
+const MIN_FOV: f32 = 30.0;
+const MAX_FOV: f32 = 120.0;
+const XMP_SCAN_SIZE: u64 = 256 * 1024;
+
+pub fn is_equirectangular(path: impl AsRef<Path>) -> bool {
+    let mut buffer = vec![];
+    let ret = File::open(path.as_ref())
+        .and_then(|file| file.take(XMP_SCAN_SIZE).read_to_end(&mut buffer));
+    if ret.is_err() {
+        return false;
+    }
+    let text = String::from_utf8_lossy(&buffer);
+    text.contains("GPano:ProjectionType=\"equirectangular\"")
+        || text.contains("<GPano:ProjectionType>equirectangular<")
+}
+
+pub struct Panorama {
+    path: PathBuf,
+    source: RgbaImage,
+    yaw: f32,
+    pitch: f32,
+    fov: f32,
+    drag: Option<(f32, f32, f32, f32)>,
+}
+
+impl Panorama {
+    pub fn new(path: impl AsRef<Path>, source: RgbaImage, fov: f32) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            source,
+            yaw: 0.0,
+            pitch: 0.0,
+            fov: fov.clamp(MIN_FOV, MAX_FOV),
+            drag: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn fov(&self) -> f32 {
+        self.fov
+    }
+
+    pub fn zoom(&mut self, delta: f32) {
+        self.fov = (self.fov - delta).clamp(MIN_FOV, MAX_FOV);
+    }
+
+    pub fn begin_drag(&mut self, x: f32, y: f32) {
+        self.drag = Some((x, y, self.yaw, self.pitch));
+    }
+
+    pub fn end_drag(&mut self) {
+        self.drag = None;
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    pub fn drag(&mut self, x: f32, y: f32, viewport_width: f32) {
+        if let Some((x0, y0, yaw, pitch)) = self.drag {
+            let degrees_per_dip = self.fov / viewport_width;
+            self.yaw = (yaw - (x - x0) * degrees_per_dip).rem_euclid(360.0);
+            self.pitch = (pitch + (y - y0) * degrees_per_dip).clamp(-90.0, 90.0);
+        }
+    }
+}
+
+const SHADER: &str = r#"
+cbuffer View : register(b0) {
+    float yaw;
+    float pitch;
+    float tan_half_fov;
+    float aspect;
+};
+Texture2D source : register(t0);
+SamplerState source_sampler : register(s0);
+
+struct Vertex {
+    float4 position : SV_POSITION;
+    float2 uv : TEXCOORD0;
+};
+
+Vertex vs_main(uint id : SV_VertexID) {
+    Vertex v;
+    v.uv = float2((id << 1) & 2, id & 2);
+    v.position = float4(v.uv * float2(2.0, -2.0) + float2(-1.0, 1.0), 0.0, 1.0);
+    return v;
+}
+
+float4 ps_main(Vertex v) : SV_TARGET {
+    const float PI = 3.14159265;
+    float x = (v.uv.x * 2.0 - 1.0) * tan_half_fov;
+    float y = (1.0 - v.uv.y * 2.0) * tan_half_fov * aspect;
+    float sin_yaw, cos_yaw, sin_pitch, cos_pitch;
+    sincos(yaw, sin_yaw, cos_yaw);
+    sincos(pitch, sin_pitch, cos_pitch);
+    float dy = y * cos_pitch + sin_pitch;
+    float dz = -y * sin_pitch + cos_pitch;
+    float dx = x * cos_yaw + dz * sin_yaw;
+    dz = -x * sin_yaw + dz * cos_yaw;
+    float lon = atan2(dx, dz);
+    float lat = asin(dy / length(float3(dx, dy, dz)));
+    float2 uv = float2(lon / (2.0 * PI) + 0.5, 0.5 - lat / PI);
+    return source.SampleLevel(source_sampler, uv, 0);
+}
+"#;
+
+#[repr(C)]
+struct View {
+    yaw: f32,
+    pitch: f32,
+    tan_half_fov: f32,
+    aspect: f32,
+}
+
+fn compile(entry: &str, target: &str) -> Result<ComPtr<ID3DBlob>, HResult> {
+    let entry = std::ffi::CString::new(entry).unwrap();
+    let target = std::ffi::CString::new(target).unwrap();
+    let mut code = std::ptr::null_mut();
+    let mut errors: *mut ID3DBlob = std::ptr::null_mut();
+    let ret = unsafe {
+        D3DCompile(
+            SHADER.as_ptr() as _,
+            SHADER.len(),
+            std::ptr::null(),
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            entry.as_ptr(),
+            target.as_ptr(),
+            0,
+            0,
+            &mut code,
+            &mut errors,
+        )
+    };
+    if !errors.is_null() {
+        let errors = unsafe { ComPtr::from_raw(errors) };
+        let message = unsafe {
+            std::slice::from_raw_parts(
+                errors.GetBufferPointer() as *const u8,
+                errors.GetBufferSize(),
+            )
+        };
+        error!("panorama shader: {}", String::from_utf8_lossy(message));
+    }
+    ComPtr::new(|| hresult(code, ret))
+}
+
+fn bytecode(blob: &ComPtr<ID3DBlob>) -> (*const winapi::ctypes::c_void, usize) {
+    unsafe { (blob.GetBufferPointer() as _, blob.GetBufferSize()) }
+}
+
+struct Target {
+    size: (u32, u32),
+    view: ComPtr<ID3D11RenderTargetView>,
+    bitmap: ComPtr<ID2D1Bitmap1>,
+}
+
+pub struct Projector {
+    device: ComPtr<ID3D11Device>,
+    context: ComPtr<ID3D11DeviceContext>,
+    vertex_shader: ComPtr<ID3D11VertexShader>,
+    pixel_shader: ComPtr<ID3D11PixelShader>,
+    sampler: ComPtr<ID3D11SamplerState>,
+    constants: ComPtr<ID3D11Buffer>,
+    source: Option<(PathBuf, ComPtr<ID3D11ShaderResourceView>)>,
+    target: Option<Target>,
+}
+
+impl Projector {
+    pub fn new(device: &ComPtr<ID3D11Device>) -> Result<Self, HResult> {
+        let context = ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            device.GetImmediateContext(&mut obj);
+            hresult(obj, if obj.is_null() { E_FAIL } else { 0 })
+        })?;
+        let vs = compile("vs_main", "vs_4_0")?;
+        let ps = compile("ps_main", "ps_4_0")?;
+        let vertex_shader = ComPtr::new(|| unsafe {
+            let (code, len) = bytecode(&vs);
+            let mut obj = std::ptr::null_mut();
+            let ret = device.CreateVertexShader(code, len, std::ptr::null_mut(), &mut obj);
+            hresult(obj, ret)
+        })?;
+        let pixel_shader = ComPtr::new(|| unsafe {
+            let (code, len) = bytecode(&ps);
+            let mut obj = std::ptr::null_mut();
+            let ret = device.CreatePixelShader(code, len, std::ptr::null_mut(), &mut obj);
+            hresult(obj, ret)
+        })?;
+        let sampler = ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = device.CreateSamplerState(
+                &D3D11_SAMPLER_DESC {
+                    Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+                    AddressU: D3D11_TEXTURE_ADDRESS_WRAP,
+                    AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    MipLODBias: 0.0,
+                    MaxAnisotropy: 1,
+                    ComparisonFunc: D3D11_COMPARISON_NEVER,
+                    BorderColor: [0.0; 4],
+                    MinLOD: 0.0,
+                    MaxLOD: D3D11_FLOAT32_MAX,
+                },
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })?;
+        let constants = ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = device.CreateBuffer(
+                &D3D11_BUFFER_DESC {
+                    ByteWidth: std::mem::size_of::<View>() as u32,
+                    Usage: D3D11_USAGE_DEFAULT,
+                    BindFlags: D3D11_BIND_CONSTANT_BUFFER,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                    StructureByteStride: 0,
+                },
+                std::ptr::null(),
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })?;
+        Ok(Self {
+            device: device.clone(),
+            context,
+            vertex_shader,
+            pixel_shader,
+            sampler,
+            constants,
+            source: None,
+            target: None,
+        })
+    }
+
+    fn upload(&mut self, panorama: &Panorama) -> Result<ComPtr<ID3D11ShaderResourceView>, HResult> {
+        if let Some((path, view)) = self.source.as_ref() {
+            if path == panorama.path() {
+                return Ok(view.clone());
+            }
+        }
+        self.source = None;
+        let (width, height) = panorama.source.dimensions();
+        let texture = ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = self.device.CreateTexture2D(
+                &D3D11_TEXTURE2D_DESC {
+                    Width: width,
+                    Height: height,
+                    MipLevels: 1,
+                    ArraySize: 1,
+                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    SampleDesc: DXGI_SAMPLE_DESC {
+                        Count: 1,
+                        Quality: 0,
+                    },
+                    Usage: D3D11_USAGE_IMMUTABLE,
+                    BindFlags: D3D11_BIND_SHADER_RESOURCE,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                },
+                &D3D11_SUBRESOURCE_DATA {
+                    pSysMem: panorama.source.as_raw().as_ptr() as _,
+                    SysMemPitch: width * 4,
+                    SysMemSlicePitch: 0,
+                },
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })?;
+        let view = ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = self.device.CreateShaderResourceView(
+                texture.as_ptr() as _,
+                std::ptr::null(),
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })?;
+        self.source = Some((panorama.path().to_path_buf(), view.clone()));
+        Ok(view)
+    }
+
+    fn target(
+        &mut self,
+        dc: &ComPtr<ID2D1DeviceContext>,
+        width: u32,
+        height: u32,
+        dpi: f32,
+    ) -> Result<(ComPtr<ID3D11RenderTargetView>, ComPtr<ID2D1Bitmap1>), HResult> {
+        if let Some(target) = self.target.as_ref().filter(|t| t.size == (width, height)) {
+            return Ok((target.view.clone(), target.bitmap.clone()));
+        }
+        self.target = None;
+        let texture = ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = self.device.CreateTexture2D(
+                &D3D11_TEXTURE2D_DESC {
+                    Width: width,
+                    Height: height,
+                    MipLevels: 1,
+                    ArraySize: 1,
+                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    SampleDesc: DXGI_SAMPLE_DESC {
+                        Count: 1,
+                        Quality: 0,
+                    },
+                    Usage: D3D11_USAGE_DEFAULT,
+                    BindFlags: D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                },
+                std::ptr::null(),
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })?;
+        let view = ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = self.device.CreateRenderTargetView(
+                texture.as_ptr() as _,
+                std::ptr::null(),
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })?;
+        let surface = texture.query_interface::<IDXGISurface>()?;
+        let bitmap = ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = dc.CreateBitmapFromDxgiSurface(
+                surface.as_ptr(),
+                &D2D1_BITMAP_PROPERTIES1 {
+                    bitmapOptions: D2D1_BITMAP_OPTIONS_NONE,
+                    pixelFormat: D2D1_PIXEL_FORMAT {
+                        format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                        alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
+                    },
+                    dpiX: dpi,
+                    dpiY: dpi,
+                    colorContext: std::ptr::null_mut(),
+                },
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })?;
+        self.target = Some(Target {
+            size: (width, height),
+            view: view.clone(),
+            bitmap: bitmap.clone(),
+        });
+        Ok((view, bitmap))
+    }
+
+    pub fn project(
+        &mut self,
+        dc: &ComPtr<ID2D1DeviceContext>,
+        panorama: &Panorama,
+        width: u32,
+        height: u32,
+        dpi: f32,
+    ) -> Result<ComPtr<ID2D1Bitmap1>, HResult> {
+        let source = self.upload(panorama)?;
+        let (target, bitmap) = self.target(dc, width, height, dpi)?;
+        let view = View {
+            yaw: panorama.yaw.to_radians(),
+            pitch: panorama.pitch.to_radians(),
+            tan_half_fov: (panorama.fov.to_radians() / 2.0).tan(),
+            aspect: height as f32 / width as f32,
+        };
+        let context = &self.context;
+        unsafe {
+            context.UpdateSubresource(
+                self.constants.as_ptr() as _,
+                0,
+                std::ptr::null(),
+                &view as *const View as _,
+                0,
+                0,
+            );
+            context.OMSetRenderTargets(1, &target.as_ptr(), std::ptr::null_mut());
+            context.OMSetBlendState(std::ptr::null_mut(), &[1.0; 4], 0xffffffff);
+            context.RSSetState(std::ptr::null_mut());
+            context.RSSetViewports(
+                1,
+                &D3D11_VIEWPORT {
+                    TopLeftX: 0.0,
+                    TopLeftY: 0.0,
+                    Width: width as f32,
+                    Height: height as f32,
+                    MinDepth: 0.0,
+                    MaxDepth: 1.0,
+                },
+            );
+            context.IASetInputLayout(std::ptr::null_mut());
+            context.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            context.VSSetShader(self.vertex_shader.as_ptr(), std::ptr::null(), 0);
+            context.PSSetShader(self.pixel_shader.as_ptr(), std::ptr::null(), 0);
+            context.PSSetShaderResources(0, 1, &source.as_ptr());
+            context.PSSetSamplers(0, 1, &self.sampler.as_ptr());
+            context.PSSetConstantBuffers(0, 1, &self.constants.as_ptr());
+            context.Draw(3, 0);
+            context.PSSetShaderResources(0, 1, &std::ptr::null_mut());
+            context.OMSetRenderTargets(0, std::ptr::null(), std::ptr::null_mut());
+        }
+        Ok(bitmap)
+    }
+}
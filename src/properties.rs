@@ -0,0 +1,114 @@
+use crate::error::Error;
+use crate::metadata::Format;
+use crate::stats;
+use std::path::{Path, PathBuf};
+use winapi::shared::minwindef::FILETIME;
+use winapi::um::fileapi::*;
+use winapi::um::minwinbase::{GetFileExInfoStandard, SYSTEMTIME};
+use winapi::um::timezoneapi::FileTimeToSystemTime;
+use winapi::um::winnt::*;
+
+const ATTRIBUTES: [(u32, &str); 8] = [
+    (FILE_ATTRIBUTE_READONLY, "read-only"),
+    (FILE_ATTRIBUTE_HIDDEN, "hidden"),
+    (FILE_ATTRIBUTE_SYSTEM, "system"),
+    (FILE_ATTRIBUTE_ARCHIVE, "archive"),
+    (FILE_ATTRIBUTE_COMPRESSED, "compressed"),
+    (FILE_ATTRIBUTE_ENCRYPTED, "encrypted"),
+    (FILE_ATTRIBUTE_SPARSE_FILE, "sparse"),
+    (FILE_ATTRIBUTE_OFFLINE, "offline"),
+];
+
+fn local_time(time: &FILETIME) -> String {
+    unsafe {
+        let mut local = FILETIME::default();
+        let mut st = SYSTEMTIME::default();
+        if FileTimeToLocalFileTime(time, &mut local) == 0
+            || FileTimeToSystemTime(&local, &mut st) == 0
+        {
+            return "-".to_string();
+        }
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            st.wYear, st.wMonth, st.wDay, st.wHour, st.wMinute, st.wSecond
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Properties {
+    pub path: PathBuf,
+    pub size: u64,
+    pub created: String,
+    pub modified: String,
+    pub accessed: String,
+    pub attributes: Vec<&'static str>,
+}
+
+impl Properties {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let wide = path
+            .to_string_lossy()
+            .encode_utf16()
+            .chain(Some(0))
+            .collect::<Vec<_>>();
+        let mut data = WIN32_FILE_ATTRIBUTE_DATA::default();
+        let ret = unsafe {
+            GetFileAttributesExW(
+                wide.as_ptr(),
+                GetFileExInfoStandard,
+                &mut data as *mut _ as _,
+            )
+        };
+        if ret == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: ((data.nFileSizeHigh as u64) << 32) | data.nFileSizeLow as u64,
+            created: local_time(&data.ftCreationTime),
+            modified: local_time(&data.ftLastWriteTime),
+            accessed: local_time(&data.ftLastAccessTime),
+            attributes: ATTRIBUTES
+                .iter()
+                .filter(|(flag, _)| data.dwFileAttributes & flag != 0)
+                .map(|(_, name)| *name)
+                .collect(),
+        })
+    }
+
+    pub fn lines(&self, format: Option<Format>) -> Vec<String> {
+        let mut lines = vec![
+            format!("Path: {}", self.path.to_string_lossy()),
+            format!(
+                "Size: {} ({} bytes)",
+                stats::format_size(self.size),
+                self.size
+            ),
+            format!("Created: {}", self.created),
+            format!("Modified: {}", self.modified),
+            format!("Accessed: {}", self.accessed),
+            format!(
+                "Attributes: {}",
+                if self.attributes.is_empty() {
+                    "-".to_string()
+                } else {
+                    self.attributes.join(", ")
+                }
+            ),
+        ];
+        if let Some(format) = format {
+            lines.push(format!(
+                "Bit depth: {} bit x {} channel{}",
+                format.bit_depth,
+                format.channels,
+                if format.channels > 1 { "s" } else { "" }
+            ));
+            if let Some(subsampling) = format.subsampling {
+                lines.push(format!("Chroma subsampling: {}", subsampling));
+            }
+        }
+        lines
+    }
+}
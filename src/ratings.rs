@@ -0,0 +1,89 @@
+use crate::archive;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+const FILE_NAME: &str = "niv-ratings.toml";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Flag {
+    Pick,
+    Reject,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Rating {
+    #[serde(default)]
+    pub stars: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flag: Option<Flag>,
+}
+
+impl Rating {
+    pub fn is_empty(&self) -> bool {
+        self.stars == 0 && self.flag.is_none()
+    }
+}
+
+impl std::fmt::Display for Rating {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let stars = (0..5)
+            .map(|i| if i < self.stars { '★' } else { '☆' })
+            .collect::<String>();
+        match self.flag {
+            Some(flag) => write!(f, "{} {:?}", stars, flag),
+            None => write!(f, "{}", stars),
+        }
+    }
+}
+
+fn location(path: &Path) -> Option<(PathBuf, String)> {
+    let base = match archive::split(path) {
+        Some((archive, _)) => archive,
+        None => path.to_path_buf(),
+    };
+    let folder = base.parent()?.to_path_buf();
+    let key = path
+        .strip_prefix(&folder)
+        .ok()?
+        .to_string_lossy()
+        .replace('\\', "/");
+    Some((folder, key))
+}
+
+#[derive(Default, Debug)]
+pub struct Ratings {
+    folders: HashMap<PathBuf, BTreeMap<String, Rating>>,
+}
+
+impl Ratings {
+    fn folder(&mut self, folder: &Path) -> &mut BTreeMap<String, Rating> {
+        self.folders.entry(folder.to_path_buf()).or_insert_with(|| {
+            std::fs::read_to_string(folder.join(FILE_NAME))
+                .ok()
+                .and_then(|text| toml::from_str(&text).ok())
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn get(&mut self, path: &Path) -> Rating {
+        match location(path) {
+            Some((folder, key)) => self.folder(&folder).get(&key).copied().unwrap_or_default(),
+            None => Rating::default(),
+        }
+    }
+
+    pub fn set(&mut self, path: &Path, rating: Rating) -> Result<(), Error> {
+        let (folder, key) = location(path).ok_or(Error::FileNotFound)?;
+        let ratings = self.folder(&folder);
+        if rating.is_empty() {
+            ratings.remove(&key);
+        } else {
+            ratings.insert(key, rating);
+        }
+        let text = toml::to_string_pretty(ratings).map_err(|e| Error::Other(e.into()))?;
+        std::fs::write(folder.join(FILE_NAME), text)?;
+        Ok(())
+    }
+}
@@ -1,10 +1,31 @@
+use crate::annotation::Shape;
 use crate::config::*;
-use com_ptr::{hresult, ComPtr};
+use crate::crop::Region;
+use crate::panorama::{Panorama, Projector};
+use com_ptr::{hresult, ComPtr, HResult};
+use log::{error, warn};
 use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+use winapi::shared::dxgi::*;
+use winapi::shared::dxgi1_2::*;
+use winapi::shared::dxgi1_5::*;
 use winapi::shared::dxgiformat::*;
-use winapi::um::{d2d1::*, d2d1_1::*, dcommon::*, dwrite::*};
+use winapi::shared::dxgitype::*;
+use winapi::shared::guiddef::GUID;
+use winapi::shared::minwindef::{BOOL, FALSE, TRUE};
+use winapi::shared::windef::{HWND, RECT};
+use winapi::shared::winerror::*;
+use winapi::um::d3d11::*;
+use winapi::um::d3dcommon::*;
+use winapi::um::dcomp::*;
+use winapi::um::winuser::GetClientRect;
+use winapi::um::{d2d1::*, d2d1_1::*, d2d1effects::*, d2d1effects_2::*, dcommon::*, dwrite::*};
 use winapi::Interface;
 
+const D2D1_CONTRAST_PROP_CONTRAST: u32 = 0;
+const D2D1_EXPOSURE_PROP_EXPOSURE_VALUE: u32 = 0;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 #[repr(u32)]
 pub enum Interpolation {
@@ -12,59 +33,553 @@ pub enum Interpolation {
     Linear = D2D1_INTERPOLATION_MODE_LINEAR,
     Cubic = D2D1_INTERPOLATION_MODE_CUBIC,
     HighQualityCubic = D2D1_INTERPOLATION_MODE_HIGH_QUALITY_CUBIC,
+    Lanczos = 6,
+}
+
+impl Interpolation {
+    fn mode(self) -> u32 {
+        match self {
+            Interpolation::Lanczos => D2D1_INTERPOLATION_MODE_HIGH_QUALITY_CUBIC,
+            mode => mode as u32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum FitMode {
+    Shrink,
+    Fit,
+    Width,
+    Height,
+    Original,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    All,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    Luminance,
+    Inverted,
+}
+
+impl Channel {
+    pub fn next(self) -> Self {
+        match self {
+            Self::All => Self::Red,
+            Self::Red => Self::Green,
+            Self::Green => Self::Blue,
+            Self::Blue => Self::Alpha,
+            Self::Alpha => Self::Luminance,
+            Self::Luminance => Self::Inverted,
+            Self::Inverted => Self::All,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Effect {
+    Contrast(f32),
+    Binarize(f32),
+    Downscale(f32),
+    Brightness(f32),
+    Gamma(f32),
+    Exposure(f32),
+    Channel(Channel),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Adjustments {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub gamma: f32,
+    pub exposure: f32,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 0.0,
+            gamma: 1.0,
+            exposure: 0.0,
+        }
+    }
+}
+
+impl Adjustments {
+    pub fn effects(&self) -> Vec<Effect> {
+        let mut effects = vec![];
+        if self.exposure != 0.0 {
+            effects.push(Effect::Exposure(self.exposure));
+        }
+        if self.brightness != 0.0 {
+            effects.push(Effect::Brightness(self.brightness));
+        }
+        if self.contrast != 0.0 {
+            effects.push(Effect::Contrast(self.contrast));
+        }
+        if self.gamma != 1.0 {
+            effects.push(Effect::Gamma(self.gamma));
+        }
+        effects
+    }
+}
+
+unsafe fn set_value<T: ?Sized>(effect: &ComPtr<ID2D1Effect>, index: u32, value: &T) {
+    effect.SetValue(
+        index,
+        D2D1_PROPERTY_TYPE_UNKNOWN,
+        value as *const T as *const u8,
+        std::mem::size_of_val(value) as u32,
+    );
+}
+
+fn get_output(effect: &ComPtr<ID2D1Effect>) -> Result<ComPtr<ID2D1Image>, HResult> {
+    ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        effect.GetOutput(&mut obj);
+        hresult(obj, S_OK)
+    })
+}
+
+fn create_d3d11_device() -> Result<ComPtr<ID3D11Device>, HResult> {
+    let create = |driver_type| {
+        ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = D3D11CreateDevice(
+                std::ptr::null_mut(),
+                driver_type,
+                std::ptr::null_mut(),
+                D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                std::ptr::null(),
+                0,
+                D3D11_SDK_VERSION,
+                &mut obj,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            );
+            hresult(obj, ret)
+        })
+    };
+    create(D3D_DRIVER_TYPE_HARDWARE).or_else(|e| {
+        warn!("D3D11CreateDevice: {}", e);
+        create(D3D_DRIVER_TYPE_WARP)
+    })
+}
+
+struct Composition {
+    _device: ComPtr<IDCompositionDevice>,
+    _target: ComPtr<IDCompositionTarget>,
+    _visual: ComPtr<IDCompositionVisual>,
+}
+
+fn create_composition(
+    device: &ComPtr<ID3D11Device>,
+    hwnd: HWND,
+    swap_chain: &ComPtr<IDXGISwapChain1>,
+) -> Result<Composition, HResult> {
+    let dxgi_device = device.query_interface::<IDXGIDevice>()?;
+    let device = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = DCompositionCreateDevice(
+            dxgi_device.as_ptr(),
+            &<IDCompositionDevice as Interface>::uuidof(),
+            &mut obj,
+        );
+        hresult(obj as *mut IDCompositionDevice, ret)
+    })?;
+    let target = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = device.CreateTargetForHwnd(hwnd, TRUE, &mut obj);
+        hresult(obj, ret)
+    })?;
+    let visual = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = device.CreateVisual(&mut obj);
+        hresult(obj, ret)
+    })?;
+    unsafe {
+        check(visual.SetContent(swap_chain.as_ptr() as _))?;
+        check(target.SetRoot(visual.as_ptr()))?;
+        check(device.Commit())?;
+    }
+    Ok(Composition {
+        _device: device,
+        _target: target,
+        _visual: visual,
+    })
+}
+
+fn check(ret: HRESULT) -> Result<(), HResult> {
+    if ret < 0 {
+        Err(HResult(ret))
+    } else {
+        Ok(())
+    }
+}
+
+struct Device {
+    d3d11_device: ComPtr<ID3D11Device>,
+    device_context: ComPtr<ID2D1DeviceContext>,
+    swap_chain: ComPtr<IDXGISwapChain1>,
+    composition: Option<Composition>,
+    dxgi_factory: ComPtr<IDXGIFactory2>,
+    tearing: bool,
+}
+
+fn create_device(
+    d2d1_factory: &ComPtr<ID2D1Factory1>,
+    hwnd: HWND,
+    size: wita::PhysicalSize<u32>,
+    dpi: f32,
+    transparent: bool,
+) -> Result<Device, HResult> {
+    let d3d11_device = create_d3d11_device()?;
+    let dxgi_device = d3d11_device.query_interface::<IDXGIDevice>()?;
+    let d2d1_device = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = d2d1_factory.CreateDevice(dxgi_device.as_ptr(), &mut obj);
+        hresult(obj, ret)
+    })?;
+    let device_context = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = d2d1_device.CreateDeviceContext(D2D1_DEVICE_CONTEXT_OPTIONS_NONE, &mut obj);
+        hresult(obj, ret)
+    })?;
+    unsafe {
+        device_context.SetDpi(dpi, dpi);
+    }
+    let (swap_chain, composition, dxgi_factory, tearing) =
+        create_swap_chain(&d3d11_device, hwnd, size, transparent)?;
+    create_target(&device_context, &swap_chain, transparent)?;
+    Ok(Device {
+        d3d11_device,
+        device_context,
+        swap_chain,
+        composition,
+        dxgi_factory,
+        tearing,
+    })
+}
+
+type SwapChain = (
+    ComPtr<IDXGISwapChain1>,
+    Option<Composition>,
+    ComPtr<IDXGIFactory2>,
+    bool,
+);
+
+fn create_swap_chain(
+    device: &ComPtr<ID3D11Device>,
+    hwnd: HWND,
+    size: wita::PhysicalSize<u32>,
+    transparent: bool,
+) -> Result<SwapChain, HResult> {
+    let dxgi_device = device.query_interface::<IDXGIDevice>()?;
+    let adapter = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = dxgi_device.GetAdapter(&mut obj);
+        hresult(obj, ret)
+    })?;
+    let factory = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = adapter.GetParent(&<IDXGIFactory2 as Interface>::uuidof(), &mut obj);
+        hresult(obj as *mut IDXGIFactory2, ret)
+    })?;
+    let tearing = factory
+        .query_interface::<IDXGIFactory5>()
+        .map(|factory| unsafe {
+            let mut allow: BOOL = FALSE;
+            let ret = factory.CheckFeatureSupport(
+                DXGI_FEATURE_PRESENT_ALLOW_TEARING,
+                &mut allow as *mut BOOL as _,
+                std::mem::size_of::<BOOL>() as u32,
+            );
+            ret == S_OK && allow == TRUE
+        })
+        .unwrap_or(false)
+        && !transparent;
+    let desc = |swap_effect| DXGI_SWAP_CHAIN_DESC1 {
+        Width: size.width.max(1),
+        Height: size.height.max(1),
+        Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+        Stereo: FALSE,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+        BufferCount: 2,
+        Scaling: DXGI_SCALING_STRETCH,
+        SwapEffect: swap_effect,
+        AlphaMode: if transparent {
+            DXGI_ALPHA_MODE_PREMULTIPLIED
+        } else {
+            DXGI_ALPHA_MODE_IGNORE
+        },
+        Flags: swap_chain_flags(tearing),
+    };
+    let create = |swap_effect| {
+        ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = if transparent {
+                factory.CreateSwapChainForComposition(
+                    device.as_ptr() as _,
+                    &desc(swap_effect),
+                    std::ptr::null_mut(),
+                    &mut obj,
+                )
+            } else {
+                factory.CreateSwapChainForHwnd(
+                    device.as_ptr() as _,
+                    hwnd,
+                    &desc(swap_effect),
+                    std::ptr::null(),
+                    std::ptr::null_mut(),
+                    &mut obj,
+                )
+            };
+            hresult(obj, ret)
+        })
+    };
+    let swap_chain = create(DXGI_SWAP_EFFECT_FLIP_DISCARD)
+        .or_else(|_| create(DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL))?;
+    let composition = if transparent {
+        Some(create_composition(device, hwnd, &swap_chain)?)
+    } else {
+        None
+    };
+    Ok((swap_chain, composition, factory, tearing))
+}
+
+fn swap_chain_flags(tearing: bool) -> u32 {
+    if tearing {
+        DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING
+    } else {
+        0
+    }
+}
+
+fn create_target(
+    dc: &ComPtr<ID2D1DeviceContext>,
+    swap_chain: &ComPtr<IDXGISwapChain1>,
+    transparent: bool,
+) -> Result<(), HResult> {
+    let surface = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = swap_chain.GetBuffer(0, &<IDXGISurface as Interface>::uuidof(), &mut obj);
+        hresult(obj as *mut IDXGISurface, ret)
+    })?;
+    let mut dpi_x = 0.0;
+    let mut dpi_y = 0.0;
+    unsafe {
+        dc.GetDpi(&mut dpi_x, &mut dpi_y);
+    }
+    let target = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = dc.CreateBitmapFromDxgiSurface(
+            surface.as_ptr(),
+            &D2D1_BITMAP_PROPERTIES1 {
+                bitmapOptions: D2D1_BITMAP_OPTIONS_TARGET | D2D1_BITMAP_OPTIONS_CANNOT_DRAW,
+                pixelFormat: D2D1_PIXEL_FORMAT {
+                    format: DXGI_FORMAT_B8G8R8A8_UNORM,
+                    alphaMode: if transparent {
+                        D2D1_ALPHA_MODE_PREMULTIPLIED
+                    } else {
+                        D2D1_ALPHA_MODE_IGNORE
+                    },
+                },
+                dpiX: dpi_x,
+                dpiY: dpi_y,
+                colorContext: std::ptr::null_mut(),
+            },
+            &mut obj,
+        );
+        hresult(obj, ret)
+    })?;
+    unsafe {
+        dc.SetTarget(target.as_ptr() as _);
+    }
+    Ok(())
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TextInfo {
-    pub face_name: String,
+    pub face_names: Vec<String>,
     pub color: RgbaColor,
     pub size: f32,
 }
 
+type TextFormats = (
+    String,
+    ComPtr<IDWriteTextFormat>,
+    ComPtr<IDWriteTextFormat>,
+    ComPtr<IDWriteTextFormat>,
+);
+
+fn create_text_formats(
+    dwrite_factory: &ComPtr<IDWriteFactory>,
+    text_info: &TextInfo,
+) -> Result<TextFormats, HResult> {
+    let font_collection = ComPtr::new(|| unsafe {
+        let mut obj = std::ptr::null_mut();
+        let ret = dwrite_factory.GetSystemFontCollection(&mut obj, FALSE);
+        hresult(obj, ret)
+    })?;
+    let installed = |face: &[u16]| unsafe {
+        let mut index = 0;
+        let mut exists = FALSE;
+        let ret = font_collection.FindFamilyName(face.as_ptr(), &mut index, &mut exists);
+        ret == S_OK && exists == TRUE
+    };
+    let create_text_format = |face: &[u16], size: f32| {
+        ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let locale = vec![0u16];
+            let ret = dwrite_factory.CreateTextFormat(
+                face.as_ptr(),
+                std::ptr::null_mut(),
+                DWRITE_FONT_WEIGHT_NORMAL,
+                DWRITE_FONT_STYLE_NORMAL,
+                DWRITE_FONT_STRETCH_NORMAL,
+                size,
+                locale.as_ptr(),
+                &mut obj,
+            );
+            hresult(obj as *mut IDWriteTextFormat, ret)
+        })
+    };
+    let mut text_format = None;
+    for name in &text_info.face_names {
+        let face = name.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+        if !installed(&face) {
+            warn!("font: {} is not installed", name);
+            continue;
+        }
+        let formats = create_text_format(&face, text_info.size).and_then(|text| {
+            let osd = create_text_format(&face, text_info.size * 1.5)?;
+            let status = create_text_format(&face, text_info.size)?;
+            Ok((text, osd, status))
+        });
+        match formats {
+            Ok(formats) => {
+                text_format = Some((name.clone(), formats));
+                break;
+            }
+            Err(e) => warn!("font: {}: {}", name, e),
+        }
+    }
+    let (face_name, (text_format, osd_format, status_format)) = match text_format {
+        Some(ret) => ret,
+        None => {
+            warn!("font: no font in the fallback chain is available: using the system default");
+            let face = vec![0u16];
+            (
+                String::new(),
+                (
+                    create_text_format(&face, text_info.size)?,
+                    create_text_format(&face, text_info.size * 1.5)?,
+                    create_text_format(&face, text_info.size)?,
+                ),
+            )
+        }
+    };
+    unsafe {
+        osd_format.SetTextAlignment(DWRITE_TEXT_ALIGNMENT_CENTER);
+        osd_format.SetParagraphAlignment(DWRITE_PARAGRAPH_ALIGNMENT_CENTER);
+        status_format.SetParagraphAlignment(DWRITE_PARAGRAPH_ALIGNMENT_CENTER);
+    }
+    Ok((face_name, text_format, osd_format, status_format))
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageLayout {
+    pub left: f32,
+    pub top: f32,
+    pub scale: f32,
+}
+
+impl ImageLayout {
+    pub fn to_image(self, x: f32, y: f32) -> (f32, f32) {
+        ((x - self.left) / self.scale, (y - self.top) / self.scale)
+    }
+
+    pub fn to_viewport(self, x: f32, y: f32) -> (f32, f32) {
+        (x * self.scale + self.left, y * self.scale + self.top)
+    }
+
+    fn rect(&self, width: f32, height: f32) -> winapi::um::d2d1::D2D1_RECT_F {
+        winapi::um::d2d1::D2D1_RECT_F {
+            left: self.left,
+            top: self.top,
+            right: self.left + width * self.scale,
+            bottom: self.top + height * self.scale,
+        }
+    }
+}
+
 pub struct Renderer {
-    render_target: ComPtr<ID2D1HwndRenderTarget>,
+    hwnd: HWND,
+    d2d1_factory: ComPtr<ID2D1Factory1>,
+    dxgi_factory: ComPtr<IDXGIFactory2>,
+    swap_chain: Option<ComPtr<IDXGISwapChain1>>,
+    composition: Option<Composition>,
+    transparent: bool,
+    tearing: bool,
+    vsync: bool,
+    device_lost: Cell<bool>,
+    d3d11_device: ComPtr<ID3D11Device>,
     device_context: ComPtr<ID2D1DeviceContext>,
+    projector: Option<Projector>,
+    dwrite_factory: ComPtr<IDWriteFactory>,
     text_format: ComPtr<IDWriteTextFormat>,
+    face_name: String,
     text_color: RgbaColor,
+    osd_format: ComPtr<IDWriteTextFormat>,
+    status_format: ComPtr<IDWriteTextFormat>,
+    status: Option<String>,
+    fit_mode: FitMode,
+    zoom: Option<ImageLayout>,
+    osd: Option<(String, Instant)>,
+    osd_duration: Duration,
+    osd_fade: Duration,
+    frame_time: Cell<Duration>,
+    mipmaps: Option<(ComPtr<ID2D1Bitmap1>, Vec<ComPtr<ID2D1Bitmap1>>)>,
+    loupe: Option<(f32, f32, Loupe)>,
+    guides: Option<Guides>,
+    compare: Option<ComPtr<ID2D1Bitmap1>>,
+    annotations: Option<(Vec<Shape>, f32)>,
 }
 
 impl Renderer {
-    pub fn new(wnd: &wita::Window, text_info: TextInfo) -> anyhow::Result<Self> {
+    pub fn new(
+        wnd: &wita::Window,
+        text_info: TextInfo,
+        osd: &Osd,
+        transparent: bool,
+    ) -> anyhow::Result<Self> {
         let wnd_size = wnd.inner_size();
         let d2d1_factory = ComPtr::new(|| unsafe {
             let mut obj = std::ptr::null_mut();
             let ret = D2D1CreateFactory(
                 D2D1_FACTORY_TYPE_MULTI_THREADED,
-                &<ID2D1Factory as Interface>::uuidof(),
+                &<ID2D1Factory1 as Interface>::uuidof(),
                 std::ptr::null(),
                 &mut obj,
             );
-            hresult(obj as *mut ID2D1Factory, ret)
-        })?;
-        let render_target = ComPtr::new(|| unsafe {
-            let mut obj = std::ptr::null_mut();
-            let ret = d2d1_factory.CreateHwndRenderTarget(
-                &D2D1_RENDER_TARGET_PROPERTIES {
-                    _type: D2D1_RENDER_TARGET_TYPE_DEFAULT,
-                    pixelFormat: D2D1_PIXEL_FORMAT {
-                        format: DXGI_FORMAT_R8G8B8A8_UNORM,
-                        alphaMode: D2D1_ALPHA_MODE_UNKNOWN,
-                    },
-                    ..Default::default()
-                },
-                &D2D1_HWND_RENDER_TARGET_PROPERTIES {
-                    hwnd: wnd.raw_handle() as _,
-                    pixelSize: winapi::um::d2d1::D2D1_SIZE_U {
-                        width: wnd_size.width as u32,
-                        height: wnd_size.height as u32,
-                    },
-                    presentOptions: D2D1_PRESENT_OPTIONS_NONE,
-                },
-                &mut obj,
-            );
-            hresult(obj as *mut ID2D1HwndRenderTarget, ret)
+            hresult(obj as *mut ID2D1Factory1, ret)
         })?;
-        let device_context = render_target.query_interface::<ID2D1DeviceContext>()?;
+        let hwnd = wnd.raw_handle() as HWND;
+        let device = create_device(
+            &d2d1_factory,
+            hwnd,
+            wita::PhysicalSize::new(wnd_size.width as u32, wnd_size.height as u32),
+            wnd.dpi() as f32,
+            transparent,
+        )?;
         let dwrite_factory = ComPtr::new(|| unsafe {
             let mut obj = std::ptr::null_mut();
             let ret = DWriteCreateFactory(
@@ -74,62 +589,1118 @@ impl Renderer {
             );
             hresult(obj as *mut IDWriteFactory, ret)
         })?;
-        let text_format = ComPtr::new(|| unsafe {
-            let mut obj = std::ptr::null_mut();
-            let face = text_info
-                .face_name
-                .encode_utf16()
-                .chain(Some(0))
-                .collect::<Vec<_>>();
-            let locale = vec![0u16];
-            let ret = dwrite_factory.CreateTextFormat(
-                face.as_ptr(),
-                std::ptr::null_mut(),
-                DWRITE_FONT_WEIGHT_NORMAL,
-                DWRITE_FONT_STYLE_NORMAL,
-                DWRITE_FONT_STRETCH_NORMAL,
-                text_info.size,
-                locale.as_ptr(),
-                &mut obj,
-            );
-            hresult(obj as *mut IDWriteTextFormat, ret)
-        })?;
+        let (face_name, text_format, osd_format, status_format) =
+            create_text_formats(&dwrite_factory, &text_info)?;
         Ok(Self {
-            render_target,
-            device_context,
+            hwnd,
+            d2d1_factory,
+            dxgi_factory: device.dxgi_factory,
+            swap_chain: Some(device.swap_chain),
+            composition: device.composition,
+            transparent,
+            tearing: device.tearing,
+            vsync: true,
+            device_lost: Cell::new(false),
+            d3d11_device: device.d3d11_device,
+            device_context: device.device_context,
+            projector: None,
+            dwrite_factory,
             text_format,
+            face_name,
             text_color: text_info.color,
+            osd_format,
+            status_format,
+            status: None,
+            fit_mode: FitMode::Shrink,
+            zoom: None,
+            osd: None,
+            osd_duration: Duration::from_millis(osd.duration),
+            osd_fade: Duration::from_millis(osd.fade),
+            frame_time: Cell::new(Duration::default()),
+            mipmaps: None,
+            loupe: None,
+            guides: None,
+            compare: None,
+            annotations: None,
         })
     }
 
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time.get()
+    }
+
+    pub fn face_name(&self) -> &str {
+        &self.face_name
+    }
+
+    pub fn set_text_info(&mut self, text_info: TextInfo) -> Result<(), HResult> {
+        let (face_name, text_format, osd_format, status_format) =
+            create_text_formats(&self.dwrite_factory, &text_info)?;
+        self.face_name = face_name;
+        self.text_format = text_format;
+        self.osd_format = osd_format;
+        self.status_format = status_format;
+        self.text_color = text_info.color;
+        Ok(())
+    }
+
+    pub fn set_osd(&mut self, osd: &Osd) {
+        self.osd_duration = Duration::from_millis(osd.duration);
+        self.osd_fade = Duration::from_millis(osd.fade);
+    }
+
     pub fn device_context(&self) -> ComPtr<ID2D1DeviceContext> {
         self.device_context.clone()
     }
 
     pub fn resize(&mut self, size: wita::PhysicalSize<u32>) {
+        let swap_chain = match self.swap_chain.as_ref() {
+            Some(swap_chain) => swap_chain,
+            None => return,
+        };
         unsafe {
-            self.render_target.Resize(&winapi::um::d2d1::D2D1_SIZE_U {
-                width: size.width,
-                height: size.height,
-            });
+            self.device_context.SetTarget(std::ptr::null());
+            let ret = swap_chain.ResizeBuffers(
+                0,
+                size.width.max(1),
+                size.height.max(1),
+                DXGI_FORMAT_UNKNOWN,
+                swap_chain_flags(self.tearing),
+            );
+            if ret < 0 {
+                error!("ResizeBuffers: {}", HResult(ret));
+                self.check_device(ret);
+            }
+        }
+        if let Err(e) = create_target(&self.device_context, swap_chain, self.transparent) {
+            error!("create_target: {}", e);
         }
     }
 
+    fn check_device(&self, ret: HRESULT) {
+        if ret == D2DERR_RECREATE_TARGET
+            || ret == DXGI_ERROR_DEVICE_REMOVED
+            || ret == DXGI_ERROR_DEVICE_RESET
+        {
+            self.device_lost.set(true);
+        }
+    }
+
+    pub fn device_lost(&self) -> bool {
+        self.device_lost.get()
+    }
+
+    pub fn display_changed(&self) {
+        if unsafe { self.dxgi_factory.IsCurrent() } == FALSE {
+            self.device_lost.set(true);
+        }
+    }
+
+    pub fn set_transparent(&mut self, transparent: bool) -> Result<(), HResult> {
+        if self.transparent == transparent {
+            return Ok(());
+        }
+        let mut rc = RECT::default();
+        unsafe {
+            self.device_context.SetTarget(std::ptr::null());
+            GetClientRect(self.hwnd, &mut rc);
+        }
+        self.swap_chain = None;
+        self.composition = None;
+        self.transparent = transparent;
+        let (swap_chain, composition, dxgi_factory, tearing) = create_swap_chain(
+            &self.d3d11_device,
+            self.hwnd,
+            wita::PhysicalSize::new(
+                (rc.right - rc.left).max(1) as u32,
+                (rc.bottom - rc.top).max(1) as u32,
+            ),
+            transparent,
+        )?;
+        create_target(&self.device_context, &swap_chain, transparent)?;
+        self.swap_chain = Some(swap_chain);
+        self.composition = composition;
+        self.dxgi_factory = dxgi_factory;
+        self.tearing = tearing;
+        Ok(())
+    }
+
+    pub fn recover(&mut self) -> Result<(), HResult> {
+        let mut dpi_x = 0.0;
+        let mut dpi_y = 0.0;
+        let mut rc = RECT::default();
+        unsafe {
+            self.device_context.GetDpi(&mut dpi_x, &mut dpi_y);
+            self.device_context.SetTarget(std::ptr::null());
+            GetClientRect(self.hwnd, &mut rc);
+        }
+        self.mipmaps = None;
+        self.projector = None;
+        self.swap_chain = None;
+        self.composition = None;
+        let device = create_device(
+            &self.d2d1_factory,
+            self.hwnd,
+            wita::PhysicalSize::new(
+                (rc.right - rc.left).max(1) as u32,
+                (rc.bottom - rc.top).max(1) as u32,
+            ),
+            dpi_x,
+            self.transparent,
+        )?;
+        self.d3d11_device = device.d3d11_device;
+        self.device_context = device.device_context;
+        self.swap_chain = Some(device.swap_chain);
+        self.composition = device.composition;
+        self.dxgi_factory = device.dxgi_factory;
+        self.tearing = device.tearing;
+        self.device_lost.set(false);
+        Ok(())
+    }
+
     pub fn set_dpi(&mut self, dpi: f32) {
         unsafe {
-            self.render_target.SetDpi(dpi, dpi);
+            self.device_context.SetDpi(dpi, dpi);
         }
     }
 
-    pub fn render<T: AsRef<str>>(
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.vsync = vsync;
+    }
+
+    fn present(&self) {
+        let swap_chain = match self.swap_chain.as_ref() {
+            Some(swap_chain) => swap_chain,
+            None => return,
+        };
+        let (interval, flags) = if self.vsync {
+            (1, 0)
+        } else if self.tearing {
+            (0, DXGI_PRESENT_ALLOW_TEARING)
+        } else {
+            (0, 0)
+        };
+        let ret = unsafe { swap_chain.Present(interval, flags) };
+        if ret < 0 {
+            error!("Present: {}", HResult(ret));
+            self.check_device(ret);
+        }
+    }
+
+    pub fn to_dip(&self, position: wita::PhysicalPosition<i32>) -> (f32, f32) {
+        let mut dpi_x = 0.0;
+        let mut dpi_y = 0.0;
+        unsafe {
+            self.device_context.GetDpi(&mut dpi_x, &mut dpi_y);
+        }
+        (
+            position.x as f32 * 96.0 / dpi_x,
+            position.y as f32 * 96.0 / dpi_y,
+        )
+    }
+
+    pub fn show_osd(&mut self, text: impl Into<String>) {
+        self.osd = Some((text.into(), Instant::now()));
+    }
+
+    fn osd_alpha(&self) -> Option<f32> {
+        let elapsed = self.osd.as_ref()?.1.elapsed();
+        if elapsed < self.osd_duration {
+            Some(1.0)
+        } else if elapsed < self.osd_duration + self.osd_fade {
+            Some(1.0 - (elapsed - self.osd_duration).as_secs_f32() / self.osd_fade.as_secs_f32())
+        } else {
+            None
+        }
+    }
+
+    pub fn osd_visible(&self) -> bool {
+        self.osd_alpha().is_some()
+    }
+
+    fn draw_osd(&self) {
+        const HEIGHT: f32 = 40.0;
+        const MARGIN: f32 = 24.0;
+        let (text, alpha) = match (self.osd.as_ref(), self.osd_alpha()) {
+            (Some((text, _)), Some(alpha)) => (text, alpha),
+            _ => return,
+        };
+        let (back, color) = match (
+            self.create_brush(0.0, 0.0, 0.0, 0.6 * alpha),
+            self.create_brush(
+                self.text_color.0,
+                self.text_color.1,
+                self.text_color.2,
+                self.text_color.3 * alpha,
+            ),
+        ) {
+            (Ok(back), Ok(color)) => (back, color),
+            _ => return,
+        };
+        let (width, height) = self.viewport_size();
+        let rect = winapi::um::d2d1::D2D1_RECT_F {
+            left: 0.0,
+            top: height - HEIGHT - MARGIN,
+            right: width,
+            bottom: height - MARGIN,
+        };
+        let text = text.encode_utf16().collect::<Vec<_>>();
+        unsafe {
+            self.device_context.FillRectangle(&rect, back.as_ptr() as _);
+            self.device_context.DrawText(
+                text.as_ptr(),
+                text.len() as u32,
+                self.osd_format.as_ptr(),
+                &rect,
+                color.as_ptr() as _,
+                D2D1_DRAW_TEXT_OPTIONS_ENABLE_COLOR_FONT,
+                DWRITE_MEASURING_MODE_NATURAL,
+            );
+        }
+    }
+
+    pub fn set_status_bar(&mut self, text: Option<String>) {
+        self.status = text;
+    }
+
+    pub fn set_loupe(&mut self, loupe: Option<(f32, f32, Loupe)>) {
+        self.loupe = loupe;
+    }
+
+    pub fn set_annotations(&mut self, annotations: Option<(Vec<Shape>, f32)>) {
+        self.annotations = annotations;
+    }
+
+    pub fn set_compare(&mut self, compare: Option<ComPtr<ID2D1Bitmap1>>) {
+        self.compare = compare;
+    }
+
+    pub fn set_guides(&mut self, guides: Guides) {
+        self.guides = Some(guides);
+    }
+
+    pub fn set_mipmaps(
+        &mut self,
+        base: Option<ComPtr<ID2D1Bitmap1>>,
+        levels: Vec<ComPtr<ID2D1Bitmap1>>,
+    ) {
+        self.mipmaps = base.map(|base| (base, levels));
+    }
+
+    fn select_level(&self, img: ComPtr<ID2D1Bitmap1>, scale: f32) -> ComPtr<ID2D1Bitmap1> {
+        let levels = match self.mipmaps.as_ref() {
+            Some((base, levels)) if base.as_ptr() == img.as_ptr() => levels,
+            _ => return img,
+        };
+        let mut dpi_x = 0.0;
+        let mut dpi_y = 0.0;
+        let width = unsafe {
+            self.device_context.GetDpi(&mut dpi_x, &mut dpi_y);
+            img.GetSize().width
+        };
+        let target = width * scale * dpi_x / 96.0;
+        levels
+            .iter()
+            .rev()
+            .find(|level| unsafe { level.GetPixelSize().width } as f32 >= target)
+            .cloned()
+            .unwrap_or(img)
+    }
+
+    fn draw_status_bar(&self, text: &str) {
+        const HEIGHT: f32 = 24.0;
+        const PADDING: f32 = 8.0;
+        let (back, color) = match (
+            self.create_brush(0.0, 0.0, 0.0, 0.6),
+            self.create_brush(
+                self.text_color.0,
+                self.text_color.1,
+                self.text_color.2,
+                self.text_color.3,
+            ),
+        ) {
+            (Ok(back), Ok(color)) => (back, color),
+            _ => return,
+        };
+        let (width, height) = self.viewport_size();
+        let rect = winapi::um::d2d1::D2D1_RECT_F {
+            left: 0.0,
+            top: height - HEIGHT,
+            right: width,
+            bottom: height,
+        };
+        let text_rect = winapi::um::d2d1::D2D1_RECT_F {
+            left: PADDING,
+            right: width - PADDING,
+            ..rect
+        };
+        let text = text.encode_utf16().collect::<Vec<_>>();
+        unsafe {
+            self.device_context.FillRectangle(&rect, back.as_ptr() as _);
+            self.device_context.DrawText(
+                text.as_ptr(),
+                text.len() as u32,
+                self.status_format.as_ptr(),
+                &text_rect,
+                color.as_ptr() as _,
+                D2D1_DRAW_TEXT_OPTIONS_ENABLE_COLOR_FONT,
+                DWRITE_MEASURING_MODE_NATURAL,
+            );
+        }
+    }
+
+    pub fn viewport_size(&self) -> (f32, f32) {
+        let size = unsafe { self.device_context.GetSize() };
+        if self.compare.is_some() {
+            (size.width / 2.0, size.height)
+        } else {
+            (size.width, size.height)
+        }
+    }
+
+    pub fn create_bitmap(
+        &self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<ComPtr<ID2D1Bitmap1>, HResult> {
+        ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = self.device_context.CreateBitmap(
+                winapi::um::d2d1::D2D1_SIZE_U { width, height },
+                data.as_ptr() as _,
+                width * 4,
+                &D2D1_BITMAP_PROPERTIES1 {
+                    bitmapOptions: D2D1_BITMAP_OPTIONS_NONE,
+                    pixelFormat: D2D1_PIXEL_FORMAT {
+                        format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                        alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
+                    },
+                    dpiX: 96.0,
+                    dpiY: 96.0,
+                    colorContext: std::ptr::null_mut(),
+                },
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })
+    }
+
+    pub fn max_bitmap_size(&self) -> u32 {
+        unsafe { self.device_context.GetMaximumBitmapSize() }
+    }
+
+    pub fn project_panorama(
+        &mut self,
+        panorama: &Panorama,
+    ) -> Result<ComPtr<ID2D1Bitmap1>, HResult> {
+        if self.projector.is_none() {
+            self.projector = Some(Projector::new(&self.d3d11_device)?);
+        }
+        let (width, height) = self.viewport_size();
+        let mut dpi_x = 0.0;
+        let mut dpi_y = 0.0;
+        unsafe {
+            self.device_context.GetDpi(&mut dpi_x, &mut dpi_y);
+        }
+        let width = (width * dpi_x / 96.0).max(1.0) as u32;
+        let height = (height * dpi_y / 96.0).max(1.0) as u32;
+        let multithread = self.d2d1_factory.query_interface::<ID2D1Multithread>()?;
+        let projector = self.projector.as_mut().unwrap();
+        unsafe {
+            multithread.Enter();
+            let ret = projector.project(&self.device_context, panorama, width, height, dpi_x);
+            multithread.Leave();
+            ret
+        }
+    }
+
+    pub fn set_fit_mode(&mut self, fit_mode: FitMode) {
+        self.fit_mode = fit_mode;
+    }
+
+    pub fn zoom_at(&mut self, width: f32, height: f32, x: f32, y: f32, factor: f32) -> f32 {
+        let layout = self.layout(width, height);
+        let scale = (layout.scale * factor).clamp(0.01, 64.0);
+        let factor = scale / layout.scale;
+        self.zoom = Some(ImageLayout {
+            left: x - (x - layout.left) * factor,
+            top: y - (y - layout.top) * factor,
+            scale,
+        });
+        scale
+    }
+
+    pub fn pan(&mut self, width: f32, height: f32, dx: f32, dy: f32) {
+        let layout = self.layout(width, height);
+        self.zoom = Some(ImageLayout {
+            left: layout.left + dx,
+            top: layout.top + dy,
+            scale: layout.scale,
+        });
+    }
+
+    pub fn ken_burns(
+        &mut self,
+        width: f32,
+        height: f32,
+        progress: f32,
+        amplitude: f32,
+        seed: usize,
+    ) {
+        const FOCUS: [(f32, f32); 4] = [(0.3, 0.3), (0.7, 0.3), (0.7, 0.7), (0.3, 0.7)];
+        self.zoom = None;
+        let base = self.layout(width, height);
+        let viewport = unsafe { self.device_context.GetSize() };
+        let t = if seed.is_multiple_of(2) {
+            progress
+        } else {
+            1.0 - progress
+        };
+        let scale = base.scale * (1.0 + amplitude * t);
+        let from = FOCUS[seed % 4];
+        let to = FOCUS[(seed + 2) % 4];
+        let place = |viewport: f32, size: f32, from: f32, to: f32| {
+            if size <= viewport {
+                return (viewport - size) / 2.0;
+            }
+            let focus = from + (to - from) * progress;
+            (viewport / 2.0 - focus * size).clamp(viewport - size, 0.0)
+        };
+        self.zoom = Some(ImageLayout {
+            left: place(viewport.width, width * scale, from.0, to.0),
+            top: place(viewport.height, height * scale, from.1, to.1),
+            scale,
+        });
+    }
+
+    pub fn reset_zoom(&mut self) {
+        self.zoom = None;
+    }
+
+    pub fn layout(&self, width: f32, height: f32) -> ImageLayout {
+        if let Some(zoom) = self.zoom {
+            return zoom;
+        }
+        let (viewport_width, viewport_height) = self.viewport_size();
+        let fit = (viewport_width / width).min(viewport_height / height);
+        let scale = match self.fit_mode {
+            FitMode::Shrink => fit.min(1.0),
+            FitMode::Fit => fit,
+            FitMode::Width => viewport_width / width,
+            FitMode::Height => viewport_height / height,
+            FitMode::Original => 1.0,
+        };
+        let offset = |viewport: f32, size: f32| {
+            if size <= viewport {
+                (viewport - size) / 2.0
+            } else {
+                0.0
+            }
+        };
+        ImageLayout {
+            left: offset(viewport_width, width * scale),
+            top: offset(viewport_height, height * scale),
+            scale,
+        }
+    }
+
+    fn create_brush(
+        &self,
+        r: f32,
+        g: f32,
+        b: f32,
+        a: f32,
+    ) -> Result<ComPtr<ID2D1SolidColorBrush>, HResult> {
+        ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = self.device_context.CreateSolidColorBrush(
+                &D2D1_COLOR_F { r, g, b, a },
+                std::ptr::null_mut(),
+                &mut obj,
+            );
+            hresult(obj, ret)
+        })
+    }
+
+    fn draw_shapes(&self, shapes: &[Shape], stroke: f32) {
+        let brush = match self.create_brush(1.0, 0.2, 0.2, 1.0) {
+            Ok(brush) => brush,
+            Err(_) => return,
+        };
+        let style = ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = self.d2d1_factory.CreateStrokeStyle(
+                &D2D1_STROKE_STYLE_PROPERTIES1 {
+                    startCap: D2D1_CAP_STYLE_ROUND,
+                    endCap: D2D1_CAP_STYLE_ROUND,
+                    dashCap: D2D1_CAP_STYLE_ROUND,
+                    lineJoin: D2D1_LINE_JOIN_ROUND,
+                    miterLimit: 10.0,
+                    dashStyle: D2D1_DASH_STYLE_SOLID,
+                    dashOffset: 0.0,
+                    transformType: D2D1_STROKE_TRANSFORM_TYPE_NORMAL,
+                },
+                std::ptr::null(),
+                0,
+                &mut obj,
+            );
+            hresult(obj, ret)
+        });
+        let style = style
+            .as_ref()
+            .map_or(std::ptr::null_mut(), |style| style.as_ptr());
+        let dc = &self.device_context;
+        let line = |(x0, y0): (f32, f32), (x1, y1): (f32, f32)| unsafe {
+            dc.DrawLine(
+                winapi::um::d2d1::D2D1_POINT_2F { x: x0, y: y0 },
+                winapi::um::d2d1::D2D1_POINT_2F { x: x1, y: y1 },
+                brush.as_ptr() as _,
+                stroke,
+                style as _,
+            );
+        };
+        for shape in shapes {
+            match shape {
+                Shape::Pen(points) => {
+                    for w in points.windows(2) {
+                        line(w[0], w[1]);
+                    }
+                }
+                Shape::Rectangle((x0, y0), (x1, y1)) => unsafe {
+                    dc.DrawRectangle(
+                        &winapi::um::d2d1::D2D1_RECT_F {
+                            left: x0.min(*x1),
+                            top: y0.min(*y1),
+                            right: x0.max(*x1),
+                            bottom: y0.max(*y1),
+                        },
+                        brush.as_ptr() as _,
+                        stroke,
+                        style as _,
+                    );
+                },
+                Shape::Arrow(start, end) => {
+                    line(*start, *end);
+                    let angle = (end.1 - start.1).atan2(end.0 - start.0);
+                    let head = stroke * 5.0;
+                    for d in [-0.5, 0.5].iter() {
+                        let a = angle + std::f32::consts::PI + d;
+                        line(*end, (end.0 + head * a.cos(), end.1 + head * a.sin()));
+                    }
+                }
+                Shape::Text((x, y), text) => {
+                    let face = self
+                        .face_name
+                        .encode_utf16()
+                        .chain(Some(0))
+                        .collect::<Vec<_>>();
+                    let format = ComPtr::new(|| unsafe {
+                        let mut obj = std::ptr::null_mut();
+                        let locale = vec![0u16];
+                        let ret = self.dwrite_factory.CreateTextFormat(
+                            face.as_ptr(),
+                            std::ptr::null_mut(),
+                            DWRITE_FONT_WEIGHT_BOLD,
+                            DWRITE_FONT_STYLE_NORMAL,
+                            DWRITE_FONT_STRETCH_NORMAL,
+                            stroke * 8.0,
+                            locale.as_ptr(),
+                            &mut obj,
+                        );
+                        hresult(obj as *mut IDWriteTextFormat, ret)
+                    });
+                    let format = match format {
+                        Ok(format) => format,
+                        Err(e) => {
+                            error!("CreateTextFormat: {}", e);
+                            continue;
+                        }
+                    };
+                    let text = text.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+                    unsafe {
+                        dc.DrawText(
+                            text.as_ptr(),
+                            text.len() as u32,
+                            format.as_ptr(),
+                            &winapi::um::d2d1::D2D1_RECT_F {
+                                left: *x,
+                                top: *y,
+                                right: x + f32::MAX / 2.0,
+                                bottom: y + f32::MAX / 2.0,
+                            },
+                            brush.as_ptr() as _,
+                            D2D1_DRAW_TEXT_OPTIONS_NONE,
+                            DWRITE_MEASURING_MODE_NATURAL,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn flatten_annotations(
+        &self,
+        img: &image::RgbaImage,
+        shapes: &[Shape],
+        stroke: f32,
+    ) -> Result<image::RgbaImage, HResult> {
+        let (width, height) = img.dimensions();
+        let src = self.create_bitmap(width, height, img.as_raw())?;
+        let create = |options: D2D1_BITMAP_OPTIONS| {
+            ComPtr::new(|| unsafe {
+                let mut obj = std::ptr::null_mut();
+                let ret = self.device_context.CreateBitmap(
+                    winapi::um::d2d1::D2D1_SIZE_U { width, height },
+                    std::ptr::null(),
+                    0,
+                    &D2D1_BITMAP_PROPERTIES1 {
+                        bitmapOptions: options,
+                        pixelFormat: D2D1_PIXEL_FORMAT {
+                            format: DXGI_FORMAT_B8G8R8A8_UNORM,
+                            alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
+                        },
+                        dpiX: 96.0,
+                        dpiY: 96.0,
+                        colorContext: std::ptr::null_mut(),
+                    },
+                    &mut obj,
+                );
+                hresult(obj, ret)
+            })
+        };
+        let target = create(D2D1_BITMAP_OPTIONS_TARGET)?;
+        let cpu = create(D2D1_BITMAP_OPTIONS_CPU_READ | D2D1_BITMAP_OPTIONS_CANNOT_DRAW)?;
+        let dc = &self.device_context;
+        unsafe {
+            let mut prev = std::ptr::null_mut();
+            dc.GetTarget(&mut prev);
+            let prev = if prev.is_null() {
+                None
+            } else {
+                Some(ComPtr::from_raw(prev))
+            };
+            let mut dpi_x = 0.0;
+            let mut dpi_y = 0.0;
+            dc.GetDpi(&mut dpi_x, &mut dpi_y);
+            dc.SetTarget(target.as_ptr() as _);
+            dc.SetDpi(96.0, 96.0);
+            dc.BeginDraw();
+            dc.SetTransform(&winapi::um::d2d1::D2D1_MATRIX_3X2_F {
+                matrix: [[1.0, 0.0], [0.0, 1.0], [0.0, 0.0]],
+            });
+            dc.Clear(&D2D1_COLOR_F {
+                r: 0.0,
+                g: 0.0,
+                b: 0.0,
+                a: 0.0,
+            });
+            dc.DrawBitmap(
+                src.as_ptr() as _,
+                std::ptr::null(),
+                1.0,
+                D2D1_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
+                std::ptr::null(),
+                std::ptr::null(),
+            );
+            self.draw_shapes(shapes, stroke);
+            let ret = dc.EndDraw(std::ptr::null_mut(), std::ptr::null_mut());
+            dc.SetDpi(dpi_x, dpi_y);
+            dc.SetTarget(
+                prev.as_ref()
+                    .map_or(std::ptr::null(), |prev| prev.as_ptr() as _),
+            );
+            hresult((), ret)?;
+            hresult(
+                (),
+                cpu.CopyFromBitmap(std::ptr::null(), target.as_ptr() as _, std::ptr::null()),
+            )?;
+            let mut mapped = D2D1_MAPPED_RECT {
+                pitch: 0,
+                bits: std::ptr::null(),
+            };
+            hresult((), cpu.Map(D2D1_MAP_OPTIONS_READ, &mut mapped))?;
+            let mut flattened = image::RgbaImage::new(width, height);
+            for y in 0..height {
+                let row = std::slice::from_raw_parts(
+                    mapped.bits.add((y * mapped.pitch) as usize),
+                    (width * 4) as usize,
+                );
+                for (x, p) in row.chunks_exact(4).enumerate() {
+                    let a = p[3];
+                    let unmultiply = |c: u8| {
+                        if a == 0 {
+                            0
+                        } else {
+                            ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+                        }
+                    };
+                    flattened.put_pixel(
+                        x as u32,
+                        y,
+                        image::Rgba([unmultiply(p[2]), unmultiply(p[1]), unmultiply(p[0]), a]),
+                    );
+                }
+            }
+            cpu.Unmap();
+            Ok(flattened)
+        }
+    }
+
+    fn draw_guides(
+        &self,
+        layout: &ImageLayout,
+        rect: &winapi::um::d2d1::D2D1_RECT_F,
+        guides: &Guides,
+    ) {
+        let RgbaColor(r, g, b, a) = guides.color;
+        let brush = match self.create_brush(r, g, b, a) {
+            Ok(brush) => brush,
+            Err(_) => return,
+        };
+        let size = unsafe { self.device_context.GetSize() };
+        let (width, height) = (size.width, size.height);
+        let dc = &self.device_context;
+        let line = |x0: f32, y0: f32, x1: f32, y1: f32| unsafe {
+            dc.DrawLine(
+                winapi::um::d2d1::D2D1_POINT_2F { x: x0, y: y0 },
+                winapi::um::d2d1::D2D1_POINT_2F { x: x1, y: y1 },
+                brush.as_ptr() as _,
+                1.0,
+                std::ptr::null_mut(),
+            );
+        };
+        if guides.pixel_grid && layout.scale >= guides.pixel_grid_scale {
+            let left = rect.left.max(0.0);
+            let top = rect.top.max(0.0);
+            let right = rect.right.min(width);
+            let bottom = rect.bottom.min(height);
+            let (x0, y0) = layout.to_image(left, top);
+            let (x1, y1) = layout.to_image(right, bottom);
+            for x in x0.ceil() as i32..=x1.floor() as i32 {
+                let (x, _) = layout.to_viewport(x as f32, 0.0);
+                line(x, top, x, bottom);
+            }
+            for y in y0.ceil() as i32..=y1.floor() as i32 {
+                let (_, y) = layout.to_viewport(0.0, y as f32);
+                line(left, y, right, y);
+            }
+        }
+        let w = rect.right - rect.left;
+        let h = rect.bottom - rect.top;
+        if guides.thirds {
+            for i in 1..3 {
+                let x = rect.left + w * i as f32 / 3.0;
+                let y = rect.top + h * i as f32 / 3.0;
+                line(x, rect.top, x, rect.bottom);
+                line(rect.left, y, rect.right, y);
+            }
+        }
+        if guides.crosshair {
+            const SIZE: f32 = 12.0;
+            let x = rect.left + w / 2.0;
+            let y = rect.top + h / 2.0;
+            line(x - SIZE, y, x + SIZE, y);
+            line(x, y - SIZE, x, y + SIZE);
+        }
+        if guides.aspect_ratio {
+            for &(rw, rh) in guides.aspect_ratios.iter() {
+                if rw <= 0.0 || rh <= 0.0 {
+                    continue;
+                }
+                let (gw, gh) = if w * rh > h * rw {
+                    (h * rw / rh, h)
+                } else {
+                    (w, w * rh / rw)
+                };
+                let left = rect.left + (w - gw) / 2.0;
+                let top = rect.top + (h - gh) / 2.0;
+                unsafe {
+                    dc.DrawRectangle(
+                        &winapi::um::d2d1::D2D1_RECT_F {
+                            left,
+                            top,
+                            right: left + gw,
+                            bottom: top + gh,
+                        },
+                        brush.as_ptr() as _,
+                        1.0,
+                        std::ptr::null_mut(),
+                    );
+                }
+            }
+        }
+    }
+
+    fn draw_crop(
+        &self,
+        layout: &ImageLayout,
+        rect: &winapi::um::d2d1::D2D1_RECT_F,
+        region: &Region,
+    ) {
+        const HANDLE_SIZE: f32 = 4.0;
+        let (shade, line) = match (
+            self.create_brush(0.0, 0.0, 0.0, 0.5),
+            self.create_brush(1.0, 1.0, 1.0, 1.0),
+        ) {
+            (Ok(shade), Ok(line)) => (shade, line),
+            _ => return,
+        };
+        let (left, top) = layout.to_viewport(region.left, region.top);
+        let (right, bottom) = layout.to_viewport(region.right, region.bottom);
+        let dc = &self.device_context;
+        unsafe {
+            for r in [
+                winapi::um::d2d1::D2D1_RECT_F {
+                    left: rect.left,
+                    top: rect.top,
+                    right: rect.right,
+                    bottom: top,
+                },
+                winapi::um::d2d1::D2D1_RECT_F {
+                    left: rect.left,
+                    top: bottom,
+                    right: rect.right,
+                    bottom: rect.bottom,
+                },
+                winapi::um::d2d1::D2D1_RECT_F {
+                    left: rect.left,
+                    top,
+                    right: left,
+                    bottom,
+                },
+                winapi::um::d2d1::D2D1_RECT_F {
+                    left: right,
+                    top,
+                    right: rect.right,
+                    bottom,
+                },
+            ]
+            .iter()
+            {
+                dc.FillRectangle(r, shade.as_ptr() as _);
+            }
+            dc.DrawRectangle(
+                &winapi::um::d2d1::D2D1_RECT_F {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                },
+                line.as_ptr() as _,
+                1.0,
+                std::ptr::null_mut(),
+            );
+            for (_, x, y) in region.handles().iter() {
+                let (x, y) = layout.to_viewport(*x, *y);
+                dc.FillRectangle(
+                    &winapi::um::d2d1::D2D1_RECT_F {
+                        left: x - HANDLE_SIZE,
+                        top: y - HANDLE_SIZE,
+                        right: x + HANDLE_SIZE,
+                        bottom: y + HANDLE_SIZE,
+                    },
+                    line.as_ptr() as _,
+                );
+            }
+        }
+    }
+
+    fn create_effect(&self, clsid: &GUID) -> Result<ComPtr<ID2D1Effect>, HResult> {
+        ComPtr::new(|| unsafe {
+            let mut obj = std::ptr::null_mut();
+            let ret = self.device_context.CreateEffect(clsid, &mut obj);
+            hresult(obj, ret)
+        })
+    }
+
+    fn apply_effects(
+        &self,
+        img: &ComPtr<ID2D1Bitmap1>,
+        effects: &[Effect],
+    ) -> Result<Vec<ComPtr<ID2D1Effect>>, HResult> {
+        let mut chain = vec![];
+        let mut input = img.query_interface::<ID2D1Image>()?;
+        for effect in effects {
+            let objs = match *effect {
+                Effect::Contrast(contrast) => {
+                    let obj = self.create_effect(&CLSID_D2D1Contrast)?;
+                    unsafe {
+                        set_value(&obj, D2D1_CONTRAST_PROP_CONTRAST, &contrast);
+                    }
+                    vec![obj]
+                }
+                Effect::Binarize(threshold) => {
+                    let gray = self.create_effect(&CLSID_D2D1Grayscale)?;
+                    let transfer = self.create_effect(&CLSID_D2D1DiscreteTransfer)?;
+                    let table = (0..100)
+                        .map(|i| {
+                            if i as f32 / 100.0 < threshold {
+                                0.0
+                            } else {
+                                1.0
+                            }
+                        })
+                        .collect::<Vec<f32>>();
+                    unsafe {
+                        set_value(&transfer, D2D1_DISCRETETRANSFER_PROP_RED_TABLE, &table[..]);
+                        set_value(
+                            &transfer,
+                            D2D1_DISCRETETRANSFER_PROP_GREEN_TABLE,
+                            &table[..],
+                        );
+                        set_value(&transfer, D2D1_DISCRETETRANSFER_PROP_BLUE_TABLE, &table[..]);
+                        set_value(&transfer, D2D1_DISCRETETRANSFER_PROP_ALPHA_DISABLE, &TRUE);
+                    }
+                    vec![gray, transfer]
+                }
+                Effect::Brightness(brightness) => {
+                    let obj = self.create_effect(&CLSID_D2D1ColorMatrix)?;
+                    let mut matrix = [0.0f32; 20];
+                    for i in 0..4 {
+                        matrix[i * 5] = 1.0;
+                    }
+                    matrix[16..19].copy_from_slice(&[brightness; 3]);
+                    unsafe {
+                        set_value(&obj, D2D1_COLORMATRIX_PROP_COLOR_MATRIX, &matrix);
+                    }
+                    vec![obj]
+                }
+                Effect::Gamma(gamma) => {
+                    let obj = self.create_effect(&CLSID_D2D1GammaTransfer)?;
+                    let exponent = 1.0 / gamma;
+                    unsafe {
+                        set_value(&obj, D2D1_GAMMATRANSFER_PROP_RED_EXPONENT, &exponent);
+                        set_value(&obj, D2D1_GAMMATRANSFER_PROP_GREEN_EXPONENT, &exponent);
+                        set_value(&obj, D2D1_GAMMATRANSFER_PROP_BLUE_EXPONENT, &exponent);
+                        set_value(&obj, D2D1_GAMMATRANSFER_PROP_ALPHA_DISABLE, &TRUE);
+                    }
+                    vec![obj]
+                }
+                Effect::Exposure(exposure) => {
+                    let obj = self.create_effect(&CLSID_D2D1Exposure)?;
+                    unsafe {
+                        set_value(&obj, D2D1_EXPOSURE_PROP_EXPOSURE_VALUE, &exposure);
+                    }
+                    vec![obj]
+                }
+                Effect::Channel(channel) => match channel {
+                    Channel::All => vec![],
+                    Channel::Luminance => vec![self.create_effect(&CLSID_D2D1Grayscale)?],
+                    Channel::Inverted => vec![self.create_effect(&CLSID_D2D1Invert)?],
+                    Channel::Red | Channel::Green | Channel::Blue | Channel::Alpha => {
+                        let index = channel as usize - Channel::Red as usize;
+                        let obj = self.create_effect(&CLSID_D2D1ColorMatrix)?;
+                        let mut matrix = [0.0f32; 20];
+                        matrix[index * 4..index * 4 + 3].copy_from_slice(&[1.0; 3]);
+                        matrix[19] = 1.0;
+                        unsafe {
+                            set_value(&obj, D2D1_COLORMATRIX_PROP_COLOR_MATRIX, &matrix);
+                        }
+                        vec![obj]
+                    }
+                },
+                Effect::Downscale(scale) => {
+                    let mut objs = vec![];
+                    let mut rest = scale;
+                    while rest < 1.0 {
+                        let step = rest.max(0.5);
+                        rest /= step;
+                        let obj = self.create_effect(&CLSID_D2D1Scale)?;
+                        unsafe {
+                            set_value(
+                                &obj,
+                                D2D1_SCALE_PROP_SCALE,
+                                &D2D_VECTOR_2F { x: step, y: step },
+                            );
+                            set_value(
+                                &obj,
+                                D2D1_SCALE_PROP_INTERPOLATION_MODE,
+                                &D2D1_SCALE_INTERPOLATION_MODE_HIGH_QUALITY_CUBIC,
+                            );
+                            set_value(&obj, D2D1_SCALE_PROP_BORDER_MODE, &D2D1_BORDER_MODE_HARD);
+                            set_value(&obj, D2D1_SCALE_PROP_SHARPNESS, &1.0f32);
+                        }
+                        objs.push(obj);
+                    }
+                    objs
+                }
+            };
+            for obj in objs {
+                unsafe {
+                    obj.SetInput(0, input.as_ptr(), TRUE);
+                }
+                input = get_output(&obj)?;
+                chain.push(obj);
+            }
+        }
+        Ok(chain)
+    }
+
+    fn draw_text(&self, text: &str) {
+        let color = match self.create_brush(
+            self.text_color.0,
+            self.text_color.1,
+            self.text_color.2,
+            self.text_color.3,
+        ) {
+            Ok(color) => color,
+            Err(_) => return,
+        };
+        let text = text.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+        let (width, height) = self.viewport_size();
+        unsafe {
+            self.device_context.DrawText(
+                text.as_ptr(),
+                text.len() as u32,
+                self.text_format.as_ptr(),
+                &winapi::um::d2d1::D2D1_RECT_F {
+                    left: 0.0,
+                    top: 0.0,
+                    right: width,
+                    bottom: height,
+                },
+                color.as_ptr() as _,
+                D2D1_DRAW_TEXT_OPTIONS_ENABLE_COLOR_FONT,
+                DWRITE_MEASURING_MODE_NATURAL,
+            );
+        }
+    }
+
+    pub fn grid_rows(&self, columns: usize) -> usize {
+        let (width, height) = self.viewport_size();
+        let cell = width / columns.max(1) as f32;
+        ((height / cell) as usize).max(1)
+    }
+
+    pub fn grid_cell(&self, columns: usize, x: f32, y: f32) -> usize {
+        let (width, _) = self.viewport_size();
+        let cell = width / columns.max(1) as f32;
+        (y / cell) as usize * columns + ((x / cell) as usize).min(columns - 1)
+    }
+
+    pub fn render_grid<T: AsRef<str>>(
         &self,
         clear_color: &ClearColor,
-        img: Option<ComPtr<ID2D1Bitmap1>>,
+        cells: &[Option<ComPtr<ID2D1Bitmap1>>],
+        selected: usize,
+        columns: usize,
         interpolation: Interpolation,
         text: Option<T>,
     ) {
+        const MARGIN: f32 = 4.0;
         let dc = &self.device_context;
+        let (width, _) = self.viewport_size();
+        let cell = width / columns.max(1) as f32;
+        let (placeholder, outline) = match (
+            self.create_brush(0.25, 0.25, 0.25, 1.0),
+            self.create_brush(0.3, 0.6, 1.0, 1.0),
+        ) {
+            (Ok(placeholder), Ok(outline)) => (placeholder, outline),
+            _ => return,
+        };
         unsafe {
+            let start = Instant::now();
             dc.BeginDraw();
             dc.Clear(&D2D1_COLOR_F {
                 r: clear_color.0,
@@ -137,90 +1708,315 @@ impl Renderer {
                 b: clear_color.2,
                 a: 0.0,
             });
-            if let Some(img) = img {
-                let img_size = {
-                    let size = img.GetSize();
-                    winapi::um::d2d1::D2D1_SIZE_F {
-                        width: size.width as f32,
-                        height: size.height as f32,
-                    }
-                };
-                let viewport = {
-                    let size = self.render_target.GetSize();
-                    winapi::um::d2d1::D2D1_SIZE_F {
-                        width: size.width as f32,
-                        height: size.height as f32,
-                    }
+            for (i, bmp) in cells.iter().enumerate() {
+                let left = (i % columns) as f32 * cell;
+                let top = (i / columns) as f32 * cell;
+                let rect = winapi::um::d2d1::D2D1_RECT_F {
+                    left: left + MARGIN,
+                    top: top + MARGIN,
+                    right: left + cell - MARGIN,
+                    bottom: top + cell - MARGIN,
                 };
-                let aspect_img = img_size.width / img_size.height;
-                let aspect_vp = viewport.width / viewport.height;
-                let size = if img_size.width <= viewport.width && img_size.height <= viewport.height
-                {
-                    img_size.clone()
-                } else if aspect_img > aspect_vp {
-                    winapi::um::d2d1::D2D1_SIZE_F {
-                        width: viewport.width,
-                        height: viewport.height * aspect_vp / aspect_img,
+                match bmp {
+                    Some(bmp) => {
+                        let size = bmp.GetSize();
+                        let inner = cell - MARGIN * 2.0;
+                        let scale = (inner / size.width).min(inner / size.height);
+                        let (w, h) = (size.width * scale, size.height * scale);
+                        let x = rect.left + (inner - w) / 2.0;
+                        let y = rect.top + (inner - h) / 2.0;
+                        dc.DrawBitmap(
+                            bmp.as_ptr() as _,
+                            &winapi::um::d2d1::D2D1_RECT_F {
+                                left: x,
+                                top: y,
+                                right: x + w,
+                                bottom: y + h,
+                            },
+                            1.0,
+                            interpolation.mode(),
+                            std::ptr::null(),
+                            std::ptr::null(),
+                        );
                     }
-                } else {
-                    winapi::um::d2d1::D2D1_SIZE_F {
-                        width: viewport.width * aspect_img / aspect_vp,
-                        height: viewport.height,
-                    }
-                };
+                    None => dc.FillRectangle(&rect, placeholder.as_ptr() as _),
+                }
+                if i == selected {
+                    dc.DrawRectangle(&rect, outline.as_ptr() as _, 3.0, std::ptr::null_mut());
+                }
+            }
+            if let Some(text) = text {
+                self.draw_text(text.as_ref());
+            }
+            if let Some(status) = self.status.as_ref() {
+                self.draw_status_bar(status);
+            }
+            self.draw_osd();
+            let ret = dc.EndDraw(std::ptr::null_mut(), std::ptr::null_mut());
+            self.frame_time.set(start.elapsed());
+            if ret < 0 {
+                error!("EndDraw: {}", HResult(ret));
+                self.check_device(ret);
+                return;
+            }
+            self.present();
+        }
+    }
+
+    unsafe fn draw_image(
+        &self,
+        img: &ComPtr<ID2D1Bitmap1>,
+        layout: &ImageLayout,
+        interpolation: Interpolation,
+        effects: &[Effect],
+    ) {
+        let dc = &self.device_context;
+        let img_size = img.GetSize();
+        let rect = layout.rect(img_size.width, img_size.height);
+        let downscale =
+            interpolation == Interpolation::Lanczos && layout.scale > 0.0 && layout.scale < 1.0;
+        let chain = if downscale {
+            let mut effects = effects.to_vec();
+            effects.push(Effect::Downscale(layout.scale));
+            self.apply_effects(img, &effects)
+        } else if effects.is_empty() {
+            Ok(vec![])
+        } else {
+            self.apply_effects(img, effects)
+        };
+        let scale = if downscale { 1.0 } else { layout.scale };
+        match chain.as_ref().map(|chain| chain.last()) {
+            Ok(Some(last)) => {
+                if let Ok(output) = get_output(last) {
+                    dc.SetTransform(&winapi::um::d2d1::D2D1_MATRIX_3X2_F {
+                        matrix: [[scale, 0.0], [0.0, scale], [layout.left, layout.top]],
+                    });
+                    dc.DrawImage(
+                        output.as_ptr(),
+                        std::ptr::null(),
+                        std::ptr::null(),
+                        interpolation.mode(),
+                        D2D1_COMPOSITE_MODE_SOURCE_OVER,
+                    );
+                    dc.SetTransform(&winapi::um::d2d1::D2D1_MATRIX_3X2_F {
+                        matrix: [[1.0, 0.0], [0.0, 1.0], [0.0, 0.0]],
+                    });
+                }
+            }
+            _ => {
+                if let Err(e) = chain {
+                    error!("apply_effects: {}", e);
+                }
                 dc.DrawBitmap(
                     img.as_ptr() as _,
-                    &winapi::um::d2d1::D2D1_RECT_F {
-                        left: (viewport.width - size.width) / 2.0,
-                        top: (viewport.height - size.height) / 2.0,
-                        right: (viewport.width + size.width) / 2.0,
-                        bottom: (viewport.height + size.height) / 2.0,
-                    },
+                    &rect,
                     1.0,
-                    interpolation as u32,
+                    interpolation.mode(),
                     std::ptr::null(),
                     std::ptr::null(),
                 );
             }
-            if let Some(text) = text {
-                let color = ComPtr::new(|| {
+        }
+    }
+
+    unsafe fn draw_compare(
+        &self,
+        img: &ComPtr<ID2D1Bitmap1>,
+        other: &ComPtr<ID2D1Bitmap1>,
+        layout: &ImageLayout,
+        interpolation: Interpolation,
+        effects: &[Effect],
+    ) {
+        let dc = &self.device_context;
+        let (width, height) = self.viewport_size();
+        let other_size = other.GetSize();
+        let mut other_layout = self.layout(other_size.width, other_size.height);
+        other_layout.left += width;
+        for (i, (img, layout)) in [(img, *layout), (other, other_layout)].iter().enumerate() {
+            let left = width * i as f32;
+            dc.PushAxisAlignedClip(
+                &winapi::um::d2d1::D2D1_RECT_F {
+                    left,
+                    top: 0.0,
+                    right: left + width,
+                    bottom: height,
+                },
+                D2D1_ANTIALIAS_MODE_ALIASED,
+            );
+            let size = img.GetSize();
+            let bmp = self.select_level((*img).clone(), layout.scale);
+            self.draw_image(&bmp, layout, interpolation, effects);
+            if let Some(guides) = self.guides.as_ref() {
+                self.draw_guides(layout, &layout.rect(size.width, size.height), guides);
+            }
+            dc.PopAxisAlignedClip();
+        }
+        if let Ok(line) = self.create_brush(1.0, 1.0, 1.0, 0.5) {
+            dc.DrawLine(
+                winapi::um::d2d1::D2D1_POINT_2F { x: width, y: 0.0 },
+                winapi::um::d2d1::D2D1_POINT_2F {
+                    x: width,
+                    y: height,
+                },
+                line.as_ptr() as _,
+                1.0,
+                std::ptr::null_mut(),
+            );
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn draw_loupe(
+        &self,
+        img: &ComPtr<ID2D1Bitmap1>,
+        layout: &ImageLayout,
+        interpolation: Interpolation,
+        effects: &[Effect],
+        (x, y): (f32, f32),
+        loupe: &Loupe,
+    ) {
+        let dc = &self.device_context;
+        let radius = loupe.radius;
+        let factor = loupe.factor.max(1.0);
+        let zoomed = ImageLayout {
+            left: x - (x - layout.left) * factor,
+            top: y - (y - layout.top) * factor,
+            scale: layout.scale * factor,
+        };
+        let bounds = winapi::um::d2d1::D2D1_RECT_F {
+            left: x - radius,
+            top: y - radius,
+            right: x + radius,
+            bottom: y + radius,
+        };
+        let ellipse = D2D1_ELLIPSE {
+            point: winapi::um::d2d1::D2D1_POINT_2F { x, y },
+            radiusX: radius,
+            radiusY: radius,
+        };
+        let geometry = match loupe.shape {
+            LoupeShape::Circle => {
+                let geometry = ComPtr::new(|| {
                     let mut obj = std::ptr::null_mut();
-                    let ret = dc.CreateSolidColorBrush(
-                        &D2D1_COLOR_F {
-                            r: self.text_color.0,
-                            g: self.text_color.1,
-                            b: self.text_color.2,
-                            a: self.text_color.3,
-                        },
-                        std::ptr::null_mut(),
-                        &mut obj,
-                    );
+                    let ret = self.d2d1_factory.CreateEllipseGeometry(&ellipse, &mut obj);
                     hresult(obj, ret)
                 });
-                if let Ok(color) = color {
-                    let text = text
-                        .as_ref()
-                        .encode_utf16()
-                        .chain(Some(0))
-                        .collect::<Vec<_>>();
-                    let size = self.render_target.GetSize();
-                    dc.DrawText(
-                        text.as_ptr(),
-                        text.len() as u32,
-                        self.text_format.as_ptr(),
-                        &winapi::um::d2d1::D2D1_RECT_F {
-                            left: 0.0,
-                            top: 0.0,
-                            right: size.width,
-                            bottom: size.height,
-                        },
-                        color.as_ptr() as _,
-                        D2D1_DRAW_TEXT_OPTIONS_ENABLE_COLOR_FONT,
-                        DWRITE_MEASURING_MODE_NATURAL,
-                    );
+                match geometry {
+                    Ok(geometry) => Some(geometry),
+                    Err(e) => {
+                        error!("CreateEllipseGeometry: {}", e);
+                        return;
+                    }
                 }
             }
-            dc.EndDraw(std::ptr::null_mut(), std::ptr::null_mut());
+            LoupeShape::Rectangle => None,
+        };
+        match geometry.as_ref() {
+            Some(geometry) => dc.PushLayer(
+                &D2D1_LAYER_PARAMETERS1 {
+                    contentBounds: bounds,
+                    geometricMask: geometry.as_ptr() as _,
+                    maskAntialiasMode: D2D1_ANTIALIAS_MODE_PER_PRIMITIVE,
+                    maskTransform: winapi::um::d2d1::D2D1_MATRIX_3X2_F {
+                        matrix: [[1.0, 0.0], [0.0, 1.0], [0.0, 0.0]],
+                    },
+                    opacity: 1.0,
+                    opacityBrush: std::ptr::null(),
+                    layerOptions: D2D1_LAYER_OPTIONS1_NONE,
+                },
+                std::ptr::null(),
+            ),
+            None => dc.PushAxisAlignedClip(&bounds, D2D1_ANTIALIAS_MODE_PER_PRIMITIVE),
+        }
+        if let Ok(back) = self.create_brush(0.0, 0.0, 0.0, 1.0) {
+            dc.FillRectangle(&bounds, back.as_ptr() as _);
+        }
+        let img = self.select_level(img.clone(), zoomed.scale);
+        self.draw_image(&img, &zoomed, interpolation, effects);
+        match geometry {
+            Some(_) => dc.PopLayer(),
+            None => dc.PopAxisAlignedClip(),
+        }
+        if let Ok(line) = self.create_brush(1.0, 1.0, 1.0, 1.0) {
+            match loupe.shape {
+                LoupeShape::Circle => {
+                    dc.DrawEllipse(&ellipse, line.as_ptr() as _, 2.0, std::ptr::null_mut())
+                }
+                LoupeShape::Rectangle => {
+                    dc.DrawRectangle(&bounds, line.as_ptr() as _, 2.0, std::ptr::null_mut())
+                }
+            }
+        }
+    }
+
+    pub fn render<T: AsRef<str>>(
+        &self,
+        clear_color: &ClearColor,
+        img: Option<ComPtr<ID2D1Bitmap1>>,
+        interpolation: Interpolation,
+        effects: &[Effect],
+        crop: Option<Region>,
+        text: Option<T>,
+    ) {
+        let dc = &self.device_context;
+        unsafe {
+            let start = Instant::now();
+            dc.BeginDraw();
+            dc.Clear(&D2D1_COLOR_F {
+                r: clear_color.0,
+                g: clear_color.1,
+                b: clear_color.2,
+                a: 0.0,
+            });
+            if let Some(img) = img {
+                let img_size = img.GetSize();
+                let layout = self.layout(img_size.width, img_size.height);
+                let rect = layout.rect(img_size.width, img_size.height);
+                if let Some(other) = self.compare.as_ref() {
+                    self.draw_compare(&img, other, &layout, interpolation, effects);
+                } else {
+                    let base = img.clone();
+                    let img = self.select_level(img, layout.scale);
+                    self.draw_image(&img, &layout, interpolation, effects);
+                    if let Some(guides) = self.guides.as_ref() {
+                        self.draw_guides(&layout, &rect, guides);
+                    }
+                    if let Some((shapes, stroke)) = self.annotations.as_ref() {
+                        dc.SetTransform(&winapi::um::d2d1::D2D1_MATRIX_3X2_F {
+                            matrix: [
+                                [layout.scale, 0.0],
+                                [0.0, layout.scale],
+                                [layout.left, layout.top],
+                            ],
+                        });
+                        self.draw_shapes(shapes, *stroke);
+                        dc.SetTransform(&winapi::um::d2d1::D2D1_MATRIX_3X2_F {
+                            matrix: [[1.0, 0.0], [0.0, 1.0], [0.0, 0.0]],
+                        });
+                    }
+                    if let Some((x, y, loupe)) = self.loupe.as_ref() {
+                        self.draw_loupe(&base, &layout, interpolation, effects, (*x, *y), loupe);
+                    }
+                }
+                if let Some(region) = crop {
+                    self.draw_crop(&layout, &rect, &region);
+                }
+            }
+            if let Some(text) = text {
+                self.draw_text(text.as_ref());
+            }
+            if let Some(status) = self.status.as_ref() {
+                self.draw_status_bar(status);
+            }
+            self.draw_osd();
+            let ret = dc.EndDraw(std::ptr::null_mut(), std::ptr::null_mut());
+            self.frame_time.set(start.elapsed());
+            if ret < 0 {
+                error!("EndDraw: {}", HResult(ret));
+                self.check_device(ret);
+                return;
+            }
+            self.present();
         }
     }
 }
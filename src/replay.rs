@@ -0,0 +1,76 @@
+use crate::config::Method;
+use crate::control::{Command, CommandQueue};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum InputEvent {
+    Open(PathBuf),
+    Pressed { method: Method, repeat: bool },
+    Released(Method),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    time: u64,
+    event: InputEvent,
+}
+
+pub struct Recorder {
+    start: Instant,
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn new(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            start: Instant::now(),
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn record(&mut self, event: InputEvent) {
+        let record = Record {
+            time: self.start.elapsed().as_millis() as u64,
+            event,
+        };
+        let ret = serde_json::to_writer(&mut self.writer, &record)
+            .map_err(|e| e.into())
+            .and_then(|_| writeln!(self.writer))
+            .and_then(|_| self.writer.flush());
+        if let Err(e) = ret {
+            error!("record input: {}", e);
+        }
+    }
+}
+
+pub fn replay(path: impl AsRef<Path>, skip_open: bool, queue: CommandQueue) -> anyhow::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let records = reader
+        .lines()
+        .map(|line| Ok(serde_json::from_str::<Record>(&line?)?))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        for record in records {
+            if skip_open && matches!(record.event, InputEvent::Open(_)) {
+                continue;
+            }
+            let t = Duration::from_millis(record.time);
+            if let Some(d) = t.checked_sub(start.elapsed()) {
+                std::thread::sleep(d);
+            }
+            debug!("replay input: {:?}", record.event);
+            queue.push(Command::Input(record.event));
+        }
+        info!(
+            "replay input: finished in {}ms",
+            start.elapsed().as_millis()
+        );
+    });
+    Ok(())
+}
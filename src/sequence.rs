@@ -0,0 +1,266 @@
+use crate::config::{KeyBinding, Method};
+use std::time::{Duration, Instant};
+use wita::VirtualKey;
+
+const MODIFIERS: [VirtualKey; 3] = [VirtualKey::Shift, VirtualKey::Ctrl, VirtualKey::Alt];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Match {
+    Pending,
+    Found(Method, Option<usize>),
+    Fallback(Vec<VirtualKey>, Option<usize>),
+    Discarded,
+}
+
+fn chord_matches(step: &[VirtualKey], pressed: &[VirtualKey]) -> bool {
+    step.iter().all(|k| pressed.contains(k))
+        && MODIFIERS
+            .iter()
+            .all(|m| step.contains(m) == pressed.contains(m))
+}
+
+fn digit(key: VirtualKey, pressed: &[VirtualKey]) -> Option<usize> {
+    if MODIFIERS.iter().any(|m| pressed.contains(m)) {
+        return None;
+    }
+    match key {
+        VirtualKey::Char(c) => c.to_digit(10).map(|d| d as usize),
+        VirtualKey::NumPad(d) if d < 10 => Some(d as usize),
+        _ => None,
+    }
+}
+
+pub struct Sequence {
+    chords: Vec<Vec<VirtualKey>>,
+    keys: Vec<VirtualKey>,
+    count: Option<usize>,
+    held: Option<Vec<VirtualKey>>,
+    exact: Option<Method>,
+    deadline: Option<Instant>,
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        Self {
+            chords: vec![],
+            keys: vec![],
+            count: None,
+            held: None,
+            exact: None,
+            deadline: None,
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn text(&self) -> Option<String> {
+        self.deadline?;
+        let mut text = self
+            .count
+            .map(|n| n.to_string())
+            .into_iter()
+            .collect::<Vec<_>>();
+        text.extend(self.keys.iter().map(|key| match key {
+            VirtualKey::Char(c) => c.to_string(),
+            key => format!("{:?}", key),
+        }));
+        Some(text.join(" "))
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn push(
+        &mut self,
+        key: VirtualKey,
+        pressed: &[VirtualKey],
+        bindings: &[KeyBinding],
+        timeout: Duration,
+    ) -> Match {
+        if MODIFIERS.contains(&key) {
+            return if self.deadline.is_some() {
+                Match::Pending
+            } else {
+                Match::Fallback(pressed.to_vec(), None)
+            };
+        }
+        if self.chords.is_empty() {
+            if let Some(d) = digit(key, pressed) {
+                if d != 0 || self.count.is_some() {
+                    let bound = bindings
+                        .iter()
+                        .any(|kb| kb.keys.iter().any(|kk| kk.as_slice() == [key]));
+                    self.held = if bound && self.count.is_none() {
+                        Some(pressed.to_vec())
+                    } else {
+                        None
+                    };
+                    self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(d));
+                    self.deadline = Some(Instant::now() + timeout);
+                    return Match::Pending;
+                }
+            }
+        }
+        self.chords.push(pressed.to_vec());
+        self.keys.push(key);
+        let mut exact = None;
+        let mut prefix = false;
+        for kb in bindings {
+            if kb.method == Method::GoTo && self.count.is_none() {
+                continue;
+            }
+            for seq in &kb.sequences {
+                if seq.len() < self.chords.len()
+                    || !seq
+                        .iter()
+                        .zip(&self.chords)
+                        .all(|(step, chord)| chord_matches(step, chord))
+                {
+                    continue;
+                }
+                if seq.len() == self.chords.len() {
+                    exact = exact.or(Some(kb.method));
+                } else {
+                    prefix = true;
+                }
+            }
+        }
+        if prefix {
+            self.exact = exact;
+            self.deadline = Some(Instant::now() + timeout);
+            return Match::Pending;
+        }
+        let count = self.count;
+        let chords = std::mem::take(&mut self.chords);
+        self.clear();
+        match exact {
+            Some(method) => Match::Found(method, count),
+            None if chords.len() == 1 => Match::Fallback(pressed.to_vec(), count),
+            None => Match::Discarded,
+        }
+    }
+
+    pub fn expire(&mut self) -> Option<Match> {
+        if Instant::now() < self.deadline? {
+            return None;
+        }
+        let count = self.count;
+        let exact = self.exact;
+        let held = self.held.take();
+        let mut chords = std::mem::take(&mut self.chords);
+        self.clear();
+        Some(match exact {
+            Some(method) => Match::Found(method, count),
+            None if chords.len() == 1 => Match::Fallback(chords.pop().unwrap(), count),
+            None => match held {
+                Some(keys) if chords.is_empty() => Match::Fallback(keys, None),
+                _ => Match::Discarded,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn push(seq: &mut Sequence, key: VirtualKey, bindings: &[KeyBinding]) -> Match {
+        seq.push(key, &[key], bindings, Duration::from_millis(0))
+    }
+
+    #[test]
+    fn count_then_g_goes_to_index() {
+        let bindings = Config::default().key_bindings;
+        let mut seq = Sequence::new();
+        assert_eq!(
+            push(&mut seq, VirtualKey::Char('5'), &bindings),
+            Match::Pending
+        );
+        assert_eq!(
+            push(&mut seq, VirtualKey::Char('G'), &bindings),
+            Match::Pending
+        );
+        assert_eq!(seq.expire(), Some(Match::Found(Method::GoTo, Some(5))));
+    }
+
+    #[test]
+    fn multi_digit_count() {
+        let bindings = Config::default().key_bindings;
+        let mut seq = Sequence::new();
+        push(&mut seq, VirtualKey::Char('1'), &bindings);
+        push(&mut seq, VirtualKey::Char('2'), &bindings);
+        push(&mut seq, VirtualKey::Char('G'), &bindings);
+        assert_eq!(seq.expire(), Some(Match::Found(Method::GoTo, Some(12))));
+    }
+
+    #[test]
+    fn count_with_g_g_goes_to_first() {
+        let bindings = Config::default().key_bindings;
+        let mut seq = Sequence::new();
+        push(&mut seq, VirtualKey::Char('3'), &bindings);
+        push(&mut seq, VirtualKey::Char('G'), &bindings);
+        assert_eq!(
+            push(&mut seq, VirtualKey::Char('G'), &bindings),
+            Match::Found(Method::First, Some(3))
+        );
+    }
+
+    #[test]
+    fn lone_bound_digit_falls_back_after_timeout() {
+        let bindings = Config::default().key_bindings;
+        let mut seq = Sequence::new();
+        assert_eq!(
+            push(&mut seq, VirtualKey::Char('5'), &bindings),
+            Match::Pending
+        );
+        assert_eq!(
+            seq.expire(),
+            Some(Match::Fallback(vec![VirtualKey::Char('5')], None))
+        );
+        assert_eq!(seq.deadline(), None);
+    }
+
+    #[test]
+    fn zero_without_count_is_not_a_count() {
+        let bindings = Config::default().key_bindings;
+        let mut seq = Sequence::new();
+        assert_eq!(
+            push(&mut seq, VirtualKey::Char('0'), &bindings),
+            Match::Fallback(vec![VirtualKey::Char('0')], None)
+        );
+    }
+
+    #[test]
+    fn default_keys_are_not_sequence_prefixes() {
+        let bindings = Config::default().key_bindings;
+        for kb in bindings.iter().filter(|kb| kb.method != Method::GoTo) {
+            for keys in &kb.keys {
+                let key = *keys.last().unwrap();
+                if digit(key, keys).is_some() {
+                    continue;
+                }
+                let mut seq = Sequence::new();
+                let m = seq.push(key, keys, &bindings, Duration::from_secs(60));
+                assert_ne!(m, Match::Pending, "{:?} waits for a sequence", kb.method);
+            }
+        }
+    }
+
+    #[test]
+    fn pending_sequence_waits_for_timeout() {
+        let bindings = Config::default().key_bindings;
+        let mut seq = Sequence::new();
+        seq.push(
+            VirtualKey::Char('G'),
+            &[VirtualKey::Char('G')],
+            &bindings,
+            Duration::from_secs(60),
+        );
+        assert_eq!(seq.expire(), None);
+        assert!(seq.text().is_some());
+    }
+}
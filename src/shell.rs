@@ -0,0 +1,218 @@
+use crate::error::Error;
+use com_ptr::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use winapi::shared::winerror::*;
+use winapi::um::combaseapi::*;
+use winapi::um::objidl::IBindCtx;
+use winapi::um::shellapi::*;
+use winapi::um::shlobj::*;
+use winapi::um::shobjidl_core::*;
+use winapi::um::shtypes::*;
+use winapi::um::winnt::PCWSTR;
+
+#[link(name = "shell32")]
+extern "system" {
+    fn SHParseDisplayName(
+        name: PCWSTR,
+        bind_ctx: *mut IBindCtx,
+        pidl: *mut PIDLIST_ABSOLUTE,
+        sfgao_in: SFGAOF,
+        sfgao_out: *mut SFGAOF,
+    ) -> HRESULT;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[repr(u32)]
+pub enum WallpaperPosition {
+    Center = DWPOS_CENTER,
+    Tile = DWPOS_TILE,
+    Stretch = DWPOS_STRETCH,
+    Fit = DWPOS_FIT,
+    Fill = DWPOS_FILL,
+    Span = DWPOS_SPAN,
+}
+
+fn to_wide(path: &Path) -> Vec<u16> {
+    path.to_string_lossy()
+        .encode_utf16()
+        .chain(Some(0))
+        .collect::<Vec<_>>()
+}
+
+pub fn reveal_in_explorer(path: impl AsRef<Path>) -> Result<(), Error> {
+    let path = to_wide(path.as_ref());
+    unsafe {
+        let mut pidl = std::ptr::null_mut();
+        let ret = SHParseDisplayName(
+            path.as_ptr(),
+            std::ptr::null_mut(),
+            &mut pidl,
+            0,
+            std::ptr::null_mut(),
+        );
+        if ret != S_OK {
+            return Err(HResult(ret).into());
+        }
+        let ret = SHOpenFolderAndSelectItems(pidl, 0, std::ptr::null(), 0);
+        CoTaskMemFree(pidl as _);
+        if ret != S_OK {
+            return Err(HResult(ret).into());
+        }
+    }
+    Ok(())
+}
+
+pub fn move_to_recycle_bin(path: impl AsRef<Path>) -> Result<(), Error> {
+    let mut path = to_wide(path.as_ref());
+    path.push(0);
+    let mut op = SHFILEOPSTRUCTW {
+        wFunc: FO_DELETE as _,
+        pFrom: path.as_ptr(),
+        fFlags: FOF_ALLOWUNDO | FOF_NO_UI,
+        ..Default::default()
+    };
+    let ret = unsafe { SHFileOperationW(&mut op) };
+    if ret != 0 || op.fAnyOperationsAborted != 0 {
+        return Err(Error::Other(anyhow::anyhow!(
+            "SHFileOperationW failed (0x{:x})",
+            ret
+        )));
+    }
+    Ok(())
+}
+
+pub fn set_wallpaper(path: impl AsRef<Path>, position: WallpaperPosition) -> Result<(), Error> {
+    let path = std::env::current_dir()?.join(path.as_ref());
+    let path = to_wide(&path);
+    let wallpaper =
+        co_create_instance::<IDesktopWallpaper>(&CLSID_DesktopWallpaper, None, CLSCTX_ALL)?;
+    unsafe {
+        let ret = wallpaper.SetPosition(position as u32);
+        if ret != S_OK {
+            return Err(HResult(ret).into());
+        }
+        let ret = wallpaper.SetWallpaper(std::ptr::null(), path.as_ptr());
+        if ret != S_OK {
+            return Err(HResult(ret).into());
+        }
+    }
+    Ok(())
+}
+
+fn split_command(command: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut has_token = false;
+    for c in command.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                has_token = true;
+            }
+            _ if c.is_whitespace() && !quoted => {
+                if has_token {
+                    tokens.push(std::mem::take(&mut token));
+                    has_token = false;
+                }
+            }
+            _ => {
+                token.push(c);
+                has_token = true;
+            }
+        }
+    }
+    if has_token {
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn expand_command(command: &str, path: &Path) -> Vec<String> {
+    let dir = path
+        .parent()
+        .map_or(String::new(), |p| p.to_string_lossy().into());
+    let name = path
+        .file_name()
+        .map_or(String::new(), |p| p.to_string_lossy().into());
+    let path = path.to_string_lossy();
+    split_command(command)
+        .into_iter()
+        .map(|token| {
+            token
+                .replace("{path}", &path)
+                .replace("{dir}", &dir)
+                .replace("{name}", &name)
+        })
+        .collect()
+}
+
+pub fn run_external_tool(command: &str, path: impl AsRef<Path>) -> Result<(), Error> {
+    let tokens = expand_command(command, path.as_ref());
+    if tokens.is_empty() {
+        return Err(Error::Other(anyhow::anyhow!("empty command")));
+    }
+    std::process::Command::new(&tokens[0])
+        .args(&tokens[1..])
+        .spawn()?;
+    Ok(())
+}
+
+pub fn open_url(url: &str) -> Result<(), Error> {
+    let verb = "open".encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    let url = url.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    let ret = unsafe {
+        ShellExecuteW(
+            std::ptr::null_mut(),
+            verb.as_ptr(),
+            url.as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            winapi::um::winuser::SW_SHOWNORMAL,
+        )
+    };
+    if ret as usize <= 32 {
+        return Err(Error::Other(anyhow::anyhow!(
+            "ShellExecuteW failed ({})",
+            ret as usize
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_keeps_quoted_spaces() {
+        assert_eq!(
+            split_command(r#""C:\Program Files\tool.exe"  -a "b c" """#),
+            vec!["C:\\Program Files\\tool.exe", "-a", "b c", ""]
+        );
+    }
+
+    #[test]
+    fn placeholders_are_substituted() {
+        let path = Path::new("/images/my file.png");
+        assert_eq!(
+            expand_command("tool --dir={dir} \"{path}\" {name}", path),
+            vec![
+                "tool",
+                "--dir=/images",
+                "/images/my file.png",
+                "my file.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn placeholders_in_quoted_path_stay_one_argument() {
+        let path = Path::new("/a b/c d.jpg");
+        assert_eq!(
+            expand_command("viewer {path}", path),
+            vec!["viewer", "/a b/c d.jpg"]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Resolution {
+    UnderOne,
+    UnderFour,
+    UnderTwelve,
+    UnderTwentyFour,
+    Huge,
+}
+
+impl Resolution {
+    fn from_dimensions(width: u32, height: u32) -> Self {
+        let pixels = width as u64 * height as u64;
+        match pixels {
+            _ if pixels < 1_000_000 => Self::UnderOne,
+            _ if pixels < 4_000_000 => Self::UnderFour,
+            _ if pixels < 12_000_000 => Self::UnderTwelve,
+            _ if pixels < 24_000_000 => Self::UnderTwentyFour,
+            _ => Self::Huge,
+        }
+    }
+}
+
+impl std::fmt::Display for Resolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnderOne => write!(f, "< 1MP"),
+            Self::UnderFour => write!(f, "1-4MP"),
+            Self::UnderTwelve => write!(f, "4-12MP"),
+            Self::UnderTwentyFour => write!(f, "12-24MP"),
+            Self::Huge => write!(f, ">= 24MP"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FolderStats {
+    pub dir: PathBuf,
+    pub count: usize,
+    pub formats: BTreeMap<String, usize>,
+    pub total_size: u64,
+    pub resolutions: BTreeMap<Resolution, usize>,
+    pub oldest: Option<SystemTime>,
+    pub newest: Option<SystemTime>,
+    pub unreadable: usize,
+}
+
+impl FolderStats {
+    pub fn new(dir: impl AsRef<Path>, paths: &[PathBuf]) -> Self {
+        let mut stats = Self {
+            dir: dir.as_ref().to_path_buf(),
+            count: paths.len(),
+            formats: BTreeMap::new(),
+            total_size: 0,
+            resolutions: BTreeMap::new(),
+            oldest: None,
+            newest: None,
+            unreadable: 0,
+        };
+        for path in paths {
+            let ext = path
+                .extension()
+                .map_or(String::new(), |ext| ext.to_string_lossy().to_lowercase());
+            *stats.formats.entry(ext).or_insert(0) += 1;
+            if let Ok(meta) = path.metadata() {
+                stats.total_size += meta.len();
+                if let Ok(modified) = meta.modified() {
+                    stats.oldest = Some(stats.oldest.map_or(modified, |t| t.min(modified)));
+                    stats.newest = Some(stats.newest.map_or(modified, |t| t.max(modified)));
+                }
+            }
+            match image::image_dimensions(path) {
+                Ok((width, height)) => {
+                    let res = Resolution::from_dimensions(width, height);
+                    *stats.resolutions.entry(res).or_insert(0) += 1;
+                }
+                Err(_) => stats.unreadable += 1,
+            }
+        }
+        stats
+    }
+
+    pub fn export_path(&self) -> Option<PathBuf> {
+        if self.dir.is_dir() {
+            return Some(self.dir.join("folder_stats.txt"));
+        }
+        self.dir
+            .parent()
+            .filter(|parent| parent.is_dir())
+            .map(|parent| parent.join("folder_stats.txt"))
+    }
+}
+
+impl std::fmt::Display for FolderStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", self.dir.to_string_lossy())?;
+        writeln!(f, "files: {}", self.count)?;
+        writeln!(
+            f,
+            "total size: {:.2}(MB)",
+            self.total_size as f64 / 1024.0 / 1024.0
+        )?;
+        writeln!(f, "formats:")?;
+        for (ext, n) in &self.formats {
+            writeln!(f, "  {}: {}", ext, n)?;
+        }
+        writeln!(f, "resolutions:")?;
+        for (res, n) in &self.resolutions {
+            writeln!(f, "  {}: {}", res, n)?;
+        }
+        if let (Some(oldest), Some(newest)) = (self.oldest, self.newest) {
+            writeln!(f, "date: {} - {}", format_date(oldest), format_date(newest))?;
+        }
+        write!(f, "unreadable: {}", self.unreadable)
+    }
+}
+
+pub fn format_date(t: SystemTime) -> String {
+    let secs = t
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs()) as i64;
+    let days = secs.div_euclid(86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
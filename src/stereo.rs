@@ -0,0 +1,91 @@
+use crate::error::Error;
+use crate::images::open_image;
+use image::{imageops, RgbaImage};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StereoMode {
+    Off,
+    LeftOnly,
+    Anaglyph,
+    CrossEye,
+}
+
+impl StereoMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::LeftOnly,
+            Self::LeftOnly => Self::Anaglyph,
+            Self::Anaglyph => Self::CrossEye,
+            Self::CrossEye => Self::Off,
+        }
+    }
+}
+
+fn split_mpo(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    if !data.windows(4).any(|w| w == b"MPF\0") {
+        return None;
+    }
+    let pos = data
+        .windows(4)
+        .position(|w| w == [0xff, 0xd9, 0xff, 0xd8])?
+        + 2;
+    Some((&data[..pos], &data[pos..]))
+}
+
+fn split_side_by_side(img: &RgbaImage, cross: bool) -> (RgbaImage, RgbaImage) {
+    let (width, height) = img.dimensions();
+    let half = width / 2;
+    let first = imageops::crop_imm(img, 0, 0, half, height).to_image();
+    let second = imageops::crop_imm(img, half, 0, half, height).to_image();
+    if cross {
+        (second, first)
+    } else {
+        (first, second)
+    }
+}
+
+pub fn load_pair(path: &Path) -> Result<Option<(RgbaImage, RgbaImage)>, Error> {
+    let ext = path
+        .extension()
+        .map_or(String::new(), |ext| ext.to_string_lossy().to_lowercase());
+    let stem = path
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().to_lowercase());
+    if ext == "jps" {
+        return Ok(Some(split_side_by_side(&open_image(path)?, true)));
+    }
+    if stem.ends_with("_sbs") || stem.ends_with("-sbs") {
+        return Ok(Some(split_side_by_side(&open_image(path)?, false)));
+    }
+    if ext != "jpg" && ext != "jpeg" && ext != "mpo" {
+        return Ok(None);
+    }
+    let data = std::fs::read(path)?;
+    match split_mpo(&data) {
+        Some((left, right)) => Ok(Some((
+            image::load_from_memory(left)?.to_rgba8(),
+            image::load_from_memory(right)?.to_rgba8(),
+        ))),
+        None => Ok(None),
+    }
+}
+
+pub fn compose(mode: StereoMode, left: &RgbaImage, right: &RgbaImage) -> RgbaImage {
+    let width = left.width().min(right.width());
+    let height = left.height().min(right.height());
+    match mode {
+        StereoMode::Off | StereoMode::LeftOnly => left.clone(),
+        StereoMode::Anaglyph => RgbaImage::from_fn(width, height, |x, y| {
+            let l = left.get_pixel(x, y);
+            let r = right.get_pixel(x, y);
+            image::Rgba([l[0], r[1], r[2], l[3].max(r[3])])
+        }),
+        StereoMode::CrossEye => {
+            let mut img = RgbaImage::new(width * 2, height);
+            imageops::replace(&mut img, right, 0, 0);
+            imageops::replace(&mut img, left, width, 0);
+            img
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use crate::error::Error;
+use image::RgbaImage;
+use log::debug;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tiff::decoder::Decoder;
+use tiff::tags::Tag;
+
+const PARALLEL_PIXELS: u64 = 16_000_000;
+
+const COMPRESSION_NONE: u16 = 1;
+const COMPRESSION_LZW: u16 = 5;
+const COMPRESSION_DEFLATE: u16 = 8;
+const COMPRESSION_OLD_DEFLATE: u16 = 32946;
+const PHOTOMETRIC_RGB: u16 = 2;
+const PREDICTOR_HORIZONTAL: u16 = 2;
+
+struct Layout {
+    width: u32,
+    height: u32,
+    rows_per_strip: u32,
+    samples: u32,
+    compression: u16,
+    predictor: u16,
+    offsets: Vec<u64>,
+    counts: Vec<u64>,
+}
+
+fn tiff_error(e: tiff::TiffError) -> Error {
+    Error::Other(e.into())
+}
+
+fn layout(path: &Path) -> Result<Option<Layout>, Error> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?)).map_err(tiff_error)?;
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    if (width as u64) * (height as u64) < PARALLEL_PIXELS {
+        return Ok(None);
+    }
+    let tag = |decoder: &mut Decoder<_>, tag, default| {
+        decoder
+            .find_tag_unsigned::<u16>(tag)
+            .map(|value| value.unwrap_or(default))
+            .map_err(tiff_error)
+    };
+    let compression = tag(&mut decoder, Tag::Compression, COMPRESSION_NONE)?;
+    let photometric = tag(&mut decoder, Tag::PhotometricInterpretation, 0)?;
+    let planar = tag(&mut decoder, Tag::PlanarConfiguration, 1)?;
+    let predictor = tag(&mut decoder, Tag::Predictor, 1)?;
+    let samples = tag(&mut decoder, Tag::SamplesPerPixel, 1)? as u32;
+    let bits = decoder
+        .find_tag_unsigned_vec::<u16>(Tag::BitsPerSample)
+        .map_err(tiff_error)?
+        .unwrap_or_default();
+    let tiled = decoder
+        .find_tag(Tag::TileWidth)
+        .map_err(tiff_error)?
+        .is_some();
+    let supported = !tiled
+        && photometric == PHOTOMETRIC_RGB
+        && planar == 1
+        && (samples == 3 || samples == 4)
+        && bits.iter().all(|&b| b == 8)
+        && matches!(
+            compression,
+            COMPRESSION_NONE | COMPRESSION_LZW | COMPRESSION_DEFLATE | COMPRESSION_OLD_DEFLATE
+        )
+        && (predictor == 1 || predictor == PREDICTOR_HORIZONTAL);
+    if !supported {
+        return Ok(None);
+    }
+    let rows_per_strip = decoder
+        .find_tag_unsigned::<u32>(Tag::RowsPerStrip)
+        .map_err(tiff_error)?
+        .unwrap_or(height)
+        .clamp(1, height);
+    let offsets = decoder
+        .get_tag_u64_vec(Tag::StripOffsets)
+        .map_err(tiff_error)?;
+    let counts = decoder
+        .get_tag_u64_vec(Tag::StripByteCounts)
+        .map_err(tiff_error)?;
+    if offsets.len() != counts.len() || offsets.len() < height.div_ceil(rows_per_strip) as usize {
+        return Ok(None);
+    }
+    Ok(Some(Layout {
+        width,
+        height,
+        rows_per_strip,
+        samples,
+        compression,
+        predictor,
+        offsets,
+        counts,
+    }))
+}
+
+fn inflate_lzw(data: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    let mut decoder = weezl::decode::Decoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8);
+    let mut buffer = vec![0u8; len];
+    let mut read = 0;
+    let mut written = 0;
+    while written < len {
+        let result = decoder.decode_bytes(&data[read..], &mut buffer[written..]);
+        read += result.consumed_in;
+        written += result.consumed_out;
+        match result.status {
+            Ok(weezl::LzwStatus::Ok) => {}
+            Ok(weezl::LzwStatus::Done) => break,
+            Ok(weezl::LzwStatus::NoProgress) => break,
+            Err(e) => return Err(Error::Other(anyhow::anyhow!("lzw: {}", e))),
+        }
+    }
+    Ok(buffer)
+}
+
+fn decode_strip(file: &mut File, layout: &Layout, index: usize) -> Result<Vec<u8>, Error> {
+    let top = index as u32 * layout.rows_per_strip;
+    let rows = layout.rows_per_strip.min(layout.height - top) as usize;
+    let stride = layout.width as usize * layout.samples as usize;
+    let len = rows * stride;
+    let mut data = vec![0u8; layout.counts[index] as usize];
+    file.seek(SeekFrom::Start(layout.offsets[index]))?;
+    file.read_exact(&mut data)?;
+    let mut data = match layout.compression {
+        COMPRESSION_LZW => inflate_lzw(&data, len)?,
+        COMPRESSION_DEFLATE | COMPRESSION_OLD_DEFLATE => {
+            miniz_oxide::inflate::decompress_to_vec_zlib(&data)
+                .map_err(|e| Error::Other(anyhow::anyhow!("deflate: {:?}", e)))?
+        }
+        _ => data,
+    };
+    data.resize(len, 0);
+    if layout.predictor == PREDICTOR_HORIZONTAL {
+        let samples = layout.samples as usize;
+        for row in data.chunks_exact_mut(stride) {
+            for i in samples..stride {
+                row[i] = row[i].wrapping_add(row[i - samples]);
+            }
+        }
+    }
+    if layout.samples == 4 {
+        return Ok(data);
+    }
+    let mut rgba = Vec::with_capacity(rows * layout.width as usize * 4);
+    for pixel in data.chunks_exact(3) {
+        rgba.extend_from_slice(pixel);
+        rgba.push(255);
+    }
+    Ok(rgba)
+}
+
+fn decode_strips(path: &Path, layout: &Layout, strips: Range<usize>) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![];
+    for index in strips {
+        buffer.extend(decode_strip(&mut file, layout, index)?);
+    }
+    Ok(buffer)
+}
+
+pub fn open(path: &Path, threads: usize) -> Result<Option<RgbaImage>, Error> {
+    let runtime = match tokio::runtime::Handle::try_current() {
+        Ok(runtime) if threads > 1 => runtime,
+        _ => return Ok(None),
+    };
+    let layout = match layout(path)? {
+        Some(layout) => Arc::new(layout),
+        None => return Ok(None),
+    };
+    let strips = layout.height.div_ceil(layout.rows_per_strip) as usize;
+    let chunk = strips.div_ceil(threads).max(1);
+    let tasks = strips.div_ceil(chunk);
+    debug!(
+        "strip decode: {}: {} strips, {} tasks",
+        path.to_string_lossy(),
+        strips,
+        tasks
+    );
+    let (tx, rx) = std::sync::mpsc::channel();
+    for (i, start) in (0..strips).step_by(chunk).enumerate() {
+        let path = PathBuf::from(path);
+        let layout = layout.clone();
+        let tx = tx.clone();
+        runtime.spawn_blocking(move || {
+            let ret = decode_strips(&path, &layout, start..(start + chunk).min(strips));
+            tx.send((i, ret)).ok();
+        });
+    }
+    let mut results = (0..tasks).map(|_| None).collect::<Vec<_>>();
+    for _ in 0..tasks {
+        let (i, ret) = rx
+            .recv()
+            .map_err(|e| Error::Other(anyhow::anyhow!("strip decode: {}", e)))?;
+        results[i] = Some(ret?);
+    }
+    let mut buffer = Vec::with_capacity(layout.width as usize * layout.height as usize * 4);
+    for strips in results.into_iter().flatten() {
+        buffer.extend(strips);
+    }
+    Ok(RgbaImage::from_raw(layout.width, layout.height, buffer))
+}
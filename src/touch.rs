@@ -0,0 +1,199 @@
+use crate::control::{Command, CommandQueue};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use winapi::shared::minwindef::{LPARAM, UINT, WPARAM};
+use winapi::shared::windef::*;
+use winapi::um::winuser::*;
+
+const SWIPE_TIME: Duration = Duration::from_millis(800);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TouchEvent {
+    Down(u32, i32, i32),
+    Move(u32, i32, i32),
+    Up(u32, i32, i32),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Zoom { x: f32, y: f32, factor: f32 },
+    Pan { dx: f32, dy: f32 },
+    Swipe(f32),
+}
+
+pub unsafe fn handle_message(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    commands: &CommandQueue,
+) -> bool {
+    match msg {
+        WM_POINTERDOWN | WM_POINTERUPDATE | WM_POINTERUP => {
+            let id = (wparam & 0xffff) as u32;
+            let mut pointer_type = 0;
+            if GetPointerType(id, &mut pointer_type) == 0 || pointer_type != PT_TOUCH {
+                return false;
+            }
+            let mut pt = POINT {
+                x: (lparam & 0xffff) as i16 as i32,
+                y: ((lparam >> 16) & 0xffff) as i16 as i32,
+            };
+            ScreenToClient(hwnd, &mut pt);
+            let event = match msg {
+                WM_POINTERDOWN => TouchEvent::Down(id, pt.x, pt.y),
+                WM_POINTERUPDATE => TouchEvent::Move(id, pt.x, pt.y),
+                _ => TouchEvent::Up(id, pt.x, pt.y),
+            };
+            commands.push(Command::Touch(event));
+            true
+        }
+        _ => false,
+    }
+}
+
+pub struct Recognizer {
+    contacts: HashMap<u32, (f32, f32)>,
+    start: Option<(f32, f32, Instant)>,
+    multi: bool,
+}
+
+impl Recognizer {
+    pub fn new() -> Self {
+        Self {
+            contacts: HashMap::new(),
+            start: None,
+            multi: false,
+        }
+    }
+
+    fn center_and_distance(&self) -> Option<((f32, f32), f32)> {
+        let mut points = self.contacts.values();
+        let a = points.next()?;
+        let b = points.next()?;
+        let center = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+        let distance = ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+        Some((center, distance))
+    }
+
+    pub fn down(&mut self, id: u32, x: f32, y: f32) {
+        self.contacts.insert(id, (x, y));
+        if self.contacts.len() == 1 {
+            self.start = Some((x, y, Instant::now()));
+            self.multi = false;
+        } else {
+            self.multi = true;
+        }
+    }
+
+    pub fn moved(&mut self, id: u32, x: f32, y: f32) -> Vec<Action> {
+        let prev = self.center_and_distance();
+        let last = match self.contacts.get_mut(&id) {
+            Some(pos) => std::mem::replace(pos, (x, y)),
+            None => return vec![],
+        };
+        match (prev, self.center_and_distance()) {
+            (Some((c0, d0)), Some((c1, d1))) => {
+                let mut actions = vec![Action::Pan {
+                    dx: c1.0 - c0.0,
+                    dy: c1.1 - c0.1,
+                }];
+                if d0 > 0.0 && d1 > 0.0 {
+                    actions.push(Action::Zoom {
+                        x: c1.0,
+                        y: c1.1,
+                        factor: d1 / d0,
+                    });
+                }
+                actions
+            }
+            _ if !self.multi => vec![Action::Pan {
+                dx: x - last.0,
+                dy: y - last.1,
+            }],
+            _ => vec![],
+        }
+    }
+
+    pub fn up(&mut self, id: u32, x: f32, y: f32, threshold: f32) -> Option<Action> {
+        self.contacts.remove(&id);
+        if !self.contacts.is_empty() || self.multi {
+            return None;
+        }
+        let (sx, sy, time) = self.start.take()?;
+        let dx = x - sx;
+        let dy = y - sy;
+        if time.elapsed() <= SWIPE_TIME && dx.abs() >= threshold && dx.abs() > dy.abs() * 2.0 {
+            Some(Action::Swipe(dx))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_finger_pans() {
+        let mut r = Recognizer::new();
+        r.down(1, 10.0, 10.0);
+        assert_eq!(
+            r.moved(1, 15.0, 8.0),
+            vec![Action::Pan { dx: 5.0, dy: -2.0 }]
+        );
+        assert_eq!(r.up(1, 15.0, 8.0, 100.0), None);
+    }
+
+    #[test]
+    fn quick_horizontal_flick_swipes() {
+        let mut r = Recognizer::new();
+        r.down(1, 300.0, 100.0);
+        r.moved(1, 200.0, 110.0);
+        assert_eq!(r.up(1, 100.0, 120.0, 100.0), Some(Action::Swipe(-200.0)));
+    }
+
+    #[test]
+    fn short_or_vertical_moves_do_not_swipe() {
+        let mut r = Recognizer::new();
+        r.down(1, 100.0, 100.0);
+        assert_eq!(r.up(1, 150.0, 100.0, 100.0), None);
+        r.down(1, 100.0, 100.0);
+        assert_eq!(r.up(1, 250.0, 300.0, 100.0), None);
+    }
+
+    #[test]
+    fn two_fingers_pinch_zoom_around_center() {
+        let mut r = Recognizer::new();
+        r.down(1, 0.0, 0.0);
+        r.down(2, 100.0, 0.0);
+        assert_eq!(
+            r.moved(2, 200.0, 0.0),
+            vec![
+                Action::Pan { dx: 50.0, dy: 0.0 },
+                Action::Zoom {
+                    x: 100.0,
+                    y: 0.0,
+                    factor: 2.0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn lifting_after_pinch_neither_pans_nor_swipes() {
+        let mut r = Recognizer::new();
+        r.down(1, 0.0, 0.0);
+        r.down(2, 100.0, 0.0);
+        assert_eq!(r.up(2, 300.0, 0.0, 100.0), None);
+        assert_eq!(r.moved(1, 10.0, 0.0), vec![]);
+        assert_eq!(r.up(1, 300.0, 0.0, 100.0), None);
+    }
+
+    #[test]
+    fn unknown_contact_is_ignored() {
+        let mut r = Recognizer::new();
+        assert_eq!(r.moved(7, 1.0, 1.0), vec![]);
+    }
+}
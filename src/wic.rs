@@ -0,0 +1,117 @@
+use crate::error::Error;
+use crate::images::fit_size;
+use com_ptr::*;
+use image::RgbaImage;
+use std::path::Path;
+use winapi::shared::wtypesbase::CLSCTX_INPROC_SERVER;
+use winapi::um::combaseapi::{CoInitializeEx, CoUninitialize};
+use winapi::um::objbase::COINIT_MULTITHREADED;
+use winapi::um::wincodec::*;
+use winapi::um::winnt::GENERIC_READ;
+
+const WINCODEC_ERR_COMPONENTNOTFOUND: i32 = 0x88982f50_u32 as i32;
+
+fn to_wide(path: &Path) -> Vec<u16> {
+    path.to_string_lossy()
+        .encode_utf16()
+        .chain(Some(0))
+        .collect::<Vec<_>>()
+}
+
+unsafe fn decode(path: &Path, limit: u32) -> Result<(RgbaImage, u32, u32), HResult> {
+    let factory = co_create_instance::<IWICImagingFactory>(
+        &CLSID_WICImagingFactory,
+        None,
+        CLSCTX_INPROC_SERVER,
+    )?;
+    let path = to_wide(path);
+    let decoder = ComPtr::new(|| {
+        let mut obj = std::ptr::null_mut();
+        let ret = factory.CreateDecoderFromFilename(
+            path.as_ptr(),
+            std::ptr::null(),
+            GENERIC_READ,
+            WICDecodeMetadataCacheOnDemand,
+            &mut obj,
+        );
+        hresult(obj, ret)
+    })?;
+    let frame = ComPtr::new(|| {
+        let mut obj = std::ptr::null_mut();
+        let ret = decoder.GetFrame(0, &mut obj);
+        hresult(obj, ret)
+    })?;
+    let mut original_width = 0;
+    let mut original_height = 0;
+    hresult((), frame.GetSize(&mut original_width, &mut original_height))?;
+    let (w, h) = fit_size(original_width, original_height, limit);
+    let source = if (w, h) == (original_width, original_height) {
+        frame.query_interface::<IWICBitmapSource>()?
+    } else {
+        let scaler = ComPtr::new(|| {
+            let mut obj = std::ptr::null_mut();
+            let ret = factory.CreateBitmapScaler(&mut obj);
+            hresult(obj, ret)
+        })?;
+        hresult(
+            (),
+            scaler.Initialize(frame.as_ptr() as _, w, h, WICBitmapInterpolationModeFant),
+        )?;
+        scaler.query_interface::<IWICBitmapSource>()?
+    };
+    let converter = ComPtr::new(|| {
+        let mut obj = std::ptr::null_mut();
+        let ret = factory.CreateFormatConverter(&mut obj);
+        hresult(obj, ret)
+    })?;
+    hresult(
+        (),
+        converter.Initialize(
+            source.as_ptr(),
+            &GUID_WICPixelFormat32bppRGBA,
+            WICBitmapDitherTypeNone,
+            std::ptr::null(),
+            0.0,
+            WICBitmapPaletteTypeCustom,
+        ),
+    )?;
+    let mut width = 0;
+    let mut height = 0;
+    hresult((), converter.GetSize(&mut width, &mut height))?;
+    let mut buffer = vec![0u8; width as usize * height as usize * 4];
+    hresult(
+        (),
+        converter.CopyPixels(
+            std::ptr::null(),
+            width * 4,
+            buffer.len() as _,
+            buffer.as_mut_ptr(),
+        ),
+    )?;
+    Ok((
+        RgbaImage::from_raw(width, height, buffer).unwrap(),
+        original_width,
+        original_height,
+    ))
+}
+
+pub fn open_image(path: &Path) -> Result<RgbaImage, Error> {
+    open_image_scaled(path, 0).map(|(img, _, _)| img)
+}
+
+pub fn open_image_scaled(path: &Path, limit: u32) -> Result<(RgbaImage, u32, u32), Error> {
+    if !path.is_file() {
+        return Err(Error::FileNotFound);
+    }
+    unsafe {
+        let init = CoInitializeEx(std::ptr::null_mut(), COINIT_MULTITHREADED);
+        let ret = decode(path, limit);
+        if init >= 0 {
+            CoUninitialize();
+        }
+        ret.map_err(|e| match e.code() {
+            WINCODEC_ERR_COMPONENTNOTFOUND => Error::Unsupported,
+            _ => e.into(),
+        })
+    }
+}
@@ -0,0 +1,86 @@
+use crate::control::{Command, CommandQueue};
+use crate::touch;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+use winapi::shared::minwindef::{LPARAM, LRESULT, TRUE, UINT, WPARAM};
+use winapi::shared::windef::*;
+use winapi::um::winuser::*;
+
+const REDRAW_TIMER: usize = 0x6e6976;
+
+thread_local! {
+    static HOOKS: RefCell<HashMap<usize, (isize, CommandQueue)>> = RefCell::new(HashMap::new());
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    let hook = HOOKS.with(|hooks| hooks.borrow().get(&(hwnd as usize)).cloned());
+    let (prev, commands) = match hook {
+        Some(hook) => hook,
+        None => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    };
+    let prev: WNDPROC = std::mem::transmute(prev);
+    if touch::handle_message(hwnd, msg, wparam, lparam, &commands) {
+        return 0;
+    }
+    match msg {
+        WM_APPCOMMAND if GET_DEVICE_LPARAM(lparam) != FAPPCOMMAND_KEY => {
+            let vkey = match GET_APPCOMMAND_LPARAM(lparam) {
+                APPCOMMAND_MEDIA_NEXTTRACK => VK_MEDIA_NEXT_TRACK,
+                APPCOMMAND_MEDIA_PREVIOUSTRACK => VK_MEDIA_PREV_TRACK,
+                APPCOMMAND_MEDIA_STOP => VK_MEDIA_STOP,
+                APPCOMMAND_MEDIA_PLAY_PAUSE | APPCOMMAND_MEDIA_PLAY | APPCOMMAND_MEDIA_PAUSE => {
+                    VK_MEDIA_PLAY_PAUSE
+                }
+                _ => return CallWindowProcW(prev, hwnd, msg, wparam, lparam),
+            };
+            commands.push(Command::MediaKey(wita::VirtualKey::Other(vkey as u32)));
+            TRUE as LRESULT
+        }
+        WM_TIMER if wparam == REDRAW_TIMER => {
+            KillTimer(hwnd, REDRAW_TIMER);
+            RedrawWindow(
+                hwnd,
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                RDW_INTERNALPAINT,
+            );
+            0
+        }
+        WM_DISPLAYCHANGE => {
+            commands.push(Command::DisplayChanged);
+            CallWindowProcW(prev, hwnd, msg, wparam, lparam)
+        }
+        WM_NCDESTROY => {
+            HOOKS.with(|hooks| hooks.borrow_mut().remove(&(hwnd as usize)));
+            SetWindowLongPtrW(hwnd, GWLP_WNDPROC, prev.map_or(0, |f| f as usize as isize));
+            CallWindowProcW(prev, hwnd, msg, wparam, lparam)
+        }
+        _ => CallWindowProcW(prev, hwnd, msg, wparam, lparam),
+    }
+}
+
+pub fn hook_window(wnd: &wita::Window, commands: CommandQueue) {
+    unsafe {
+        let hwnd = wnd.raw_handle() as HWND;
+        let proc: WNDPROC = Some(window_proc);
+        let prev = SetWindowLongPtrW(hwnd, GWLP_WNDPROC, proc.map_or(0, |f| f as usize as isize));
+        HOOKS.with(|hooks| {
+            hooks
+                .borrow_mut()
+                .insert(hwnd as usize, (prev as isize, commands))
+        });
+    }
+}
+
+pub fn set_redraw_timer(wnd: &wita::Window, delay: Duration) {
+    let ms = (delay.as_millis() as u32).max(USER_TIMER_MINIMUM);
+    unsafe {
+        SetTimer(wnd.raw_handle() as HWND, REDRAW_TIMER, ms, None);
+    }
+}